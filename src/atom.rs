@@ -0,0 +1,627 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Shared references to persistent values.
+//!
+//! An [`Atom`][Atom] holds a value which can be read and replaced
+//! from any number of threads at once, in the manner of Clojure's
+//! atoms. Reading it with [`load`][Atom::load] never blocks, and
+//! gives you an `Arc` snapshot of the current value which won't
+//! change under you, however the atom is updated afterwards.
+//!
+//! Rather than locking the value while it's changed, you update it
+//! with [`swap`][Atom::swap], passing a function from the current
+//! value to the new one. If another thread replaces the value while
+//! your function is running, it's simply called again with the newer
+//! value, so it should be free of side effects. This works well with
+//! the collections in this crate, where making a changed copy of a
+//! value only copies the nodes which change, and the function can
+//! just use their persistent update methods. Neither reading nor
+//! updating an atom ever waits for another thread, so a thread which
+//! is suspended halfway through either can't hold up the others.
+//!
+//! An atom can also have a validator, which every new value must
+//! pass before it's stored, and watches, which are called with the
+//! old and the new value after every change.
+//!
+//! This module is only available with the `std` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::atom::Atom;
+//! # use std::sync::Arc;
+//! # use std::thread;
+//! # fn main() {
+//! let atom = Arc::new(Atom::new(hashmap!{}));
+//! let threads: Vec<_> = (0..4)
+//!     .map(|i| {
+//!         let atom = atom.clone();
+//!         thread::spawn(move || {
+//!             atom.swap(|map| map.insert(i, i * 2)).unwrap();
+//!         })
+//!     })
+//!     .collect();
+//! for thread in threads {
+//!     thread.join().unwrap();
+//! }
+//! assert_eq!(hashmap!{0 => 0, 1 => 2, 2 => 4, 3 => 6}, *atom.load());
+//! # }
+//! ```
+//!
+//! [Atom]: ./struct.Atom.html
+//! [Atom::load]: ./struct.Atom.html#method.load
+//! [Atom::swap]: ./struct.Atom.html#method.swap
+
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use shared::Shared;
+
+// An `Arc` which can be loaded and replaced atomically.
+//
+// The cell owns one strong reference to the value its pointer points
+// to. A load has to add a reference of its own before the cell's
+// reference can be released, so it first records the pointer it read
+// in a debt slot, and then reads the pointer again. If it's still the
+// same, any writer which replaces it from then on will see the debt
+// when it looks through the slots after the replacement, and takes a
+// reference for the load before releasing the cell's, marking the
+// debt as paid. The load then settles its debt, and if it turns out
+// to have been paid, it keeps the writer's reference instead of its
+// own.
+//
+// Neither side ever waits for the other: a load only retries if a
+// writer replaced the value while it was running, and a writer only
+// looks at each slot once. Slots are never freed while the cell is
+// alive, so a cell holds one for every load it's had running at
+// once.
+struct ArcCell<T> {
+    ptr: AtomicPtr<T>,
+    debts: AtomicPtr<Debt<T>>,
+    // The cell behaves like an `Arc<T>` as far as `Send` and `Sync`
+    // are concerned.
+    value: PhantomData<Arc<T>>,
+}
+
+// A slot in which a load records the value it's about to take a
+// reference to. It's free while it holds a null pointer.
+struct Debt<T> {
+    ptr: AtomicPtr<T>,
+    next: *mut Debt<T>,
+}
+
+// What a writer leaves in a debt slot when it's taken a reference on
+// the load's behalf. No `Arc` can point to a static.
+static PAID: u8 = 0;
+
+fn paid<T>() -> *mut T {
+    &PAID as *const u8 as *mut T
+}
+
+impl<T> ArcCell<T> {
+    fn new(value: Arc<T>) -> Self {
+        ArcCell {
+            ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+            debts: AtomicPtr::new(ptr::null_mut()),
+            value: PhantomData,
+        }
+    }
+
+    fn load(&self) -> Arc<T> {
+        loop {
+            let ptr = self.ptr.load(Ordering::SeqCst);
+            let debt = self.record(ptr);
+            if self.ptr.load(Ordering::SeqCst) == ptr {
+                // Whoever replaces `ptr` now has to pay the debt
+                // before releasing the cell's reference, so it's
+                // still alive here.
+                unsafe { Arc::increment_strong_count(ptr) };
+                if Self::settle(debt) {
+                    unsafe { Arc::decrement_strong_count(ptr) };
+                }
+                return unsafe { Arc::from_raw(ptr) };
+            }
+            if Self::settle(debt) {
+                // The writer which replaced `ptr` took a reference
+                // for us, so we've got a snapshot anyway.
+                return unsafe { Arc::from_raw(ptr) };
+            }
+        }
+    }
+
+    // Replace the value if it's still `current`, returning the old
+    // value, or give `new` back if it isn't.
+    fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let current = &**current as *const T as *mut T;
+        let new = Arc::into_raw(new) as *mut T;
+        match self
+            .ptr
+            .compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(old) => Ok(self.release(old)),
+            // `new` came from `Arc::into_raw` above and hasn't been
+            // shared.
+            Err(_) => Err(unsafe { Arc::from_raw(new) }),
+        }
+    }
+
+    fn replace(&self, new: Arc<T>) -> Arc<T> {
+        let old = self.ptr
+            .swap(Arc::into_raw(new) as *mut T, Ordering::SeqCst);
+        self.release(old)
+    }
+
+    // Take back the reference the cell held to a value it no longer
+    // points to, after paying the debts of any loads which might be
+    // about to clone it.
+    fn release(&self, old: *mut T) -> Arc<T> {
+        let mut node = self.debts.load(Ordering::SeqCst);
+        while !node.is_null() {
+            let debt = unsafe { &*node };
+            if debt.ptr.load(Ordering::SeqCst) == old {
+                // The cell's reference keeps `old` alive until we
+                // return, so it's safe to take one more, and to give
+                // it back if the load settled its debt first.
+                unsafe { Arc::increment_strong_count(old) };
+                if debt.ptr
+                    .compare_exchange(old, paid(), Ordering::SeqCst, Ordering::SeqCst)
+                    .is_err()
+                {
+                    unsafe { Arc::decrement_strong_count(old) };
+                }
+            }
+            node = debt.next;
+        }
+        unsafe { Arc::from_raw(old) }
+    }
+
+    // Record a debt for `ptr` in a free slot, adding a new slot if
+    // they're all taken.
+    fn record(&self, ptr: *mut T) -> &AtomicPtr<T> {
+        let mut node = self.debts.load(Ordering::SeqCst);
+        while !node.is_null() {
+            let debt = unsafe { &*node };
+            if debt.ptr
+                .compare_exchange(ptr::null_mut(), ptr, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return &debt.ptr;
+            }
+            node = debt.next;
+        }
+        let node = Box::into_raw(Box::new(Debt {
+            ptr: AtomicPtr::new(ptr),
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.debts.load(Ordering::SeqCst);
+        loop {
+            // Nobody else can see `node` until it's been pushed.
+            unsafe { (*node).next = head };
+            match self.debts
+                .compare_exchange(head, node, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return unsafe { &(*node).ptr },
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    // Free a debt slot, returning whether a writer paid the debt.
+    fn settle(debt: &AtomicPtr<T>) -> bool {
+        debt.swap(ptr::null_mut(), Ordering::SeqCst) == paid()
+    }
+}
+
+impl<T> Drop for ArcCell<T> {
+    fn drop(&mut self) {
+        // Nothing else can be using the cell any more.
+        unsafe { drop(Arc::from_raw(*self.ptr.get_mut())) }
+        let mut node = *self.debts.get_mut();
+        while !node.is_null() {
+            let debt = unsafe { Box::from_raw(node) };
+            node = debt.next;
+        }
+    }
+}
+
+/// The error returned when an [`Atom`][Atom]'s validator rejects a
+/// new value, holding the rejected value.
+///
+/// [Atom]: ./struct.Atom.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejected<T>(Arc<T>);
+
+impl<T> Rejected<T> {
+    /// Get the value which was rejected.
+    pub fn value(&self) -> &Arc<T> {
+        &self.0
+    }
+
+    /// Get the value which was rejected, consuming the error.
+    pub fn into_value(self) -> Arc<T> {
+        self.0
+    }
+}
+
+impl<T> Display for Rejected<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "the atom's validator rejected the new value")
+    }
+}
+
+impl<T: Debug> Error for Rejected<T> {}
+
+type Validator<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+type Watch<T> = Arc<dyn Fn(&T, &T) + Send + Sync>;
+
+/// A shared, atomically updated reference to a persistent value.
+///
+/// See the [module documentation][atom] for an overview.
+///
+/// [atom]: ./index.html
+pub struct Atom<T> {
+    value: ArcCell<T>,
+    validator: ArcCell<Option<Validator<T>>>,
+    watches: ArcCell<Vec<(String, Watch<T>)>>,
+}
+
+impl<T> Atom<T> {
+    /// Construct an atom holding `value`.
+    pub fn new<R>(value: R) -> Self
+    where
+        R: Shared<T>,
+    {
+        Atom {
+            value: ArcCell::new(value.shared()),
+            validator: ArcCell::new(Arc::new(None)),
+            watches: ArcCell::new(Arc::new(Vec::new())),
+        }
+    }
+
+    /// Get a snapshot of the current value.
+    ///
+    /// This never blocks, and only costs the increment of a
+    /// reference count.
+    pub fn load(&self) -> Arc<T> {
+        self.value.load()
+    }
+
+    /// Replace the value with `value`, and return the old value.
+    pub fn store<R>(&self, value: R) -> Result<Arc<T>, Rejected<T>>
+    where
+        R: Shared<T>,
+    {
+        let new = self.validate(value.shared())?;
+        let old = self.value.replace(new.clone());
+        self.notify(&old, &new);
+        Ok(old)
+    }
+
+    /// Replace the value with the result of calling `f` on it, and
+    /// return the new value.
+    ///
+    /// If another thread changes the value while `f` is running, `f`
+    /// is called again with the newer value, until the value it was
+    /// given is still current when it returns. It shouldn't have any
+    /// side effects, since it may be called any number of times.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::atom::Atom;
+    /// # fn main() {
+    /// let atom = Atom::new(vector![1, 2]);
+    /// atom.swap(|vec| vec.push_back(3)).unwrap();
+    /// assert_eq!(vector![1, 2, 3], *atom.load());
+    /// # }
+    /// ```
+    pub fn swap<F, R>(&self, mut f: F) -> Result<Arc<T>, Rejected<T>>
+    where
+        F: FnMut(&T) -> R,
+        R: Shared<T>,
+    {
+        let mut current = self.load();
+        loop {
+            let new = self.validate(f(&current).shared())?;
+            match self.value.compare_and_swap(&current, new.clone()) {
+                Ok(old) => {
+                    self.notify(&old, &new);
+                    return Ok(new);
+                }
+                Err(_) => current = self.load(),
+            }
+        }
+    }
+
+    /// Replace the value with `new`, but only if it's still
+    /// `current`, which should be a snapshot returned by
+    /// [`load`][load]. Returns whether the value was replaced.
+    ///
+    /// The value is compared by identity rather than equality: a
+    /// different but equal value counts as a change.
+    ///
+    /// [load]: #method.load
+    pub fn compare_and_set<R>(&self, current: &Arc<T>, new: R) -> Result<bool, Rejected<T>>
+    where
+        R: Shared<T>,
+    {
+        let new = self.validate(new.shared())?;
+        match self.value.compare_and_swap(current, new.clone()) {
+            Ok(old) => {
+                self.notify(&old, &new);
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Set a function which every new value has to pass before it's
+    /// stored, replacing any validator already set. Fails without
+    /// changing the validator if the current value doesn't pass it.
+    ///
+    /// Like watches, validators can be changed while the atom is
+    /// shared. A change which was already being made when the
+    /// validator was replaced may still be checked by the old one.
+    pub fn set_validator<F>(&self, validator: F) -> Result<(), Rejected<T>>
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        let current = self.load();
+        if !validator(&current) {
+            return Err(Rejected(current));
+        }
+        let validator: Validator<T> = Box::new(validator);
+        self.validator.replace(Arc::new(Some(validator)));
+        Ok(())
+    }
+
+    /// Remove the validator, if there is one.
+    pub fn clear_validator(&self) {
+        self.validator.replace(Arc::new(None));
+    }
+
+    /// Add a function to be called with the old and the new value
+    /// after every change, replacing any watch already added with
+    /// the same key.
+    ///
+    /// Watches are called on the thread which made the change, after
+    /// it's been made, so when several threads change the atom at
+    /// once, the value may have changed again by the time a watch is
+    /// called, and watches may see the changes out of order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::atom::Atom;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let atom = Atom::new(ordset![1]);
+    /// let added = Arc::new(AtomicUsize::new(0));
+    /// let counter = added.clone();
+    /// atom.add_watch("count", move |old, new| {
+    ///     counter.fetch_add(new.len() - old.len(), Ordering::SeqCst);
+    /// });
+    /// atom.swap(|set| set.insert(2)).unwrap();
+    /// atom.swap(|set| set.insert(3)).unwrap();
+    /// assert_eq!(2, added.load(Ordering::SeqCst));
+    /// # }
+    /// ```
+    pub fn add_watch<F>(&self, key: &str, watch: F)
+    where
+        F: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        let watch: Watch<T> = Arc::new(watch);
+        self.update_watches(|watches| {
+            let mut watches: Vec<_> = watches
+                .iter()
+                .filter(|&&(ref name, _)| name != key)
+                .cloned()
+                .collect();
+            watches.push((key.to_string(), watch.clone()));
+            watches
+        });
+    }
+
+    /// Remove the watch added with the given key, and return whether
+    /// there was one.
+    pub fn remove_watch(&self, key: &str) -> bool {
+        let old = self.update_watches(|watches| {
+            watches
+                .iter()
+                .filter(|&&(ref name, _)| name != key)
+                .cloned()
+                .collect()
+        });
+        old.iter().any(|&(ref name, _)| name == key)
+    }
+
+    fn validate(&self, value: Arc<T>) -> Result<Arc<T>, Rejected<T>> {
+        match *self.validator.load() {
+            Some(ref validator) if !validator(&value) => Err(Rejected(value)),
+            _ => Ok(value),
+        }
+    }
+
+    fn notify(&self, old: &T, new: &T) {
+        for &(_, ref watch) in self.watches.load().iter() {
+            watch(old, new);
+        }
+    }
+
+    // Change the watches with a compare and swap loop, returning the
+    // old watches.
+    fn update_watches<F>(&self, f: F) -> Arc<Vec<(String, Watch<T>)>>
+    where
+        F: Fn(&[(String, Watch<T>)]) -> Vec<(String, Watch<T>)>,
+    {
+        let mut current = self.watches.load();
+        loop {
+            let new = Arc::new(f(&current));
+            match self.watches.compare_and_swap(&current, new) {
+                Ok(old) => return old,
+                Err(_) => current = self.watches.load(),
+            }
+        }
+    }
+}
+
+impl<T: Default> Default for Atom<T> {
+    fn default() -> Self {
+        Atom::new(T::default())
+    }
+}
+
+impl<T: Debug> Debug for Atom<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Atom").field(&self.load()).finish()
+    }
+}
+
+impl<T> From<T> for Atom<T> {
+    fn from(value: T) -> Self {
+        Atom::new(value)
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hashmap::HashMap;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use vector::Vector;
+
+    #[test]
+    fn load_and_store() {
+        let atom = Atom::new(vector![1, 2, 3]);
+        let before = atom.load();
+        assert_eq!(before, atom.store(vector![4]).unwrap());
+        assert_eq!(vector![1, 2, 3], *before);
+        assert_eq!(vector![4], *atom.load());
+    }
+
+    #[test]
+    fn compare_and_set() {
+        let atom = Atom::new(ordset![1]);
+        let first = atom.load();
+        assert!(atom.compare_and_set(&first, ordset![2]).unwrap());
+        assert!(!atom.compare_and_set(&first, ordset![3]).unwrap());
+        // An equal value isn't the same value.
+        assert!(!atom.compare_and_set(&Arc::new(ordset![2]), ordset![3]).unwrap());
+        let second = atom.load();
+        assert!(atom.compare_and_set(&second, ordset![3]).unwrap());
+        assert_eq!(ordset![3], *atom.load());
+    }
+
+    #[test]
+    fn validators() {
+        let atom = Atom::new(vector![1, 2]);
+        assert!(atom.set_validator(|vec: &Vector<i32>| vec.len() > 2).is_err());
+        atom.set_validator(|vec: &Vector<i32>| vec.len() < 3).unwrap();
+        atom.swap(|vec| vec.pop_back().unwrap().1).unwrap();
+        let error = atom.swap(|vec| vec.push_back(0).push_back(0)).unwrap_err();
+        assert_eq!(vector![1, 0, 0], **error.value());
+        assert!(atom.store(vector![1, 2, 3]).is_err());
+        let current = atom.load();
+        assert!(atom.compare_and_set(&current, vector![1, 2, 3]).is_err());
+        assert_eq!(vector![1], *atom.load());
+        atom.clear_validator();
+        atom.store(vector![1, 2, 3]).unwrap();
+    }
+
+    #[test]
+    fn shared_validators() {
+        let atom = Arc::new(Atom::new(0));
+        let writer = atom.clone();
+        atom.set_validator(|n: &i32| *n >= 0).unwrap();
+        thread::spawn(move || assert!(writer.store(-1).is_err()))
+            .join()
+            .unwrap();
+        atom.clear_validator();
+        atom.store(-1).unwrap();
+    }
+
+    #[test]
+    fn writers_dont_wait_for_loads() {
+        let cell = ArcCell::new(Arc::new(vector![1]));
+        // A load which has recorded its debt but not yet settled it.
+        let ptr = cell.ptr.load(Ordering::SeqCst);
+        let debt = cell.record(ptr);
+        let old = cell.replace(Arc::new(vector![2]));
+        assert_eq!(vector![1], *old);
+        assert_eq!(2, Arc::strong_count(&old));
+        assert!(ArcCell::settle(debt));
+        let loaded = unsafe { Arc::from_raw(ptr) };
+        assert!(Arc::ptr_eq(&old, &loaded));
+        drop(loaded);
+        assert_eq!(1, Arc::strong_count(&old));
+        assert_eq!(vector![2], *cell.load());
+    }
+
+    #[test]
+    fn watches() {
+        let atom = Atom::new(0);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        atom.add_watch("check", move |old, new| {
+            assert_eq!(old + 1, *new);
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = calls.clone();
+        atom.add_watch("count", move |_, _| {
+            counter.fetch_add(10, Ordering::SeqCst);
+        });
+        atom.swap(|n| n + 1).unwrap();
+        assert_eq!(11, calls.load(Ordering::SeqCst));
+        assert!(atom.remove_watch("count"));
+        assert!(!atom.remove_watch("count"));
+        atom.store(2).unwrap();
+        assert_eq!(12, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn concurrent_swaps() {
+        let atom = Arc::new(Atom::new(HashMap::<usize, usize>::new()));
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let atom = atom.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        let snapshot = atom.load();
+                        atom.swap(|map| map.insert(thread * 1000 + i, i)).unwrap();
+                        assert!(atom.load().len() > snapshot.len());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let map = atom.load();
+        assert_eq!(4000, map.len());
+        map.assert_invariants();
+    }
+
+    #[test]
+    fn values_are_released() {
+        let first = Arc::new(vector![1]);
+        let atom: Atom<Vector<i32>> = Atom::new(first.clone());
+        let second = atom.swap(|vec| vec.push_back(2)).unwrap();
+        assert_eq!(1, Arc::strong_count(&first));
+        assert_eq!(2, Arc::strong_count(&second));
+        drop(atom);
+        assert_eq!(1, Arc::strong_count(&second));
+    }
+}
@@ -0,0 +1,1342 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Bidirectional maps.
+//!
+//! A bidirectional map is a one-to-one mapping between left values
+//! and right values, where you can look up a pair by either side in
+//! O(log n) time.
+//!
+//! Internally, a bidirectional map is just two maps, one from left
+//! to right and one from right to left, kept in sync. The left and
+//! right values are stored in [`Arc`][std::sync::Arc]s which are
+//! shared between both maps, and each map shares structure with the
+//! corresponding map in any bidirectional map it was derived from, so
+//! cloning and updating are as cheap as they are for the underlying
+//! maps.
+//!
+//! Because the mapping is one-to-one, inserting a pair will evict any
+//! existing pair which has either the same left value or the same
+//! right value.
+//!
+//! [`BiMap`][BiMap] is built on [`HashMap`][hashmap::HashMap] and
+//! requires both sides to implement [`Hash`][std::hash::Hash] and
+//! [`Eq`][std::cmp::Eq]. [`OrdBiMap`][OrdBiMap] is built on
+//! [`OrdMap`][ordmap::OrdMap] and requires both sides to implement
+//! [`Ord`][std::cmp::Ord].
+//!
+//! [BiMap]: ./struct.BiMap.html
+//! [OrdBiMap]: ./struct.OrdBiMap.html
+//! [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+//! [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+//! [std::sync::Arc]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+//! [std::cmp::Eq]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+//! [std::cmp::Ord]: https://doc.rust-lang.org/std/cmp/trait.Ord.html
+//! [std::hash::Hash]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+
+use std::borrow::Borrow;
+use std::cmp::{max, Ordering};
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+use std::fmt::{Debug, Error, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::iter::FromIterator;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::{Footprint, HeapSize};
+use hashmap::{self, HashMap};
+use ordmap::{self, OrdMap};
+use shared::Shared;
+
+use nodes::btree;
+use nodes::hamt;
+
+/// A hashed bidirectional map.
+///
+/// A one-to-one mapping between left values and right values, built
+/// from a pair of [`HashMap`][hashmap::HashMap]s.
+///
+/// Lookups, insertions and removals are O(log n) by either side.
+/// Inserting a pair will evict any existing pair which shares either
+/// its left or its right value, so that the mapping always stays
+/// one-to-one.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::bimap::BiMap;
+/// # use std::sync::Arc;
+/// # fn main() {
+/// let map = BiMap::new().insert(1, "one").insert(2, "two");
+/// assert_eq!(Some(Arc::new("two")), map.get_by_left(&2));
+/// assert_eq!(Some(Arc::new(1)), map.get_by_right(&"one"));
+///
+/// // Inserting (1, "two") evicts both (1, "one") and (2, "two").
+/// let map = map.insert(1, "two");
+/// assert_eq!(1, map.len());
+/// assert_eq!(None, map.get_by_right(&"one"));
+/// assert_eq!(None, map.get_by_left(&2));
+/// # }
+/// ```
+///
+/// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+#[cfg(feature = "std")]
+pub struct BiMap<L, R, S = RandomState> {
+    left: HashMap<L, R, S>,
+    right: HashMap<R, L, S>,
+}
+
+/// A bidirectional hash map.
+///
+/// Without the `std` feature there is no default hasher, so one must
+/// always be given through `S`.
+#[cfg(not(feature = "std"))]
+pub struct BiMap<L, R, S> {
+    left: HashMap<L, R, S>,
+    right: HashMap<R, L, S>,
+}
+
+#[cfg(feature = "std")]
+impl<L, R> BiMap<L, R, RandomState>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+{
+    /// Construct an empty bidirectional map.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Construct a bidirectional map with a single pair.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate im;
+    /// # use im::bimap::BiMap;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let map = BiMap::singleton(123, "onetwothree");
+    /// assert_eq!(
+    ///   map.get_by_right(&"onetwothree"),
+    ///   Some(Arc::new(123))
+    /// );
+    /// # }
+    /// ```
+    #[inline]
+    pub fn singleton<RL, RR>(l: RL, r: RR) -> Self
+    where
+        RL: Shared<L>,
+        RR: Shared<R>,
+    {
+        BiMap::new().insert(l, r)
+    }
+}
+
+impl<L, R, S> BiMap<L, R, S> {
+    /// Test whether a bidirectional map is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of pairs in a bidirectional map.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    /// Get the left to right mapping as a [`HashMap`][hashmap::HashMap].
+    ///
+    /// Time: O(1)
+    ///
+    /// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+    #[inline]
+    pub fn left(&self) -> &HashMap<L, R, S> {
+        &self.left
+    }
+
+    /// Get the right to left mapping as a [`HashMap`][hashmap::HashMap].
+    ///
+    /// Time: O(1)
+    ///
+    /// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+    #[inline]
+    pub fn right(&self) -> &HashMap<R, L, S> {
+        &self.right
+    }
+
+    /// Swap the left and right sides of a bidirectional map.
+    ///
+    /// Time: O(1)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate im;
+    /// # use im::bimap::BiMap;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let map = BiMap::singleton(1, "one");
+    /// assert_eq!(Some(Arc::new(1)), map.inverse().get_by_left(&"one"));
+    /// # }
+    /// ```
+    #[inline]
+    pub fn inverse(&self) -> BiMap<R, L, S> {
+        BiMap {
+            left: self.right.clone(),
+            right: self.left.clone(),
+        }
+    }
+}
+
+impl<L, R, S> BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Construct an empty bidirectional map using the provided
+    /// hasher for both sides.
+    #[inline]
+    pub fn with_hasher<RS>(hasher: RS) -> Self
+    where
+        RS: Shared<S>,
+    {
+        let hasher = hasher.shared();
+        BiMap {
+            left: HashMap::with_hasher(&hasher),
+            right: HashMap::with_hasher(hasher),
+        }
+    }
+
+    /// Get an iterator over the left/right pairs of a bidirectional
+    /// map.
+    ///
+    /// The pairs come out in the order of the left to right
+    /// [`HashMap`][hashmap::HashMap].
+    ///
+    /// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+    #[inline]
+    pub fn iter(&self) -> hamt::Iter<(Arc<L>, Arc<R>)> {
+        self.left.iter()
+    }
+
+    /// Get an iterator over the left values of a bidirectional map.
+    #[inline]
+    pub fn lefts(&self) -> hashmap::Keys<L, R> {
+        self.left.keys()
+    }
+
+    /// Get an iterator over the right values of a bidirectional map.
+    #[inline]
+    pub fn rights(&self) -> hashmap::Keys<R, L> {
+        self.right.keys()
+    }
+
+    /// Get the right value paired with a left value.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn get_by_left<BL>(&self, l: &BL) -> Option<Arc<R>>
+    where
+        BL: Hash + Eq + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.left.get(l)
+    }
+
+    /// Get the left value paired with a right value.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn get_by_right<BR>(&self, r: &BR) -> Option<Arc<L>>
+    where
+        BR: Hash + Eq + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.right.get(r)
+    }
+
+    /// Test whether a left value is present in a bidirectional map.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn contains_left<BL>(&self, l: &BL) -> bool
+    where
+        BL: Hash + Eq + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.left.contains_key(l)
+    }
+
+    /// Test whether a right value is present in a bidirectional map.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn contains_right<BR>(&self, r: &BR) -> bool
+    where
+        BR: Hash + Eq + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.right.contains_key(r)
+    }
+
+    /// Construct a new bidirectional map by inserting a pair.
+    ///
+    /// Any existing pairs with the same left value or the same right
+    /// value are removed.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate im;
+    /// # use im::bimap::BiMap;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let map = BiMap::singleton(1, "one").insert(2, "one");
+    /// assert_eq!(1, map.len());
+    /// assert!(!map.contains_left(&1));
+    /// assert_eq!(Some(Arc::new(2)), map.get_by_right(&"one"));
+    /// # }
+    /// ```
+    pub fn insert<RL, RR>(&self, l: RL, r: RR) -> Self
+    where
+        RL: Shared<L>,
+        RR: Shared<R>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(l, r);
+        out
+    }
+
+    /// Insert a pair into a bidirectional map.
+    ///
+    /// Any existing pairs with the same left value or the same right
+    /// value are removed.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<RL, RR>(&mut self, l: RL, r: RR)
+    where
+        RL: Shared<L>,
+        RR: Shared<R>,
+    {
+        let (l, r) = (l.shared(), r.shared());
+        if let Some(old_r) = self.left.pop_mut(&*l) {
+            self.right.remove_mut(&*old_r);
+        }
+        if let Some(old_l) = self.right.pop_mut(&*r) {
+            self.left.remove_mut(&*old_l);
+        }
+        self.left.insert_mut(l.clone(), r.clone());
+        self.right.insert_mut(r, l);
+    }
+
+    /// Construct a new bidirectional map without the pair with the
+    /// given left value.
+    ///
+    /// Time: O(log n)
+    pub fn remove_by_left<BL>(&self, l: &BL) -> Self
+    where
+        BL: Hash + Eq + ?Sized,
+        L: Borrow<BL>,
+    {
+        match self.pop_by_left(l) {
+            None => self.clone(),
+            Some((_, map)) => map,
+        }
+    }
+
+    /// Construct a new bidirectional map without the pair with the
+    /// given right value.
+    ///
+    /// Time: O(log n)
+    pub fn remove_by_right<BR>(&self, r: &BR) -> Self
+    where
+        BR: Hash + Eq + ?Sized,
+        R: Borrow<BR>,
+    {
+        match self.pop_by_right(r) {
+            None => self.clone(),
+            Some((_, map)) => map,
+        }
+    }
+
+    /// Remove the pair with the given left value, if it exists.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn remove_by_left_mut<BL>(&mut self, l: &BL)
+    where
+        BL: Hash + Eq + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.pop_by_left_mut(l);
+    }
+
+    /// Remove the pair with the given right value, if it exists.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn remove_by_right_mut<BR>(&mut self, r: &BR)
+    where
+        BR: Hash + Eq + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.pop_by_right_mut(r);
+    }
+
+    /// Remove the pair with the given left value, if it exists, and
+    /// return its right value as well as the updated map.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_left<BL>(&self, l: &BL) -> Option<(Arc<R>, Self)>
+    where
+        BL: Hash + Eq + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.left.pop(l).map(|(r, left)| {
+            let right = self.right.remove(&*r);
+            (r, BiMap { left, right })
+        })
+    }
+
+    /// Remove the pair with the given right value, if it exists, and
+    /// return its left value as well as the updated map.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_right<BR>(&self, r: &BR) -> Option<(Arc<L>, Self)>
+    where
+        BR: Hash + Eq + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.right.pop(r).map(|(l, right)| {
+            let left = self.left.remove(&*l);
+            (l, BiMap { left, right })
+        })
+    }
+
+    /// Remove the pair with the given left value, if it exists, and
+    /// return its right value.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_left_mut<BL>(&mut self, l: &BL) -> Option<Arc<R>>
+    where
+        BL: Hash + Eq + ?Sized,
+        L: Borrow<BL>,
+    {
+        let r = self.left.pop_mut(l);
+        if let Some(ref r) = r {
+            self.right.remove_mut(&**r);
+        }
+        r
+    }
+
+    /// Remove the pair with the given right value, if it exists, and
+    /// return its left value.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_right_mut<BR>(&mut self, r: &BR) -> Option<Arc<L>>
+    where
+        BR: Hash + Eq + ?Sized,
+        R: Borrow<BR>,
+    {
+        let l = self.right.pop_mut(r);
+        if let Some(ref l) = l {
+            self.left.remove_mut(&**l);
+        }
+        l
+    }
+}
+
+/// An ordered bidirectional map.
+///
+/// A one-to-one mapping between left values and right values, built
+/// from a pair of [`OrdMap`][ordmap::OrdMap]s.
+///
+/// Lookups, insertions and removals are O(log n) by either side.
+/// Inserting a pair will evict any existing pair which shares either
+/// its left or its right value, so that the mapping always stays
+/// one-to-one. Pairs are iterated in order of their left values.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::bimap::OrdBiMap;
+/// # use std::sync::Arc;
+/// # fn main() {
+/// let map = OrdBiMap::new().insert(2, "two").insert(1, "one");
+/// assert_eq!(Some(Arc::new(2)), map.get_by_right(&"two"));
+/// assert_eq!(
+///   vec![(1, "one"), (2, "two")],
+///   map.iter().map(|(l, r)| (*l, *r)).collect::<Vec<_>>()
+/// );
+/// # }
+/// ```
+///
+/// [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+pub struct OrdBiMap<L, R> {
+    left: OrdMap<L, R>,
+    right: OrdMap<R, L>,
+}
+
+impl<L, R> OrdBiMap<L, R> {
+    /// Construct an empty bidirectional map.
+    #[inline]
+    pub fn new() -> Self {
+        OrdBiMap {
+            left: OrdMap::new(),
+            right: OrdMap::new(),
+        }
+    }
+
+    /// Test whether a bidirectional map is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of pairs in a bidirectional map.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    /// Get the left to right mapping as an [`OrdMap`][ordmap::OrdMap].
+    ///
+    /// Time: O(1)
+    ///
+    /// [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+    #[inline]
+    pub fn left(&self) -> &OrdMap<L, R> {
+        &self.left
+    }
+
+    /// Get the right to left mapping as an [`OrdMap`][ordmap::OrdMap].
+    ///
+    /// Time: O(1)
+    ///
+    /// [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+    #[inline]
+    pub fn right(&self) -> &OrdMap<R, L> {
+        &self.right
+    }
+
+    /// Swap the left and right sides of a bidirectional map.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn inverse(&self) -> OrdBiMap<R, L> {
+        OrdBiMap {
+            left: self.right.clone(),
+            right: self.left.clone(),
+        }
+    }
+}
+
+impl<L: Ord, R: Ord> OrdBiMap<L, R> {
+    /// Construct a bidirectional map with a single pair.
+    #[inline]
+    pub fn singleton<RL, RR>(l: RL, r: RR) -> Self
+    where
+        RL: Shared<L>,
+        RR: Shared<R>,
+    {
+        OrdBiMap::new().insert(l, r)
+    }
+
+    /// Get an iterator over the left/right pairs of a bidirectional
+    /// map, in order of their left values.
+    #[inline]
+    pub fn iter(&self) -> btree::Iter<(Arc<L>, Arc<R>)> {
+        self.left.iter()
+    }
+
+    /// Get an iterator over the left values of a bidirectional map,
+    /// in sorted order.
+    #[inline]
+    pub fn lefts(&self) -> ordmap::Keys<L, R> {
+        self.left.keys()
+    }
+
+    /// Get an iterator over the right values of a bidirectional map,
+    /// in sorted order.
+    #[inline]
+    pub fn rights(&self) -> ordmap::Keys<R, L> {
+        self.right.keys()
+    }
+
+    /// Get the right value paired with a left value.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn get_by_left<BL>(&self, l: &BL) -> Option<Arc<R>>
+    where
+        BL: Ord + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.left.get(l)
+    }
+
+    /// Get the left value paired with a right value.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn get_by_right<BR>(&self, r: &BR) -> Option<Arc<L>>
+    where
+        BR: Ord + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.right.get(r)
+    }
+
+    /// Test whether a left value is present in a bidirectional map.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn contains_left<BL>(&self, l: &BL) -> bool
+    where
+        BL: Ord + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.left.contains_key(l)
+    }
+
+    /// Test whether a right value is present in a bidirectional map.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn contains_right<BR>(&self, r: &BR) -> bool
+    where
+        BR: Ord + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.right.contains_key(r)
+    }
+
+    /// Construct a new bidirectional map by inserting a pair.
+    ///
+    /// Any existing pairs with the same left value or the same right
+    /// value are removed.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RL, RR>(&self, l: RL, r: RR) -> Self
+    where
+        RL: Shared<L>,
+        RR: Shared<R>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(l, r);
+        out
+    }
+
+    /// Insert a pair into a bidirectional map.
+    ///
+    /// Any existing pairs with the same left value or the same right
+    /// value are removed.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<RL, RR>(&mut self, l: RL, r: RR)
+    where
+        RL: Shared<L>,
+        RR: Shared<R>,
+    {
+        let (l, r) = (l.shared(), r.shared());
+        if let Some(old_r) = self.left.pop_mut(&*l) {
+            self.right.remove_mut(&*old_r);
+        }
+        if let Some(old_l) = self.right.pop_mut(&*r) {
+            self.left.remove_mut(&*old_l);
+        }
+        self.left.insert_mut(l.clone(), r.clone());
+        self.right.insert_mut(r, l);
+    }
+
+    /// Construct a new bidirectional map without the pair with the
+    /// given left value.
+    ///
+    /// Time: O(log n)
+    pub fn remove_by_left<BL>(&self, l: &BL) -> Self
+    where
+        BL: Ord + ?Sized,
+        L: Borrow<BL>,
+    {
+        match self.pop_by_left(l) {
+            None => self.clone(),
+            Some((_, map)) => map,
+        }
+    }
+
+    /// Construct a new bidirectional map without the pair with the
+    /// given right value.
+    ///
+    /// Time: O(log n)
+    pub fn remove_by_right<BR>(&self, r: &BR) -> Self
+    where
+        BR: Ord + ?Sized,
+        R: Borrow<BR>,
+    {
+        match self.pop_by_right(r) {
+            None => self.clone(),
+            Some((_, map)) => map,
+        }
+    }
+
+    /// Remove the pair with the given left value, if it exists.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn remove_by_left_mut<BL>(&mut self, l: &BL)
+    where
+        BL: Ord + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.pop_by_left_mut(l);
+    }
+
+    /// Remove the pair with the given right value, if it exists.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn remove_by_right_mut<BR>(&mut self, r: &BR)
+    where
+        BR: Ord + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.pop_by_right_mut(r);
+    }
+
+    /// Remove the pair with the given left value, if it exists, and
+    /// return its right value as well as the updated map.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_left<BL>(&self, l: &BL) -> Option<(Arc<R>, Self)>
+    where
+        BL: Ord + ?Sized,
+        L: Borrow<BL>,
+    {
+        self.left.pop(l).map(|(r, left)| {
+            let right = self.right.remove(&*r);
+            (r, OrdBiMap { left, right })
+        })
+    }
+
+    /// Remove the pair with the given right value, if it exists, and
+    /// return its left value as well as the updated map.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_right<BR>(&self, r: &BR) -> Option<(Arc<L>, Self)>
+    where
+        BR: Ord + ?Sized,
+        R: Borrow<BR>,
+    {
+        self.right.pop(r).map(|(l, right)| {
+            let left = self.left.remove(&*l);
+            (l, OrdBiMap { left, right })
+        })
+    }
+
+    /// Remove the pair with the given left value, if it exists, and
+    /// return its right value.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_left_mut<BL>(&mut self, l: &BL) -> Option<Arc<R>>
+    where
+        BL: Ord + ?Sized,
+        L: Borrow<BL>,
+    {
+        let r = self.left.pop_mut(l);
+        if let Some(ref r) = r {
+            self.right.remove_mut(&**r);
+        }
+        r
+    }
+
+    /// Remove the pair with the given right value, if it exists, and
+    /// return its left value.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn pop_by_right_mut<BR>(&mut self, r: &BR) -> Option<Arc<L>>
+    where
+        BR: Ord + ?Sized,
+        R: Borrow<BR>,
+    {
+        let l = self.right.pop_mut(r);
+        if let Some(ref l) = l {
+            self.left.remove_mut(&**l);
+        }
+        l
+    }
+}
+
+// Core traits
+
+impl<L, R, S> Clone for BiMap<L, R, S> {
+    #[inline]
+    fn clone(&self) -> Self {
+        BiMap {
+            left: self.left.clone(),
+            right: self.right.clone(),
+        }
+    }
+}
+
+impl<L, R, S> PartialEq for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left
+    }
+}
+
+impl<L: Hash + Eq, R: Hash + Eq, S: BuildHasher> Eq for BiMap<L, R, S> {}
+
+impl<L, R, S> Hash for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.left.hash(state)
+    }
+}
+
+impl<L, R, S> Default for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    #[inline]
+    fn default() -> Self {
+        BiMap::with_hasher(S::default())
+    }
+}
+
+impl<L, R, S> HeapSize for BiMap<L, R, S> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        max(
+            self.left.measure_heap(footprint),
+            self.right.measure_heap(footprint),
+        )
+    }
+}
+
+impl<L, R, S, RL, RR> Extend<(RL, RR)> for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+    RL: Shared<L>,
+    RR: Shared<R>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (RL, RR)>,
+    {
+        for (l, r) in iter {
+            self.insert_mut(l, r);
+        }
+    }
+}
+
+impl<L, R, S> Debug for BiMap<L, R, S>
+where
+    L: Hash + Eq + Debug,
+    R: Hash + Eq + Debug,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<L, R> Clone for OrdBiMap<L, R> {
+    #[inline]
+    fn clone(&self) -> Self {
+        OrdBiMap {
+            left: self.left.clone(),
+            right: self.right.clone(),
+        }
+    }
+}
+
+impl<L: Ord, R: Ord> PartialEq for OrdBiMap<L, R> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left
+    }
+}
+
+impl<L: Ord, R: Ord> Eq for OrdBiMap<L, R> {}
+
+impl<L: Ord, R: Ord> PartialOrd for OrdBiMap<L, R> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<L: Ord, R: Ord> Ord for OrdBiMap<L, R> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.left.cmp(&other.left)
+    }
+}
+
+impl<L: Ord + Hash, R: Ord + Hash> Hash for OrdBiMap<L, R> {
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.left.hash(state)
+    }
+}
+
+impl<L, R> Default for OrdBiMap<L, R> {
+    #[inline]
+    fn default() -> Self {
+        OrdBiMap::new()
+    }
+}
+
+impl<L, R> HeapSize for OrdBiMap<L, R> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        max(
+            self.left.measure_heap(footprint),
+            self.right.measure_heap(footprint),
+        )
+    }
+}
+
+impl<L, R, RL, RR> Extend<(RL, RR)> for OrdBiMap<L, R>
+where
+    L: Ord,
+    R: Ord,
+    RL: Shared<L>,
+    RR: Shared<R>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (RL, RR)>,
+    {
+        for (l, r) in iter {
+            self.insert_mut(l, r);
+        }
+    }
+}
+
+impl<L: Ord + Debug, R: Ord + Debug> Debug for OrdBiMap<L, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Iterators
+
+impl<'a, L, R, S> IntoIterator for &'a BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = (Arc<L>, Arc<R>);
+    type IntoIter = hamt::Iter<(Arc<L>, Arc<R>)>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<L, R, S> IntoIterator for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = (Arc<L>, Arc<R>);
+    type IntoIter = hamt::Iter<(Arc<L>, Arc<R>)>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, L: Ord, R: Ord> IntoIterator for &'a OrdBiMap<L, R> {
+    type Item = (Arc<L>, Arc<R>);
+    type IntoIter = btree::Iter<(Arc<L>, Arc<R>)>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<L: Ord, R: Ord> IntoIterator for OrdBiMap<L, R> {
+    type Item = (Arc<L>, Arc<R>);
+    type IntoIter = btree::Iter<(Arc<L>, Arc<R>)>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Conversions
+
+impl<L, R, S, RL, RR> FromIterator<(RL, RR)> for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher + Default,
+    RL: Shared<L>,
+    RR: Shared<R>,
+{
+    fn from_iter<T>(i: T) -> Self
+    where
+        T: IntoIterator<Item = (RL, RR)>,
+    {
+        let mut map: Self = Default::default();
+        map.extend(i);
+        map
+    }
+}
+
+impl<L, R, S, RL, RR> From<Vec<(RL, RR)>> for BiMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher + Default,
+    RL: Shared<L>,
+    RR: Shared<R>,
+{
+    fn from(v: Vec<(RL, RR)>) -> Self {
+        v.into_iter().collect()
+    }
+}
+
+impl<L, R, RL, RR> FromIterator<(RL, RR)> for OrdBiMap<L, R>
+where
+    L: Ord,
+    R: Ord,
+    RL: Shared<L>,
+    RR: Shared<R>,
+{
+    fn from_iter<T>(i: T) -> Self
+    where
+        T: IntoIterator<Item = (RL, RR)>,
+    {
+        let mut map = OrdBiMap::new();
+        map.extend(i);
+        map
+    }
+}
+
+impl<L, R, RL, RR> From<Vec<(RL, RR)>> for OrdBiMap<L, R>
+where
+    L: Ord,
+    R: Ord,
+    RL: Shared<L>,
+    RR: Shared<R>,
+{
+    fn from(v: Vec<(RL, RR)>) -> Self {
+        v.into_iter().collect()
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<L, R> Arbitrary for BiMap<L, R>
+where
+    L: Hash + Eq + Arbitrary + Sync,
+    R: Hash + Eq + Arbitrary + Sync,
+{
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        BiMap::from(Vec::<(L, R)>::arbitrary(g))
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<L, R> Arbitrary for OrdBiMap<L, R>
+where
+    L: Ord + Arbitrary + Sync,
+    R: Ord + Arbitrary + Sync,
+{
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        OrdBiMap::from(Vec::<(L, R)>::arbitrary(g))
+    }
+}
+
+// Proptest
+
+#[cfg(any(test, feature = "proptest"))]
+pub mod proptest {
+    use super::*;
+    use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
+    use std::ops::Range;
+
+    /// A strategy for a hashed bidirectional map of a given size.
+    ///
+    /// Because conflicting pairs evict each other, the generated map
+    /// may have fewer pairs than the number generated, but never
+    /// fewer than `size.start`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_works(ref m in bi_map(0..9999, ".*", 10..100)) {
+    ///         assert!(m.len() < 100);
+    ///         assert!(m.len() >= 10);
+    ///     }
+    /// }
+    /// ```
+    pub fn bi_map<L: Strategy + 'static, R: Strategy + 'static>(
+        left: L,
+        right: R,
+        size: Range<usize>,
+    ) -> BoxedStrategy<BiMap<<L::Value as ValueTree>::Value, <R::Value as ValueTree>::Value>>
+    where
+        <L::Value as ValueTree>::Value: Hash + Eq,
+        <R::Value as ValueTree>::Value: Hash + Eq,
+    {
+        ::proptest::collection::vec((left, right), size.clone())
+            .prop_map(BiMap::from)
+            .prop_filter("BiMap minimum size".to_owned(), move |m| {
+                m.len() >= size.start
+            })
+            .boxed()
+    }
+
+    /// A strategy for an ordered bidirectional map of a given size.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_works(ref m in ord_bi_map(0..9999, ".*", 10..100)) {
+    ///         assert!(m.len() < 100);
+    ///         assert!(m.len() >= 10);
+    ///     }
+    /// }
+    /// ```
+    pub fn ord_bi_map<L: Strategy + 'static, R: Strategy + 'static>(
+        left: L,
+        right: R,
+        size: Range<usize>,
+    ) -> BoxedStrategy<OrdBiMap<<L::Value as ValueTree>::Value, <R::Value as ValueTree>::Value>>
+    where
+        <L::Value as ValueTree>::Value: Ord,
+        <R::Value as ValueTree>::Value: Ord,
+    {
+        ::proptest::collection::vec((left, right), size.clone())
+            .prop_map(OrdBiMap::from)
+            .prop_filter("OrdBiMap minimum size".to_owned(), move |m| {
+                m.len() >= size.start
+            })
+            .boxed()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::collection;
+    use proptest::num::i16;
+    use std::collections;
+
+    fn check_hashed(map: &BiMap<i16, i16>) {
+        assert_eq!(map.left().len(), map.right().len());
+        for (l, r) in map.iter() {
+            let back = map.right().get(&*r).unwrap();
+            assert!(Arc::ptr_eq(&l, &back));
+        }
+    }
+
+    fn check_ordered(map: &OrdBiMap<i16, i16>) {
+        assert_eq!(map.left().len(), map.right().len());
+        for (l, r) in map.iter() {
+            let back = map.right().get(&*r).unwrap();
+            assert!(Arc::ptr_eq(&l, &back));
+        }
+    }
+
+    #[test]
+    fn insert_evicts_conflicts() {
+        let map = BiMap::new().insert(1, 10).insert(2, 20).insert(3, 30);
+        let map = map.insert(1, 20);
+        assert_eq!(2, map.len());
+        assert_eq!(Some(Arc::new(20)), map.get_by_left(&1));
+        assert_eq!(Some(Arc::new(1)), map.get_by_right(&20));
+        assert!(!map.contains_left(&2));
+        assert!(!map.contains_right(&10));
+        assert_eq!(Some(Arc::new(3)), map.get_by_right(&30));
+    }
+
+    #[test]
+    fn remove_both_sides() {
+        let mut map: OrdBiMap<&str, i32> = OrdBiMap::from(vec![("a", 1), ("b", 2), ("c", 3)]);
+        assert_eq!(Some(Arc::new(2)), map.pop_by_left_mut("b"));
+        assert!(!map.contains_right(&2));
+        map.remove_by_right_mut(&3);
+        assert!(!map.contains_left("c"));
+        assert_eq!(OrdBiMap::singleton("a", 1), map);
+    }
+
+    #[test]
+    fn persistence() {
+        let map1 = BiMap::singleton(1, 2);
+        let map2 = map1.insert(3, 2);
+        assert_eq!(Some(Arc::new(1)), map1.get_by_right(&2));
+        assert_eq!(Some(Arc::new(3)), map2.get_by_right(&2));
+        assert_eq!(map2.inverse().inverse(), map2);
+    }
+
+    #[test]
+    fn proper_formatting() {
+        let map = OrdBiMap::singleton(1, 2);
+        assert_eq!("{1: 2}", format!("{:?}", map));
+    }
+
+    proptest! {
+        #[test]
+        fn hashed_matches_model(ref pairs in collection::vec((i16::ANY, i16::ANY), 0..100)) {
+            let mut map: BiMap<i16, i16> = BiMap::new();
+            let mut model: collections::HashMap<i16, i16> = collections::HashMap::new();
+            for &(l, r) in pairs {
+                map.insert_mut(l, r);
+                model.retain(|_, v| *v != r);
+                model.insert(l, r);
+                check_hashed(&map);
+            }
+            assert_eq!(model.len(), map.len());
+            for (l, r) in &model {
+                assert_eq!(Some(*r), map.get_by_left(l).map(|r| *r));
+                assert_eq!(Some(*l), map.get_by_right(r).map(|l| *l));
+            }
+        }
+
+        #[test]
+        fn ordered_matches_model(ref pairs in collection::vec((i16::ANY, i16::ANY), 0..100)) {
+            let mut map: OrdBiMap<i16, i16> = OrdBiMap::new();
+            let mut model: collections::BTreeMap<i16, i16> = collections::BTreeMap::new();
+            for &(l, r) in pairs {
+                map = map.insert(l, r);
+                let evict: Vec<i16> = model.iter().filter(|&(_, v)| *v == r).map(|(k, _)| *k).collect();
+                for k in evict {
+                    model.remove(&k);
+                }
+                model.insert(l, r);
+                check_ordered(&map);
+            }
+            let expected: Vec<(i16, i16)> = model.into_iter().collect();
+            let actual: Vec<(i16, i16)> = map.iter().map(|(l, r)| (*l, *r)).collect();
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn remove_by_either_side(ref map in proptest::bi_map(i16::ANY, i16::ANY, 1..100)) {
+            let mut by_left = map.clone();
+            let mut by_right = map.clone();
+            for (l, r) in map.iter() {
+                by_left = by_left.remove_by_left(&*l);
+                by_right.remove_by_right_mut(&*r);
+                check_hashed(&by_left);
+                check_hashed(&by_right);
+                assert_eq!(by_left, by_right);
+            }
+            assert!(by_left.is_empty());
+            assert_eq!(0, by_right.right().len());
+        }
+
+        #[test]
+        fn ordered_remove_by_either_side(ref map in proptest::ord_bi_map(i16::ANY, i16::ANY, 1..100)) {
+            let mut by_left = map.clone();
+            let mut by_right = map.clone();
+            for (l, r) in map.iter() {
+                by_left.remove_by_left_mut(&*l);
+                by_right = by_right.remove_by_right(&*r);
+                check_ordered(&by_left);
+                check_ordered(&by_right);
+                assert_eq!(by_left, by_right);
+            }
+            assert!(by_left.is_empty());
+            assert_eq!(0, by_right.right().len());
+        }
+    }
+}
@@ -33,3 +33,147 @@ pub fn hash_key<K: Hash + ?Sized, S: BuildHasher>(bh: &S, key: &K) -> Bitmap {
     // (hasher.finish() & HASH_COERCE) as Bitmap
     hasher.finish() as Bitmap
 }
+
+/// Hash a value with a fixed hasher, independent of any
+/// `BuildHasher`.
+pub fn hash_one<A: Hash + ?Sized>(value: &A) -> u64 {
+    let mut hasher = fixed_hasher();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn fixed_hasher() -> FixedHasher {
+    FixedHasher {
+        v0: 0x736f_6d65_7073_6575,
+        v1: 0x646f_7261_6e64_6f6d,
+        v2: 0x6c79_6765_6e65_7261,
+        v3: 0x7465_6462_7974_6573,
+        tail: 0,
+        ntail: 0,
+        length: 0,
+    }
+}
+
+// SipHash-1-3 with both keys zero, which is what `DefaultHasher::new`
+// uses at the time of writing. It's kept here rather than borrowed
+// from `std`, so that builds with and without `std` hash alike, and
+// so that a change to the standard library's choice of algorithm
+// can't change the hashes of the same data from one build to the
+// next.
+#[derive(Clone)]
+struct FixedHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    // Bytes written but not yet compressed, and how many there are.
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+impl FixedHasher {
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+// Read up to eight bytes as a little endian number.
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes.iter().enumerate().fold(0, |word, (index, byte)| {
+        word | u64::from(*byte) << (8 * index)
+    })
+}
+
+impl Hasher for FixedHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length += bytes.len();
+        let mut start = 0;
+        if self.ntail != 0 {
+            let needed = 8 - self.ntail;
+            if bytes.len() < needed {
+                self.tail |= read_le(bytes) << (8 * self.ntail);
+                self.ntail += bytes.len();
+                return;
+            }
+            let word = self.tail | read_le(&bytes[..needed]) << (8 * self.ntail);
+            self.compress(word);
+            start = needed;
+        }
+        self.tail = 0;
+        self.ntail = 0;
+        for chunk in bytes[start..].chunks(8) {
+            if chunk.len() == 8 {
+                self.compress(read_le(chunk));
+            } else {
+                self.tail = read_le(chunk);
+                self.ntail = chunk.len();
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let last = (self.length as u64 & 0xff) << 56 | self.tail;
+        state.compress(last);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// Sum the fixed hashes of a collection's elements, which gives the
+/// same result whatever order they come in.
+pub fn unordered_sum<I, A>(iter: I) -> u64
+where
+    I: IntoIterator<Item = A>,
+    A: Hash,
+{
+    iter.into_iter()
+        .fold(0u64, |sum, item| sum.wrapping_add(hash_one(&item)))
+}
+
+/// Hash a collection without regard to the order of its elements or
+/// the `BuildHasher` it was built with.
+pub fn hash_unordered<I, A, H>(len: usize, iter: I, state: &mut H)
+where
+    I: IntoIterator<Item = A>,
+    A: Hash,
+    H: Hasher,
+{
+    state.write_usize(len);
+    state.write_u64(unordered_sum(iter));
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // These must never change: they're what makes content hashes
+    // comparable between builds.
+    #[test]
+    fn hash_one_is_fixed() {
+        assert_eq!(0xbd60_acb6_58c7_9e45, hash_one(&0u64));
+        assert_eq!(0xe037_876b_880b_8ed9, hash_one("hello"));
+        assert_eq!(0x7038_a626_8d64_a5c1, hash_one(&vec![1u8, 2, 3]));
+    }
+}
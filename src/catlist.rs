@@ -16,14 +16,19 @@
 //! [conslist::ConsList]: ../conslist/struct.ConsList.html
 
 use bits::HASH_SIZE;
+use footprint::{Footprint, HeapSize};
+use invariant::InvariantError;
 use shared::Shared;
 use std::borrow::Borrow;
-use std::cmp::Ordering;
+use std::cmp::{max, Ordering};
 use std::fmt::{Debug, Error, Formatter};
 use std::hash::{Hash, Hasher};
 use std::iter::{FromIterator, Sum};
-use std::ops::{Add, Deref};
+use std::mem::take;
+use std::ops::Add;
+use std::string::String;
 use std::sync::Arc;
+use std::vec::Vec;
 use vector::Vector;
 
 /// Construct a list from a sequence of elements.
@@ -173,6 +178,19 @@ impl<A> CatList<A> {
         self.len() == 0
     }
 
+    /// Test whether two lists refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a list to
+    /// itself, or if you're comparing a list to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.size == other.size && Arc::ptr_eq(&self.head, &other.head)
+            && self.tail.ptr_eq(&other.tail)
+    }
+
     /// Get the length of a list.
     ///
     /// Time: O(1)
@@ -243,10 +261,15 @@ impl<A> CatList<A> {
     pub fn last(&self) -> Option<Arc<A>> {
         if self.is_empty() {
             None
-        } else if self.tail.is_empty() {
-            self.head.first().cloned()
         } else {
-            self.tail.last().unwrap().last()
+            let mut list = self.tail.last();
+            while let Some(next) = list.as_ref().and_then(|l| l.tail.last()) {
+                list = Some(next);
+            }
+            match list {
+                None => self.head.first().cloned(),
+                Some(list) => list.head.first().cloned(),
+            }
         }
     }
 
@@ -394,7 +417,7 @@ impl<A> CatList<A> {
             self.tail = other.tail.clone();
         } else if self.tail.is_empty() && self.head.len() + other.head.len() <= HASH_SIZE {
             self.head = Arc::new(other.head.iter().chain(self.head.iter()).cloned().collect());
-            self.tail = Vector::singleton(other.clone());
+            self.tail = other.tail.clone();
             self.size += other.len();
         } else {
             self.tail.push_back_mut(other.borrow().clone());
@@ -656,11 +679,7 @@ impl<A> CatList<A> {
     ///
     /// [rev]: https://doc.rust-lang.org/std/iter/trait.Iterator.html#method.rev
     pub fn reverse(&self) -> Self {
-        let mut out = CatList::new();
-        for i in self.iter() {
-            out = out.cons(i)
-        }
-        out
+        self.iter().rev().collect()
     }
 
     /// Sort a list using a comparator function.
@@ -670,76 +689,9 @@ impl<A> CatList<A> {
     where
         F: Fn(&A, &A) -> Ordering,
     {
-        fn merge<A>(la: &CatList<A>, lb: &CatList<A>, cmp: &Fn(&A, &A) -> Ordering) -> CatList<A> {
-            match (la.uncons(), lb.uncons()) {
-                (Some((ref a, _)), Some((ref b, ref lb1))) if cmp(a, b) == Ordering::Greater => {
-                    cons(b.clone(), &merge(la, lb1, cmp))
-                }
-                (Some((a, la1)), Some((_, _))) => cons(a.clone(), &merge(&la1, lb, cmp)),
-                (None, _) => lb.clone(),
-                (_, None) => la.clone(),
-            }
-        }
-
-        fn merge_pairs<A>(
-            l: &CatList<CatList<A>>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> CatList<CatList<A>> {
-            match l.uncons2() {
-                Some((a, b, rest)) => cons(merge(&a, &b, cmp), &merge_pairs(&rest, cmp)),
-                _ => l.clone(),
-            }
-        }
-
-        fn merge_all<A>(l: &CatList<CatList<A>>, cmp: &Fn(&A, &A) -> Ordering) -> CatList<A> {
-            match l.uncons() {
-                None => catlist![],
-                Some((ref a, ref d)) if d.is_empty() => a.deref().clone(),
-                _ => merge_all(&merge_pairs(l, cmp), cmp),
-            }
-        }
-
-        fn ascending<A>(
-            a: &Arc<A>,
-            f: &Fn(CatList<A>) -> CatList<A>,
-            l: &CatList<A>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> CatList<CatList<A>> {
-            match l.uncons() {
-                Some((ref b, ref lb)) if cmp(a, b) != Ordering::Greater => {
-                    ascending(&b.clone(), &|ys| f(cons(a.clone(), &ys)), lb, cmp)
-                }
-                _ => cons(f(CatList::singleton(a.clone())), &sequences(l, cmp)),
-            }
-        }
-
-        fn descending<A>(
-            a: &Arc<A>,
-            la: &CatList<A>,
-            lb: &CatList<A>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> CatList<CatList<A>> {
-            match lb.uncons() {
-                Some((ref b, ref bs)) if cmp(a, b) == Ordering::Greater => {
-                    descending(&b.clone(), &cons(a.clone(), la), bs, cmp)
-                }
-                _ => cons(cons(a.clone(), la), &sequences(lb, cmp)),
-            }
-        }
-
-        fn sequences<A>(l: &CatList<A>, cmp: &Fn(&A, &A) -> Ordering) -> CatList<CatList<A>> {
-            match l.uncons2() {
-                Some((ref a, ref b, ref xs)) if cmp(a, b) == Ordering::Greater => {
-                    descending(&b.clone(), &CatList::singleton(a.clone()), xs, cmp)
-                }
-                Some((ref a, ref b, ref xs)) => {
-                    ascending(&b.clone(), &|l| cons(a.clone(), l), xs, cmp)
-                }
-                None => catlist![l.clone()],
-            }
-        }
-
-        merge_all(&sequences(self, &cmp), &cmp)
+        let mut items: Vec<Arc<A>> = self.iter().collect();
+        items.sort_by(|a, b| cmp(a, b));
+        CatList::from(items)
     }
 
     /// Sort a list of ordered elements.
@@ -817,6 +769,62 @@ impl<A> CatList<A> {
         }
         out
     }
+
+    /// Check the internal structure of a list, returning a
+    /// description of the first broken invariant found, if any.
+    ///
+    /// This checks that the cached length of the list, and of every
+    /// list it's made of, is correct, and validates the vectors
+    /// holding its sublists. See the [`invariant`][invariant] module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        // Lists can be nested too deeply to recurse down, so keep the
+        // sublists still to be checked on a stack instead.
+        let mut stack = Vec::new();
+        let mut result = self.check(&mut stack);
+        while let Some(list) = stack.pop() {
+            if result.is_err() {
+                break;
+            }
+            result = list.check(&mut stack);
+        }
+        result.map_err(|message| InvariantError::new("CatList", message))
+    }
+
+    /// Panic if the internal structure of a list is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
+
+    // Check this list's own length, pushing its sublists onto `stack`
+    // to be checked next.
+    fn check(&self, stack: &mut Vec<Arc<CatList<A>>>) -> Result<(), String> {
+        if let Err(error) = self.tail.validate() {
+            return Err(format!("a sublist vector is broken: {}", error.message()));
+        }
+        let mut size = self.head.len();
+        for sublist in self.tail.iter() {
+            size += sublist.size;
+            stack.push(sublist);
+        }
+        if size != self.size {
+            return Err(format!(
+                "a list has length {} but holds {} values",
+                self.size, size
+            ));
+        }
+        Ok(())
+    }
 }
 
 // Core traits
@@ -837,6 +845,68 @@ impl<A> Default for CatList<A> {
     }
 }
 
+impl<A> CatList<A> {
+    // Count the memory used by this list's own head and tail, pushing
+    // the sublists seen for the first time onto `stack` along with
+    // how deep they are, and return the depth reached.
+    fn measure_heap_from(
+        &self,
+        above: usize,
+        footprint: &mut Footprint,
+        stack: &mut Vec<(Arc<CatList<A>>, usize)>,
+    ) -> usize {
+        let head = &self.head;
+        let head_height = footprint.node(head, |footprint| {
+            footprint.buffer::<Arc<A>>(head.capacity());
+            for value in head.iter() {
+                footprint.value(value);
+            }
+            footprint.leaf(head.len(), head.capacity());
+            1
+        });
+        let mut sublists = Vec::new();
+        let tail_height = self.tail.measure_heap_with(footprint, &mut |footprint, sublist| {
+            if footprint.value(sublist) {
+                sublists.push(sublist.clone());
+            }
+        });
+        let below = above + tail_height;
+        stack.extend(sublists.into_iter().map(|sublist| (sublist, below)));
+        max(above + head_height, below)
+    }
+}
+
+impl<A> HeapSize for CatList<A> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        // Lists can be nested too deeply to recurse down, so keep the
+        // ones still to be counted on a stack instead.
+        let mut stack = Vec::new();
+        let mut depth = self.measure_heap_from(0, footprint, &mut stack);
+        while let Some((list, above)) = stack.pop() {
+            depth = max(depth, list.measure_heap_from(above, footprint, &mut stack));
+        }
+        depth
+    }
+}
+
+impl<A> Drop for CatList<A> {
+    fn drop(&mut self) {
+        // Lists nest inside each other's tails, so dropping a deeply
+        // nested list recursively could overflow the stack. Instead,
+        // we take apart every nested list which isn't shared with
+        // another list, and drop them one at a time.
+        if self.tail.is_empty() {
+            return;
+        }
+        let mut lists = take(&mut self.tail).into_unique();
+        while let Some(mut list) = lists.pop() {
+            if !list.tail.is_empty() {
+                lists.extend(take(&mut list.tail).into_unique());
+            }
+        }
+    }
+}
+
 impl<A> Add for CatList<A> {
     type Output = CatList<A>;
 
@@ -868,26 +938,9 @@ where
     }
 }
 
-#[cfg(not(has_specialisation))]
-impl<A: PartialEq> PartialEq for CatList<A> {
-    fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() && self.iter().eq(other.iter())
-    }
-}
-
-#[cfg(has_specialisation)]
 impl<A: PartialEq> PartialEq for CatList<A> {
-    default fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() && self.iter().eq(other.iter())
-    }
-}
-
-#[cfg(has_specialisation)]
-impl<A: Eq> PartialEq for CatList<A> {
     fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len()
-            && ((Arc::ptr_eq(&self.head, &other.head) && self.tail == other.tail)
-                || self.iter().eq(other.iter()))
+        self.ptr_eq(other) || (self.len() == other.len() && self.iter().eq(other.iter()))
     }
 }
 
@@ -965,29 +1018,28 @@ impl<A> Iterator for Iter<A> {
     type Item = Arc<A>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.remaining == 0 {
-            None
-        } else if self.fwd_current.head.len() > self.fwd_head_index {
-            let item =
-                &self.fwd_current.head[(self.fwd_current.head.len() - 1) - self.fwd_head_index];
-            self.fwd_head_index += 1;
-            self.remaining -= 1;
-            Some(item.clone())
-        } else if let Some(list) = self.fwd_current.tail.get(self.fwd_tail_index) {
-            self.fwd_stack
-                .push((self.fwd_current.clone(), self.fwd_tail_index + 1));
-            self.fwd_current = list;
-            self.fwd_head_index = 0;
-            self.fwd_tail_index = 0;
-            self.next()
-        } else if let Some((list, index)) = self.fwd_stack.pop() {
-            self.fwd_head_index = list.head.len();
-            self.fwd_tail_index = index;
-            self.fwd_current = list;
-            self.next()
-        } else {
-            None
+        while self.remaining > 0 {
+            if self.fwd_current.head.len() > self.fwd_head_index {
+                let item =
+                    &self.fwd_current.head[(self.fwd_current.head.len() - 1) - self.fwd_head_index];
+                self.fwd_head_index += 1;
+                self.remaining -= 1;
+                return Some(item.clone());
+            } else if let Some(list) = self.fwd_current.tail.get(self.fwd_tail_index) {
+                self.fwd_stack
+                    .push((self.fwd_current.clone(), self.fwd_tail_index + 1));
+                self.fwd_current = list;
+                self.fwd_head_index = 0;
+                self.fwd_tail_index = 0;
+            } else if let Some((list, index)) = self.fwd_stack.pop() {
+                self.fwd_head_index = list.head.len();
+                self.fwd_tail_index = index;
+                self.fwd_current = list;
+            } else {
+                break;
+            }
         }
+        None
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -997,35 +1049,31 @@ impl<A> Iterator for Iter<A> {
 
 impl<A> DoubleEndedIterator for Iter<A> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if self.remaining == 0 {
-            None
-        } else if self.rev_current.tail.len() > self.rev_tail_index {
-            println!("pushing from tail");
-            let list = self.rev_current
-                .tail
-                .get((self.rev_current.tail.len() - 1) - self.rev_tail_index)
-                .unwrap();
-            self.rev_stack
-                .push((self.rev_current.clone(), self.rev_tail_index + 1));
-            self.rev_current = list;
-            self.rev_head_index = 0;
-            self.rev_tail_index = 0;
-            self.next_back()
-        } else if self.rev_current.head.len() > self.rev_head_index {
-            println!("yielding from head");
-            let item = &self.rev_current.head[self.rev_head_index];
-            self.rev_head_index += 1;
-            self.remaining -= 1;
-            Some(item.clone())
-        } else if let Some((list, index)) = self.rev_stack.pop() {
-            println!("popping from stack");
-            self.rev_head_index = 0;
-            self.rev_tail_index = index;
-            self.rev_current = list;
-            self.next_back()
-        } else {
-            None
+        while self.remaining > 0 {
+            if self.rev_current.tail.len() > self.rev_tail_index {
+                let list = self.rev_current
+                    .tail
+                    .get((self.rev_current.tail.len() - 1) - self.rev_tail_index)
+                    .unwrap();
+                self.rev_stack
+                    .push((self.rev_current.clone(), self.rev_tail_index + 1));
+                self.rev_current = list;
+                self.rev_head_index = 0;
+                self.rev_tail_index = 0;
+            } else if self.rev_current.head.len() > self.rev_head_index {
+                let item = &self.rev_current.head[self.rev_head_index];
+                self.rev_head_index += 1;
+                self.remaining -= 1;
+                return Some(item.clone());
+            } else if let Some((list, index)) = self.rev_stack.pop() {
+                self.rev_head_index = 0;
+                self.rev_tail_index = index;
+                self.rev_current = list;
+            } else {
+                break;
+            }
         }
+        None
     }
 }
 
@@ -1066,7 +1114,22 @@ where
     where
         I: IntoIterator<Item = T>,
     {
-        source.into_iter().fold(Self::new(), |l, i| l.push_back(i))
+        // Build the list out of full sized heads, so that we neither
+        // copy a head for every element nor end up with a tail of
+        // singleton lists.
+        let mut out = Self::new();
+        let mut chunk = Vec::with_capacity(HASH_SIZE);
+        for item in source {
+            chunk.push(item.shared());
+            if chunk.len() == HASH_SIZE {
+                out.append_mut(CatList::from(chunk));
+                chunk = Vec::with_capacity(HASH_SIZE);
+            }
+        }
+        if !chunk.is_empty() {
+            out.append_mut(CatList::from(chunk));
+        }
+        out
     }
 }
 
@@ -1097,8 +1160,10 @@ impl<A> From<Vec<A>> for CatList<A> {
 }
 
 impl<A> From<Vec<Arc<A>>> for CatList<A> {
-    fn from(vec: Vec<Arc<A>>) -> Self {
+    fn from(mut vec: Vec<Arc<A>>) -> Self {
         if vec.len() <= HASH_SIZE {
+            // The head is stored back to front.
+            vec.reverse();
             Self::from_head(vec)
         } else {
             vec.into_iter().collect()
@@ -1125,6 +1190,9 @@ pub mod proptest {
     use super::*;
     use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
     use std::ops::Range;
+    use model::{list_op, ListOp};
+    use proptest::collection::vec;
+    use proptest::num::{u8, usize};
 
     /// A strategy for generating a list of a certain size.
     ///
@@ -1171,6 +1239,24 @@ pub mod proptest {
             .prop_map(|v| CatList::from(v).sort())
             .boxed()
     }
+
+    /// A strategy for a sequence of operations on a list, of a length
+    /// in the given range, for use with
+    /// [`check_against_model`][check_against_model].
+    ///
+    /// [check_against_model]: ../../model/fn.check_against_model.html
+    pub fn list_ops<T: Strategy + 'static>(
+        element: T,
+        size: Range<usize>,
+    ) -> BoxedStrategy<Vec<ListOp<<T::Value as ValueTree>::Value>>> {
+        vec((u8::ANY, element, usize::ANY), size)
+            .prop_map(|ops| {
+                ops.into_iter()
+                    .map(|(choice, value, index)| list_op(choice, value, index))
+                    .collect()
+            })
+            .boxed()
+    }
 }
 
 // Tests
@@ -1181,8 +1267,43 @@ mod test {
     use super::*;
     use proptest::collection;
     use proptest::num::i32;
+    use std::collections::hash_map::DefaultHasher;
     use test::is_sorted;
 
+    fn hash<A: Hash>(value: &A) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn ptr_eq() {
+        let list = CatList::from_iter(0..1000).append(CatList::from_iter(0..10));
+        assert!(list.ptr_eq(&list.clone()));
+        assert!(!list.ptr_eq(&list.push_back(1)));
+        assert!(!list.ptr_eq(&CatList::from_iter(0..1000).append(CatList::from_iter(0..10))));
+    }
+
+    #[test]
+    fn long_lists_are_stack_safe() {
+        let size = 3_000_000;
+        let mut list = CatList::new();
+        for i in 0..size {
+            list.push_front_mut(size - 1 - i);
+        }
+        assert_eq!(Some(Arc::new(size - 1)), list.last());
+        assert!(list.iter().map(|i| *i).eq(0..size));
+        assert!(list.iter().rev().map(|i| *i).eq((0..size).rev()));
+        let copy: CatList<i32> = list.iter().collect();
+        assert_eq!(list, copy);
+        assert_eq!(hash(&list), hash(&copy));
+        let reversed = list.reverse();
+        assert_eq!(Some(Arc::new(size - 1)), reversed.head());
+        let sorted = list.append(&reversed).sort();
+        assert_eq!(size as usize * 2, sorted.len());
+        assert!(is_sorted(sorted.iter()));
+    }
+
     #[test]
     fn basic_consistency() {
         let vec: Vec<i32> = vec![
@@ -1212,7 +1333,37 @@ mod test {
         }
     }
 
+    #[test]
+    fn validate_finds_wrong_length() {
+        let list = CatList::from_iter(0..10).append(CatList::from_iter(10..20));
+        let mut nested = CatList::singleton(-1).append(list);
+        assert_eq!(Ok(()), nested.validate());
+        nested.size += 1;
+        assert_eq!("CatList", nested.validate().unwrap_err().collection());
+    }
+
     proptest! {
+        #[test]
+        fn operations_keep_invariants(ref ops in collection::vec((0u8..5, i32::ANY), 0..200)) {
+            let mut list = CatList::new();
+            for &(op, value) in ops {
+                match op {
+                    0 => list.push_back_mut(value),
+                    1 => list.push_front_mut(value),
+                    2 => {
+                        list.pop_front_mut();
+                    }
+                    3 => {
+                        list.pop_back_mut();
+                    }
+                    _ => if list.len() < 1000 {
+                        list = list.append(&CatList::from_iter(0..value & 0xf)).append(&list)
+                    },
+                }
+                list.assert_invariants();
+            }
+        }
+
         #[test]
         fn length(ref v in collection::vec(i32::ANY, 0..100)) {
             let list = CatList::from_iter(v.clone());
@@ -25,6 +25,8 @@
 //!
 //! [vector::Vector]: ../vector/struct.Vector.html
 
+use footprint::{Footprint, HeapSize};
+use invariant::InvariantError;
 use shared::Shared;
 use std::borrow::Borrow;
 use std::cmp::Ordering;
@@ -33,6 +35,7 @@ use std::hash::{Hash, Hasher};
 use std::iter::{FromIterator, Iterator, Sum};
 use std::ops::{Add, Deref};
 use std::sync::Arc;
+use std::vec::Vec;
 
 /// Construct a list from a sequence of elements.
 ///
@@ -323,10 +326,11 @@ impl<A> ConsList<A> {
     where
         R: Borrow<Self>,
     {
-        match self.uncons() {
-            None => right.borrow().clone(),
-            Some((car, cdr)) => cons(car, &cdr.append(right)),
-        }
+        let items: Vec<Arc<A>> = self.iter().collect();
+        items
+            .into_iter()
+            .rev()
+            .fold(right.borrow().clone(), |list, item| list.cons(item))
     }
 
     /// Construct a list which is the reverse of the current list.
@@ -367,82 +371,20 @@ impl<A> ConsList<A> {
     where
         F: Fn(&A, &A) -> Ordering,
     {
-        fn merge<A>(
-            la: &ConsList<A>,
-            lb: &ConsList<A>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> ConsList<A> {
-            match (la.uncons(), lb.uncons()) {
-                (Some((ref a, _)), Some((ref b, ref lb1))) if cmp(a, b) == Ordering::Greater => {
-                    cons(b.clone(), &merge(la, lb1, cmp))
-                }
-                (Some((a, la1)), Some((_, _))) => cons(a.clone(), &merge(&la1, lb, cmp)),
-                (None, _) => lb.clone(),
-                (_, None) => la.clone(),
-            }
-        }
-
-        fn merge_pairs<A>(
-            l: &ConsList<ConsList<A>>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> ConsList<ConsList<A>> {
-            match l.uncons2() {
-                Some((a, b, rest)) => cons(merge(&a, &b, cmp), &merge_pairs(&rest, cmp)),
-                _ => l.clone(),
-            }
-        }
-
-        fn merge_all<A>(l: &ConsList<ConsList<A>>, cmp: &Fn(&A, &A) -> Ordering) -> ConsList<A> {
-            match l.uncons() {
-                None => conslist![],
-                Some((ref a, ref d)) if d.is_empty() => a.deref().clone(),
-                _ => merge_all(&merge_pairs(l, cmp), cmp),
-            }
-        }
-
-        fn ascending<A>(
-            a: &Arc<A>,
-            f: &Fn(ConsList<A>) -> ConsList<A>,
-            l: &ConsList<A>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> ConsList<ConsList<A>> {
-            match l.uncons() {
-                Some((ref b, ref lb)) if cmp(a, b) != Ordering::Greater => {
-                    ascending(&b.clone(), &|ys| f(cons(a.clone(), &ys)), lb, cmp)
-                }
-                _ => cons(f(ConsList::singleton(a.clone())), &sequences(l, cmp)),
-            }
-        }
-
-        fn descending<A>(
-            a: &Arc<A>,
-            la: &ConsList<A>,
-            lb: &ConsList<A>,
-            cmp: &Fn(&A, &A) -> Ordering,
-        ) -> ConsList<ConsList<A>> {
-            match lb.uncons() {
-                Some((ref b, ref bs)) if cmp(a, b) == Ordering::Greater => {
-                    descending(&b.clone(), &cons(a.clone(), la), bs, cmp)
-                }
-                _ => cons(cons(a.clone(), la), &sequences(lb, cmp)),
-            }
-        }
-
-        fn sequences<A>(l: &ConsList<A>, cmp: &Fn(&A, &A) -> Ordering) -> ConsList<ConsList<A>> {
-            match l.uncons2() {
-                Some((ref a, ref b, ref xs)) if cmp(a, b) == Ordering::Greater => {
-                    descending(&b.clone(), &ConsList::singleton(a.clone()), xs, cmp)
-                }
-                Some((ref a, ref b, ref xs)) => {
-                    ascending(&b.clone(), &|l| cons(a.clone(), l), xs, cmp)
-                }
-                None => conslist![l.clone()],
-            }
-        }
-
-        merge_all(&sequences(self, &cmp), &cmp)
+        let mut items: Vec<Arc<A>> = self.iter().collect();
+        items.sort_by(|a, b| cmp(a, b));
+        items
+            .into_iter()
+            .rev()
+            .fold(ConsList::new(), |list, item| list.cons(item))
     }
 
+    /// Test whether two lists refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a list to itself,
+    /// or if you're comparing a list to a fresh clone of itself.
+    ///
+    /// Time: O(1)
     pub fn ptr_eq(&self, other: &Self) -> bool {
         match (&self.link, &other.link) {
             (&Some(ref a1), &Some(ref a2)) => Arc::ptr_eq(a1, a2),
@@ -482,15 +424,19 @@ impl<A> ConsList<A> {
     where
         A: Ord,
     {
-        match self.uncons() {
-            None => ConsList::singleton(item),
-            Some((car, cdr)) =>
-                if car.deref() > item.deref() {
-                    self.cons(item)
-                } else {
-                    cdr.insert_ref(item).cons(car)
-                }
+        let mut before = Vec::new();
+        let mut rest = self.clone();
+        while let Some((car, cdr)) = rest.uncons() {
+            if car.deref() > item.deref() {
+                break;
+            }
+            before.push(car);
+            rest = cdr;
         }
+        before
+            .into_iter()
+            .rev()
+            .fold(rest.cons(item), |list, car| list.cons(car))
     }
 
     /// Sort a list.
@@ -516,6 +462,44 @@ impl<A> ConsList<A> {
     {
         self.sort_by(Ord::cmp)
     }
+
+    /// Check the internal structure of a list, returning a
+    /// description of the first broken invariant found, if any.
+    ///
+    /// This checks that the cached length of the list matches the
+    /// number of cons cells in it. See the [`invariant`][invariant]
+    /// module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let mut cells = 0;
+        let mut link = &self.link;
+        while let Some(ref node) = *link {
+            cells += 1;
+            link = &node.cdr;
+        }
+        if cells != self.len {
+            return Err(InvariantError::new(
+                "ConsList",
+                format!("the list has length {} but {} cells", self.len, cells),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Panic if the internal structure of a list is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
 }
 
 impl<A> ConsList<A>
@@ -548,50 +532,52 @@ impl<A> Default for ConsList<A> {
     }
 }
 
-#[cfg(not(has_specialisation))]
-impl<A> PartialEq for ConsList<A>
-where
-    A: PartialEq,
-{
-    /// Test if two lists are equal.
-    ///
-    /// This could potentially be an expensive operation, as we need to walk
-    /// both lists to test for equality. We can very quickly determine equality
-    /// if the lists have different lengths (can't be equal). Otherwise, we walk the
-    /// lists to compare values.
-    ///
-    /// Time: O(n)
-    fn eq(&self, other: &ConsList<A>) -> bool {
-        self.len() == other.len() && self.iter().eq(other.iter())
+impl<A> HeapSize for ConsList<A> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        // Walk the cells in a loop, as a list can be too long to
+        // recurse down. The height of a cell is the length of the list
+        // starting from it, and once a cell has been seen, so has the
+        // rest of the list.
+        let mut link = &self.link;
+        let mut len = self.len;
+        while let Some(ref cell) = *link {
+            let mut seen = true;
+            footprint.node(cell, |footprint| {
+                seen = false;
+                footprint.value(&cell.car);
+                if cell.cdr.is_none() {
+                    footprint.leaf(1, 1);
+                }
+                len
+            });
+            if seen {
+                break;
+            }
+            link = &cell.cdr;
+            len -= 1;
+        }
+        self.len
     }
 }
 
-#[cfg(has_specialisation)]
-impl<A> PartialEq for ConsList<A>
-where
-    A: PartialEq,
-{
-    /// Test if two lists are equal.
-    ///
-    /// This could potentially be an expensive operation, as we need to walk
-    /// both lists to test for equality. We can very quickly determine equality
-    /// if the lists have different lengths (can't be equal). Otherwise, we walk the
-    /// lists to compare values.
-    ///
-    /// If `A` implements `Eq`, we have an additional shortcut available to us: if
-    /// both lists refer to the same cons cell, as determined by `Arc::ptr_eq`, they
-    /// have to be equal.
-    ///
-    /// Time: O(n)
-    default fn eq(&self, other: &ConsList<A>) -> bool {
-        self.len() == other.len() && self.iter().eq(other.iter())
+impl<A> Drop for ConsList<A> {
+    fn drop(&mut self) {
+        // Dropping the cells one by one, rather than letting each cell
+        // drop the next, keeps long lists from overflowing the stack.
+        // We stop at the first cell which is shared with another list.
+        let mut link = self.link.take();
+        while let Some(node) = link {
+            match Arc::try_unwrap(node) {
+                Ok(mut node) => link = node.cdr.take(),
+                Err(_) => break,
+            }
+        }
     }
 }
 
-#[cfg(has_specialisation)]
 impl<A> PartialEq for ConsList<A>
 where
-    A: Eq,
+    A: PartialEq,
 {
     /// Test if two lists are equal.
     ///
@@ -600,9 +586,8 @@ where
     /// if the lists have different lengths (can't be equal). Otherwise, we walk the
     /// lists to compare values.
     ///
-    /// If `A` implements `Eq`, we have an additional shortcut available to us: if
-    /// both lists refer to the same cons cell, as determined by `Arc::ptr_eq`, they
-    /// have to be equal.
+    /// We also have a shortcut available to us: if both lists refer to the
+    /// same cons cell, as determined by `Arc::ptr_eq`, they have to be equal.
     ///
     /// Time: O(n)
     fn eq(&self, other: &ConsList<A>) -> bool {
@@ -792,8 +777,15 @@ pub mod proptest {
 mod test {
     use super::proptest::*;
     use super::*;
+    use std::collections::hash_map::DefaultHasher;
     use test::is_sorted;
 
+    fn hash<A: Hash>(value: &A) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
     #[test]
     fn exact_size_iterator() {
         assert_eq!(10, ConsList::from_iter(1..11).iter().len());
@@ -826,6 +818,23 @@ mod test {
         assert_eq!(l2.len(), 1);
     }
 
+    #[test]
+    fn long_lists_are_stack_safe() {
+        let size = 3_000_000;
+        let list = (0..size).fold(ConsList::new(), |list, i| list.cons(i));
+        let copy = ConsList::from_iter(list.iter());
+        assert_eq!(list, copy);
+        assert_eq!(hash(&list), hash(&copy));
+        let reversed = list.reverse();
+        assert_eq!(Some(Arc::new(0)), reversed.head());
+        let appended = list.append(&reversed);
+        assert_eq!(size * 2, appended.len() as i32);
+        let sorted = appended.sort();
+        assert_eq!(size * 2, sorted.len() as i32);
+        assert!(is_sorted(sorted.iter()));
+        assert_eq!(Some(Arc::new(0)), list.insert(-1).tail().unwrap().reverse().head());
+    }
+
     quickcheck! {
         fn length(vec: Vec<i32>) -> bool {
             let list = ConsList::from(vec.clone());
@@ -860,7 +869,19 @@ mod test {
         }
     }
 
+    #[test]
+    fn validate_finds_wrong_length() {
+        let mut list: ConsList<i32> = (0..100).collect();
+        assert_eq!(Ok(()), list.validate());
+        assert_eq!(Ok(()), list.tail().unwrap().append(&list).validate());
+        list.len = 99;
+        let error = list.validate().unwrap_err();
+        assert_eq!("ConsList", error.collection());
+        assert_eq!("the list has length 99 but 100 cells", error.message());
+    }
+
     proptest! {
+
         #[test]
         fn proptest_a_conslist(ref l in conslist(".*", 10..100)) {
             assert!(l.len() < 100);
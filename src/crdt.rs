@@ -0,0 +1,1774 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Conflict-free replicated data types.
+//!
+//! A CRDT is a value which several replicas can change independently,
+//! without coordinating, and which always comes out the same on every
+//! replica once they've all seen each other's changes. Each type in
+//! this module has a [`merge`][Crdt::merge] which is commutative,
+//! associative and idempotent, so replicas can exchange states in any
+//! order, as often as they like, and still agree in the end.
+//!
+//! Every change takes the id of the replica making it, which can be
+//! any `Ord + Hash + Clone` type but must never be shared by two
+//! replicas, and returns a delta: a state holding just that change,
+//! which can be sent to the other replicas and merged there instead
+//! of the whole state. [`delta`][Crdt::delta] extracts all the
+//! changes made since an earlier version of a state in the same way,
+//! stepping over the structure the two versions share, so a replica
+//! can keep the version it last sent and ship only what's new.
+//!
+//! The types are built on the collections in this crate, so cloning
+//! one is cheap, and so are the old versions kept around for `delta`.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate im;
+//! # use im::crdt::{Crdt, OrSet};
+//! # fn main() {
+//! let mut ours = OrSet::new();
+//! ours.insert("milk", "phone");
+//! let mut theirs = ours.clone();
+//!
+//! // While offline, we take milk off the list, and they add eggs.
+//! let synced = ours.clone();
+//! ours.remove("milk");
+//! theirs.insert("eggs", "laptop");
+//!
+//! // Back online, we send each other what's changed.
+//! theirs.merge_mut(&ours.delta(&synced));
+//! ours.merge_mut(&theirs);
+//! assert_eq!(ours, theirs);
+//! assert!(ours.contains("eggs"));
+//! assert!(!ours.contains("milk"));
+//! # }
+//! ```
+//!
+//! [Crdt::merge]: ./trait.Crdt.html#method.merge
+//! [Crdt::delta]: ./trait.Crdt.html#tymethod.delta
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Error, Formatter};
+use std::hash::Hash;
+use std::iter::once;
+use std::sync::Arc;
+
+use fingertree::{self, FingerTree, Measure};
+use hashmap::{self, HashMap};
+use hashset::HashSet;
+use nodes::btree::{DiffItem, Iter};
+use ordmap::OrdMap;
+use shared::Shared;
+use vector::Vector;
+
+/// A state which can be merged with other replicas' states.
+pub trait Crdt: Clone {
+    /// Merge another state, or a delta, into this one.
+    fn merge_mut(&mut self, other: &Self);
+
+    /// Construct a delta holding the changes in this state which
+    /// `since` doesn't have.
+    ///
+    /// Merging the delta into `since`, or into any state which has
+    /// seen everything `since` has, has the same effect as merging
+    /// the whole of this state.
+    fn delta(&self, since: &Self) -> Self;
+
+    /// Construct the merge of two states.
+    fn merge(&self, other: &Self) -> Self {
+        let mut out = self.clone();
+        out.merge_mut(other);
+        out
+    }
+}
+
+/// An event on a replica, identified by the replica's id and a
+/// counter.
+///
+/// Dots order by their counters first, so a dot made with a counter
+/// higher than any the replica has seen comes after everything that
+/// replica knew about, which lets them double as [Lamport
+/// timestamps][lamport].
+///
+/// [lamport]: https://en.wikipedia.org/wiki/Lamport_timestamps
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dot<R> {
+    pub counter: u64,
+    pub replica: R,
+}
+
+// Counters
+
+/// A counter which can only go up.
+///
+/// Each replica counts its own increments, and the value is the sum
+/// of the counts.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::crdt::{Crdt, GCounter};
+/// # fn main() {
+/// let mut left = GCounter::new();
+/// let mut right = GCounter::new();
+/// left.increment(1);
+/// right.increment_by(2, 5);
+/// assert_eq!(6, left.merge(&right).value());
+/// # }
+/// ```
+pub struct GCounter<R> {
+    pub(crate) counts: OrdMap<R, u64>,
+}
+
+impl<R> GCounter<R> {
+    /// Construct a counter at zero.
+    #[inline]
+    pub fn new() -> Self {
+        GCounter {
+            counts: OrdMap::new(),
+        }
+    }
+}
+
+impl<R: Ord + Clone> GCounter<R> {
+    /// Get the value of a counter.
+    ///
+    /// Time: O(r) (where r is the number of replicas)
+    pub fn value(&self) -> u64 {
+        self.counts.values().map(|count| *count).sum()
+    }
+
+    /// Get the number of increments made by one replica.
+    ///
+    /// Time: O(log r)
+    pub fn get(&self, replica: &R) -> u64 {
+        self.counts.get(replica).map_or(0, |count| *count)
+    }
+
+    /// Add one to a counter, returning the delta.
+    ///
+    /// Time: O(log r)
+    pub fn increment(&mut self, replica: R) -> Self {
+        self.increment_by(replica, 1)
+    }
+
+    /// Add `amount` to a counter, returning the delta.
+    ///
+    /// Time: O(log r)
+    pub fn increment_by(&mut self, replica: R, amount: u64) -> Self {
+        let count = self.get(&replica) + amount;
+        self.counts.insert_mut(replica.clone(), count);
+        GCounter {
+            counts: OrdMap::singleton(replica, count),
+        }
+    }
+}
+
+impl<R: Ord + Clone> Crdt for GCounter<R> {
+    fn merge_mut(&mut self, other: &Self) {
+        for change in self.counts.diff(&other.counts) {
+            match change {
+                DiffItem::Add((replica, count)) => self.counts.insert_mut(replica, count),
+                DiffItem::Update {
+                    old: (_, old),
+                    new: (replica, new),
+                } => if new > old {
+                    self.counts.insert_mut(replica, new)
+                },
+                DiffItem::Remove(_) => {}
+            }
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let mut delta = GCounter::new();
+        for change in since.counts.diff(&self.counts) {
+            match change {
+                DiffItem::Add((replica, count)) => delta.counts.insert_mut(replica, count),
+                DiffItem::Update {
+                    old: (_, old),
+                    new: (replica, new),
+                } => if new > old {
+                    delta.counts.insert_mut(replica, new)
+                },
+                DiffItem::Remove(_) => {}
+            }
+        }
+        delta
+    }
+}
+
+/// A counter which can go up and down.
+///
+/// Made of two [`GCounter`][GCounter]s, one counting increments and
+/// one counting decrements.
+///
+/// [GCounter]: ./struct.GCounter.html
+pub struct PNCounter<R> {
+    pub(crate) increments: GCounter<R>,
+    pub(crate) decrements: GCounter<R>,
+}
+
+impl<R> PNCounter<R> {
+    /// Construct a counter at zero.
+    #[inline]
+    pub fn new() -> Self {
+        PNCounter {
+            increments: GCounter::new(),
+            decrements: GCounter::new(),
+        }
+    }
+}
+
+impl<R: Ord + Clone> PNCounter<R> {
+    /// Get the value of a counter.
+    ///
+    /// Time: O(r) (where r is the number of replicas)
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    /// Add one to a counter, returning the delta.
+    ///
+    /// Time: O(log r)
+    pub fn increment(&mut self, replica: R) -> Self {
+        self.increment_by(replica, 1)
+    }
+
+    /// Add `amount` to a counter, returning the delta.
+    ///
+    /// Time: O(log r)
+    pub fn increment_by(&mut self, replica: R, amount: u64) -> Self {
+        PNCounter {
+            increments: self.increments.increment_by(replica, amount),
+            decrements: GCounter::new(),
+        }
+    }
+
+    /// Subtract one from a counter, returning the delta.
+    ///
+    /// Time: O(log r)
+    pub fn decrement(&mut self, replica: R) -> Self {
+        self.decrement_by(replica, 1)
+    }
+
+    /// Subtract `amount` from a counter, returning the delta.
+    ///
+    /// Time: O(log r)
+    pub fn decrement_by(&mut self, replica: R, amount: u64) -> Self {
+        PNCounter {
+            increments: GCounter::new(),
+            decrements: self.decrements.increment_by(replica, amount),
+        }
+    }
+}
+
+impl<R: Ord + Clone> Crdt for PNCounter<R> {
+    fn merge_mut(&mut self, other: &Self) {
+        self.increments.merge_mut(&other.increments);
+        self.decrements.merge_mut(&other.decrements);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        PNCounter {
+            increments: self.increments.delta(&since.increments),
+            decrements: self.decrements.delta(&since.decrements),
+        }
+    }
+}
+
+// Registers
+
+/// A register holding the value written last.
+///
+/// Each write is stamped with a [`Dot`][Dot] whose counter is one
+/// higher than that of the value it replaces, and merging keeps the
+/// value with the latest stamp, so a write always wins over the
+/// writes its replica had seen, and concurrent writes are settled by
+/// their replica ids.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::crdt::{Crdt, LwwRegister};
+/// # use std::sync::Arc;
+/// # fn main() {
+/// let mut left = LwwRegister::new();
+/// left.set("draft", 1);
+/// let mut right = left.clone();
+/// right.set("final", 2);
+/// left.merge_mut(&right);
+/// assert_eq!(Some(Arc::new("final")), left.get());
+/// # }
+/// ```
+///
+/// [Dot]: ./struct.Dot.html
+pub struct LwwRegister<A, R> {
+    pub(crate) entry: Option<(Dot<R>, Arc<A>)>,
+}
+
+impl<A, R> LwwRegister<A, R> {
+    /// Construct a register which has never been written.
+    #[inline]
+    pub fn new() -> Self {
+        LwwRegister { entry: None }
+    }
+
+    /// Get the value of a register, if it's been written.
+    pub fn get(&self) -> Option<Arc<A>> {
+        self.entry.as_ref().map(|&(_, ref value)| value.clone())
+    }
+
+    /// Get the stamp of the last write to a register.
+    pub fn stamp(&self) -> Option<&Dot<R>> {
+        self.entry.as_ref().map(|&(ref stamp, _)| stamp)
+    }
+}
+
+impl<A, R: Ord + Clone> LwwRegister<A, R> {
+    /// Write a value to a register, returning the delta.
+    pub fn set<RA>(&mut self, value: RA, replica: R) -> Self
+    where
+        RA: Shared<A>,
+    {
+        let counter = self.stamp().map_or(0, |stamp| stamp.counter) + 1;
+        self.entry = Some((Dot { counter, replica }, value.shared()));
+        self.clone()
+    }
+}
+
+impl<A, R: Ord + Clone> Crdt for LwwRegister<A, R> {
+    fn merge_mut(&mut self, other: &Self) {
+        if other.stamp() > self.stamp() {
+            self.entry = other.entry.clone();
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        if self.stamp() > since.stamp() {
+            self.clone()
+        } else {
+            LwwRegister::new()
+        }
+    }
+}
+
+// Sets
+
+/// An observed-remove set.
+///
+/// Each element maps to the [`Dot`][Dot]s of the inserts which put
+/// it there, and removing an element removes only the dots its
+/// replica had seen, so an insert made concurrently with a remove
+/// wins. The removed dots are kept as tombstones, which means a set
+/// keeps growing with the number of inserts made over its lifetime,
+/// but lets any two states be merged.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::crdt::{Crdt, OrSet};
+/// # fn main() {
+/// let mut left = OrSet::new();
+/// left.insert(1, 'a');
+/// let mut right = left.clone();
+/// left.remove(&1);
+/// right.insert(1, 'b');
+/// assert!(left.merge(&right).contains(&1));
+/// # }
+/// ```
+///
+/// [Dot]: ./struct.Dot.html
+pub struct OrSet<A, R> {
+    pub(crate) entries: HashMap<A, HashSet<Dot<R>>>,
+    pub(crate) removed: HashMap<A, HashSet<Dot<R>>>,
+    pub(crate) clock: GCounter<R>,
+}
+
+impl<A, R> OrSet<A, R>
+where
+    A: Hash + Eq,
+    R: Ord + Hash + Clone,
+{
+    /// Construct an empty set.
+    #[inline]
+    pub fn new() -> Self {
+        OrSet {
+            entries: HashMap::new(),
+            removed: HashMap::new(),
+            clock: GCounter::new(),
+        }
+    }
+
+    /// Test whether a set is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the number of elements in a set.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Get an iterator over the elements in a set.
+    #[inline]
+    pub fn iter(&self) -> hashmap::Keys<A, HashSet<Dot<R>>> {
+        self.entries.keys()
+    }
+
+    /// Test whether a set contains an element.
+    ///
+    /// Time: O(log n)
+    pub fn contains<BA>(&self, value: &BA) -> bool
+    where
+        BA: Hash + Eq + ?Sized,
+        A: Borrow<BA>,
+    {
+        self.entries.contains_key(value)
+    }
+
+    /// Insert an element into a set, returning the delta.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RA>(&mut self, value: RA, replica: R) -> Self
+    where
+        RA: Shared<A>,
+    {
+        let value = value.shared();
+        let dot = Dot {
+            counter: self.clock.get(&replica) + 1,
+            replica: replica.clone(),
+        };
+        let clock = self.clock.increment(replica);
+        let dots = self
+            .entries
+            .get(&*value)
+            .map_or_else(HashSet::new, |dots| (*dots).clone())
+            .insert(dot.clone());
+        self.entries.insert_mut(value.clone(), dots);
+        OrSet {
+            entries: HashMap::singleton(value, HashSet::singleton(dot)),
+            removed: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// Remove an element from a set, returning the delta.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BA>(&mut self, value: &BA) -> Self
+    where
+        BA: Hash + Eq + ?Sized,
+        A: Borrow<BA>,
+    {
+        let mut delta = OrSet::new();
+        if let Some((value, dots, entries)) = self.entries.pop_with_key(value) {
+            self.entries = entries;
+            let removed = match self.removed.get::<A>(&*value) {
+                None => (*dots).clone(),
+                Some(removed) => removed.union(&*dots),
+            };
+            self.removed.insert_mut(value.clone(), removed);
+            delta.removed.insert_mut(value, dots);
+        }
+        delta
+    }
+
+    // Work out an element's live dots from the dots inserted and
+    // removed by two states.
+    fn settle(&mut self, value: Arc<A>, other: &Self) {
+        let removed = union(self.removed.get(&*value), other.removed.get(&*value));
+        let live =
+            union(self.entries.get(&*value), other.entries.get(&*value)).difference(&removed);
+        if !removed.is_empty() {
+            self.removed.insert_mut(value.clone(), removed);
+        }
+        if live.is_empty() {
+            self.entries.remove_mut(&*value);
+        } else {
+            self.entries.insert_mut(value, live);
+        }
+    }
+}
+
+fn union<A: Hash + Eq>(
+    left: Option<Arc<HashSet<A>>>,
+    right: Option<Arc<HashSet<A>>>,
+) -> HashSet<A> {
+    match (left, right) {
+        (None, None) => HashSet::new(),
+        (Some(set), None) | (None, Some(set)) => (*set).clone(),
+        (Some(left), Some(right)) => left.union(&*right),
+    }
+}
+
+// The values whose dots differ from one map to the next, which are
+// the only ones merging could change.
+fn changed<A, R>(
+    from: &HashMap<A, HashSet<Dot<R>>>,
+    to: &HashMap<A, HashSet<Dot<R>>>,
+) -> Vec<Arc<A>>
+where
+    A: Hash + Eq,
+    R: Hash + Eq,
+{
+    from.diff(to)
+        .filter_map(|change| match change {
+            DiffItem::Add((value, _))
+            | DiffItem::Update {
+                new: (value, _), ..
+            } => Some(value),
+            DiffItem::Remove(_) => None,
+        })
+        .collect()
+}
+
+impl<A, R> Crdt for OrSet<A, R>
+where
+    A: Hash + Eq,
+    R: Ord + Hash + Clone,
+{
+    fn merge_mut(&mut self, other: &Self) {
+        let mut values = changed(&self.entries, &other.entries);
+        values.extend(changed(&self.removed, &other.removed));
+        for value in values {
+            self.settle(value, other);
+        }
+        self.clock.merge_mut(&other.clock);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let mut delta = OrSet::new();
+        for value in changed(&since.entries, &self.entries) {
+            let dots = self.entries.get(&*value).unwrap();
+            let new = match since.entries.get(&*value) {
+                None => (*dots).clone(),
+                Some(old) => dots.difference(&*old),
+            };
+            if !new.is_empty() {
+                delta.entries.insert_mut(value, new);
+            }
+        }
+        for value in changed(&since.removed, &self.removed) {
+            let dots = self.removed.get(&*value).unwrap();
+            let new = match since.removed.get(&*value) {
+                None => (*dots).clone(),
+                Some(old) => dots.difference(&*old),
+            };
+            if !new.is_empty() {
+                delta.removed.insert_mut(value, new);
+            }
+        }
+        delta.clock = self.clock.delta(&since.clock);
+        delta
+    }
+}
+
+// Maps
+
+// An entry in an `LwwMap`: the value it was last given, or nothing if
+// it was last removed, and the stamp of that write.
+pub(crate) struct Stamped<V, R> {
+    pub(crate) stamp: Dot<R>,
+    pub(crate) value: Option<Arc<V>>,
+}
+
+impl<V, R: Clone> Clone for Stamped<V, R> {
+    fn clone(&self) -> Self {
+        Stamped {
+            stamp: self.stamp.clone(),
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<V: PartialEq, R: PartialEq> PartialEq for Stamped<V, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.stamp == other.stamp && self.value == other.value
+    }
+}
+
+impl<V: Debug, R: Debug> Debug for Stamped<V, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{:?} at {:?}", self.value, self.stamp)
+    }
+}
+
+/// A map whose keys each hold the value written last.
+///
+/// Every key works like an [`LwwRegister`][LwwRegister], and removing
+/// a key writes a tombstone in its place, so a remove and a
+/// concurrent insert are settled by their stamps too. Stamps come
+/// from a clock shared by the whole map, one higher than the latest
+/// stamp in it.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::crdt::{Crdt, LwwMap};
+/// # use std::sync::Arc;
+/// # fn main() {
+/// let mut left = LwwMap::new();
+/// left.insert("name", "Ada", 1);
+/// let mut right = left.clone();
+/// right.insert("name", "Grace", 2);
+/// left.remove("name", 1);
+/// // Both writes came after the first, so replica 2's id settles it.
+/// let merged = left.merge(&right);
+/// assert_eq!(Some(Arc::new("Grace")), merged.get("name"));
+/// assert_eq!(merged, right.merge(&left));
+/// # }
+/// ```
+///
+/// [LwwRegister]: ./struct.LwwRegister.html
+pub struct LwwMap<K, V, R> {
+    pub(crate) entries: OrdMap<K, Stamped<V, R>>,
+    pub(crate) clock: u64,
+    pub(crate) size: usize,
+}
+
+impl<K, V, R> LwwMap<K, V, R> {
+    /// Construct an empty map.
+    #[inline]
+    pub fn new() -> Self {
+        LwwMap {
+            entries: OrdMap::new(),
+            clock: 0,
+            size: 0,
+        }
+    }
+
+    /// Test whether a map is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of keys in a map, not counting the removed
+    /// ones.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+}
+
+impl<K: Ord, V, R: Ord + Clone> LwwMap<K, V, R> {
+    /// Get the value for a key, if it has one.
+    ///
+    /// Time: O(log n)
+    pub fn get<BK>(&self, key: &BK) -> Option<Arc<V>>
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        self.entries.get(key).and_then(|entry| entry.value.clone())
+    }
+
+    /// Test whether a map has a value for a key.
+    ///
+    /// Time: O(log n)
+    pub fn contains_key<BK>(&self, key: &BK) -> bool
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Get an iterator over the keys in a map and their values, in
+    /// key order.
+    pub fn iter(&self) -> LwwMapIter<K, V, R> {
+        LwwMapIter {
+            it: self.entries.iter(),
+        }
+    }
+
+    /// Set the value for a key, returning the delta.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RK, RV>(&mut self, key: RK, value: RV, replica: R) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        self.write(key.shared(), Some(value.shared()), replica)
+    }
+
+    /// Remove the value for a key, returning the delta.
+    ///
+    /// Time: O(log n)
+    pub fn remove<RK>(&mut self, key: RK, replica: R) -> Self
+    where
+        RK: Shared<K>,
+    {
+        let key = key.shared();
+        if self.contains_key(&*key) {
+            self.write(key, None, replica)
+        } else {
+            LwwMap::new()
+        }
+    }
+
+    fn write(&mut self, key: Arc<K>, value: Option<Arc<V>>, replica: R) -> Self {
+        self.clock += 1;
+        let entry = Stamped {
+            stamp: Dot {
+                counter: self.clock,
+                replica,
+            },
+            value,
+        };
+        let mut delta = LwwMap {
+            entries: OrdMap::new(),
+            clock: self.clock,
+            size: 0,
+        };
+        delta.put(key.clone(), Arc::new(entry.clone()));
+        self.put(key, Arc::new(entry));
+        delta
+    }
+
+    // Store an entry, keeping count of the keys which have values.
+    pub(crate) fn put(&mut self, key: Arc<K>, entry: Arc<Stamped<V, R>>) {
+        if self.contains_key(&*key) {
+            self.size -= 1;
+        }
+        if entry.value.is_some() {
+            self.size += 1;
+        }
+        self.entries.insert_mut(key, entry);
+    }
+}
+
+impl<K: Ord, V: PartialEq, R: Ord + Clone> Crdt for LwwMap<K, V, R> {
+    fn merge_mut(&mut self, other: &Self) {
+        for change in self.entries.diff(&other.entries) {
+            match change {
+                DiffItem::Add((key, entry)) => self.put(key, entry),
+                DiffItem::Update {
+                    old: (_, old),
+                    new: (key, new),
+                } => if new.stamp > old.stamp {
+                    self.put(key, new)
+                },
+                DiffItem::Remove(_) => {}
+            }
+        }
+        self.clock = self.clock.max(other.clock);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let mut delta = LwwMap::new();
+        for change in since.entries.diff(&self.entries) {
+            match change {
+                DiffItem::Add((key, entry)) => delta.put(key, entry),
+                DiffItem::Update {
+                    old: (_, old),
+                    new: (key, new),
+                } => if new.stamp > old.stamp {
+                    delta.put(key, new)
+                },
+                DiffItem::Remove(_) => {}
+            }
+        }
+        delta.clock = self.clock;
+        delta
+    }
+}
+
+/// An iterator over the keys and values of an
+/// [`LwwMap`][LwwMap].
+///
+/// [LwwMap]: ./struct.LwwMap.html
+pub struct LwwMapIter<K, V, R> {
+    it: Iter<Entry<K, V, R>>,
+}
+
+type Entry<K, V, R> = (Arc<K>, Arc<Stamped<V, R>>);
+
+impl<K: Ord, V, R> Iterator for LwwMapIter<K, V, R> {
+    type Item = (Arc<K>, Arc<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, entry) = self.it.next()?;
+            if let Some(ref value) = entry.value {
+                return Some((key, value.clone()));
+            }
+        }
+    }
+}
+
+// Sequences
+
+// An element of an `Rga`: the element it was inserted after, if any,
+// and its value, or nothing once it's been removed.
+pub(crate) struct Element<A, R> {
+    pub(crate) after: Option<Dot<R>>,
+    pub(crate) value: Option<Arc<A>>,
+}
+
+impl<A, R: Clone> Clone for Element<A, R> {
+    fn clone(&self) -> Self {
+        Element {
+            after: self.after.clone(),
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<A: PartialEq, R: PartialEq> PartialEq for Element<A, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.after == other.after && self.value == other.value
+    }
+}
+
+impl<A: Debug, R: Debug> Debug for Element<A, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{:?} after {:?}", self.value, self.after)
+    }
+}
+
+// Where an element sits in the order of an `Rga`: its id, a label which
+// grows along the sequence, and its value, or nothing once it's been
+// removed.
+pub(crate) struct Place<A, R> {
+    id: Dot<R>,
+    label: u64,
+    value: Option<Arc<A>>,
+}
+
+// What an `Rga` keeps track of for a run of its order: how many
+// elements it holds, how many of them are live, its largest label
+// and its smallest counter.
+#[derive(Clone, Copy)]
+pub(crate) struct Span {
+    elements: usize,
+    live: usize,
+    label: u64,
+    oldest: u64,
+}
+
+pub(crate) struct Spans;
+
+impl<A, R> Measure<Place<A, R>> for Spans {
+    type Value = Span;
+
+    fn zero() -> Span {
+        Span {
+            elements: 0,
+            live: 0,
+            label: 0,
+            oldest: u64::MAX,
+        }
+    }
+
+    fn combine(left: &Span, right: &Span) -> Span {
+        Span {
+            elements: left.elements + right.elements,
+            live: left.live + right.live,
+            label: left.label.max(right.label),
+            oldest: left.oldest.min(right.oldest),
+        }
+    }
+
+    fn measure(entry: &Place<A, R>) -> Span {
+        Span {
+            elements: 1,
+            live: if entry.value.is_some() { 1 } else { 0 },
+            label: entry.label,
+            oldest: entry.id.counter,
+        }
+    }
+}
+
+type Order<A, R> = FingerTree<Place<A, R>, Spans>;
+
+/// A replicated growable array, a sequence which replicas can insert
+/// into and remove from anywhere.
+///
+/// Each element is identified by the [`Dot`][Dot] of its insert, and
+/// remembers the element it was inserted after. The elements
+/// inserted after the same element are ordered newest first, so an
+/// element typed in after another stays next to it, and the elements
+/// concurrent inserts put in the same place end up in the same order
+/// everywhere. Removed elements stay in the sequence as tombstones,
+/// so later inserts can still find their place after them.
+///
+/// The elements are kept in an [`OrdMap`][OrdMap] by id, and their
+/// order in a [`FingerTree`][FingerTree] which counts the live ones,
+/// so finding an index takes O(log n) time. Each element in the
+/// order also has a label, and the labels grow along the sequence,
+/// so merging can find the element a new one was inserted after by
+/// its id, and splice the new one in next to it.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::crdt::{Crdt, Rga};
+/// # fn main() {
+/// let mut left = Rga::new();
+/// left.insert(0, 'a', 1);
+/// left.insert(1, 'c', 1);
+/// let mut right = left.clone();
+/// left.insert(1, 'b', 1);
+/// right.remove(1);
+/// right.insert(1, 'd', 2);
+/// let merged = left.merge(&right);
+/// assert_eq!(vec!['a', 'd', 'b'], merged.iter().map(|c| *c).collect::<Vec<_>>());
+/// assert_eq!(merged, right.merge(&left));
+/// # }
+/// ```
+///
+/// [Dot]: ./struct.Dot.html
+/// [OrdMap]: ../ordmap/struct.OrdMap.html
+/// [FingerTree]: ../fingertree/struct.FingerTree.html
+pub struct Rga<A, R> {
+    pub(crate) elements: OrdMap<Dot<R>, Element<A, R>>,
+    order: Order<A, R>,
+    labels: OrdMap<Dot<R>, u64>,
+    // Elements inserted after one this state hasn't seen yet, by the
+    // id of that one. They're left out of the order until it arrives.
+    waiting: OrdMap<Dot<R>, Vector<Dot<R>>>,
+    clock: u64,
+}
+
+impl<A, R> Rga<A, R> {
+    /// Construct an empty sequence.
+    #[inline]
+    pub fn new() -> Self {
+        Rga {
+            elements: OrdMap::new(),
+            order: FingerTree::new(),
+            labels: OrdMap::new(),
+            waiting: OrdMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Test whether a sequence is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of elements in a sequence, not counting the
+    /// removed ones.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.order.measure().live
+    }
+}
+
+impl<A, R: Ord + Clone> Rga<A, R> {
+    /// Get an iterator over the elements of a sequence.
+    pub fn iter(&self) -> RgaIter<A, R> {
+        RgaIter {
+            entries: self.order.iter(),
+        }
+    }
+
+    /// Get the element at an index.
+    ///
+    /// Time: O(log n)
+    pub fn get(&self, index: usize) -> Option<Arc<A>> {
+        self.order
+            .find(|span| span.live > index)
+            .and_then(|entry| entry.value.clone())
+    }
+
+    /// Insert an element at an index, returning the delta.
+    ///
+    /// Panics if the index is greater than the length of the
+    /// sequence.
+    ///
+    /// Time: O(log² n) amortised
+    pub fn insert<RA>(&mut self, index: usize, value: RA, replica: R) -> Self
+    where
+        RA: Shared<A>,
+    {
+        assert!(index <= self.len(), "Rga::insert: index out of bounds");
+        let after = if index == 0 {
+            None
+        } else {
+            self.order
+                .find(|span| span.live >= index)
+                .map(|entry| entry.id.clone())
+        };
+        self.clock += 1;
+        let id = Dot {
+            counter: self.clock,
+            replica,
+        };
+        let element = Element {
+            after,
+            value: Some(value.shared()),
+        };
+        self.elements.insert_mut(id.clone(), element.clone());
+        self.place(id.clone());
+        Rga::from_elements(OrdMap::singleton(id, element))
+    }
+
+    /// Remove the element at an index, returning the delta.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn remove(&mut self, index: usize) -> Self {
+        assert!(index < self.len(), "Rga::remove: index out of bounds");
+        let (before, rest) = self.order.split(|span| span.live > index);
+        let id = rest.head().unwrap().id.clone();
+        self.bury(before, rest);
+        let element = Element {
+            after: self.elements.get(&id).unwrap().after.clone(),
+            value: None,
+        };
+        self.elements.insert_mut(id.clone(), element.clone());
+        Rga::from_elements(OrdMap::singleton(id, element))
+    }
+
+    pub(crate) fn from_elements(elements: OrdMap<Dot<R>, Element<A, R>>) -> Self {
+        let mut rga = Rga::new();
+        rga.elements = elements;
+        rga.rebuild();
+        rga
+    }
+
+    // Lay the elements out from scratch, each followed by the ones
+    // inserted after it, newest first, with their labels spread out
+    // evenly.
+    fn rebuild(&mut self) {
+        let mut following: BTreeMap<Option<Dot<R>>, Vec<Dot<R>>> = BTreeMap::new();
+        self.clock = 0;
+        for (id, element) in self.elements.iter() {
+            following
+                .entry(element.after.clone())
+                .or_default()
+                .push((*id).clone());
+            self.clock = self.clock.max(id.counter);
+        }
+        let mut ids = Vec::new();
+        let mut stack = following.remove(&None).unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if let Some(after) = following.remove(&Some(id.clone())) {
+                stack.extend(after);
+            }
+            ids.push(id);
+        }
+        let step = u64::MAX / (ids.len() as u64 + 1);
+        self.order = FingerTree::new();
+        self.labels = OrdMap::new();
+        for (index, id) in ids.into_iter().enumerate() {
+            let label = (index as u64 + 1) * step;
+            let value = self.elements.get(&id).unwrap().value.clone();
+            self.labels.insert_mut(id.clone(), label);
+            self.order.push_back_mut(Place { id, label, value });
+        }
+        // Whatever's left was inserted after an element this state
+        // hasn't seen.
+        self.waiting = OrdMap::new();
+        for (after, ids) in following {
+            if let Some(after) = after {
+                self.waiting
+                    .insert_mut(after, ids.into_iter().collect::<Vector<_>>());
+            }
+        }
+    }
+
+    // Put an element into the order after the one it was inserted
+    // after, followed by any elements which were waiting for it, or
+    // leave it waiting if that one hasn't arrived yet.
+    fn place(&mut self, id: Dot<R>) {
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let element = self.elements.get(&id).unwrap();
+            let (before, rest) = match element.after {
+                None => (FingerTree::new(), self.order.clone()),
+                Some(ref after) => match self.labels.get(after) {
+                    Some(label) => self.order.split(|span| span.label > *label),
+                    None => {
+                        let mut waiting = self.waiting
+                            .get(after)
+                            .map_or_else(Vector::new, |ids| (*ids).clone());
+                        waiting.push_back_mut(id);
+                        self.waiting.insert_mut(after.clone(), waiting);
+                        continue;
+                    }
+                },
+            };
+            self.splice(id.clone(), element.value.clone(), before, rest);
+            if let Some((_, waiting)) = self.waiting.pop_with_key_mut(&id) {
+                pending.extend(waiting.iter().map(|id| (*id).clone()));
+            }
+        }
+    }
+
+    // Put an element between `before` and `rest`, after the elements
+    // at the start of `rest` which are newer than it: those were
+    // inserted after the same element, or after those.
+    fn splice(
+        &mut self,
+        id: Dot<R>,
+        value: Option<Arc<A>>,
+        mut before: Order<A, R>,
+        mut rest: Order<A, R>,
+    ) {
+        loop {
+            let (newer, older) = rest.split(|span| span.oldest <= id.counter);
+            before = before.append(newer);
+            rest = older;
+            // Dots with the same counter go by their replicas.
+            match rest.head() {
+                Some(ref entry) if entry.id > id => {
+                    before.push_back_mut(entry.clone());
+                    rest.pop_front_mut();
+                }
+                _ => break,
+            }
+        }
+        let low = match before.last() {
+            Some(entry) => entry.label.checked_add(1),
+            None => Some(0),
+        };
+        let high = match rest.head() {
+            Some(entry) => entry.label.checked_sub(1),
+            None => Some(u64::MAX),
+        };
+        let entry = Place {
+            id,
+            label: 0,
+            value,
+        };
+        match (low, high) {
+            (Some(low), Some(high)) if low <= high => {
+                let label = low + (high - low) / 2;
+                self.labels.insert_mut(entry.id.clone(), label);
+                self.order = before.push_back(Place { label, ..entry }).append(rest);
+            }
+            _ => self.relabel(before, entry, rest),
+        }
+    }
+
+    // Make room for an element between two with adjacent labels by
+    // spreading out the labels around them. This relabels the
+    // smallest aligned range of labels around them which is sparse
+    // enough, allowing bigger ranges to be less sparse, which keeps
+    // the number of labels changed to O(log n) per insert amortised.
+    fn relabel(&mut self, before: Order<A, R>, entry: Place<A, R>, rest: Order<A, R>) {
+        let pivot = u128::from(match before.last() {
+            Some(entry) => entry.label,
+            None => rest.head().unwrap().label,
+        });
+        let mut density = 1.0;
+        for bits in 1..=64 {
+            density *= 4.0 / 3.0;
+            let start = pivot >> bits << bits;
+            let end = start + (1 << bits);
+            let (kept_before, moved_before) = before.split(|span| u128::from(span.label) >= start);
+            let (moved_rest, kept_rest) = rest.split(|span| u128::from(span.label) >= end);
+            let count = moved_before.measure().elements + 1 + moved_rest.measure().elements;
+            if count as f64 > density && bits < 64 {
+                continue;
+            }
+            let step = (end - start) / (count as u128 + 1);
+            let entries = moved_before
+                .iter()
+                .chain(once(Arc::new(entry)))
+                .chain(moved_rest.iter());
+            let mut moved = FingerTree::new();
+            for (index, entry) in entries.enumerate() {
+                let label = (start + (index as u128 + 1) * step) as u64;
+                self.labels.insert_mut(entry.id.clone(), label);
+                moved.push_back_mut(Place {
+                    id: entry.id.clone(),
+                    label,
+                    value: entry.value.clone(),
+                });
+            }
+            self.order = kept_before.append(moved).append(kept_rest);
+            return;
+        }
+    }
+
+    // Merge in another state's version of an element, adding it if
+    // it's new, or removing it if the other state has removed it.
+    fn absorb(&mut self, id: Arc<Dot<R>>, element: Arc<Element<A, R>>) {
+        match self.elements.get(&*id) {
+            None => {
+                self.clock = self.clock.max(id.counter);
+                self.elements.insert_mut(id.clone(), element);
+                self.place((*id).clone());
+            }
+            Some(ref old) if old.value.is_some() && element.value.is_none() => {
+                self.elements.insert_mut(id.clone(), element);
+                if let Some(label) = self.labels.get(&*id) {
+                    let (before, rest) = self.order.split(|span| span.label >= *label);
+                    self.bury(before, rest);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Turn the first element of `rest` into a tombstone, and put the
+    // order back together.
+    fn bury(&mut self, before: Order<A, R>, mut rest: Order<A, R>) {
+        let entry = rest.pop_front_mut().unwrap();
+        let tombstone = Place {
+            id: entry.id.clone(),
+            label: entry.label,
+            value: None,
+        };
+        self.order = before.push_back(tombstone).append(rest);
+    }
+}
+
+impl<A: PartialEq, R: Ord + Clone> Crdt for Rga<A, R> {
+    fn merge_mut(&mut self, other: &Self) {
+        // A delta shares no nodes with the state it's merged into, so
+        // diffing the two would visit the whole state. If the other
+        // state is small enough, it's cheaper to look up each of its
+        // elements instead.
+        let size = self.elements.len();
+        let depth = (0usize.leading_zeros() - size.leading_zeros()) as usize;
+        if other.elements.len() * depth < size {
+            for (id, element) in other.elements.iter() {
+                self.absorb(id, element);
+            }
+            return;
+        }
+        for change in self.elements.diff(&other.elements) {
+            match change {
+                DiffItem::Add((id, element))
+                | DiffItem::Update {
+                    new: (id, element), ..
+                } => self.absorb(id, element),
+                DiffItem::Remove(_) => {}
+            }
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let mut elements = OrdMap::new();
+        for change in since.elements.diff(&self.elements) {
+            match change {
+                DiffItem::Add((id, element)) => elements.insert_mut(id, element),
+                DiffItem::Update {
+                    old: (_, old),
+                    new: (id, new),
+                } => if old.value.is_some() && new.value.is_none() {
+                    elements.insert_mut(id, new)
+                },
+                DiffItem::Remove(_) => {}
+            }
+        }
+        Rga::from_elements(elements)
+    }
+}
+
+/// An iterator over the elements of an [`Rga`][Rga].
+///
+/// [Rga]: ./struct.Rga.html
+pub struct RgaIter<A, R> {
+    entries: fingertree::Iter<Place<A, R>, Spans>,
+}
+
+impl<A, R: Ord> Iterator for RgaIter<A, R> {
+    type Item = Arc<A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.entries.next()?;
+            if let Some(ref value) = entry.value {
+                return Some(value.clone());
+            }
+        }
+    }
+}
+
+// Core traits
+
+impl<R> Clone for GCounter<R> {
+    fn clone(&self) -> Self {
+        GCounter {
+            counts: self.counts.clone(),
+        }
+    }
+}
+
+impl<R> Default for GCounter<R> {
+    fn default() -> Self {
+        GCounter::new()
+    }
+}
+
+impl<R: Ord> PartialEq for GCounter<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.counts == other.counts
+    }
+}
+
+impl<R: Ord> Eq for GCounter<R> {}
+
+impl<R: Ord + Debug> Debug for GCounter<R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_tuple("GCounter").field(&self.counts).finish()
+    }
+}
+
+impl<R> Clone for PNCounter<R> {
+    fn clone(&self) -> Self {
+        PNCounter {
+            increments: self.increments.clone(),
+            decrements: self.decrements.clone(),
+        }
+    }
+}
+
+impl<R> Default for PNCounter<R> {
+    fn default() -> Self {
+        PNCounter::new()
+    }
+}
+
+impl<R: Ord> PartialEq for PNCounter<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.increments == other.increments && self.decrements == other.decrements
+    }
+}
+
+impl<R: Ord> Eq for PNCounter<R> {}
+
+impl<R: Ord + Debug> Debug for PNCounter<R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_struct("PNCounter")
+            .field("increments", &self.increments.counts)
+            .field("decrements", &self.decrements.counts)
+            .finish()
+    }
+}
+
+impl<A, R: Clone> Clone for LwwRegister<A, R> {
+    fn clone(&self) -> Self {
+        LwwRegister {
+            entry: self.entry.clone(),
+        }
+    }
+}
+
+impl<A, R> Default for LwwRegister<A, R> {
+    fn default() -> Self {
+        LwwRegister::new()
+    }
+}
+
+impl<A: PartialEq, R: PartialEq> PartialEq for LwwRegister<A, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entry == other.entry
+    }
+}
+
+impl<A: Eq, R: Eq> Eq for LwwRegister<A, R> {}
+
+impl<A: Debug, R: Debug> Debug for LwwRegister<A, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self.entry {
+            None => write!(f, "LwwRegister"),
+            Some((ref stamp, ref value)) => write!(f, "LwwRegister({:?} at {:?})", value, stamp),
+        }
+    }
+}
+
+impl<A, R> Clone for OrSet<A, R> {
+    fn clone(&self) -> Self {
+        OrSet {
+            entries: self.entries.clone(),
+            removed: self.removed.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl<A: Hash + Eq, R: Ord + Hash + Clone> Default for OrSet<A, R> {
+    fn default() -> Self {
+        OrSet::new()
+    }
+}
+
+impl<A: Hash + Eq, R: Ord + Hash> PartialEq for OrSet<A, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries && self.removed == other.removed && self.clock == other.clock
+    }
+}
+
+impl<A: Hash + Eq, R: Ord + Hash> Eq for OrSet<A, R> {}
+
+impl<A: Hash + Eq + Debug, R: Ord + Hash + Debug> Debug for OrSet<A, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_struct("OrSet")
+            .field("entries", &self.entries)
+            .field("removed", &self.removed)
+            .field("clock", &self.clock.counts)
+            .finish()
+    }
+}
+
+impl<K, V, R> Clone for LwwMap<K, V, R> {
+    fn clone(&self) -> Self {
+        LwwMap {
+            entries: self.entries.clone(),
+            clock: self.clock,
+            size: self.size,
+        }
+    }
+}
+
+impl<K, V, R> Default for LwwMap<K, V, R> {
+    fn default() -> Self {
+        LwwMap::new()
+    }
+}
+
+// The clock only decides the stamps of future writes, so two maps with
+// the same entries are equal whatever their clocks say.
+impl<K: Ord, V: PartialEq, R: PartialEq> PartialEq for LwwMap<K, V, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl<K: Ord, V: Eq, R: Eq> Eq for LwwMap<K, V, R> {}
+
+impl<K: Ord + Debug, V: Debug, R: Debug> Debug for LwwMap<K, V, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_tuple("LwwMap").field(&self.entries).finish()
+    }
+}
+
+impl<A, R> Clone for Rga<A, R> {
+    fn clone(&self) -> Self {
+        Rga {
+            elements: self.elements.clone(),
+            order: self.order.clone(),
+            labels: self.labels.clone(),
+            waiting: self.waiting.clone(),
+            clock: self.clock,
+        }
+    }
+}
+
+impl<A, R> Default for Rga<A, R> {
+    fn default() -> Self {
+        Rga::new()
+    }
+}
+
+// The order of the elements follows from the elements themselves.
+impl<A: PartialEq, R: Ord> PartialEq for Rga<A, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.elements == other.elements
+    }
+}
+
+impl<A: Eq, R: Ord> Eq for Rga<A, R> {}
+
+impl<A: Debug, R: Ord + Clone + Debug> Debug for Rga<A, R> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::bool;
+    use proptest::collection::{vec, VecStrategy};
+    use proptest::num::{i16, u8};
+    use proptest::strategy::Strategy;
+
+    // A CRDT which can replay a test's operations on a replica.
+    trait Replay: Crdt + PartialEq + Debug {
+        type Op: Debug;
+
+        fn replay(&mut self, op: &Self::Op, replica: u8) -> Self;
+    }
+
+    impl Replay for GCounter<u8> {
+        type Op = u8;
+
+        fn replay(&mut self, amount: &u8, replica: u8) -> Self {
+            self.increment_by(replica, u64::from(*amount))
+        }
+    }
+
+    impl Replay for PNCounter<u8> {
+        type Op = (bool, u8);
+
+        fn replay(&mut self, &(up, amount): &(bool, u8), replica: u8) -> Self {
+            if up {
+                self.increment_by(replica, u64::from(amount))
+            } else {
+                self.decrement_by(replica, u64::from(amount))
+            }
+        }
+    }
+
+    impl Replay for LwwRegister<i16, u8> {
+        type Op = i16;
+
+        fn replay(&mut self, value: &i16, replica: u8) -> Self {
+            self.set(*value, replica)
+        }
+    }
+
+    impl Replay for OrSet<u8, u8> {
+        type Op = (bool, u8);
+
+        fn replay(&mut self, &(insert, value): &(bool, u8), replica: u8) -> Self {
+            if insert {
+                self.insert(value % 8, replica)
+            } else {
+                self.remove(&(value % 8))
+            }
+        }
+    }
+
+    impl Replay for LwwMap<u8, i16, u8> {
+        type Op = (bool, u8, i16);
+
+        fn replay(&mut self, &(insert, key, value): &(bool, u8, i16), replica: u8) -> Self {
+            if insert {
+                self.insert(key % 8, value, replica)
+            } else {
+                self.remove(key % 8, replica)
+            }
+        }
+    }
+
+    impl Replay for Rga<i16, u8> {
+        type Op = (bool, u8, i16);
+
+        fn replay(&mut self, &(insert, index, value): &(bool, u8, i16), replica: u8) -> Self {
+            let index = index as usize;
+            if insert || self.is_empty() {
+                self.insert(index % (self.len() + 1), value, replica)
+            } else {
+                let index = index % self.len();
+                self.remove(index)
+            }
+        }
+    }
+
+    // Play operations on a replica, checking that the deltas they
+    // return add up to the same changes.
+    fn play<C: Replay>(start: &C, ops: &[C::Op], replica: u8) -> C {
+        let mut state = start.clone();
+        let mut deltas = start.clone();
+        for op in ops {
+            let delta = state.replay(op, replica);
+            deltas.merge_mut(&delta);
+        }
+        assert_eq!(state, deltas);
+        state
+    }
+
+    // Build three replicas from a common history, and check that
+    // merging them follows the laws.
+    fn check_laws<C: Replay + Default>(base: &[C::Op], ops: &[Vec<C::Op>]) {
+        let base = play(&C::default(), base, 3);
+        let a = play(&base, &ops[0], 0);
+        let b = play(&base, &ops[1], 1);
+        let c = play(&base, &ops[2], 2);
+        assert_eq!(a.merge(&b), b.merge(&a));
+        assert_eq!(a.merge(&b).merge(&c), a.merge(&b.merge(&c)));
+        assert_eq!(a, a.merge(&a));
+        assert_eq!(a.merge(&b), a.merge(&b.delta(&a)));
+        assert_eq!(a, base.merge(&a.delta(&base)));
+        assert_eq!(a.merge(&b), a.delta(&b).merge(&b));
+    }
+
+    // Check that a sequence's order, built up one change at a time, is
+    // the one it would have been given all at once, and that its
+    // labels grow along it.
+    fn assert_rebuilds(rga: &Rga<i16, u8>) {
+        let rebuilt = Rga::from_elements(rga.elements.clone());
+        let ids = |rga: &Rga<i16, u8>| {
+            rga.order
+                .iter()
+                .map(|entry| entry.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&rebuilt), ids(rga));
+        assert!(rga.iter().eq(rebuilt.iter()));
+        assert_eq!(rebuilt.len(), rga.len());
+        assert_eq!(rebuilt.waiting, rga.waiting);
+        let labels: Vec<_> = rga.order.iter().map(|entry| entry.label).collect();
+        assert!(labels.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(labels.len(), rga.labels.len());
+        for entry in rga.order.iter() {
+            assert_eq!(Some(Arc::new(entry.label)), rga.labels.get(&entry.id));
+        }
+    }
+
+    // A common history, and the operations each replica plays on it.
+    fn ops<S: Strategy + Clone>(op: S) -> (VecStrategy<S>, VecStrategy<VecStrategy<S>>) {
+        (vec(op.clone(), 0..16), vec(vec(op, 0..16), 3))
+    }
+
+    #[test]
+    fn counters_count() {
+        let mut left = PNCounter::new();
+        let mut right = PNCounter::new();
+        left.increment_by('a', 5);
+        right.decrement_by('b', 2);
+        let delta = right.increment('b');
+        left.merge_mut(&delta);
+        assert_eq!(6, left.value());
+        assert_eq!(4, left.merge(&right).value());
+    }
+
+    #[test]
+    fn or_set_insert_wins() {
+        let mut left = OrSet::new();
+        left.insert("x", 1);
+        let mut right = left.clone();
+        let removed = left.remove("x");
+        right.insert("x", 2);
+        let merged = left.merge(&right);
+        assert!(merged.contains("x"));
+        assert_eq!(merged, right.merge(&removed));
+        let mut both = merged.clone();
+        let delta = both.remove("x");
+        assert!(!both.contains("x"));
+        assert!(!delta.merge(&left).merge(&right).contains("x"));
+    }
+
+    #[test]
+    fn lww_map_keeps_latest() {
+        let mut left = LwwMap::new();
+        left.insert(1, "a", 'l');
+        let mut right = left.clone();
+        right.insert(1, "b", 'r');
+        right.insert(1, "c", 'r');
+        left.insert(2, "d", 'l');
+        let merged = left.merge(&right);
+        assert_eq!(2, merged.len());
+        assert_eq!(
+            vec![(1, "c"), (2, "d")],
+            merged
+                .iter()
+                .map(|(key, value)| (*key, *value))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rga_keeps_concurrent_inserts_together() {
+        let mut left = Rga::new();
+        left.insert(0, 'x', 1);
+        let mut right = left.clone();
+        left.insert(1, 'a', 1);
+        left.insert(2, 'b', 1);
+        right.insert(1, 'c', 2);
+        right.insert(2, 'd', 2);
+        let merged = left.merge(&right);
+        assert_eq!(merged, right.merge(&left));
+        let text: String = merged.iter().map(|c| *c).collect();
+        assert!(text == "xabcd" || text == "xcdab", "interleaved: {}", text);
+    }
+
+    #[test]
+    fn rga_relabels_crowded_inserts() {
+        let mut rga = Rga::new();
+        let mut copy = Rga::new();
+        for i in 0..1000 {
+            // Always typing in the same place, in the middle, and at
+            // the end runs out of room between labels quickly.
+            let index = match i % 3 {
+                0 => rga.len().min(1),
+                1 => rga.len() / 2,
+                _ => rga.len(),
+            };
+            copy.merge_mut(&rga.insert(index, i, 0));
+        }
+        assert_eq!(1000, rga.len());
+        assert_rebuilds(&rga);
+        assert_rebuilds(&copy);
+        assert!(rga.iter().eq(copy.iter()));
+        for (index, value) in rga.iter().enumerate() {
+            assert_eq!(Some(value), rga.get(index));
+        }
+        assert_eq!(None, rga.get(1000));
+    }
+
+    proptest! {
+        #[test]
+        fn g_counter_laws((ref base, ref ops) in ops(u8::ANY)) {
+            check_laws::<GCounter<u8>>(base, ops);
+        }
+
+        #[test]
+        fn pn_counter_laws((ref base, ref ops) in ops((bool::ANY, u8::ANY))) {
+            check_laws::<PNCounter<u8>>(base, ops);
+        }
+
+        #[test]
+        fn lww_register_laws((ref base, ref ops) in ops(i16::ANY)) {
+            check_laws::<LwwRegister<i16, u8>>(base, ops);
+        }
+
+        #[test]
+        fn or_set_laws((ref base, ref ops) in ops((bool::ANY, u8::ANY))) {
+            check_laws::<OrSet<u8, u8>>(base, ops);
+        }
+
+        #[test]
+        fn lww_map_laws((ref base, ref ops) in ops((bool::ANY, u8::ANY, i16::ANY))) {
+            check_laws::<LwwMap<u8, i16, u8>>(base, ops);
+        }
+
+        #[test]
+        fn rga_laws((ref base, ref ops) in ops((bool::ANY, u8::ANY, i16::ANY))) {
+            check_laws::<Rga<i16, u8>>(base, ops);
+        }
+
+        #[test]
+        fn rga_matches_rebuild((ref base, ref ops) in ops((bool::ANY, u8::ANY, i16::ANY))) {
+            let base = play(&Rga::default(), base, 3);
+            let a = play(&base, &ops[0], 0);
+            let b = play(&base, &ops[1], 1);
+            assert_rebuilds(&a);
+            assert_rebuilds(&a.merge(&b));
+            assert_rebuilds(&base.merge(&a.delta(&base)));
+            // The delta's elements wait for the ones they were
+            // inserted after until `b` brings them.
+            assert_rebuilds(&a.delta(&b));
+            assert_rebuilds(&a.delta(&b).merge(&b));
+        }
+    }
+}
@@ -0,0 +1,285 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Drawing the internal structure of collections.
+//!
+//! This module is only available with the `dot` feature. It renders
+//! the nodes collections are built from as a [Graphviz][graphviz]
+//! graph in the DOT language, to help see how a tree is shaped and
+//! which parts of it are shared with other versions of it.
+//!
+//! Every node is labelled with how full it is, and shaded darker the
+//! fuller it gets. A [`Dot`][Dot] draws each node only once, however
+//! many of the collections added to it share it, so the nodes two
+//! versions have in common show up with edges from both of them.
+//!
+//! [`ToDot`][ToDot] is implemented for [`Vector`][vector::Vector],
+//! [`OrdMap`][ordmap::OrdMap], [`OrdSet`][ordset::OrdSet],
+//! [`HashMap`][hashmap::HashMap] and [`HashSet`][hashset::HashSet].
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::ordmap::OrdMap;
+//! # use im::dot::Dot;
+//! # fn main() {
+//! let map: OrdMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+//! let updated = map.insert(1000, 1000);
+//! let graph = Dot::new().add("before", &map).add("after", &updated).render();
+//! assert!(graph.starts_with("digraph im {"));
+//! # }
+//! ```
+//!
+//! The result can be turned into an image with Graphviz's `dot`
+//! command, for instance `dot -Tsvg graph.dot > graph.svg`.
+//!
+//! [graphviz]: https://www.graphviz.org/
+//! [Dot]: ./struct.Dot.html
+//! [ToDot]: ./trait.ToDot.html
+//! [vector::Vector]: ../vector/struct.Vector.html
+//! [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+//! [ordset::OrdSet]: ../ordset/struct.OrdSet.html
+//! [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+//! [hashset::HashSet]: ../hashset/struct.HashSet.html
+
+use std::collections::BTreeSet;
+use std::fmt::{Arguments, Write};
+use std::marker::PhantomData;
+use std::string::String;
+use std::sync::Arc;
+
+/// A graph of the nodes of the collections added to it, where nodes
+/// shared between them are only drawn once.
+///
+/// Nodes are told apart by their addresses, so a graph borrows every
+/// collection added to it for as long as it lives, which keeps a
+/// dropped collection's nodes from being mistaken for those of a new
+/// one allocated in their place.
+///
+/// ```compile_fail,E0716
+/// # #[macro_use] extern crate im;
+/// # use im::dot::Dot;
+/// # fn main() {
+/// let mut dot = Dot::new();
+/// dot.add("first", &vector![1, 2, 3]);
+/// dot.add("second", &vector![4, 5, 6]);
+/// println!("{}", dot.render());
+/// # }
+/// ```
+#[derive(Default)]
+pub struct Dot<'a> {
+    // The addresses of the nodes drawn so far.
+    seen: BTreeSet<usize>,
+    collections: usize,
+    body: String,
+    added: PhantomData<&'a ()>,
+}
+
+impl<'a> Dot<'a> {
+    /// Construct an empty graph.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Draw a collection, labelled with `name`, along with whichever
+    /// of its nodes haven't been drawn already.
+    pub fn add<C>(&mut self, name: &str, collection: &'a C) -> &mut Self
+    where
+        C: ToDot + ?Sized,
+    {
+        let id = format!("c{}", self.collections);
+        self.collections += 1;
+        let mut label = escape(name);
+        if !label.is_empty() {
+            label.push_str("\\n");
+        }
+        self.line(format_args!(
+            "{} [label=\"{}len {}\", shape=box, style=bold];",
+            id,
+            label,
+            collection.dot_len()
+        ));
+        collection.write_dot(self, &id);
+        self
+    }
+
+    /// Render the graph in the DOT language.
+    pub fn render(&self) -> String {
+        format!(
+            "digraph im {{\n    node [shape=ellipse, fontname=monospace];\n{}}}\n",
+            self.body
+        )
+    }
+
+    // Write a line of the graph's body.
+    fn line(&mut self, line: Arguments) {
+        self.body.push_str("    ");
+        self.body.write_fmt(line).unwrap();
+        self.body.push('\n');
+    }
+
+    /// Draw an edge from `from` to the node behind `node`, labelled
+    /// with `label` if it isn't empty. Returns the node's id if it
+    /// hasn't been drawn yet, in which case the caller should give it
+    /// a [`label`][label] and draw its own edges.
+    ///
+    /// [label]: #method.label
+    pub(crate) fn edge<A>(&mut self, from: &str, label: &str, node: &Arc<A>) -> Option<String> {
+        let address = &**node as *const A as usize;
+        let id = format!("n{:x}", address);
+        if label.is_empty() {
+            self.line(format_args!("{} -> {};", from, id));
+        } else {
+            self.line(format_args!("{} -> {} [label=\"{}\"];", from, id, label));
+        }
+        if self.seen.insert(address) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    /// Label the node `id` with `text`, and shade it by how full it
+    /// is, holding `used` entries out of `capacity`.
+    pub(crate) fn label(&mut self, id: &str, text: &str, used: usize, capacity: usize) {
+        self.line(format_args!(
+            "{} [label=\"{}\\n{}/{}\", style=filled, fillcolor=\"/blues9/{}\"];",
+            id,
+            text,
+            used,
+            capacity,
+            shade(used, capacity)
+        ));
+    }
+}
+
+// The colour from the nine shades of the `blues9` scheme for a node
+// filled to `used` out of `capacity`, where 1 is the lightest.
+fn shade(used: usize, capacity: usize) -> usize {
+    let eighths = (used.min(capacity) * 8 + capacity / 2).checked_div(capacity);
+    1 + eighths.unwrap_or(0)
+}
+
+// Escape a string for use inside a quoted DOT label.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Collections whose internal structure can be drawn.
+pub trait ToDot {
+    /// The number of elements in the collection, for its label.
+    #[doc(hidden)]
+    fn dot_len(&self) -> usize;
+
+    /// Draw the collection's nodes into `dot`, with edges from the
+    /// node `from`.
+    #[doc(hidden)]
+    fn write_dot(&self, dot: &mut Dot, from: &str);
+
+    /// Render this collection's internal structure in the DOT
+    /// language.
+    fn to_dot(&self) -> String {
+        Dot::new().add("", self).render()
+    }
+
+    /// Render the internal structure of a list of collections in the
+    /// DOT language, drawing the nodes they share only once.
+    fn shared_to_dot(collections: &[&Self]) -> String
+    where
+        Self: Sized,
+    {
+        let mut dot = Dot::new();
+        for (index, collection) in collections.iter().enumerate() {
+            dot.add(&format!("{}", index), *collection);
+        }
+        dot.render()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use footprint::HeapSize;
+    use hashmap::HashMap;
+    use hashset::HashSet;
+    use ordmap::OrdMap;
+    use ordset::OrdSet;
+    use vector::Vector;
+
+    // The number of distinct nodes in a graph, not counting the
+    // collections themselves.
+    fn nodes(graph: &str) -> usize {
+        graph
+            .lines()
+            .filter(|line| line.contains("style=filled"))
+            .count()
+    }
+
+    #[test]
+    fn draws_every_collection() {
+        let vector: Vector<i32> = (0..1000).collect();
+        let ordmap: OrdMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+        let ordset: OrdSet<i32> = (0..1000).collect();
+        let hashmap: HashMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+        let hashset: HashSet<i32> = (0..1000).collect();
+        for graph in &[
+            vector.to_dot(),
+            ordmap.to_dot(),
+            ordset.to_dot(),
+            hashmap.to_dot(),
+            hashset.to_dot(),
+        ] {
+            assert!(graph.starts_with("digraph im {\n"));
+            assert!(graph.ends_with("}\n"));
+            assert!(nodes(graph) > 1);
+        }
+        assert!(vector.to_dot().contains("len 1000"));
+    }
+
+    #[test]
+    fn shared_nodes_are_drawn_once() {
+        let map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let updated = map.insert(5000, 0);
+        let single = nodes(&map.to_dot());
+        let both = nodes(&ToDot::shared_to_dot(&[&map, &updated]));
+        let depth = map.heap_stats().depth;
+        assert_eq!(single + depth, both);
+        assert_eq!(single, nodes(&ToDot::shared_to_dot(&[&map, &map.clone()])));
+
+        let vector: Vector<i32> = (0..10_000).collect();
+        let both = Dot::new()
+            .add("vector", &vector)
+            .add("vector", &vector.clone())
+            .render();
+        assert_eq!(nodes(&vector.to_dot()), nodes(&both));
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        let graph = Dot::new().add("a \"quoted\"\nname", &Vector::<i32>::new()).render();
+        assert!(graph.contains("label=\"a \\\"quoted\\\"\\nname\\nlen 0\""));
+    }
+
+    #[test]
+    fn fill_is_shaded() {
+        assert_eq!(1, shade(0, 16));
+        assert_eq!(5, shade(8, 16));
+        assert_eq!(9, shade(16, 16));
+        assert_eq!(1, shade(0, 0));
+    }
+}
@@ -0,0 +1,746 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A persistent finger tree.
+//!
+//! This is an implementation of [2-3 finger trees][fingertree], a
+//! general purpose sequence which offers amortised O(1) access to
+//! both of its ends, O(log min(n, m)) concatenation, and O(log n)
+//! splitting at any point.
+//!
+//! Where the tree splits is decided by a [`Measure`][Measure]: every
+//! node in the tree caches the combined measure of the elements
+//! beneath it, and a split happens at the first element at which a
+//! predicate over the accumulated measure becomes true. Measuring
+//! elements by their count, as [`Size`][Size] does, gives you a deque
+//! with O(log n) indexing; measuring them by their maximum gives you
+//! a priority queue, and measuring them by a key in sorted order gives
+//! you an ordered sequence.
+//!
+//! [fingertree]: http://www.staff.city.ac.uk/~ross/papers/FingerTree.html
+//! [Measure]: ./trait.Measure.html
+//! [Size]: ./struct.Size.html
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, Sum};
+use std::ops::Add;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::{Footprint, HeapSize};
+use nodes::fingertree::{Node, Tree};
+use shared::Shared;
+
+pub use nodes::fingertree::Iter;
+
+/// A monoid of summaries over the elements of a
+/// [`FingerTree`][FingerTree].
+///
+/// `combine` must be associative, and `zero` must be its identity.
+///
+/// [FingerTree]: ./struct.FingerTree.html
+pub trait Measure<A> {
+    /// The type of the summary.
+    type Value: Clone;
+
+    /// The summary of an empty sequence.
+    fn zero() -> Self::Value;
+
+    /// Combine the summaries of two adjacent sequences.
+    fn combine(left: &Self::Value, right: &Self::Value) -> Self::Value;
+
+    /// The summary of a single element.
+    fn measure(value: &A) -> Self::Value;
+}
+
+/// A [`Measure`][Measure] which counts the elements of a sequence.
+///
+/// [Measure]: ./trait.Measure.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Size;
+
+impl<A> Measure<A> for Size {
+    type Value = usize;
+
+    #[inline]
+    fn zero() -> usize {
+        0
+    }
+
+    #[inline]
+    fn combine(left: &usize, right: &usize) -> usize {
+        left + right
+    }
+
+    #[inline]
+    fn measure(_: &A) -> usize {
+        1
+    }
+}
+
+/// A persistent deque, supporting indexing and splitting in O(log n).
+pub type Deque<A> = FingerTree<A, Size>;
+
+/// A persistent finger tree of elements of type `A`, measured by `M`.
+///
+/// # Examples
+///
+/// A sequence which can find its largest element in O(1):
+///
+/// ```
+/// # extern crate im;
+/// # use im::fingertree::{FingerTree, Measure};
+/// # fn main() {
+/// struct Max;
+///
+/// impl Measure<u32> for Max {
+///     type Value = u32;
+///     fn zero() -> u32 { 0 }
+///     fn combine(left: &u32, right: &u32) -> u32 { *left.max(right) }
+///     fn measure(value: &u32) -> u32 { *value }
+/// }
+///
+/// let tree: FingerTree<u32, Max> = vec![3, 1, 4, 1, 5, 9, 2, 6].into_iter().collect();
+/// assert_eq!(9, tree.measure());
+///
+/// // Split at the first element which is at least 5.
+/// let (left, right) = tree.split(|max| *max >= 5);
+/// assert_eq!(4, left.measure());
+/// assert_eq!(Some(5), right.head().map(|v| *v));
+/// # }
+/// ```
+pub struct FingerTree<A, M: Measure<A> = Size> {
+    tree: Tree<A, M>,
+}
+
+impl<A, M: Measure<A>> FingerTree<A, M> {
+    /// Construct an empty finger tree.
+    #[inline]
+    pub fn new() -> Self {
+        FingerTree { tree: Tree::Empty }
+    }
+
+    /// Construct a finger tree with a single value.
+    #[inline]
+    pub fn singleton<R>(a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        FingerTree {
+            tree: Tree::Single(Node::Leaf(a.shared())),
+        }
+    }
+
+    /// Test whether a finger tree is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Test whether two trees refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a tree to
+    /// itself, or if you're comparing a tree to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.tree.ptr_eq(&other.tree)
+    }
+
+    /// Get the combined measure of every element in a finger tree.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn measure(&self) -> M::Value {
+        self.tree.measure()
+    }
+
+    /// Get an iterator over a finger tree.
+    ///
+    /// Time: O(1) per [`next()`][next] call, amortised
+    ///
+    /// [next]: https://doc.rust-lang.org/std/iter/trait.Iterator.html#tymethod.next
+    #[inline]
+    pub fn iter(&self) -> Iter<A, M> {
+        self.tree.iter()
+    }
+
+    /// Get the first element of a finger tree.
+    ///
+    /// If the tree is empty, `None` is returned.
+    ///
+    /// Time: O(1)
+    pub fn head(&self) -> Option<Arc<A>> {
+        self.tree.head().map(|node| node.unwrap_leaf().clone())
+    }
+
+    /// Get the last element of a finger tree.
+    ///
+    /// If the tree is empty, `None` is returned.
+    ///
+    /// Time: O(1)
+    pub fn last(&self) -> Option<Arc<A>> {
+        self.tree.last().map(|node| node.unwrap_leaf().clone())
+    }
+
+    /// Construct a finger tree with a new value prepended to the
+    /// front of the current tree.
+    ///
+    /// Time: O(1) amortised
+    pub fn push_front<R>(&self, a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        FingerTree {
+            tree: self.tree.push_front(Node::Leaf(a.shared())),
+        }
+    }
+
+    /// Prepend a value to the front of the current tree in place.
+    ///
+    /// Time: O(1) amortised
+    pub fn push_front_mut<R>(&mut self, a: R)
+    where
+        R: Shared<A>,
+    {
+        *self = self.push_front(a)
+    }
+
+    /// Construct a finger tree with a new value appended to the back
+    /// of the current tree.
+    ///
+    /// Time: O(1) amortised
+    pub fn push_back<R>(&self, a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        FingerTree {
+            tree: self.tree.push_back(Node::Leaf(a.shared())),
+        }
+    }
+
+    /// Append a value to the back of the current tree in place.
+    ///
+    /// Time: O(1) amortised
+    pub fn push_back_mut<R>(&mut self, a: R)
+    where
+        R: Shared<A>,
+    {
+        *self = self.push_back(a)
+    }
+
+    /// Get the first element of a finger tree, as well as the tree
+    /// with the first element removed.
+    ///
+    /// If the tree is empty, `None` is returned.
+    ///
+    /// Time: O(1) amortised
+    pub fn pop_front(&self) -> Option<(Arc<A>, Self)> {
+        self.tree
+            .pop_front()
+            .map(|(node, tree)| (node.unwrap_leaf().clone(), FingerTree { tree }))
+    }
+
+    /// Remove the first element of a finger tree in place and return
+    /// it.
+    ///
+    /// Time: O(1) amortised
+    pub fn pop_front_mut(&mut self) -> Option<Arc<A>> {
+        self.pop_front().map(|(value, tree)| {
+            *self = tree;
+            value
+        })
+    }
+
+    /// Get the last element of a finger tree, as well as the tree
+    /// with the last element removed.
+    ///
+    /// If the tree is empty, `None` is returned.
+    ///
+    /// Time: O(1) amortised
+    pub fn pop_back(&self) -> Option<(Arc<A>, Self)> {
+        self.tree
+            .pop_back()
+            .map(|(node, tree)| (node.unwrap_leaf().clone(), FingerTree { tree }))
+    }
+
+    /// Remove the last element of a finger tree in place and return
+    /// it.
+    ///
+    /// Time: O(1) amortised
+    pub fn pop_back_mut(&mut self) -> Option<Arc<A>> {
+        self.pop_back().map(|(value, tree)| {
+            *self = tree;
+            value
+        })
+    }
+
+    /// Append the finger tree `other` to the end of the current tree.
+    ///
+    /// Time: O(log min(n, m))
+    pub fn append<R>(&self, other: R) -> Self
+    where
+        R: Borrow<Self>,
+    {
+        FingerTree {
+            tree: Tree::concat(&self.tree, &[], &other.borrow().tree),
+        }
+    }
+
+    /// Split a finger tree in two at the first element where `pred`
+    /// becomes true for the measure of every element up to and
+    /// including it.
+    ///
+    /// The first tree contains every element before that one, and the
+    /// second tree starts with it. If `pred` is true for the measure
+    /// of the empty sequence, the first tree is empty; if it never
+    /// becomes true, the second tree is empty.
+    ///
+    /// `pred` should be monotonic: once it's true for a measure, it
+    /// should stay true as more elements are combined into it.
+    ///
+    /// Time: O(log n)
+    pub fn split<P>(&self, pred: P) -> (Self, Self)
+    where
+        P: Fn(&M::Value) -> bool,
+    {
+        if self.is_empty() {
+            return (FingerTree::new(), FingerTree::new());
+        }
+        if pred(&M::zero()) {
+            return (FingerTree::new(), self.clone());
+        }
+        if !pred(&self.measure()) {
+            return (self.clone(), FingerTree::new());
+        }
+        let (left, node, right) = self.tree.split(&pred, M::zero());
+        (
+            FingerTree { tree: left },
+            FingerTree {
+                tree: right.push_front(node),
+            },
+        )
+    }
+
+    /// Find the first element where `pred` becomes true for the
+    /// measure of every element up to and including it.
+    ///
+    /// Returns `None` if `pred` never becomes true.
+    ///
+    /// Time: O(log n)
+    pub fn find<P>(&self, pred: P) -> Option<Arc<A>>
+    where
+        P: Fn(&M::Value) -> bool,
+    {
+        if self.is_empty() || !pred(&self.measure()) {
+            return None;
+        }
+        let (_, node) = self.tree.lookup(&pred, M::zero());
+        Some(node.unwrap_leaf().clone())
+    }
+}
+
+impl<A> FingerTree<A, Size> {
+    /// Get the length of a deque.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.measure()
+    }
+
+    /// Get the value at index `index` in a deque.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn get(&self, index: usize) -> Option<Arc<A>> {
+        self.find(|size| *size > index)
+    }
+
+    /// Create a new deque with the value at index `index` updated.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn set<RA>(&self, index: usize, value: RA) -> Self
+    where
+        RA: Shared<A>,
+    {
+        assert!(
+            index < self.len(),
+            "index out of bounds: {} < {}",
+            index,
+            self.len()
+        );
+        let (left, right) = self.split_at(index);
+        let right = right.pop_front().unwrap().1.push_front(value);
+        left.append(right)
+    }
+
+    /// Split a deque at a given index, returning a deque containing
+    /// every element before the index and a deque containing every
+    /// element from the index onward.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate im;
+    /// # use im::fingertree::Deque;
+    /// # fn main() {
+    /// let deque: Deque<i32> = (0..100).collect();
+    /// let (left, right) = deque.split_at(40);
+    /// assert_eq!(40, left.len());
+    /// assert_eq!(Some(40), right.head().map(|v| *v));
+    /// assert_eq!(deque, left.append(right));
+    /// # }
+    /// ```
+    pub fn split_at(&self, index: usize) -> (Self, Self) {
+        self.split(|size| *size > index)
+    }
+
+    /// Construct a deque with `count` elements removed from the start
+    /// of the current deque.
+    ///
+    /// Time: O(log n)
+    pub fn skip(&self, count: usize) -> Self {
+        self.split_at(count).1
+    }
+
+    /// Construct a deque of the first `count` elements from the
+    /// current deque.
+    ///
+    /// Time: O(log n)
+    pub fn take(&self, count: usize) -> Self {
+        self.split_at(count).0
+    }
+}
+
+// Core traits
+
+impl<A, M: Measure<A>> Clone for FingerTree<A, M> {
+    #[inline]
+    fn clone(&self) -> Self {
+        FingerTree {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<A, M: Measure<A>> Default for FingerTree<A, M> {
+    #[inline]
+    fn default() -> Self {
+        FingerTree::new()
+    }
+}
+
+impl<A, M: Measure<A>> HeapSize for FingerTree<A, M> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.tree.measure_heap(footprint)
+    }
+}
+
+impl<A: Debug, M: Measure<A>> Debug for FingerTree<A, M> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<A: PartialEq, M: Measure<A>> PartialEq for FingerTree<A, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.iter().eq(other.iter())
+    }
+}
+
+impl<A: Eq, M: Measure<A>> Eq for FingerTree<A, M> {}
+
+impl<A: PartialOrd, M: Measure<A>> PartialOrd for FingerTree<A, M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<A: Ord, M: Measure<A>> Ord for FingerTree<A, M> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<A: Hash, M: Measure<A>> Hash for FingerTree<A, M> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for i in self {
+            i.hash(state)
+        }
+    }
+}
+
+impl<A, M: Measure<A>> Add for FingerTree<A, M> {
+    type Output = FingerTree<A, M>;
+
+    fn add(self, other: Self) -> Self::Output {
+        self.append(other)
+    }
+}
+
+impl<A, M: Measure<A>> Add for &FingerTree<A, M> {
+    type Output = FingerTree<A, M>;
+
+    fn add(self, other: Self) -> Self::Output {
+        self.append(other)
+    }
+}
+
+impl<A, M: Measure<A>> Sum for FingerTree<A, M> {
+    fn sum<I>(it: I) -> Self
+    where
+        I: Iterator<Item = Self>,
+    {
+        it.fold(Self::new(), |a, b| a + b)
+    }
+}
+
+impl<A, M: Measure<A>, R: Shared<A>> Extend<R> for FingerTree<A, M> {
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = R>,
+    {
+        for value in iter {
+            self.push_back_mut(value)
+        }
+    }
+}
+
+impl<A, M: Measure<A>, RA: Shared<A>> FromIterator<RA> for FingerTree<A, M> {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = RA>,
+    {
+        let mut tree = FingerTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<A, M: Measure<A>> IntoIterator for FingerTree<A, M> {
+    type Item = Arc<A>;
+    type IntoIter = Iter<A, M>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<A, M: Measure<A>> IntoIterator for &FingerTree<A, M> {
+    type Item = Arc<A>;
+    type IntoIter = Iter<A, M>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Conversions
+
+impl<A, M: Measure<A>> From<Vec<A>> for FingerTree<A, M> {
+    fn from(v: Vec<A>) -> Self {
+        v.into_iter().collect()
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Arbitrary + Sync> Arbitrary for Deque<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Deque::from_iter(Vec::<A>::arbitrary(g))
+    }
+}
+
+// Proptest
+
+#[cfg(any(test, feature = "proptest"))]
+pub mod proptest {
+    use super::*;
+    use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
+    use std::ops::Range;
+
+    /// A strategy for generating a deque of a certain size.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_a_deque(ref d in deque(".*", 10..100)) {
+    ///         assert!(d.len() < 100);
+    ///         assert!(d.len() >= 10);
+    ///     }
+    /// }
+    /// ```
+    pub fn deque<T: Strategy + 'static>(
+        element: T,
+        size: Range<usize>,
+    ) -> BoxedStrategy<Deque<<T::Value as ValueTree>::Value>> {
+        ::proptest::collection::vec(element, size)
+            .prop_map(Deque::from_iter)
+            .boxed()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::proptest::*;
+    use super::*;
+    use proptest::collection;
+    use proptest::num::{i32, usize};
+
+    struct Max;
+
+    impl Measure<i32> for Max {
+        type Value = Option<i32>;
+
+        fn zero() -> Option<i32> {
+            None
+        }
+
+        fn combine(left: &Option<i32>, right: &Option<i32>) -> Option<i32> {
+            match (*left, *right) {
+                (Some(l), Some(r)) => Some(l.max(r)),
+                (l, None) => l,
+                (None, r) => r,
+            }
+        }
+
+        fn measure(value: &i32) -> Option<i32> {
+            Some(*value)
+        }
+    }
+
+    fn to_vec<A: Clone, M: Measure<A>>(tree: &FingerTree<A, M>) -> Vec<A> {
+        tree.iter().map(|v| (*v).clone()).collect()
+    }
+
+    #[test]
+    fn ptr_eq() {
+        let tree: Deque<i32> = (0..100).collect();
+        assert!(tree.ptr_eq(&tree.clone()));
+        assert!(!tree.ptr_eq(&tree.push_back(100)));
+        assert!(!tree.ptr_eq(&(0..100).collect()));
+        assert!(Deque::<i32>::new().ptr_eq(&Deque::new()));
+    }
+
+    #[test]
+    fn empty_deque() {
+        let deque: Deque<i32> = Deque::new();
+        assert!(deque.is_empty());
+        assert_eq!(0, deque.len());
+        assert_eq!(None, deque.head());
+        assert_eq!(None, deque.last());
+        assert_eq!(None, deque.get(0));
+        assert_eq!(None, deque.pop_front());
+        assert_eq!((Deque::new(), Deque::new()), deque.split_at(0));
+    }
+
+    #[test]
+    fn large_deque() {
+        let deque: Deque<usize> = (0..10_000).collect();
+        assert_eq!(10_000, deque.len());
+        for i in (0..10_000).step_by(97) {
+            assert_eq!(Some(i), deque.get(i).map(|v| *v));
+        }
+        assert_eq!(None, deque.get(10_000));
+        assert_eq!(Some(0), deque.head().map(|v| *v));
+        assert_eq!(Some(9999), deque.last().map(|v| *v));
+        let set = deque.set(5000, 0);
+        assert_eq!(Some(0), set.get(5000).map(|v| *v));
+        assert_eq!(Some(5000), deque.get(5000).map(|v| *v));
+    }
+
+    #[test]
+    fn custom_measure() {
+        let tree: FingerTree<i32, Max> = vec![3, 1, 4, 1, 5, 9, 2, 6].into();
+        assert_eq!(Some(9), tree.measure());
+        assert_eq!(Some(5), tree.find(|m| *m >= Some(5)).map(|v| *v));
+        assert_eq!(None, tree.find(|m| *m >= Some(10)));
+        let (left, right) = tree.split(|m| *m >= Some(9));
+        assert_eq!(vec![3, 1, 4, 1, 5], to_vec(&left));
+        assert_eq!(vec![9, 2, 6], to_vec(&right));
+        assert_eq!(Some(6), right.pop_front().unwrap().1.measure());
+    }
+
+    proptest! {
+        #[test]
+        fn ends_match_vec(ref ops in collection::vec((0..4u8, i32::ANY), 0..500)) {
+            let mut deque = Deque::new();
+            let mut model = ::std::collections::VecDeque::new();
+            for &(op, value) in ops {
+                match op {
+                    0 => {
+                        deque.push_front_mut(value);
+                        model.push_front(value);
+                    }
+                    1 => {
+                        deque.push_back_mut(value);
+                        model.push_back(value);
+                    }
+                    2 => assert_eq!(model.pop_front(), deque.pop_front_mut().map(|v| *v)),
+                    _ => assert_eq!(model.pop_back(), deque.pop_back_mut().map(|v| *v)),
+                }
+                assert_eq!(model.len(), deque.len());
+                assert_eq!(model.front(), deque.head().as_ref().map(|v| &**v));
+                assert_eq!(model.back(), deque.last().as_ref().map(|v| &**v));
+            }
+            let expected: Vec<i32> = model.into_iter().collect();
+            assert_eq!(expected, to_vec(&deque));
+        }
+
+        #[test]
+        fn split_and_append(ref deque in deque(i32::ANY, 0..300), index in usize::ANY) {
+            let vec = to_vec(deque);
+            let index = index % (vec.len() + 1);
+            let (left, right) = deque.split_at(index);
+            assert_eq!(&vec[..index], &to_vec(&left)[..]);
+            assert_eq!(&vec[index..], &to_vec(&right)[..]);
+            assert_eq!(*deque, left.append(&right));
+            assert_eq!(*deque, right.append(&left).skip(vec.len() - index).append(right.take(vec.len() - index)));
+            for (i, value) in vec.iter().enumerate() {
+                assert_eq!(Some(value), deque.get(i).as_ref().map(|v| &**v));
+            }
+        }
+
+        #[test]
+        fn append_many(ref deques in collection::vec(deque(i32::ANY, 0..50), 0..20)) {
+            let appended: Deque<i32> = deques.iter().cloned().sum();
+            let expected: Vec<i32> = deques.iter().flat_map(|d| to_vec(d)).collect();
+            assert_eq!(expected.len(), appended.len());
+            assert_eq!(expected, to_vec(&appended));
+        }
+
+        #[test]
+        fn max_measure(ref values in collection::vec(i32::ANY, 1..300), bound in i32::ANY) {
+            let tree: FingerTree<i32, Max> = values.clone().into();
+            assert_eq!(values.iter().cloned().max(), tree.measure());
+            let position = values.iter().position(|v| *v >= bound);
+            let (left, right) = tree.split(|m| *m >= Some(bound));
+            let position = position.unwrap_or_else(|| values.len());
+            assert_eq!(&values[..position], &to_vec(&left)[..]);
+            assert_eq!(&values[position..], &to_vec(&right)[..]);
+            assert_eq!(values.get(position), tree.find(|m| *m >= Some(bound)).as_ref().map(|v| &**v));
+        }
+    }
+}
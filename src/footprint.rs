@@ -0,0 +1,390 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Measuring how much memory collections use.
+//!
+//! Persistent collections share most of their structure between
+//! versions, so the memory a set of collections takes up together is
+//! usually much less than the sum of their sizes. A
+//! [`Footprint`][Footprint] walks the nodes of every collection added
+//! to it, and counts each node, and each `Arc` holding an element,
+//! only the first time it comes across it, so it reports the memory
+//! the collections actually occupy between them.
+//!
+//! [`HeapSize`][HeapSize] is implemented for all of the core
+//! collections, and offers shortcuts for measuring a single
+//! collection, or a list of collections of the same type.
+//!
+//! Only memory allocated by the collections themselves is counted:
+//! the nodes, and the `Arc`s they keep their elements in. Any heap
+//! memory owned by the elements, such as the contents of a `String`,
+//! isn't included, and neither is the hasher of a hash collection.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::ordmap::OrdMap;
+//! # use im::footprint::HeapSize;
+//! # fn main() {
+//! let map: OrdMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+//! let updated = map.insert(1000, 1000);
+//! let both = HeapSize::shared_heap_size(&[&map, &updated]);
+//! assert!(both < map.heap_size() + updated.heap_size());
+//! # }
+//! ```
+//!
+//! [Footprint]: ./struct.Footprint.html
+//! [HeapSize]: ./trait.HeapSize.html
+
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// Statistics about the memory used by one or more collections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct HeapStats {
+    /// The number of distinct nodes.
+    pub nodes: usize,
+    /// The number of distinct nodes without any child nodes.
+    pub leaves: usize,
+    /// The number of elements held in leaves.
+    pub leaf_values: usize,
+    /// The number of elements the leaves have room for.
+    pub leaf_capacity: usize,
+    /// The most nodes on any path from a root down to a leaf.
+    pub depth: usize,
+    /// The number of bytes allocated for the nodes and for the `Arc`s
+    /// holding the elements.
+    pub bytes: usize,
+}
+
+impl HeapStats {
+    /// The proportion of the room in the leaves which is in use,
+    /// between `0.0` and `1.0`, or `1.0` if there are no leaves.
+    pub fn leaf_fill(&self) -> f64 {
+        if self.leaf_capacity == 0 {
+            1.0
+        } else {
+            self.leaf_values as f64 / self.leaf_capacity as f64
+        }
+    }
+}
+
+/// A running count of the memory used by the collections added to it,
+/// where memory shared between them is only counted once.
+///
+/// Nodes are told apart by their addresses, so a footprint borrows
+/// every collection added to it for as long as it lives: otherwise a
+/// collection could be dropped and a new one allocated in its place,
+/// and the new one's nodes would be taken for ones already counted.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::footprint::Footprint;
+/// # fn main() {
+/// let set = ordset![1, 2, 3];
+/// let list = vector![4, 5, 6];
+/// let mut footprint = Footprint::new();
+/// footprint.add(&set).add(&list);
+/// let both = footprint.stats();
+/// // A clone shares all of its memory with the original.
+/// let clone = set.clone();
+/// footprint.add(&clone);
+/// assert_eq!(both, footprint.stats());
+/// # }
+/// ```
+///
+/// A collection can't be dropped while a footprint it was added to is
+/// still in use:
+///
+/// ```compile_fail,E0716
+/// # #[macro_use] extern crate im;
+/// # use im::footprint::Footprint;
+/// # fn main() {
+/// let mut footprint = Footprint::new();
+/// footprint.add(&vector![1, 2, 3]);
+/// footprint.add(&vector![4, 5, 6]);
+/// println!("{:?}", footprint.stats());
+/// # }
+/// ```
+#[derive(Default)]
+pub struct Footprint<'a> {
+    // The height of each node seen so far, by address. Elements are
+    // recorded with a height of zero.
+    seen: BTreeMap<usize, usize>,
+    stats: HeapStats,
+    collections: PhantomData<&'a ()>,
+}
+
+impl<'a> Footprint<'a> {
+    /// Construct an empty footprint.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Count the memory used by a collection which hasn't been
+    /// counted already.
+    pub fn add<C>(&mut self, collection: &'a C) -> &mut Self
+    where
+        C: HeapSize + ?Sized,
+    {
+        let depth = collection.measure_heap(self);
+        self.stats.depth = max(self.stats.depth, depth);
+        self
+    }
+
+    /// The statistics for everything added so far.
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    // The size of the allocation behind an `Arc<A>`, including its
+    // reference counts.
+    fn arc_size<A>() -> usize {
+        2 * size_of::<usize>() + size_of::<A>()
+    }
+
+    // The address identifying an `Arc`'s allocation.
+    fn address<A>(arc: &Arc<A>) -> usize {
+        &**arc as *const A as usize
+    }
+
+    /// Count a node, unless it's been counted before, calling `walk`
+    /// to count whatever it owns, which returns the node's height.
+    pub(crate) fn node<A, F>(&mut self, node: &Arc<A>, walk: F) -> usize
+    where
+        F: FnOnce(&mut Self) -> usize,
+    {
+        let address = Footprint::address(node);
+        if let Some(height) = self.seen.get(&address) {
+            return *height;
+        }
+        self.stats.nodes += 1;
+        self.stats.bytes += Footprint::arc_size::<A>();
+        let height = walk(self);
+        self.seen.insert(address, height);
+        height
+    }
+
+    /// Count the `Arc` holding an element, unless it's been counted
+    /// before. Returns whether it's new.
+    pub(crate) fn value<A>(&mut self, value: &Arc<A>) -> bool {
+        let address = Footprint::address(value);
+        if self.seen.contains_key(&address) {
+            return false;
+        }
+        self.seen.insert(address, 0);
+        self.stats.bytes += Footprint::arc_size::<A>();
+        true
+    }
+
+    /// Count the buffer of a `Vec` with room for `capacity` `A`s.
+    pub(crate) fn buffer<A>(&mut self, capacity: usize) {
+        self.stats.bytes += capacity * size_of::<A>();
+    }
+
+    /// Record that the node being counted is a leaf holding `values`
+    /// elements, with room for `capacity`.
+    pub(crate) fn leaf(&mut self, values: usize, capacity: usize) {
+        self.stats.leaves += 1;
+        self.stats.leaf_values += values;
+        self.stats.leaf_capacity += capacity;
+    }
+}
+
+/// Collections whose memory use can be measured.
+pub trait HeapSize {
+    /// Count the memory used by this collection into `footprint`,
+    /// and return its depth.
+    #[doc(hidden)]
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize;
+
+    /// Get statistics about the memory used by this collection.
+    fn heap_stats(&self) -> HeapStats {
+        Footprint::new().add(self).stats()
+    }
+
+    /// Get the number of bytes of memory used by this collection.
+    fn heap_size(&self) -> usize {
+        self.heap_stats().bytes
+    }
+
+    /// Get statistics about the memory used by a list of
+    /// collections, where memory shared between them is only
+    /// counted once.
+    fn shared_heap_stats(collections: &[&Self]) -> HeapStats
+    where
+        Self: Sized,
+    {
+        let mut footprint = Footprint::new();
+        for collection in collections {
+            footprint.add(*collection);
+        }
+        footprint.stats()
+    }
+
+    /// Get the number of bytes of memory used by a list of
+    /// collections, where memory shared between them is only
+    /// counted once.
+    fn shared_heap_size(collections: &[&Self]) -> usize
+    where
+        Self: Sized,
+    {
+        Self::shared_heap_stats(collections).bytes
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bits::HASH_SIZE;
+    use catlist::CatList;
+    use conslist::ConsList;
+    use fingertree::Deque;
+    use hashmap::HashMap;
+    use hashset::HashSet;
+    use multimap::OrdMultiMap;
+    use ordmap::OrdMap;
+    use ordset::OrdSet;
+    use proptest::collection;
+    use proptest::num::i32;
+    use rope::Rope;
+    use std::hash::{BuildHasherDefault, Hasher};
+    use triemap::TrieMap;
+    use vector::Vector;
+
+    // Hashes everything to the same value, so every entry in a hash
+    // map ends up in a single collision node.
+    #[derive(Default)]
+    struct ConstantHasher;
+
+    impl Hasher for ConstantHasher {
+        fn write(&mut self, _bytes: &[u8]) {}
+
+        fn finish(&self) -> u64 {
+            0
+        }
+    }
+
+    // Two unrelated collections share nothing, and a clone shares
+    // everything.
+    fn check_sharing<C: HeapSize + Clone>(left: &C, right: &C) {
+        let left_stats = left.heap_stats();
+        let right_stats = right.heap_stats();
+        let both = HeapSize::shared_heap_stats(&[left, right]);
+        assert_eq!(left_stats.bytes + right_stats.bytes, both.bytes);
+        assert_eq!(left_stats.nodes + right_stats.nodes, both.nodes);
+        assert_eq!(left_stats, HeapSize::shared_heap_stats(&[left, &left.clone()]));
+    }
+
+    #[test]
+    fn every_collection_is_measured() {
+        check_sharing(&conslist![1, 2, 3], &conslist![1, 2, 3]);
+        check_sharing(&catlist![1, 2, 3], &catlist![1, 2, 3]);
+        check_sharing(&vector![1, 2, 3], &vector![1, 2, 3]);
+        check_sharing(&ordset![1, 2, 3], &ordset![1, 2, 3]);
+        check_sharing(&ordmap!{1 => 2}, &ordmap!{1 => 2});
+        check_sharing(&hashset![1, 2, 3], &hashset![1, 2, 3]);
+        check_sharing(&hashmap!{1 => 2}, &hashmap!{1 => 2});
+        check_sharing(
+            &TrieMap::singleton("one", 1),
+            &TrieMap::singleton("one", 1),
+        );
+        check_sharing(&Rope::from("text"), &Rope::from("text"));
+        let deque: Deque<i32> = (0..100).collect();
+        check_sharing(&deque, &(0..100).collect());
+        let multimap: OrdMultiMap<i32, i32> = (0..100).map(|i| (i % 7, i)).collect();
+        check_sharing(&multimap, &(0..100).map(|i| (i % 7, i)).collect());
+    }
+
+    #[test]
+    fn updates_share_memory() {
+        let map: OrdMap<i32, i32> = (0..10000).map(|i| (i, i)).collect();
+        let updated = map.insert(5000, 0);
+        let single = map.heap_stats();
+        let both = HeapSize::shared_heap_stats(&[&map, &updated]);
+        // Only the path down to the updated key, and the new value,
+        // are added.
+        assert_eq!(single.nodes + single.depth, both.nodes);
+        assert!(both.bytes < single.bytes + single.bytes / 10);
+    }
+
+    #[test]
+    fn counts_values_once() {
+        let map: HashMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+        let other: HashMap<i32, i32> = map.iter().map(|(k, v)| (k, v)).collect();
+        let both = HeapSize::shared_heap_size(&[&map, &other]);
+        // The nodes are rebuilt, but the keys and values aren't.
+        let values = 2 * 1000 * (2 * size_of::<usize>() + size_of::<i32>());
+        assert_eq!(map.heap_size() + other.heap_size() - values, both);
+    }
+
+    #[test]
+    fn reports_shape() {
+        let set: OrdSet<i32> = (0..100_000).collect();
+        let stats = set.heap_stats();
+        assert!(stats.leaf_values < set.len() && stats.leaves < stats.nodes);
+        assert!(stats.depth >= 4 && stats.depth <= 6);
+        assert!(stats.leaf_fill() > 0.5);
+
+        let vector: Vector<i32> = (0..100_000).collect();
+        let stats = vector.heap_stats();
+        assert_eq!(vector.len(), stats.leaf_values);
+        assert_eq!((vector.len() - 1) / HASH_SIZE + 1, stats.leaves);
+        assert!(HASH_SIZE.pow(stats.depth as u32 - 1) < vector.len());
+        assert!(stats.leaf_fill() > 0.99);
+
+        let set: HashSet<i32> = HashSet::new();
+        assert_eq!(1, set.heap_stats().nodes);
+
+        // With every hash the same, the map is a chain of nodes as long
+        // as the hash has bits, ending in a collision node which is
+        // part of the leaf it sits in.
+        let hasher: BuildHasherDefault<ConstantHasher> = Default::default();
+        let mut map = HashMap::with_hasher(hasher);
+        for i in 0..100 {
+            map.insert_mut(i, i);
+        }
+        let stats = map.heap_stats();
+        assert_eq!(stats.nodes - 1, stats.depth);
+        assert_eq!(1, stats.leaves);
+        assert_eq!(100, stats.leaf_values);
+        assert!(stats.leaf_fill() <= 1.0);
+        assert_eq!(0, Deque::<i32>::new().heap_stats().nodes);
+    }
+
+    #[test]
+    fn long_lists() {
+        let list: ConsList<i32> = (0..100_000).collect();
+        let stats = list.heap_stats();
+        assert_eq!(100_000, stats.nodes);
+        assert_eq!(100_000, stats.depth);
+        assert_eq!(stats, HeapSize::shared_heap_stats(&[&list, &list.tail().unwrap()]));
+
+        let mut list = CatList::new();
+        for i in 0..100_000 {
+            list = CatList::singleton(i).append(list);
+        }
+        assert!(list.heap_stats().bytes > 100_000 * size_of::<i32>());
+    }
+
+    proptest! {
+        #[test]
+        fn shared_never_exceeds_sum(ref input in collection::vec(i32::ANY, 0..1000), split in 0usize..1000) {
+            let vector: Vector<i32> = input.iter().cloned().collect();
+            let split = split.min(vector.len());
+            let (left, right) = vector.split_at(split);
+            let both = HeapSize::shared_heap_size(&[&vector, &left, &right]);
+            assert!(both <= vector.heap_size() + left.heap_size() + right.heap_size());
+            assert!(both >= vector.heap_size());
+        }
+    }
+}
@@ -0,0 +1,1053 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Collections with a cached hash.
+//!
+//! Hashing a persistent collection walks every element in it, which
+//! gets expensive when the collection is used as a key in a map or a
+//! memo table and is hashed over and over again. Wrapping it in a
+//! [`Hashed`][Hashed] computes its hash once and keeps it alongside
+//! the collection, so that hashing it is O(1) and two collections
+//! with different hashes can be told apart without comparing their
+//! elements.
+//!
+//! The cache is opt-in: the collections themselves don't carry it,
+//! so you only pay for the extra memory where you need it.
+//!
+//! The hash is cached once, in the wrapper, and not in the nodes of
+//! the collection, so the only thing that gets cheaper is hashing and
+//! comparing the whole collection. Changes to a [`Hashed`][Hashed]
+//! collection have to go through it, so that the hash stays up to
+//! date, and only a few kinds of change can work out the new hash
+//! without rehashing every element:
+//!
+//! * maps and sets hash their entries in any order, so wrapped maps
+//!   and sets have `insert` and `remove` methods which adjust the
+//!   hash by just the entry being added or removed;
+//! * sequences hash as a polynomial in the hashes of their elements,
+//!   so wrapped [`Vector`][vector::Vector]s,
+//!   [`CatList`][catlist::CatList]s and
+//!   [`ConsList`][conslist::ConsList]s can have elements pushed onto
+//!   and popped off either end and be appended to each other, and
+//!   vectors can have elements replaced.
+//!
+//! Anything else goes through [`update`][Hashed::update], which works
+//! for any collection but rehashes the whole of it afterwards, so
+//! changes in the middle of a sequence, like
+//! [`Vector::insert`][vector::Vector::insert] or
+//! [`Vector::split_at`][vector::Vector::split_at], take O(n) time.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::hashed::Hashed;
+//! # fn main() {
+//! let mut set = Hashed::new(hashset!{1, 2, 3});
+//! set.insert_mut(4);
+//! assert_eq!(Hashed::new(hashset!{1, 2, 3, 4}), set);
+//! assert_eq!(Hashed::new(hashset!{4, 3, 2, 1}).content_hash(), set.content_hash());
+//! # }
+//! ```
+//!
+//! [Hashed]: ./struct.Hashed.html
+//! [Hashed::update]: ./struct.Hashed.html#method.update
+//! [vector::Vector]: ../vector/struct.Vector.html
+//! [vector::Vector::insert]: ../vector/struct.Vector.html#method.insert
+//! [vector::Vector::split_at]: ../vector/struct.Vector.html#method.split_at
+//! [catlist::CatList]: ../catlist/struct.CatList.html
+//! [conslist::ConsList]: ../conslist/struct.ConsList.html
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Error, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use bits::{hash_one, unordered_sum};
+use catlist::CatList;
+use conslist::ConsList;
+use fingertree::{FingerTree, Measure};
+use footprint::{Footprint, HeapSize};
+use hashmap::HashMap;
+use hashset::HashSet;
+use ordmap::OrdMap;
+use ordset::OrdSet;
+use rope::Rope;
+use shared::Shared;
+use triemap::TrieMap;
+use vector::Vector;
+
+/// A hash of a collection's contents.
+///
+/// Unlike [`Hash`][std::hash::Hash], this produces a single number
+/// which doesn't depend on a `Hasher`, so that it can be cached. It
+/// must be consistent with [`Eq`][std::cmp::Eq]: equal values must
+/// have equal content hashes.
+///
+/// [std::hash::Hash]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+/// [std::cmp::Eq]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+pub trait ContentHash {
+    /// Compute the content hash of a value.
+    fn content_hash(&self) -> u64;
+}
+
+impl<K: Hash + Eq, V: Hash, S: BuildHasher> ContentHash for HashMap<K, V, S> {
+    fn content_hash(&self) -> u64 {
+        unordered_sum(self.iter())
+    }
+}
+
+impl<A: Hash + Eq, S: BuildHasher> ContentHash for HashSet<A, S> {
+    fn content_hash(&self) -> u64 {
+        unordered_sum(self.iter())
+    }
+}
+
+impl<A: Hash> ContentHash for ConsList<A> {
+    fn content_hash(&self) -> u64 {
+        sequence_hash(self.iter())
+    }
+}
+
+impl<A: Hash> ContentHash for CatList<A> {
+    fn content_hash(&self) -> u64 {
+        sequence_hash(self.iter())
+    }
+}
+
+impl<A: Hash> ContentHash for Vector<A> {
+    fn content_hash(&self) -> u64 {
+        sequence_hash(self.iter())
+    }
+}
+
+impl<K: Ord + Hash, V: Hash> ContentHash for OrdMap<K, V> {
+    fn content_hash(&self) -> u64 {
+        unordered_sum(self.iter())
+    }
+}
+
+impl<A: Ord + Hash> ContentHash for OrdSet<A> {
+    fn content_hash(&self) -> u64 {
+        unordered_sum(self.iter())
+    }
+}
+
+impl<K: Hash, V: Hash> ContentHash for TrieMap<K, V> {
+    fn content_hash(&self) -> u64 {
+        hash_one(self)
+    }
+}
+
+impl<A: Hash, M: Measure<A>> ContentHash for FingerTree<A, M> {
+    fn content_hash(&self) -> u64 {
+        hash_one(self)
+    }
+}
+
+impl ContentHash for Rope {
+    fn content_hash(&self) -> u64 {
+        hash_one(self)
+    }
+}
+
+// Sequences hash as h(a0) + h(a1)·B + h(a2)·B² + ..., wrapping
+// around at 2⁶⁴, so that the hash of a sequence with elements added
+// or removed at either end, or of two sequences joined together, can
+// be worked out from the hashes of its parts.
+const BASE: u64 = 0x9e37_79b9_7f4a_7c15;
+
+fn sequence_hash<I, A>(iter: I) -> u64
+where
+    I: IntoIterator<Item = A>,
+    A: Hash,
+{
+    iter.into_iter()
+        .fold((0u64, 1u64), |(sum, power), item| {
+            (
+                sum.wrapping_add(hash_one(&item).wrapping_mul(power)),
+                power.wrapping_mul(BASE),
+            )
+        })
+        .0
+}
+
+// B to the power of `exp`, by repeated squaring.
+fn power(mut exp: usize) -> u64 {
+    let mut square = BASE;
+    let mut out = 1u64;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            out = out.wrapping_mul(square);
+        }
+        square = square.wrapping_mul(square);
+        exp >>= 1;
+    }
+    out
+}
+
+// B is odd, so it has an inverse modulo 2⁶⁴, which lets us divide by
+// it to drop the first element of a sequence. Each Newton step
+// doubles the number of correct low bits, starting from the three
+// which B gets right as its own inverse.
+fn inverse() -> u64 {
+    let mut inv = BASE;
+    for _ in 0..5 {
+        inv = inv.wrapping_mul(2u64.wrapping_sub(BASE.wrapping_mul(inv)));
+    }
+    inv
+}
+
+/// A collection with a cached content hash.
+///
+/// Hashing a `Hashed` collection, or comparing two of them which
+/// aren't equal, takes O(1) time, at the cost of keeping the hash up
+/// to date whenever the collection changes, which means rehashing
+/// all of it for any change the [module documentation][hashed]
+/// doesn't list as cheap. It dereferences to the wrapped collection,
+/// so all of its read only methods are available as usual.
+///
+/// Note that a `Hashed` collection hashes differently from the
+/// collection it wraps, so you can't look one up in a map keyed by
+/// the other.
+///
+/// [hashed]: ./index.html
+pub struct Hashed<A> {
+    value: A,
+    hash: u64,
+}
+
+impl<A: ContentHash> Hashed<A> {
+    /// Wrap a collection, computing its hash.
+    ///
+    /// Time: O(n)
+    pub fn new(value: A) -> Self {
+        let hash = value.content_hash();
+        Hashed { value, hash }
+    }
+
+    /// Change the collection with the given function, and then
+    /// recompute its hash.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashed::Hashed;
+    /// # fn main() {
+    /// let mut vec = Hashed::new(vector![1, 2, 3]);
+    /// vec.update(|vec| vec.push_back_mut(4));
+    /// assert_eq!(Hashed::new(vector![1, 2, 3, 4]), vec);
+    /// # }
+    /// ```
+    pub fn update<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut A) -> R,
+    {
+        let result = f(&mut self.value);
+        self.hash = self.value.content_hash();
+        result
+    }
+}
+
+impl<A> Hashed<A> {
+    /// Get the cached hash of the collection.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn content_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Unwrap the collection, discarding its hash.
+    #[inline]
+    pub fn into_inner(self) -> A {
+        self.value
+    }
+}
+
+impl<K, V, S> Hashed<HashMap<K, V, S>>
+where
+    K: Hash + Eq,
+    V: Hash,
+    S: BuildHasher,
+{
+    /// Construct a new map by inserting a key/value mapping into a
+    /// map, updating the hash from the changed entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RK, RV>(&self, k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(k, v);
+        out
+    }
+
+    /// Insert a key/value mapping into a map, mutating it in place
+    /// when it is safe to do so, and update the hash from the changed
+    /// entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<RK, RV>(&mut self, k: RK, v: RV)
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let entry = (k.shared(), v.shared());
+        if let Some(old) = self.value.pop_with_key_mut(&*entry.0) {
+            self.hash = self.hash.wrapping_sub(hash_one(&old));
+        }
+        self.hash = self.hash.wrapping_add(hash_one(&entry));
+        self.value.insert_mut(entry.0, entry.1);
+    }
+
+    /// Construct a new map without the given key, updating the hash
+    /// from the changed entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BK>(&self, k: &BK) -> Self
+    where
+        BK: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+    {
+        let mut out = self.clone();
+        out.remove_mut(k);
+        out
+    }
+
+    /// Remove a key from a map, mutating it in place when it is safe
+    /// to do so, and update the hash from the changed entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut<BK>(&mut self, k: &BK)
+    where
+        BK: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+    {
+        if let Some(old) = self.value.pop_with_key_mut(k) {
+            self.hash = self.hash.wrapping_sub(hash_one(&old));
+        }
+    }
+}
+
+impl<A, S> Hashed<HashSet<A, S>>
+where
+    A: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Construct a new set by inserting a value into a set, updating
+    /// the hash from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert<R>(&self, a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(a);
+        out
+    }
+
+    /// Insert a value into a set, mutating it in place when it is
+    /// safe to do so, and update the hash from the changed value
+    /// alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<R>(&mut self, a: R)
+    where
+        R: Shared<A>,
+    {
+        let a: Arc<A> = a.shared();
+        if !self.value.contains(&*a) {
+            self.hash = self.hash.wrapping_add(hash_one(&a));
+        }
+        self.value.insert_mut(a);
+    }
+
+    /// Construct a new set without the given value, updating the hash
+    /// from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BA>(&self, a: &BA) -> Self
+    where
+        BA: Hash + Eq + ?Sized,
+        A: Borrow<BA>,
+    {
+        let mut out = self.clone();
+        out.remove_mut(a);
+        out
+    }
+
+    /// Remove a value from a set, mutating it in place when it is
+    /// safe to do so, and update the hash from the changed value
+    /// alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut<BA>(&mut self, a: &BA)
+    where
+        BA: Hash + Eq + ?Sized,
+        A: Borrow<BA>,
+    {
+        if self.value.contains(a) {
+            // `Borrow` guarantees the borrowed form hashes the same
+            // as the value it was borrowed from.
+            self.hash = self.hash.wrapping_sub(hash_one(a));
+            self.value.remove_mut(a);
+        }
+    }
+}
+
+impl<K: Ord + Hash, V: Hash> Hashed<OrdMap<K, V>> {
+    /// Construct a new map by inserting a key/value mapping into a
+    /// map, updating the hash from the changed entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RK, RV>(&self, k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(k, v);
+        out
+    }
+
+    /// Insert a key/value mapping into a map, mutating it in place
+    /// when it is safe to do so, and update the hash from the changed
+    /// entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<RK, RV>(&mut self, k: RK, v: RV)
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let entry = (k.shared(), v.shared());
+        if let Some(old) = self.value.pop_with_key_mut(&*entry.0) {
+            self.hash = self.hash.wrapping_sub(hash_one(&old));
+        }
+        self.hash = self.hash.wrapping_add(hash_one(&entry));
+        self.value.insert_mut(entry.0, entry.1);
+    }
+
+    /// Construct a new map without the given key, updating the hash
+    /// from the changed entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BK>(&self, k: &BK) -> Self
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        let mut out = self.clone();
+        out.remove_mut(k);
+        out
+    }
+
+    /// Remove a key from a map, mutating it in place when it is safe
+    /// to do so, and update the hash from the changed entry alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut<BK>(&mut self, k: &BK)
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        if let Some(old) = self.value.pop_with_key_mut(k) {
+            self.hash = self.hash.wrapping_sub(hash_one(&old));
+        }
+    }
+}
+
+impl<A: Ord + Hash> Hashed<OrdSet<A>> {
+    /// Construct a new set by inserting a value into a set, updating
+    /// the hash from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert<R>(&self, a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(a);
+        out
+    }
+
+    /// Insert a value into a set, mutating it in place when it is
+    /// safe to do so, and update the hash from the changed value
+    /// alone.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<R>(&mut self, a: R)
+    where
+        R: Shared<A>,
+    {
+        let a: Arc<A> = a.shared();
+        if !self.value.contains(&*a) {
+            self.hash = self.hash.wrapping_add(hash_one(&a));
+        }
+        self.value.insert_mut(a);
+    }
+
+    /// Construct a new set without the given value, updating the hash
+    /// from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BA>(&self, a: &BA) -> Self
+    where
+        BA: Ord + Hash + ?Sized,
+        A: Borrow<BA>,
+    {
+        let mut out = self.clone();
+        out.remove_mut(a);
+        out
+    }
+
+    /// Remove a value from a set, mutating it in place when it is
+    /// safe to do so, and update the hash from the changed value
+    /// alone.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut<BA>(&mut self, a: &BA)
+    where
+        BA: Ord + Hash + ?Sized,
+        A: Borrow<BA>,
+    {
+        if self.value.contains(a) {
+            // `Borrow` guarantees the borrowed form hashes the same
+            // as the value it was borrowed from.
+            self.hash = self.hash.wrapping_sub(hash_one(a));
+            self.value.remove_mut(a);
+        }
+    }
+}
+
+impl<A: Hash> Hashed<Vector<A>> {
+    /// Construct a new vector with a value added to the back,
+    /// updating the hash from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn push_back<RA>(&self, value: RA) -> Self
+    where
+        RA: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.push_back_mut(value);
+        out
+    }
+
+    /// Add a value to the back of a vector, mutating it in place when
+    /// it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Time: O(log n)
+    pub fn push_back_mut<RA>(&mut self, value: RA)
+    where
+        RA: Shared<A>,
+    {
+        let value = value.shared();
+        self.hash = self
+            .hash
+            .wrapping_add(hash_one(&value).wrapping_mul(power(self.value.len())));
+        self.value.push_back_mut(value);
+    }
+
+    /// Construct a new vector with a value added to the front,
+    /// updating the hash from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn push_front<RA>(&self, value: RA) -> Self
+    where
+        RA: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.push_front_mut(value);
+        out
+    }
+
+    /// Add a value to the front of a vector, mutating it in place
+    /// when it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Time: O(log n)
+    pub fn push_front_mut<RA>(&mut self, value: RA)
+    where
+        RA: Shared<A>,
+    {
+        let value = value.shared();
+        self.hash = self.hash.wrapping_mul(BASE).wrapping_add(hash_one(&value));
+        self.value.push_front_mut(value);
+    }
+
+    /// Remove the last value of a vector, mutating it in place when
+    /// it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Time: O(log n)
+    pub fn pop_back_mut(&mut self) -> Option<Arc<A>> {
+        let value = self.value.pop_back_mut()?;
+        self.hash = self
+            .hash
+            .wrapping_sub(hash_one(&value).wrapping_mul(power(self.value.len())));
+        Some(value)
+    }
+
+    /// Remove the first value of a vector, mutating it in place when
+    /// it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Time: O(log n)
+    pub fn pop_front_mut(&mut self) -> Option<Arc<A>> {
+        let value = self.value.pop_front_mut()?;
+        self.hash = self
+            .hash
+            .wrapping_sub(hash_one(&value))
+            .wrapping_mul(inverse());
+        Some(value)
+    }
+
+    /// Construct a new vector with the value at `index` replaced,
+    /// updating the hash from the changed value alone.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn set<RA>(&self, index: usize, value: RA) -> Self
+    where
+        RA: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.set_mut(index, value);
+        out
+    }
+
+    /// Replace the value at `index` in a vector, mutating it in place
+    /// when it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn set_mut<RA>(&mut self, index: usize, value: RA)
+    where
+        RA: Shared<A>,
+    {
+        let value = value.shared();
+        let old = self.value.get_unwrapped(index);
+        let change = hash_one(&value).wrapping_sub(hash_one(&old));
+        self.hash = self.hash.wrapping_add(change.wrapping_mul(power(index)));
+        self.value.set_mut(index, value);
+    }
+
+    /// Construct a vector with the elements of another vector added
+    /// to the back of this one, combining their hashes.
+    ///
+    /// Time: O(n) (where n is the length of the other vector)
+    pub fn append<R>(&self, other: R) -> Self
+    where
+        R: Borrow<Self>,
+    {
+        let other = other.borrow();
+        Hashed {
+            value: self.value.append(&other.value),
+            hash: self
+                .hash
+                .wrapping_add(other.hash.wrapping_mul(power(self.value.len()))),
+        }
+    }
+}
+
+impl<A: Hash> Hashed<CatList<A>> {
+    /// Construct a new list with a value added to the back, updating
+    /// the hash from the changed value alone.
+    ///
+    /// Time: O(log n)
+    pub fn push_back<R>(&self, a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.push_back_mut(a);
+        out
+    }
+
+    /// Add a value to the back of a list, mutating it in place when
+    /// it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Time: O(log n)
+    pub fn push_back_mut<R>(&mut self, a: R)
+    where
+        R: Shared<A>,
+    {
+        let a = a.shared();
+        self.hash = self
+            .hash
+            .wrapping_add(hash_one(&a).wrapping_mul(power(self.value.len())));
+        self.value.push_back_mut(a);
+    }
+
+    /// Construct a new list with a value added to the front, updating
+    /// the hash from the changed value alone.
+    ///
+    /// Time: O(1)
+    pub fn push_front<R>(&self, a: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        let mut out = self.clone();
+        out.push_front_mut(a);
+        out
+    }
+
+    /// Add a value to the front of a list, mutating it in place when
+    /// it is safe to do so, and update the hash from the changed
+    /// value alone.
+    ///
+    /// Time: O(1)
+    pub fn push_front_mut<R>(&mut self, a: R)
+    where
+        R: Shared<A>,
+    {
+        let a = a.shared();
+        self.hash = self.hash.wrapping_mul(BASE).wrapping_add(hash_one(&a));
+        self.value.push_front_mut(a);
+    }
+
+    /// Remove the last value of a list, mutating it in place when it
+    /// is safe to do so, and update the hash from the changed value
+    /// alone.
+    ///
+    /// Time: O(log n)
+    pub fn pop_back_mut(&mut self) -> Option<Arc<A>> {
+        let a = self.value.pop_back_mut()?;
+        self.hash = self
+            .hash
+            .wrapping_sub(hash_one(&a).wrapping_mul(power(self.value.len())));
+        Some(a)
+    }
+
+    /// Remove the first value of a list, mutating it in place when it
+    /// is safe to do so, and update the hash from the changed value
+    /// alone.
+    ///
+    /// Time: O(1)
+    pub fn pop_front_mut(&mut self) -> Option<Arc<A>> {
+        let a = self.value.pop_front_mut()?;
+        self.hash = self.hash.wrapping_sub(hash_one(&a)).wrapping_mul(inverse());
+        Some(a)
+    }
+
+    /// Construct a list with the elements of another list added to
+    /// the back of this one, combining their hashes.
+    ///
+    /// Time: O(log n)
+    pub fn append<R>(&self, other: R) -> Self
+    where
+        R: Borrow<Self>,
+    {
+        let mut out = self.clone();
+        out.append_mut(other);
+        out
+    }
+
+    /// Add the elements of another list to the back of this one,
+    /// mutating it in place when it is safe to do so, and combine
+    /// their hashes.
+    ///
+    /// Time: O(log n)
+    pub fn append_mut<R>(&mut self, other: R)
+    where
+        R: Borrow<Self>,
+    {
+        let other = other.borrow();
+        self.hash = self
+            .hash
+            .wrapping_add(other.hash.wrapping_mul(power(self.value.len())));
+        self.value.append_mut(&other.value);
+    }
+}
+
+impl<A: Hash> Hashed<ConsList<A>> {
+    /// Construct a list with a value added to the front, updating the
+    /// hash from the changed value alone.
+    ///
+    /// Time: O(1)
+    pub fn cons<R>(&self, car: R) -> Self
+    where
+        R: Shared<A>,
+    {
+        let car = car.shared();
+        Hashed {
+            hash: self.hash.wrapping_mul(BASE).wrapping_add(hash_one(&car)),
+            value: self.value.cons(car),
+        }
+    }
+
+    /// Get the first value of a list and the rest of it, updating the
+    /// hash from the changed value alone.
+    ///
+    /// Time: O(1)
+    pub fn uncons(&self) -> Option<(Arc<A>, Self)> {
+        let (car, cdr) = self.value.uncons()?;
+        let hash = self
+            .hash
+            .wrapping_sub(hash_one(&car))
+            .wrapping_mul(inverse());
+        Some((car, Hashed { value: cdr, hash }))
+    }
+}
+
+// Core traits
+
+impl<A: Clone> Clone for Hashed<A> {
+    fn clone(&self) -> Self {
+        Hashed {
+            value: self.value.clone(),
+            hash: self.hash,
+        }
+    }
+}
+
+impl<A: PartialEq> PartialEq for Hashed<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.value == other.value
+    }
+}
+
+impl<A: Eq> Eq for Hashed<A> {}
+
+impl<A: PartialOrd> PartialOrd for Hashed<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl<A: Ord> Ord for Hashed<A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<A> Hash for Hashed<A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+impl<A: ContentHash + Default> Default for Hashed<A> {
+    fn default() -> Self {
+        Hashed::new(A::default())
+    }
+}
+
+impl<A: HeapSize> HeapSize for Hashed<A> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.value.measure_heap(footprint)
+    }
+}
+
+impl<A> Deref for Hashed<A> {
+    type Target = A;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<A> AsRef<A> for Hashed<A> {
+    fn as_ref(&self) -> &A {
+        &self.value
+    }
+}
+
+impl<A: Debug> Debug for Hashed<A> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.value.fmt(f)
+    }
+}
+
+// Conversions
+
+impl<A: ContentHash> From<A> for Hashed<A> {
+    fn from(value: A) -> Self {
+        Hashed::new(value)
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: ContentHash + Arbitrary> Arbitrary for Hashed<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Hashed::new(A::arbitrary(g))
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hashmap::proptest::hash_map;
+    use proptest::collection;
+    use proptest::num::i16;
+
+    #[test]
+    fn equal_maps_have_equal_hashes() {
+        let left = Hashed::new(hashmap!{1 => 2, 3 => 4, 5 => 6});
+        let right = Hashed::new(hashmap!{5 => 6, 3 => 4}).insert(1, 2);
+        assert_eq!(left.content_hash(), right.content_hash());
+        assert_eq!(left, right);
+        assert_ne!(left, right.insert(1, 3));
+        assert_eq!(left, right.insert(1, 3).insert(1, 2));
+        assert_eq!(left.remove(&3), Hashed::new(hashmap!{1 => 2, 5 => 6}));
+    }
+
+    #[test]
+    fn update_rehashes() {
+        let mut list = Hashed::new(catlist![1, 2, 3]);
+        let hash = list.content_hash();
+        list.update(|list| list.push_back_mut(4));
+        assert_ne!(hash, list.content_hash());
+        assert_eq!(Hashed::new(catlist![1, 2, 3, 4]), list);
+        assert_eq!(catlist![1, 2, 3, 4], list.into_inner());
+    }
+
+    #[test]
+    fn inverse_undoes_base() {
+        assert_eq!(1, BASE.wrapping_mul(inverse()));
+        assert_eq!(sequence_hash(vec![1, 2, 3]), {
+            let tail = sequence_hash(vec![2, 3]);
+            tail.wrapping_mul(BASE).wrapping_add(hash_one(&1))
+        });
+    }
+
+    #[test]
+    fn appended_sequences_combine_hashes() {
+        let left = Hashed::new(vector![1, 2, 3]);
+        let right = Hashed::new(vector![4, 5]);
+        assert_eq!(Hashed::new(vector![1, 2, 3, 4, 5]), left.append(&right));
+        let left = Hashed::new(catlist![1, 2]);
+        let right = Hashed::new(catlist![3]);
+        assert_eq!(Hashed::new(catlist![1, 2, 3]), left.append(right));
+    }
+
+    proptest! {
+        #[test]
+        fn vector_hash_follows_changes(
+            ref vector in ::vector::proptest::vector(i16::ANY, 0..100),
+            ref ops in collection::vec((0u8..5, i16::ANY, ::proptest::num::usize::ANY), 0..100)
+        ) {
+            let mut hashed = Hashed::new(vector.clone());
+            for &(op, value, index) in ops {
+                match op {
+                    0 => hashed.push_back_mut(value),
+                    1 => hashed.push_front_mut(value),
+                    2 => {
+                        hashed.pop_back_mut();
+                    }
+                    3 => {
+                        hashed.pop_front_mut();
+                    }
+                    _ => if !hashed.is_empty() {
+                        let index = index % hashed.len();
+                        hashed.set_mut(index, value);
+                    },
+                }
+                assert_eq!(hashed.value.content_hash(), hashed.content_hash());
+            }
+        }
+
+        #[test]
+        fn list_hashes_follow_changes(
+            ref values in collection::vec(i16::ANY, 0..100),
+            ref ops in collection::vec((0u8..4, i16::ANY), 0..100)
+        ) {
+            let mut cat: Hashed<CatList<i16>> = Hashed::new(values.iter().cloned().collect());
+            let mut cons: Hashed<ConsList<i16>> = Hashed::new(values.iter().cloned().collect());
+            for &(op, value) in ops {
+                match op {
+                    0 => cat.push_back_mut(value),
+                    1 => cat.push_front_mut(value),
+                    2 => {
+                        cat.pop_back_mut();
+                    }
+                    _ => {
+                        cat.pop_front_mut();
+                    }
+                }
+                if op % 2 == 0 {
+                    cons = cons.cons(value);
+                } else if let Some((_, tail)) = cons.uncons() {
+                    cons = tail;
+                }
+                assert_eq!(cat.value.content_hash(), cat.content_hash());
+                assert_eq!(cons.value.content_hash(), cons.content_hash());
+            }
+        }
+
+        #[test]
+        fn ord_map_hash_follows_changes(
+            ref map in ::ordmap::proptest::ord_map(i16::ANY, i16::ANY, 0..100),
+            ref ops in collection::vec((i16::ANY, i16::ANY, ::proptest::bool::ANY), 0..100)
+        ) {
+            let mut hashed = Hashed::new(map.clone());
+            let mut set: Hashed<OrdSet<i16>> = Hashed::new(map.keys().map(|k| *k).collect());
+            for &(key, value, insert) in ops {
+                if insert {
+                    hashed.insert_mut(key, value);
+                    set.insert_mut(key);
+                } else {
+                    hashed.remove_mut(&key);
+                    set.remove_mut(&key);
+                }
+                assert_eq!(hashed.value.content_hash(), hashed.content_hash());
+                assert_eq!(set.value.content_hash(), set.content_hash());
+            }
+        }
+
+        #[test]
+        fn map_hash_follows_changes(
+            ref map in hash_map(i16::ANY, i16::ANY, 0..100),
+            ref ops in collection::vec((i16::ANY, i16::ANY, ::proptest::bool::ANY), 0..100)
+        ) {
+            let mut hashed = Hashed::new(map.clone());
+            for &(key, value, insert) in ops {
+                if insert {
+                    hashed.insert_mut(key, value);
+                } else {
+                    hashed.remove_mut(&key);
+                }
+                assert_eq!(hashed.value.content_hash(), hashed.content_hash());
+            }
+        }
+
+        #[test]
+        fn set_hash_follows_changes(
+            ref values in collection::vec(i16::ANY, 0..100),
+            ref ops in collection::vec((i16::ANY, ::proptest::bool::ANY), 0..100)
+        ) {
+            let mut hashed: Hashed<HashSet<i16>> = Hashed::new(values.iter().cloned().collect());
+            for &(value, insert) in ops {
+                if insert {
+                    hashed.insert_mut(value);
+                } else {
+                    hashed.remove_mut(&value);
+                }
+                assert_eq!(hashed.value.content_hash(), hashed.content_hash());
+            }
+        }
+    }
+}
@@ -26,19 +26,26 @@
 
 #![cfg_attr(feature = "clippy", allow(implicit_hasher))]
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, ToOwned};
 use std::cmp::Ordering;
-use std::collections;
+#[cfg(feature = "std")]
 use std::collections::hash_map::RandomState;
+use std::collections;
 use std::fmt::{Debug, Error, Formatter};
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::{FromIterator, Sum};
 use std::ops::{Add, Index, IndexMut};
 use std::sync::Arc;
+use std::vec::{self, Vec};
 
-use bits::hash_key;
+use bits::{hash_key, hash_unordered};
+use footprint::{Footprint, HeapSize};
+#[cfg(any(test, feature = "dot"))]
+use dot::{Dot, ToDot};
+use invariant::InvariantError;
 use shared::Shared;
 
+use nodes::btree::DiffItem;
 use nodes::hamt::{HashValue, Iter, Node};
 
 /// Construct a hash map from a sequence of key/value pairs.
@@ -93,13 +100,28 @@ macro_rules! hashmap {
 /// [std::cmp::Eq]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
 /// [std::hash::Hash]: https://doc.rust-lang.org/std/hash/trait.Hash.html
 /// [std::collections::hash_map::RandomState]: https://doc.rust-lang.org/std/collections/hash_map/struct.RandomState.html
-
+#[cfg(feature = "std")]
 pub struct HashMap<K, V, S = RandomState> {
     size: usize,
     root: Arc<Node<(Arc<K>, Arc<V>)>>,
     hasher: Arc<S>,
 }
 
+/// A hash map.
+///
+/// An immutable hash map using [hash array mapped tries][1].
+///
+/// Without the `std` feature there is no default hasher, so one must
+/// always be given through `S`.
+///
+/// [1]: https://en.wikipedia.org/wiki/Hash_array_mapped_trie
+#[cfg(not(feature = "std"))]
+pub struct HashMap<K, V, S> {
+    size: usize,
+    root: Arc<Node<(Arc<K>, Arc<V>)>>,
+    hasher: Arc<S>,
+}
+
 impl<K, V> HashValue for (Arc<K>, Arc<V>)
 where
     K: Eq,
@@ -115,6 +137,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<K, V> HashMap<K, V, RandomState>
 where
     K: Hash + Eq,
@@ -175,6 +198,18 @@ impl<K, V, S> HashMap<K, V, S> {
         self.len() == 0
     }
 
+    /// Test whether two maps refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a map to
+    /// itself, or if you're comparing a map to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
     /// Get the size of a hash map.
     ///
     /// Time: O(1)
@@ -203,6 +238,17 @@ where
     K: Hash + Eq,
     S: BuildHasher,
 {
+    // The entries of the map sorted by key, which puts equal maps in
+    // the same order regardless of their hashers.
+    fn sorted_entries<F>(&self, cmp: F) -> Vec<(Arc<K>, Arc<V>)>
+    where
+        F: Fn(&K, &K) -> Ordering,
+    {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_by(|left, right| cmp(&left.0, &right.0));
+        entries
+    }
+
     fn test_eq(&self, other: &Self) -> bool
     where
         V: PartialEq,
@@ -210,19 +256,13 @@ where
         if self.len() != other.len() {
             return false;
         }
-        let mut seen = collections::HashSet::new();
-        for (key, value) in self.iter() {
-            if Some(value) != other.get(&key) {
-                return false;
-            }
-            seen.insert(key);
-        }
-        for key in other.keys() {
-            if !seen.contains(&key) {
-                return false;
-            }
+        if Arc::ptr_eq(&self.hasher, &other.hasher) {
+            return Arc::ptr_eq(&self.root, &other.root) || self.root.equal(&other.root, 0);
         }
-        true
+        // With equal sizes, finding every key of `self` in `other`
+        // means there's nothing left over in `other`.
+        self.iter()
+            .all(|(key, value)| Some(value) == other.get(&key))
     }
 
     /// Construct an empty hash map using the provided hasher.
@@ -264,6 +304,61 @@ where
         Node::iter(self.root.clone(), self.size)
     }
 
+    /// Get an iterator over the differences between this map and
+    /// another, i.e. the set of entries to add, update, or remove to
+    /// this map in order to make it equal to the other map.
+    ///
+    /// When both maps use the same hasher, as a map and the maps made
+    /// from it by updating it do, this avoids visiting nodes which
+    /// are shared between them, meaning that even very large maps
+    /// can be compared quickly if most of their structure is shared.
+    /// The differences come out in no particular order.
+    ///
+    /// Time: O(n) (where n is the number of unique elements across
+    /// the two maps, minus the number of elements belonging to nodes
+    /// shared between them)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::btree::DiffItem;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let map = hashmap!{1 => 1, 2 => 2};
+    /// let changes: Vec<_> = map.diff(map.insert(3, 3)).collect();
+    /// assert_eq!(vec![DiffItem::Add((Arc::new(3), Arc::new(3)))], changes);
+    /// # }
+    /// ```
+    pub fn diff<RM>(&self, other: RM) -> vec::IntoIter<DiffItem<(Arc<K>, Arc<V>)>>
+    where
+        V: PartialEq,
+        RM: Borrow<Self>,
+    {
+        let other = other.borrow();
+        let mut changes = Vec::new();
+        if Arc::ptr_eq(&self.hasher, &other.hasher) {
+            self.root.diff(&other.root, &mut changes);
+        } else {
+            for (key, value) in self.iter() {
+                match other.get(&*key) {
+                    None => changes.push(DiffItem::Remove((key, value))),
+                    Some(ref new) if *new == value => (),
+                    Some(new) => changes.push(DiffItem::Update {
+                        old: (key.clone(), value),
+                        new: (key, new),
+                    }),
+                }
+            }
+            for (key, value) in other.iter() {
+                if !self.contains_key(&*key) {
+                    changes.push(DiffItem::Add((key, value)));
+                }
+            }
+        }
+        changes.into_iter()
+    }
+
     /// Get an iterator over a hash map's keys.
     ///
     /// Please note that the order is consistent between maps using
@@ -855,6 +950,85 @@ where
             .fold(Default::default(), |a, b| a.union_with_key(&b, &f))
     }
 
+    /// Merge two maps which were both changed from a common `base`.
+    ///
+    /// A key changed on only one side, or changed the same way on
+    /// both, ends up with that change applied. When both sides
+    /// changed a key differently, `resolver` is called with the key
+    /// and its values in `base`, `ours` and `theirs`, where a missing
+    /// value means the key isn't in that map, and the key gets the
+    /// value it returns, or is removed if it returns `None`.
+    ///
+    /// Only the keys which differ from `base` are visited, found with
+    /// [`diff`][diff], and the changes from the side with fewer of
+    /// them are applied to the other, so that the result shares every
+    /// node it can with the maps it was merged from. This works best
+    /// when all three maps use the same hasher, as when `ours` and
+    /// `theirs` were made by updating `base`.
+    ///
+    /// Time: O(n log m) (where n is the number of keys changed on
+    /// either side)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashmap::HashMap;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let base = hashmap!{"apples" => 3, "pears" => 2};
+    /// let ours = base.insert("apples", 4).insert("plums", 1);
+    /// let theirs = base.insert("apples", 5).remove(&"pears");
+    /// let merged = HashMap::merge3(&base, &ours, &theirs, |_, _, ours, theirs| {
+    ///     Some(Arc::new(*ours.unwrap() + *theirs.unwrap()))
+    /// });
+    /// assert_eq!(hashmap!{"apples" => 9, "plums" => 1}, merged);
+    /// # }
+    /// ```
+    ///
+    /// [diff]: #method.diff
+    pub fn merge3<F>(base: &Self, ours: &Self, theirs: &Self, mut resolver: F) -> Self
+    where
+        V: PartialEq,
+        F: FnMut(Arc<K>, Option<Arc<V>>, Option<Arc<V>>, Option<Arc<V>>) -> Option<Arc<V>>,
+    {
+        let our_changes: Vec<_> = base.diff(ours).collect();
+        let their_changes: Vec<_> = base.diff(theirs).collect();
+        let ours_smaller = our_changes.len() <= their_changes.len();
+        let (mut merged, changes) = if ours_smaller {
+            (theirs.clone(), our_changes)
+        } else {
+            (ours.clone(), their_changes)
+        };
+        for change in changes {
+            let (key, old, new) = match change {
+                DiffItem::Add((key, value)) => (key, None, Some(value)),
+                DiffItem::Remove((key, value)) => (key, Some(value), None),
+                DiffItem::Update {
+                    old: (key, old),
+                    new: (_, new),
+                } => (key, Some(old), Some(new)),
+            };
+            let other = merged.get(&*key);
+            let value = if other == old {
+                new
+            } else if other == new {
+                continue;
+            } else if ours_smaller {
+                resolver(key.clone(), old, new, other)
+            } else {
+                resolver(key.clone(), old, other, new)
+            };
+            match value {
+                Some(value) => merged.insert_mut(key, value),
+                None => {
+                    merged.remove_mut(&*key);
+                }
+            }
+        }
+        merged
+    }
+
     /// Construct the difference between two maps by discarding keys
     /// which occur in both maps.
     #[inline]
@@ -1027,6 +1201,38 @@ where
     {
         self.is_proper_submap_by(other.borrow(), |a, b| a.as_ref().eq(b.as_ref()))
     }
+
+    /// Check the internal structure of a map, returning a description
+    /// of the first broken invariant found, if any.
+    ///
+    /// This checks that the bitmaps of the map's nodes agree with
+    /// their contents, that every key is stored where its hash says
+    /// it should be, that collision nodes only hold distinct keys
+    /// with identical hashes, that no subnode should have been merged
+    /// into its parent, and that the size of the map is correct. See
+    /// the [`invariant`][invariant] module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let hasher = &*self.hasher;
+        self.root
+            .check(self.size, &|key| hash_key(hasher, key))
+            .map_err(|message| InvariantError::new("HashMap", message))
+    }
+
+    /// Panic if the internal structure of a map is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
 }
 
 // Core traits
@@ -1042,42 +1248,14 @@ impl<K, V, S> Clone for HashMap<K, V, S> {
     }
 }
 
-#[cfg(not(has_specialisation))]
-impl<K, V, S> PartialEq for HashMap<K, V, S>
-where
-    K: Hash + Eq,
-    V: PartialEq,
-    S: BuildHasher,
-{
-    fn eq(&self, other: &Self) -> bool {
-        self.test_eq(other)
-    }
-}
-
-#[cfg(has_specialisation)]
 impl<K, V, S> PartialEq for HashMap<K, V, S>
 where
     K: Hash + Eq,
     V: PartialEq,
     S: BuildHasher,
-{
-    default fn eq(&self, other: &Self) -> bool {
-        self.test_eq(other)
-    }
-}
-
-#[cfg(has_specialisation)]
-impl<K, V, S> PartialEq for HashMap<K, V, S>
-where
-    K: Hash + Eq,
-    V: Eq,
-    S: BuildHasher,
 {
     fn eq(&self, other: &Self) -> bool {
-        if Arc::ptr_eq(&self.root, &other.root) {
-            return true;
-        }
-        self.test_eq(other)
+        self.ptr_eq(other) || self.test_eq(other)
     }
 }
 
@@ -1090,12 +1268,12 @@ where
     S: BuildHasher,
 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if Arc::ptr_eq(&self.hasher, &other.hasher) {
-            return self.iter().partial_cmp(other.iter());
+        if Arc::ptr_eq(&self.root, &other.root) {
+            return Some(Ordering::Equal);
         }
-        let m1: ::std::collections::HashMap<Arc<K>, Arc<V>> = self.iter().collect();
-        let m2: ::std::collections::HashMap<Arc<K>, Arc<V>> = other.iter().collect();
-        m1.iter().partial_cmp(m2.iter())
+        let cmp = |left: &K, right: &K| left.partial_cmp(right).unwrap_or(Ordering::Equal);
+        self.sorted_entries(cmp)
+            .partial_cmp(&other.sorted_entries(cmp))
     }
 }
 
@@ -1106,12 +1284,11 @@ where
     S: BuildHasher,
 {
     fn cmp(&self, other: &Self) -> Ordering {
-        if Arc::ptr_eq(&self.hasher, &other.hasher) {
-            return self.iter().cmp(other.iter());
+        if Arc::ptr_eq(&self.root, &other.root) {
+            return Ordering::Equal;
         }
-        let m1: ::std::collections::HashMap<Arc<K>, Arc<V>> = self.iter().collect();
-        let m2: ::std::collections::HashMap<Arc<K>, Arc<V>> = other.iter().collect();
-        m1.iter().cmp(m2.iter())
+        self.sorted_entries(Ord::cmp)
+            .cmp(&other.sorted_entries(Ord::cmp))
     }
 }
 
@@ -1125,9 +1302,7 @@ where
     where
         H: Hasher,
     {
-        for i in self.iter() {
-            i.hash(state);
-        }
+        hash_unordered(self.len(), self.iter(), state)
     }
 }
 
@@ -1146,6 +1321,37 @@ where
     }
 }
 
+impl<K, V, S> HashMap<K, V, S> {
+    // Count the memory used by this map, calling `entry` for each
+    // key/value pair in it, and return its depth.
+    pub(crate) fn measure_heap_with<F>(&self, footprint: &mut Footprint, entry: &mut F) -> usize
+    where
+        F: FnMut(&mut Footprint, &(Arc<K>, Arc<V>)),
+    {
+        Node::measure_heap(&self.root, footprint, entry)
+    }
+}
+
+impl<K, V, S> HeapSize for HashMap<K, V, S> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.measure_heap_with(footprint, &mut |footprint, &(ref key, ref value)| {
+            footprint.value(key);
+            footprint.value(value);
+        })
+    }
+}
+
+#[cfg(any(test, feature = "dot"))]
+impl<K, V, S> ToDot for HashMap<K, V, S> {
+    fn dot_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write_dot(&self, dot: &mut Dot, from: &str) {
+        Node::write_dot(&self.root, dot, from)
+    }
+}
+
 impl<K, V, S> Add for HashMap<K, V, S>
 where
     K: Hash + Eq,
@@ -1232,7 +1438,6 @@ where
     }
 }
 
-#[cfg(not(has_specialisation))]
 impl<K, V, S> Debug for HashMap<K, V, S>
 where
     K: Hash + Eq + Debug,
@@ -1244,30 +1449,6 @@ where
     }
 }
 
-#[cfg(has_specialisation)]
-impl<K, V, S> Debug for HashMap<K, V, S>
-where
-    K: Hash + Eq + Debug,
-    V: Debug,
-    S: BuildHasher,
-{
-    default fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.debug_map().entries(self.iter()).finish()
-    }
-}
-
-#[cfg(has_specialisation)]
-impl<K, V, S> Debug for HashMap<K, V, S>
-where
-    K: Hash + Eq + Ord + Debug,
-    V: Debug,
-    S: BuildHasher,
-{
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.debug_map().entries(self.iter()).finish()
-    }
-}
-
 // // Iterators
 
 pub struct Keys<K, V> {
@@ -1405,6 +1586,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<K: Hash + Eq, V, RK: Hash + Eq, RV, S> From<collections::HashMap<RK, RV>> for HashMap<K, V, S>
 where
     RK: Shared<K>,
@@ -1418,6 +1600,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a, K: Hash + Eq, V, RK: Hash + Eq, RV, S> From<&'a collections::HashMap<RK, RV>>
     for HashMap<K, V, S>
 where
@@ -1496,6 +1679,8 @@ pub mod proptest {
     use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
     use std::ops::Range;
 
+    pub use ordmap::proptest::map_ops;
+
     /// A strategy for a hash map of a given size.
     ///
     /// # Examples
@@ -1534,7 +1719,13 @@ mod test {
     use proptest::collection;
     use proptest::num::{i16, usize};
     use std::hash::BuildHasherDefault;
-    use test::LolHasher;
+    use test::{edit_model, merge3_model, CollidingHasher, Counted, Edit, LolHasher};
+
+    fn hash<A: Hash>(value: &A) -> u64 {
+        let mut hasher = collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
 
     #[test]
     fn safe_mutation() {
@@ -1553,6 +1744,19 @@ mod test {
         assert_eq!(hashmap![1 => 2, 3 => 8, 5 => 6], map);
     }
 
+    #[test]
+    fn equality_skips_shared_nodes() {
+        let map: HashMap<usize, Counted> = (0..100_000).map(|i| (i, Counted(i))).collect();
+        let same = map.insert(50_000, Counted(50_000));
+        let different = map.insert(50_000, Counted(0));
+        Counted::comparisons();
+        assert!(map == same);
+        assert!(map != different);
+        assert!(map != map.remove(&50_000).insert(100_000, Counted(0)));
+        assert!(Counted::comparisons() <= 2);
+        assert!(map == different.insert(50_000, Counted(50_000)));
+    }
+
     #[test]
     fn proper_formatting() {
         let map = hashmap![1 => 2];
@@ -1595,7 +1799,89 @@ mod test {
         assert_eq!(8, map["bar"]);
     }
 
+    #[test]
+    fn hash_and_order_ignore_hasher() {
+        let pairs: Vec<(i32, i32)> = (0..1000).map(|i| (i, i * 2)).collect();
+        let left: HashMap<i32, i32> = pairs.iter().cloned().collect();
+        let right: HashMap<i32, i32, BuildHasherDefault<LolHasher>> =
+            pairs.iter().rev().cloned().collect();
+        let other: HashMap<i32, i32> = pairs.iter().rev().cloned().collect();
+        assert_eq!(hash(&left), hash(&other));
+        assert_eq!(Ordering::Equal, left.cmp(&other));
+        assert_eq!(Some(Ordering::Equal), left.partial_cmp(&other));
+        assert_eq!(
+            hash(&left),
+            hash(&right.iter().collect::<HashMap<Arc<i32>, Arc<i32>>>())
+        );
+        let mut set = collections::HashSet::new();
+        set.insert(left.clone());
+        assert!(set.contains(&other));
+        assert_eq!(Ordering::Less, left.cmp(&left.insert(1000, 0)));
+        assert_eq!(Ordering::Greater, left.cmp(&left.insert(0, -1)));
+    }
+
+    #[test]
+    fn collision_removal_keeps_invariants() {
+        let hasher: BuildHasherDefault<CollidingHasher> = Default::default();
+        let full: HashMap<i32, i32, _> = (0..20).map(|i| (i, i)).collect::<Vec<_>>().into_iter().fold(
+            HashMap::with_hasher(hasher),
+            |map, (k, v)| map.insert(k, v),
+        );
+        full.assert_invariants();
+        let mut map = full.clone();
+        let mut mutable = full.clone();
+        for i in 0..20 {
+            map = map.remove(&i);
+            mutable.remove_mut(&i);
+            map.assert_invariants();
+            mutable.assert_invariants();
+        }
+        assert!(map.is_empty() && mutable.is_empty());
+    }
+
+    #[test]
+    fn validate_finds_wrong_size() {
+        let mut map: HashMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+        assert_eq!(Ok(()), map.validate());
+        map.size += 1;
+        let error = map.validate().unwrap_err();
+        assert_eq!("HashMap", error.collection());
+        assert_eq!("the size is 1001 but there are 1000 values", error.message());
+    }
+
+    #[test]
+    fn removing_collisions_frees_their_nodes() {
+        let hasher: BuildHasherDefault<CollidingHasher> = Default::default();
+        let mut map = HashMap::with_hasher(hasher);
+        for i in 0..20 {
+            map.insert_mut(i, i);
+        }
+        let mut persistent = map.clone();
+        for i in 1..20 {
+            map.remove_mut(&i);
+            persistent = persistent.remove(&i);
+        }
+        // No collision nodes are left over, empty or otherwise.
+        assert_eq!(1, map.heap_stats().nodes);
+        assert_eq!(1, persistent.heap_stats().nodes);
+        assert_eq!(Some(Arc::new(0)), map.get(&0));
+    }
+
     proptest! {
+        #[test]
+        fn operations_keep_invariants(ref ops in collection::vec((i16::ANY, i16::ANY), 0..200)) {
+            let hasher: BuildHasherDefault<LolHasher> = Default::default();
+            let mut map: HashMap<i16, i16, _> = HashMap::with_hasher(hasher);
+            for &(k, v) in ops {
+                if v % 3 == 0 {
+                    map = map.remove(&(k & 0xff));
+                } else {
+                    map.insert_mut(k & 0xff, v);
+                }
+                map.assert_invariants();
+            }
+        }
+
         #[test]
         fn insert_and_length(ref m in collection::hash_map(i16::ANY, i16::ANY, 0..100)) {
             let mut map: HashMap<i16, i16, BuildHasherDefault<LolHasher>> = Default::default();
@@ -1722,5 +2008,168 @@ mod test {
             }
             assert_eq!(0, it.len());
         }
+
+        #[test]
+        fn equality_ignores_history(
+            ref m in collection::hash_map(i16::ANY, i16::ANY, 0..100),
+            ref extra in collection::vec(i16::ANY, 0..20)
+        ) {
+            let hasher: BuildHasherDefault<CollidingHasher> = Default::default();
+            let mut left: HashMap<i16, i16, _> = HashMap::with_hasher(hasher);
+            let mut right = left.clone();
+            for (k, v) in m {
+                left.insert_mut(*k, *v);
+            }
+            for k in extra {
+                right.insert_mut(*k, 0);
+            }
+            for (k, v) in m.iter().collect::<Vec<_>>().into_iter().rev() {
+                right.insert_mut(*k, *v);
+            }
+            for k in extra {
+                if !m.contains_key(k) {
+                    right.remove_mut(k);
+                }
+            }
+            assert_eq!(left, right);
+            for (k, v) in m {
+                assert_ne!(left, right.insert(*k, v.wrapping_add(1)));
+                assert_ne!(left, right.remove(k));
+            }
+        }
+
+        #[test]
+        fn order_matches_sorted_entries(
+            ref left in collection::btree_map(i16::ANY, i16::ANY, 0..50),
+            ref right in collection::btree_map(i16::ANY, i16::ANY, 0..50)
+        ) {
+            let hash_left: HashMap<i16, i16> = left.clone().into_iter().collect();
+            let hash_right: HashMap<i16, i16> = right.clone().into_iter().collect();
+            assert_eq!(left.cmp(right), hash_left.cmp(&hash_right));
+            assert_eq!(left.partial_cmp(right), hash_left.partial_cmp(&hash_right));
+            if left == right {
+                assert_eq!(hash(&hash_left), hash(&hash_right));
+            }
+        }
+    }
+
+    // The changes from one map to another, in a canonical order.
+    fn sorted_diff<S: BuildHasher>(
+        old: &HashMap<i16, i16, S>,
+        new: &HashMap<i16, i16, S>,
+    ) -> Vec<(i16, Option<i16>, Option<i16>)> {
+        let mut changes: Vec<_> = old.diff(new)
+            .map(|change| match change {
+                DiffItem::Add((key, value)) => (*key, None, Some(*value)),
+                DiffItem::Remove((key, value)) => (*key, Some(*value), None),
+                DiffItem::Update { old, new } => (*old.0, Some(*old.1), Some(*new.1)),
+            })
+            .collect();
+        changes.sort();
+        changes
+    }
+
+    fn naive_diff(
+        old: &collections::BTreeMap<i16, i16>,
+        new: &collections::BTreeMap<i16, i16>,
+    ) -> Vec<(i16, Option<i16>, Option<i16>)> {
+        let keys: collections::BTreeSet<i16> = old.keys().chain(new.keys()).cloned().collect();
+        keys.into_iter()
+            .map(|key| (key, old.get(&key).cloned(), new.get(&key).cloned()))
+            .filter(|&(_, old, new)| old != new)
+            .collect()
+    }
+
+    #[test]
+    fn merge3_shares_unchanged_subtrees() {
+        let base: HashMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let ours = base.insert(10, 0);
+        let theirs = base.insert(9000, 0).remove(&5000);
+        let merged = HashMap::merge3(&base, &ours, &theirs, |_, _, _, _| unreachable!());
+        assert_eq!(Some(Arc::new(0)), merged.get(&10));
+        assert_eq!(Some(Arc::new(0)), merged.get(&9000));
+        assert_eq!(9999, merged.len());
+        let base_nodes = base.heap_stats().nodes;
+        let both = HeapSize::shared_heap_stats(&[&base, &merged]).nodes;
+        assert!(both < base_nodes + 3 * merged.heap_stats().depth);
+    }
+
+    proptest! {
+        #[test]
+        fn diff_matches_model(
+            ref old in collection::hash_map(i16::ANY, i16::ANY, 0..200),
+            ref edits in collection::vec((i16::ANY, i16::ANY, ::proptest::bool::ANY), 0..50)
+        ) {
+            let edit = |map: &collections::BTreeMap<i16, i16>| {
+                let mut map = map.clone();
+                for &(key, value, remove) in edits {
+                    if remove {
+                        // Mostly remove keys which are in the map.
+                        let index = key as u16 as usize % (old.len() + 1);
+                        map.remove(old.keys().nth(index).unwrap_or(&key));
+                    } else {
+                        map.insert(key, value);
+                    }
+                }
+                map
+            };
+            let old_model: collections::BTreeMap<i16, i16> = old.iter().map(|(k, v)| (*k, *v)).collect();
+            let new_model = edit(&old_model);
+            let expected = naive_diff(&old_model, &new_model);
+
+            let map: HashMap<i16, i16> = old_model.iter().map(|(k, v)| (*k, *v)).collect();
+            let mut new = map.clone();
+            for (key, _, _) in &expected {
+                match new_model.get(key) {
+                    Some(value) => new.insert_mut(*key, *value),
+                    None => new.remove_mut(key),
+                }
+            }
+            assert_eq!(expected, sorted_diff(&map, &new));
+            // Different hashers can't share anything.
+            let rebuilt: HashMap<i16, i16> = new.iter().collect();
+            assert_eq!(expected, sorted_diff(&map, &rebuilt));
+
+            let hasher = BuildHasherDefault::<CollidingHasher>::default();
+            let map = HashMap::with_hasher(hasher).union(map.iter().collect::<HashMap<_, _, _>>());
+            let mut new = map.clone();
+            for (key, _, _) in &expected {
+                match new_model.get(key) {
+                    Some(value) => new.insert_mut(*key, *value),
+                    None => new.remove_mut(key),
+                }
+            }
+            assert_eq!(expected, sorted_diff(&map, &new));
+        }
+
+        #[test]
+        fn merge3_matches_model(
+            ref base in collection::vec((0..64u8, i16::ANY), 0..100),
+            ref ours in collection::vec((0..64u8, i16::ANY, ::proptest::bool::ANY), 0..20),
+            ref theirs in collection::vec((0..64u8, i16::ANY, ::proptest::bool::ANY), 0..20)
+        ) {
+            let base_model: collections::BTreeMap<u8, i16> = base.iter().cloned().collect();
+            let ours_model = edit_model(&base_model, ours);
+            let theirs_model = edit_model(&base_model, theirs);
+            let (expected, expected_conflicts) =
+                merge3_model(&base_model, &ours_model, &theirs_model);
+
+            let edit = |map: &HashMap<u8, i16, BuildHasherDefault<LolHasher>>, edits: &[Edit]| {
+                edits.iter().fold(map.clone(), |map, &(key, value, remove)| {
+                    if remove { map.remove(&key) } else { map.insert(key, value) }
+                })
+            };
+            let base: HashMap<u8, i16, BuildHasherDefault<LolHasher>> = base.iter().cloned().collect();
+            let (ours, theirs) = (edit(&base, ours), edit(&base, theirs));
+            let mut conflicts = 0;
+            let merged = HashMap::merge3(&base, &ours, &theirs, |_, old, left, right| {
+                assert!(left != old && right != old && left != right);
+                conflicts += 1;
+                ::std::cmp::max(left, right)
+            });
+            merged.assert_invariants();
+            assert_eq!(expected_conflicts, conflicts);
+            assert_eq!(expected, merged.iter().map(|(k, v)| (*k, *v)).collect());
+        }
     }
 }
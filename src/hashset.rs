@@ -14,17 +14,25 @@
 
 #![cfg_attr(feature = "clippy", allow(implicit_hasher))]
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, ToOwned};
 use std::cmp::Ordering;
+#[cfg(feature = "std")]
 use std::collections::hash_map::RandomState;
-use std::collections::{self, BTreeSet};
+use std::collections::BTreeSet;
+#[cfg(feature = "std")]
+use std::collections;
 use std::fmt::{Debug, Error, Formatter};
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::{FromIterator, IntoIterator, Sum};
 use std::ops::{Add, Mul};
 use std::sync::Arc;
+use std::vec::Vec;
 
-use bits::hash_key;
+use bits::{hash_key, hash_unordered};
+use footprint::{Footprint, HeapSize};
+#[cfg(any(test, feature = "dot"))]
+use dot::{Dot, ToDot};
+use invariant::InvariantError;
 use nodes::hamt::{HashValue, Iter, Node};
 use ordset::OrdSet;
 use shared::Shared;
@@ -65,12 +73,29 @@ macro_rules! hashset {
 /// [`HashMap`][hashmap::HashMap].
 ///
 /// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+#[cfg(feature = "std")]
 pub struct HashSet<A, S = RandomState> {
     hasher: Arc<S>,
     root: Arc<Node<Arc<A>>>,
     size: usize,
 }
 
+/// A hash set.
+///
+/// An immutable hash set, implemented as a
+/// [`HashMap`][hashmap::HashMap] with no values.
+///
+/// Without the `std` feature there is no default hasher, so one must
+/// always be given through `S`.
+///
+/// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+#[cfg(not(feature = "std"))]
+pub struct HashSet<A, S> {
+    hasher: Arc<S>,
+    root: Arc<Node<Arc<A>>>,
+    size: usize,
+}
+
 impl<A: Hash + Eq> HashValue for Arc<A> {
     type Key = A;
 
@@ -83,6 +108,7 @@ impl<A: Hash + Eq> HashValue for Arc<A> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<A> HashSet<A, RandomState>
 where
     A: Hash + Eq,
@@ -136,6 +162,18 @@ impl<A, S> HashSet<A, S> {
         self.len() == 0
     }
 
+    /// Test whether two sets refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a set to
+    /// itself, or if you're comparing a set to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
     /// Get the size of a set.
     ///
     /// Time: O(1)
@@ -159,23 +197,27 @@ where
     A: Hash + Eq,
     S: BuildHasher,
 {
+    // The values of the set in sorted order, which puts equal sets in
+    // the same order regardless of their hashers.
+    fn sorted_values<F>(&self, cmp: F) -> Vec<Arc<A>>
+    where
+        F: Fn(&A, &A) -> Ordering,
+    {
+        let mut values: Vec<_> = self.iter().collect();
+        values.sort_by(|left, right| cmp(left, right));
+        values
+    }
+
     fn test_eq(&self, other: &Self) -> bool {
         if self.len() != other.len() {
             return false;
         }
-        let mut seen = collections::HashSet::new();
-        for value in self.iter() {
-            if !other.contains(&value) {
-                return false;
-            }
-            seen.insert(value);
-        }
-        for value in other.iter() {
-            if !seen.contains(&value) {
-                return false;
-            }
+        if Arc::ptr_eq(&self.hasher, &other.hasher) {
+            return Arc::ptr_eq(&self.root, &other.root) || self.root.equal(&other.root, 0);
         }
-        true
+        // With equal sizes, finding every value of `self` in `other`
+        // means there's nothing left over in `other`.
+        self.iter().all(|value| other.contains(&value))
     }
 
     /// Construct an empty hash set using the provided hasher.
@@ -389,6 +431,38 @@ where
     {
         self.len() != other.borrow().len() && self.is_subset(other)
     }
+
+    /// Check the internal structure of a set, returning a description
+    /// of the first broken invariant found, if any.
+    ///
+    /// This checks that the bitmaps of the set's nodes agree with
+    /// their contents, that every value is stored where its hash says
+    /// it should be, that collision nodes only hold distinct values
+    /// with identical hashes, that no subnode should have been merged
+    /// into its parent, and that the size of the set is correct. See
+    /// the [`invariant`][invariant] module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let hasher = &*self.hasher;
+        self.root
+            .check(self.size, &|key| hash_key(hasher, key))
+            .map_err(|message| InvariantError::new("HashSet", message))
+    }
+
+    /// Panic if the internal structure of a set is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
 }
 
 // Core traits
@@ -405,7 +479,7 @@ impl<A, S> Clone for HashSet<A, S> {
 
 impl<A: Hash + Eq, S: BuildHasher + Default> PartialEq for HashSet<A, S> {
     fn eq(&self, other: &Self) -> bool {
-        self.test_eq(other)
+        self.ptr_eq(other) || self.test_eq(other)
     }
 }
 
@@ -413,23 +487,22 @@ impl<A: Hash + Eq, S: BuildHasher + Default> Eq for HashSet<A, S> {}
 
 impl<A: Hash + Eq + PartialOrd, S: BuildHasher + Default> PartialOrd for HashSet<A, S> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if Arc::ptr_eq(&self.hasher, &other.hasher) {
-            return self.iter().partial_cmp(other.iter());
+        if Arc::ptr_eq(&self.root, &other.root) {
+            return Some(Ordering::Equal);
         }
-        let m1: ::std::collections::HashSet<Arc<A>> = self.iter().collect();
-        let m2: ::std::collections::HashSet<Arc<A>> = other.iter().collect();
-        m1.iter().partial_cmp(m2.iter())
+        let cmp = |left: &A, right: &A| left.partial_cmp(right).unwrap_or(Ordering::Equal);
+        self.sorted_values(cmp)
+            .partial_cmp(&other.sorted_values(cmp))
     }
 }
 
 impl<A: Hash + Eq + Ord, S: BuildHasher + Default> Ord for HashSet<A, S> {
     fn cmp(&self, other: &Self) -> Ordering {
-        if Arc::ptr_eq(&self.hasher, &other.hasher) {
-            return self.iter().cmp(other.iter());
+        if Arc::ptr_eq(&self.root, &other.root) {
+            return Ordering::Equal;
         }
-        let m1: ::std::collections::HashSet<Arc<A>> = self.iter().collect();
-        let m2: ::std::collections::HashSet<Arc<A>> = other.iter().collect();
-        m1.iter().cmp(m2.iter())
+        self.sorted_values(Ord::cmp)
+            .cmp(&other.sorted_values(Ord::cmp))
     }
 }
 
@@ -438,9 +511,7 @@ impl<A: Hash + Eq, S: BuildHasher + Default> Hash for HashSet<A, S> {
     where
         H: Hasher,
     {
-        for i in self.iter() {
-            i.hash(state);
-        }
+        hash_unordered(self.len(), self.iter(), state)
     }
 }
 
@@ -454,6 +525,25 @@ impl<A: Hash + Eq, S: BuildHasher + Default> Default for HashSet<A, S> {
     }
 }
 
+impl<A, S> HeapSize for HashSet<A, S> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        Node::measure_heap(&self.root, footprint, &mut |footprint, value| {
+            footprint.value(value);
+        })
+    }
+}
+
+#[cfg(any(test, feature = "dot"))]
+impl<A, S> ToDot for HashSet<A, S> {
+    fn dot_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write_dot(&self, dot: &mut Dot, from: &str) {
+        Node::write_dot(&self.root, dot, from)
+    }
+}
+
 impl<A: Hash + Eq, S: BuildHasher> Add for HashSet<A, S> {
     type Output = HashSet<A, S>;
 
@@ -515,27 +605,12 @@ where
     }
 }
 
-#[cfg(not(has_specialisation))]
 impl<A: Hash + Eq + Debug, S: BuildHasher + Default> Debug for HashSet<A, S> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         f.debug_set().entries(self.iter()).finish()
     }
 }
 
-#[cfg(has_specialisation)]
-impl<A: Hash + Eq + Debug, S: BuildHasher + Default> Debug for HashSet<A, S> {
-    default fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.debug_set().entries(self.iter()).finish()
-    }
-}
-
-#[cfg(has_specialisation)]
-impl<A: Hash + Eq + Debug + Ord, S: BuildHasher + Default> Debug for HashSet<A, S> {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.debug_set().entries(self.iter()).finish()
-    }
-}
-
 // Iterators
 
 impl<A: Hash + Eq, RA, S> FromIterator<RA> for HashSet<A, S>
@@ -617,12 +692,14 @@ impl<'a, A: Hash + Eq, S: BuildHasher + Default> From<&'a Vec<Arc<A>>> for HashS
     }
 }
 
+#[cfg(feature = "std")]
 impl<A: Eq + Hash, S: BuildHasher + Default> From<collections::HashSet<A>> for HashSet<A, S> {
     fn from(hash_set: collections::HashSet<A>) -> Self {
         hash_set.into_iter().collect()
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a, A: Eq + Hash + Clone, S: BuildHasher + Default> From<&'a collections::HashSet<A>>
     for HashSet<A, S>
 {
@@ -631,6 +708,7 @@ impl<'a, A: Eq + Hash + Clone, S: BuildHasher + Default> From<&'a collections::H
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a, A: Eq + Hash, S: BuildHasher + Default> From<&'a collections::HashSet<Arc<A>>>
     for HashSet<A, S>
 {
@@ -689,6 +767,8 @@ pub mod proptest {
     use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
     use std::ops::Range;
 
+    pub use ordset::proptest::set_ops;
+
     /// A strategy for a hash set of a given size.
     ///
     /// # Examples
@@ -722,6 +802,15 @@ pub mod proptest {
 mod test {
     use super::proptest::*;
     use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+    use test::LolHasher;
+
+    fn hash<A: Hash>(value: &A) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
 
     #[test]
     fn match_strings_with_string_slices() {
@@ -732,11 +821,53 @@ mod test {
         assert!(!set.contains("foo"));
     }
 
+    #[test]
+    fn hash_and_order_ignore_hasher() {
+        let left: HashSet<i32> = (0..1000).collect();
+        let right: HashSet<i32, BuildHasherDefault<LolHasher>> = (0..1000).rev().collect();
+        let other: HashSet<i32> = (0..1000).rev().collect();
+        assert_eq!(hash(&left), hash(&right));
+        assert_eq!(hash(&left), hash(&other));
+        assert_eq!(Ordering::Equal, left.cmp(&other));
+        assert_eq!(Some(Ordering::Equal), left.partial_cmp(&other));
+        assert_eq!(Ordering::Less, left.cmp(&left.insert(1000)));
+        assert_eq!(Ordering::Greater, left.cmp(&left.remove(&999)));
+    }
+
+    #[test]
+    fn validate_finds_wrong_size() {
+        let mut set: HashSet<i32> = (0..1000).collect();
+        assert_eq!(Ok(()), set.validate());
+        set.size -= 1;
+        assert_eq!("HashSet", set.validate().unwrap_err().collection());
+    }
+
     proptest! {
+        #[test]
+        fn removals_keep_invariants(ref values in ::proptest::collection::vec(::proptest::num::i16::ANY, 0..200)) {
+            let mut set: HashSet<i16, BuildHasherDefault<LolHasher>> = values.iter().cloned().collect();
+            set.assert_invariants();
+            for value in values {
+                set.remove_mut(value);
+                set.assert_invariants();
+            }
+        }
+
         #[test]
         fn proptest_a_set(ref s in hash_set(".*", 10..100)) {
             assert!(s.len() < 100);
             assert!(s.len() >= 10);
         }
+
+        #[test]
+        fn order_matches_sorted_values(
+            ref left in ::proptest::collection::btree_set(::proptest::num::i16::ANY, 0..50),
+            ref right in ::proptest::collection::btree_set(::proptest::num::i16::ANY, 0..50)
+        ) {
+            let hash_left: HashSet<i16> = left.iter().cloned().collect();
+            let hash_right: HashSet<i16> = right.iter().cloned().collect();
+            assert_eq!(left.cmp(right), hash_left.cmp(&hash_right));
+            assert_eq!(left == right, hash(&hash_left) == hash(&hash_right));
+        }
     }
 }
@@ -0,0 +1,517 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! An undo history of persistent values.
+//!
+//! Keeping every version of a persistent collection around is cheap,
+//! because each version shares most of its structure with the ones
+//! before it, which makes them a natural fit for implementing undo. A
+//! [`History`][History] keeps a tree of committed versions, or
+//! revisions, of a value of any type: it can [`undo`][History::undo]
+//! back towards the first one and [`redo`][History::redo] forward
+//! again, and committing after an undo starts a new branch rather
+//! than throwing away the revisions which were undone, so those can
+//! still be reached with [`redo_branch`][History::redo_branch].
+//!
+//! A history can be given a limit on how many revisions it keeps, in
+//! which case the oldest ones are dropped as new ones are committed,
+//! and several commits can be grouped into a single revision with a
+//! [`transaction`][History::transaction], so that they're undone
+//! together.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::history::History;
+//! # use std::sync::Arc;
+//! # fn main() {
+//! let mut history = History::new(vector![1, 2, 3]);
+//! let next = history.current().push_back(4);
+//! history.commit(next);
+//! assert_eq!(Some(Arc::new(vector![1, 2, 3])), history.undo());
+//! assert_eq!(Some(Arc::new(vector![1, 2, 3, 4])), history.redo());
+//! # }
+//! ```
+//!
+//! [History]: ./struct.History.html
+//! [History::undo]: ./struct.History.html#method.undo
+//! [History::redo]: ./struct.History.html#method.redo
+//! [History::redo_branch]: ./struct.History.html#method.redo_branch
+//! [History::transaction]: ./struct.History.html#method.transaction
+
+use std::cmp::max;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::{Footprint, HeapSize};
+use shared::Shared;
+use vector::Vector;
+
+#[derive(Clone, Debug)]
+struct Revision<T> {
+    value: Arc<T>,
+    parent: Option<usize>,
+    // The revisions committed on top of this one, oldest first.
+    children: Vec<usize>,
+    // The child `redo` goes to: the one most recently committed or
+    // undone from.
+    redo: Option<usize>,
+}
+
+impl<T> Revision<T> {
+    fn new(value: Arc<T>, parent: Option<usize>) -> Self {
+        Revision {
+            value,
+            parent,
+            children: Vec::new(),
+            redo: None,
+        }
+    }
+}
+
+/// A tree of revisions of a value, with undo and redo.
+///
+/// Revisions are numbered in the order they're committed, starting
+/// from zero for the initial value. Moving around the history never
+/// changes these numbers, and dropping old revisions to stay within
+/// the history's limit doesn't renumber the ones which are kept.
+///
+/// Each revision is stored as an `Arc<T>`, so moving between them
+/// never copies a value, and committing a value derived from the
+/// current one through a persistent collection's update methods
+/// only costs the memory those updates allocated.
+#[derive(Clone, Debug)]
+pub struct History<T> {
+    // The revisions kept, the first one numbered `first`.
+    revisions: Vector<Revision<T>>,
+    first: usize,
+    current: usize,
+    limit: usize,
+    // The number of transactions open, and the revision committed
+    // inside them, if any.
+    transactions: usize,
+    grouped: Option<usize>,
+}
+
+impl<T: Clone> History<T> {
+    /// Construct a history with a single revision holding `value`,
+    /// which keeps every revision committed to it.
+    pub fn new<R>(value: R) -> Self
+    where
+        R: Shared<T>,
+    {
+        History::with_limit(value, usize::MAX)
+    }
+
+    /// Construct a history with a single revision holding `value`,
+    /// which keeps at most `limit` revisions.
+    ///
+    /// A limit of zero is treated as one.
+    pub fn with_limit<R>(value: R, limit: usize) -> Self
+    where
+        R: Shared<T>,
+    {
+        History {
+            revisions: Vector::singleton(Revision::new(value.shared(), None)),
+            first: 0,
+            current: 0,
+            limit: max(limit, 1),
+            transactions: 0,
+            grouped: None,
+        }
+    }
+
+    /// Get the value of the current revision.
+    ///
+    /// Time: O(log n)
+    pub fn current(&self) -> Arc<T> {
+        self.get(self.current).value.clone()
+    }
+
+    /// Get the number of the current revision.
+    pub fn revision(&self) -> usize {
+        self.current
+    }
+
+    /// Get the value of a revision, if it's still kept.
+    ///
+    /// Time: O(log n)
+    pub fn value(&self, revision: usize) -> Option<Arc<T>> {
+        revision
+            .checked_sub(self.first)
+            .and_then(|index| self.revisions.get(index))
+            .map(|revision| revision.value.clone())
+    }
+
+    /// Get the number of revisions kept.
+    pub fn revision_count(&self) -> usize {
+        self.revisions.len()
+    }
+
+    /// Get the most revisions this history keeps.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Change the most revisions this history keeps, dropping the
+    /// oldest ones if it holds more than that.
+    ///
+    /// A limit of zero is treated as one.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = max(limit, 1);
+        self.prune();
+    }
+
+    /// Commit a new revision on top of the current one, and make it
+    /// the current revision.
+    ///
+    /// Inside a transaction, only the first commit creates a
+    /// revision, and later ones replace its value.
+    ///
+    /// Time: O(log n)
+    pub fn commit<R>(&mut self, value: R)
+    where
+        R: Shared<T>,
+    {
+        let value = value.shared();
+        if self.transactions > 0 && self.grouped == Some(self.current) {
+            let current = self.current;
+            self.update(current, |revision| revision.value = value);
+            return;
+        }
+        let parent = self.current;
+        let revision = self.first + self.revisions.len();
+        self.revisions
+            .push_back_mut(Revision::new(value, Some(parent)));
+        self.update(parent, |parent| {
+            parent.children.push(revision);
+            parent.redo = Some(revision);
+        });
+        self.current = revision;
+        if self.transactions > 0 {
+            self.grouped = Some(revision);
+        }
+        self.prune();
+    }
+
+    /// Test whether there's a revision to undo to.
+    pub fn can_undo(&self) -> bool {
+        self.get(self.current).parent.is_some()
+    }
+
+    /// Go back to the revision the current one was committed on top
+    /// of, and return its value, or return `None` if there's none.
+    ///
+    /// Undoing inside a transaction ends the revision the
+    /// transaction was committing to, so later commits in it start a
+    /// new one.
+    ///
+    /// Time: O(log n)
+    pub fn undo(&mut self) -> Option<Arc<T>> {
+        let from = self.current;
+        let to = self.get(from).parent?;
+        self.update(to, |revision| revision.redo = Some(from));
+        self.move_to(to)
+    }
+
+    /// Test whether there's a revision to redo.
+    pub fn can_redo(&self) -> bool {
+        self.get(self.current).redo.is_some()
+    }
+
+    /// Go forward to the revision most recently committed on top of
+    /// the current one, or most recently undone from, and return its
+    /// value, or return `None` if there's none.
+    ///
+    /// Time: O(log n)
+    pub fn redo(&mut self) -> Option<Arc<T>> {
+        let to = self.get(self.current).redo?;
+        self.move_to(to)
+    }
+
+    /// Get the numbers of the revisions committed on top of the
+    /// current one, oldest first, which are the branches
+    /// [`redo_branch`][redo_branch] can go to.
+    ///
+    /// [redo_branch]: #method.redo_branch
+    pub fn branches(&self) -> Vec<usize> {
+        self.get(self.current).children.clone()
+    }
+
+    /// Go forward to the given revision, which must have been
+    /// committed on top of the current one, and return its value, or
+    /// return `None` if it wasn't.
+    ///
+    /// Later calls to [`redo`][redo] from the current revision will
+    /// follow the same branch.
+    ///
+    /// Time: O(log n)
+    ///
+    /// [redo]: #method.redo
+    pub fn redo_branch(&mut self, revision: usize) -> Option<Arc<T>> {
+        let from = self.current;
+        if !self.get(from).children.contains(&revision) {
+            return None;
+        }
+        self.update(from, |current| current.redo = Some(revision));
+        self.move_to(revision)
+    }
+
+    /// Start a transaction, grouping the commits made until the
+    /// matching [`end_transaction`][end_transaction] into a single
+    /// revision.
+    ///
+    /// Transactions can be nested, in which case the commits are
+    /// grouped until the outermost one ends.
+    ///
+    /// [end_transaction]: #method.end_transaction
+    pub fn begin_transaction(&mut self) {
+        self.transactions += 1;
+    }
+
+    /// End a transaction started with
+    /// [`begin_transaction`][begin_transaction].
+    ///
+    /// [begin_transaction]: #method.begin_transaction
+    pub fn end_transaction(&mut self) {
+        self.transactions = self.transactions.saturating_sub(1);
+        if self.transactions == 0 {
+            self.grouped = None;
+        }
+    }
+
+    /// Call `f` inside a transaction, so that all of the commits it
+    /// makes become a single revision.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::history::History;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let mut history = History::new(ordset![1]);
+    /// history.transaction(|history| {
+    ///     let next = history.current().insert(2);
+    ///     history.commit(next);
+    ///     let next = history.current().insert(3);
+    ///     history.commit(next);
+    /// });
+    /// assert_eq!(2, history.revision_count());
+    /// assert_eq!(Some(Arc::new(ordset![1])), history.undo());
+    /// # }
+    /// ```
+    pub fn transaction<F, A>(&mut self, f: F) -> A
+    where
+        F: FnOnce(&mut Self) -> A,
+    {
+        self.begin_transaction();
+        let result = f(self);
+        self.end_transaction();
+        result
+    }
+
+    fn get(&self, revision: usize) -> Arc<Revision<T>> {
+        self.revisions.get_unwrapped(revision - self.first)
+    }
+
+    fn update<F>(&mut self, revision: usize, f: F)
+    where
+        F: FnOnce(&mut Revision<T>),
+    {
+        let index = revision - self.first;
+        let mut updated = (*self.revisions.get_unwrapped(index)).clone();
+        f(&mut updated);
+        self.revisions.set_mut(index, updated);
+    }
+
+    fn move_to(&mut self, revision: usize) -> Option<Arc<T>> {
+        self.current = revision;
+        self.grouped = None;
+        Some(self.current())
+    }
+
+    // Drop the oldest revisions until there are no more than the
+    // limit, stopping at the current one. Parents always come before
+    // their children, so the oldest revision is always a root.
+    fn prune(&mut self) {
+        while self.revisions.len() > self.limit && self.first != self.current {
+            let oldest = self.revisions.pop_front_mut().unwrap();
+            self.first += 1;
+            for child in &oldest.children {
+                self.update(*child, |child| child.parent = None);
+            }
+        }
+    }
+}
+
+impl<T: HeapSize> HeapSize for History<T> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        let mut depth = 0;
+        let timeline = self.revisions
+            .measure_heap_with(footprint, &mut |footprint, revision| {
+                if footprint.value(revision) {
+                    footprint.buffer::<usize>(revision.children.capacity());
+                    if footprint.value(&revision.value) {
+                        depth = max(depth, revision.value.measure_heap(footprint));
+                    }
+                }
+            });
+        max(timeline, depth)
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ordmap::OrdMap;
+    use proptest::collection;
+    use proptest::num::{i32, u8};
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::new(vector![1]);
+        assert!(!history.can_undo() && !history.can_redo());
+        assert_eq!(None, history.undo());
+        for i in 2..5 {
+            let next = history.current().push_back(i);
+            history.commit(next);
+        }
+        assert_eq!(3, history.revision());
+        assert_eq!(Some(Arc::new(vector![1, 2, 3])), history.undo());
+        assert_eq!(Some(Arc::new(vector![1, 2])), history.undo());
+        assert!(history.can_undo() && history.can_redo());
+        assert_eq!(Some(Arc::new(vector![1, 2, 3])), history.redo());
+        assert_eq!(Some(Arc::new(vector![1, 2, 3, 4])), history.redo());
+        assert_eq!(None, history.redo());
+        assert_eq!(Some(Arc::new(vector![1, 2])), history.value(1));
+    }
+
+    #[test]
+    fn branches() {
+        let mut history = History::new(0);
+        history.commit(1);
+        history.commit(2);
+        history.undo();
+        history.undo();
+        history.commit(3);
+        assert_eq!(3, history.revision());
+        assert_eq!(Some(Arc::new(0)), history.undo());
+        assert_eq!(vec![1, 3], history.branches());
+        // Redo follows the branch most recently undone from.
+        assert_eq!(Some(Arc::new(3)), history.redo());
+        history.undo();
+        assert_eq!(None, history.redo_branch(2));
+        assert_eq!(Some(Arc::new(1)), history.redo_branch(1));
+        assert_eq!(Some(Arc::new(2)), history.redo());
+        history.undo();
+        history.undo();
+        assert_eq!(Some(Arc::new(1)), history.redo());
+    }
+
+    #[test]
+    fn limit_drops_oldest() {
+        let mut history = History::with_limit(0, 3);
+        for i in 1..10 {
+            history.commit(i);
+        }
+        assert_eq!(3, history.revision_count());
+        assert_eq!(None, history.value(6));
+        assert_eq!(Some(Arc::new(8)), history.undo());
+        assert_eq!(Some(Arc::new(7)), history.undo());
+        assert_eq!(None, history.undo());
+
+        // The current revision is never dropped.
+        history.set_limit(1);
+        assert_eq!(3, history.revision_count());
+        assert_eq!(Arc::new(7), history.current());
+        history.commit(10);
+        assert_eq!(1, history.revision_count());
+        assert!(!history.can_undo());
+        assert_eq!(Arc::new(10), history.current());
+    }
+
+    #[test]
+    fn transactions_group_commits() {
+        let mut history = History::new(ordset![0]);
+        history.begin_transaction();
+        history.transaction(|history| {
+            for i in 1..5 {
+                let next = history.current().insert(i);
+                history.commit(next);
+            }
+        });
+        history.commit(history.current().insert(5));
+        history.end_transaction();
+        assert_eq!(2, history.revision_count());
+        assert_eq!(Arc::new(ordset![0, 1, 2, 3, 4, 5]), history.current());
+        assert_eq!(Some(Arc::new(ordset![0])), history.undo());
+        history.commit(ordset![6]);
+        assert_eq!(3, history.revision_count());
+
+        // Undoing inside a transaction ends its revision.
+        history.transaction(|history| {
+            history.commit(ordset![7]);
+            history.undo();
+            history.commit(ordset![8]);
+        });
+        assert_eq!(5, history.revision_count());
+    }
+
+    #[test]
+    fn revisions_share_memory() {
+        let map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let mut history = History::new(map.clone());
+        for i in 0..100 {
+            let next = history.current().insert(i, -i);
+            history.commit(next);
+        }
+        assert!(history.heap_size() < 2 * map.heap_size());
+        assert!(history.heap_size() > map.heap_size());
+    }
+
+    proptest! {
+        // Without branching, the history behaves like a pair of
+        // undo and redo stacks.
+        #[test]
+        fn matches_undo_stacks(ref ops in collection::vec((u8::ANY, i32::ANY), 0..200)) {
+            let mut history = History::new(0);
+            let mut current = 0;
+            let mut undo = Vec::new();
+            let mut redo = Vec::new();
+            for &(op, value) in ops {
+                match op % 3 {
+                    0 => {
+                        history.commit(value);
+                        undo.push(current);
+                        current = value;
+                        redo.clear();
+                    }
+                    1 => {
+                        let expected = undo.pop().map(|previous| {
+                            redo.push(current);
+                            current = previous;
+                            Arc::new(previous)
+                        });
+                        assert_eq!(expected, history.undo());
+                    }
+                    _ => {
+                        let expected = redo.pop().map(|next| {
+                            undo.push(current);
+                            current = next;
+                            Arc::new(next)
+                        });
+                        assert_eq!(expected, history.redo());
+                    }
+                }
+                assert_eq!(Arc::new(current), history.current());
+                assert_eq!(!undo.is_empty(), history.can_undo());
+                assert_eq!(!redo.is_empty(), history.can_redo());
+            }
+        }
+    }
+}
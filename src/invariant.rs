@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Checking the internal structure of collections.
+//!
+//! Every collection has a `validate` method, which walks its internal
+//! structure and checks the invariants the rest of its code relies
+//! on, and an `assert_invariants` method, which panics if any of them
+//! don't hold. These are mostly useful when testing code which builds
+//! collections in unusual ways, or when chasing down a bug in this
+//! crate: a broken invariant will usually cause wrong results well
+//! after the operation which broke it.
+//!
+//! What's checked depends on the collection:
+//!
+//! * [`OrdMap`][ordmap::OrdMap] and [`OrdSet`][ordset::OrdSet] check
+//!   that the keys of their B-tree are in order, that every node is
+//!   full enough and every leaf is at the same depth, and that the
+//!   count of values kept in each node is correct.
+//! * [`HashMap`][hashmap::HashMap] and [`HashSet`][hashset::HashSet]
+//!   check that the bitmaps of each node agree with its contents,
+//!   that every value sits where its hash says it should, that
+//!   collision nodes hold at least two distinct keys with the same
+//!   hash, and that no subnode could have been merged into its
+//!   parent.
+//! * [`Vector`][vector::Vector] checks that its origin, capacity and
+//!   level fit together, and that every index leads to a value.
+//! * [`ConsList`][conslist::ConsList] and
+//!   [`CatList`][catlist::CatList] check that their cached lengths
+//!   are correct.
+//!
+//! Validation visits every node, so it takes linear time.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::ordmap::OrdMap;
+//! # fn main() {
+//! let map: OrdMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+//! assert_eq!(Ok(()), map.remove(&500).validate());
+//! # }
+//! ```
+//!
+//! [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+//! [ordset::OrdSet]: ../ordset/struct.OrdSet.html
+//! [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+//! [hashset::HashSet]: ../hashset/struct.HashSet.html
+//! [vector::Vector]: ../vector/struct.Vector.html
+//! [conslist::ConsList]: ../conslist/struct.ConsList.html
+//! [catlist::CatList]: ../catlist/struct.CatList.html
+
+#[cfg(feature = "std")]
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::string::String;
+
+/// A description of a broken invariant, found by a collection's
+/// `validate` method.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantError {
+    collection: &'static str,
+    message: String,
+}
+
+impl InvariantError {
+    pub(crate) fn new(collection: &'static str, message: String) -> Self {
+        InvariantError {
+            collection,
+            message,
+        }
+    }
+
+    /// The name of the type of collection which is broken.
+    pub fn collection(&self) -> &'static str {
+        self.collection
+    }
+
+    /// A description of what's wrong with it.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for InvariantError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.collection, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for InvariantError {}
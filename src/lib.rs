@@ -146,6 +146,7 @@
 //! | --- | --- | --- | --- | --- | --- | --- |
 //! | [`Vector<A>`][vector::Vector] | | insertion | O(log n) | O(log n) | O(log n) | O(log n) | O(n) | O(log n) |
 //! | [`CatList<A>`][catlist::CatList] | | insertion | O(1) | O(1)* | O(1) | O(1)* | O(1) | O(n) |
+//! | [`Deque<A>`][fingertree::Deque] | | insertion | O(1)* | O(1)* | O(1)* | O(1)* | O(log n) | O(log n) |
 //! | [`ConsList<A>`][conslist::ConsList] | | insertion | O(1) | O(1) | O(n) | O(n) | O(n) | O(n) |
 //!
 //! ### Maps
@@ -160,6 +161,11 @@
 //! | --- | --- | --- | --- | --- | --- |
 //! | [`HashMap<K, V>`][hashmap::HashMap] | [`Hash`][std::hash::Hash] + [`Eq`][std::cmp::Eq] | undefined | O(log n) | O(log n) | O(log n) |
 //! | [`OrdMap<K, V>`][ordmap::OrdMap] | [`Ord`][std::cmp::Ord] | sorted | O(log n) | O(log n) | O(log n) |
+//! | [`BiMap<L, R>`][bimap::BiMap] | [`Hash`][std::hash::Hash] + [`Eq`][std::cmp::Eq] | undefined | O(log n) | O(log n) | O(log n) |
+//! | [`OrdBiMap<L, R>`][bimap::OrdBiMap] | [`Ord`][std::cmp::Ord] | sorted | O(log n) | O(log n) | O(log n) |
+//! | [`HashMultiMap<K, V>`][multimap::HashMultiMap] | [`Hash`][std::hash::Hash] + [`Eq`][std::cmp::Eq] | undefined | O(log n) | O(log n) | O(log n) |
+//! | [`OrdMultiMap<K, V>`][multimap::OrdMultiMap] | [`Ord`][std::cmp::Ord] | sorted | O(log n) | O(log n) | O(log n) |
+//! | [`TrieMap<K, V>`][triemap::TrieMap] | [`AsRef<[u8]>`][std::convert::AsRef] | sorted | O(k) | O(k) | O(k) |
 //!
 //! ### Sets
 //!
@@ -188,20 +194,55 @@
 //! the node before modifying it, thus preserving the usual guarantees
 //! you get from using an immutable data structure.
 //!
+//! ## Equality
+//!
+//! Comparing two collections for equality skips any parts of them
+//! which share the same memory, treating them as equal without
+//! looking at the elements inside, so comparing a collection with a
+//! modified copy of itself only compares the parts which changed.
+//! This assumes that equality on the elements is reflexive, which is
+//! true for any type implementing [`Eq`][std::cmp::Eq], but not for
+//! every type implementing only [`PartialEq`][std::cmp::PartialEq]: a
+//! [`Vector<f64>`][vector::Vector] containing `NaN` is equal to
+//! itself and to its clones, even though `NaN != NaN`. If you need
+//! the elements compared one by one, compare the collections'
+//! iterators instead, as in `left.iter().eq(right.iter())`.
+//!
+//! ## `no_std`
+//!
+//! The collections only need an allocator, so the crate can be built
+//! without the standard library by turning off its default `std`
+//! feature, as long as the `alloc` crate is available. Without `std`
+//! there's no [`RandomState`][std::collections::hash_map::RandomState],
+//! so [`HashMap`][hashmap::HashMap], [`HashSet`][hashset::HashSet] and
+//! the maps built on them have to be given a hasher explicitly, and
+//! the conversions from the `std::collections` hash types aren't
+//! available. The `quickcheck`, `proptest` and `serde` features
+//! require `std`.
+//!
 //! [std::collections]: https://doc.rust-lang.org/std/collections/index.html
 //! [std::vec::Vec]: https://doc.rust-lang.org/std/vec/struct.Vec.html
 //! [std::sync::Arc]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+//! [std::collections::hash_map::RandomState]: https://doc.rust-lang.org/std/collections/hash_map/struct.RandomState.html
 //! [std::cmp::Eq]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+//! [std::cmp::PartialEq]: https://doc.rust-lang.org/std/cmp/trait.PartialEq.html
 //! [std::cmp::Ord]: https://doc.rust-lang.org/std/cmp/trait.Ord.html
 //! [std::clone::Clone]: https://doc.rust-lang.org/std/clone/trait.Clone.html
 //! [std::clone::Clone::clone]: https://doc.rust-lang.org/std/clone/trait.Clone.html#tymethod.clone
 //! [std::hash::Hash]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+//! [std::convert::AsRef]: https://doc.rust-lang.org/std/convert/trait.AsRef.html
 //! [hashmap::HashMap]: ./hashmap/struct.HashMap.html
 //! [hashset::HashSet]: ./hashset/struct.HashSet.html
 //! [ordmap::OrdMap]: ./ordmap/struct.OrdMap.html
+//! [bimap::BiMap]: ./bimap/struct.BiMap.html
+//! [bimap::OrdBiMap]: ./bimap/struct.OrdBiMap.html
+//! [multimap::HashMultiMap]: ./multimap/struct.HashMultiMap.html
+//! [multimap::OrdMultiMap]: ./multimap/struct.OrdMultiMap.html
+//! [triemap::TrieMap]: ./triemap/struct.TrieMap.html
 //! [ordset::OrdSet]: ./ordset/struct.OrdSet.html
 //! [conslist::ConsList]: ./conslist/struct.ConsList.html
 //! [catlist::CatList]: ./catlist/struct.CatList.html
+//! [fingertree::Deque]: ./fingertree/type.Deque.html
 //! [vector::Vector]: ./vector/struct.Vector.html
 //! [vector::Vector::snoc]: ./vector/struct.Vector.html#method.snoc
 
@@ -210,7 +251,27 @@
 #![cfg_attr(feature = "clippy", plugin(clippy))]
 #![cfg_attr(feature = "clippy", allow(type_complexity))]
 #![cfg_attr(feature = "clippy", allow(unreadable_literal))]
-#![cfg_attr(has_specialisation, feature(specialization))]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+// Without `std`, stand in for it with the parts of `core` and `alloc`
+// we use, so that the rest of the crate can keep importing from
+// `std` either way.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use alloc::{borrow, fmt, str, string, sync, vec};
+    pub use core::{cmp, hash, iter, marker, mem, ops};
+
+    pub mod collections {
+        pub use alloc::collections::*;
+    }
+}
+
+#[cfg(all(not(feature = "std"), any(feature = "quickcheck", feature = "proptest", feature = "serde")))]
+compile_error!("the `quickcheck`, `proptest` and `serde` features require the `std` feature");
 
 #[cfg(test)]
 #[macro_use]
@@ -251,9 +312,32 @@ pub mod hashset;
 pub mod catlist;
 #[macro_use]
 pub mod vector;
+#[macro_use]
+pub mod triemap;
 
+#[cfg(feature = "std")]
+pub mod atom;
+pub mod bimap;
+#[cfg(feature = "std")]
+pub mod crdt;
+#[cfg(any(test, feature = "dot"))]
+pub mod dot;
+pub mod fingertree;
+pub mod footprint;
+pub mod hashed;
+pub mod history;
+pub mod invariant;
 pub mod iter;
+#[cfg(any(test, feature = "proptest", feature = "quickcheck"))]
+pub mod model;
+pub mod multimap;
+pub mod patch;
+pub mod rope;
 pub mod shared;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod stm;
 
 #[cfg(any(test, feature = "serde"))]
 pub mod ser;
@@ -262,16 +346,22 @@ pub mod btree {
     pub use nodes::btree::{DiffItem, DiffIter, Iter};
 }
 
+pub use bimap::{BiMap, OrdBiMap};
 pub use catlist::CatList;
 pub use conslist::ConsList;
+pub use hashed::Hashed;
 pub use hashmap::HashMap;
 pub use hashset::HashSet;
+pub use multimap::{HashMultiMap, OrdMultiMap};
 pub use ordmap::OrdMap;
 pub use ordset::OrdSet;
+pub use triemap::TrieMap;
 pub use vector::Vector;
 
 pub type List<A> = vector::Vector<A>;
+#[cfg(feature = "std")]
 pub type Set<A> = hashset::HashSet<A>;
+#[cfg(feature = "std")]
 pub type Map<K, V> = hashmap::HashMap<K, V>;
 
 #[cfg(test)]
@@ -0,0 +1,929 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Model based testing of collections.
+//!
+//! This module is available with either the `proptest` or the
+//! `quickcheck` feature. Building a collection from a list of values
+//! only ever produces one shape of it, so tests written that way
+//! never reach states like a vector which has been pushed onto at
+//! the front, sliced and reversed, or a map which shares most of its
+//! nodes with several older versions of itself. Instead, this module
+//! describes collections by sequences of operations, such as
+//! [`VectorOp`][VectorOp] or [`MapOp`][MapOp], which can be
+//! generated at random by strategies like
+//! [`vector::proptest::vector_ops`][vector_ops] in each collection's
+//! `proptest` module, or through the [`Arbitrary`][Arbitrary]
+//! instances for the operations.
+//!
+//! [`check_against_model`][check_against_model] applies a sequence of
+//! operations both to a collection and to a simple model of it from
+//! `std::collections`, and checks after every step that the two hold
+//! the same elements and that the collection's invariants hold.
+//! Versions of the collection saved along the way by `Snapshot`
+//! operations are checked again at the end, to make sure updating a
+//! collection never changes the versions it shares nodes with.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::model::{check_against_model, VectorOp};
+//! # use im::vector::Vector;
+//! # fn main() {
+//! let ops = vec![
+//!     VectorOp::PushBack(1),
+//!     VectorOp::PushFront(2),
+//!     VectorOp::Snapshot,
+//!     VectorOp::Reverse,
+//!     VectorOp::Set(0, 3),
+//! ];
+//! let vector: Vector<i32> = check_against_model(&ops).unwrap();
+//! assert_eq!(vector![3, 2], vector);
+//! # }
+//! ```
+//!
+//! [VectorOp]: ./enum.VectorOp.html
+//! [MapOp]: ./enum.MapOp.html
+//! [vector_ops]: ../vector/proptest/fn.vector_ops.html
+//! [Arbitrary]: https://docs.rs/quickcheck/0.6/quickcheck/trait.Arbitrary.html
+//! [check_against_model]: ./fn.check_against_model.html
+
+use std::collections::{self, BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{BuildHasher, Hash};
+
+use catlist::CatList;
+use hashmap::HashMap;
+use hashset::HashSet;
+use ordmap::OrdMap;
+use ordset::OrdSet;
+use vector::Vector;
+
+/// The most elements an operation which appends a list to itself
+/// will leave it with. Past this, it does nothing, so that a long
+/// run of appends can't grow a list exponentially.
+pub const APPEND_LIMIT: usize = 4096;
+
+/// An operation on a [`Vector`][Vector], modelled by a `Vec`.
+///
+/// Indices are taken modulo the length of the vector, and
+/// operations which can't be applied to the vector as it is, such
+/// as `Set` on an empty vector, do nothing.
+///
+/// [Vector]: ../vector/struct.Vector.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VectorOp<A> {
+    /// Push a value onto the front.
+    PushFront(A),
+    /// Push a value onto the back.
+    PushBack(A),
+    /// Remove the first value.
+    PopFront,
+    /// Remove the last value.
+    PopBack,
+    /// Replace the value at an index.
+    Set(usize, A),
+    /// Keep only the values from the first index up to the second.
+    Slice(usize, usize),
+    /// Append the vector to itself.
+    Append,
+    /// Reverse the vector.
+    Reverse,
+    /// Save the current version.
+    Snapshot,
+    /// Go back to a saved version.
+    Restore(usize),
+}
+
+/// An operation on a [`CatList`][CatList], modelled by a `Vec`.
+///
+/// [CatList]: ../catlist/struct.CatList.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListOp<A> {
+    /// Push a value onto the front.
+    PushFront(A),
+    /// Push a value onto the back.
+    PushBack(A),
+    /// Remove the first value.
+    PopFront,
+    /// Remove the last value.
+    PopBack,
+    /// Append the list to itself.
+    Append,
+    /// Reverse the list.
+    Reverse,
+    /// Save the current version.
+    Snapshot,
+    /// Go back to a saved version.
+    Restore(usize),
+}
+
+/// An operation on an [`OrdMap`][OrdMap] or a [`HashMap`][HashMap],
+/// modelled by the corresponding map from `std::collections`.
+///
+/// [OrdMap]: ../ordmap/struct.OrdMap.html
+/// [HashMap]: ../hashmap/struct.HashMap.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapOp<K, V> {
+    /// Insert a key and a value.
+    Insert(K, V),
+    /// Remove a key.
+    Remove(K),
+    /// Add the keys from a saved version which aren't in the map.
+    Union(usize),
+    /// Save the current version.
+    Snapshot,
+    /// Go back to a saved version.
+    Restore(usize),
+}
+
+/// An operation on an [`OrdSet`][OrdSet] or a [`HashSet`][HashSet],
+/// modelled by the corresponding set from `std::collections`.
+///
+/// [OrdSet]: ../ordset/struct.OrdSet.html
+/// [HashSet]: ../hashset/struct.HashSet.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetOp<A> {
+    /// Insert a value.
+    Insert(A),
+    /// Remove a value.
+    Remove(A),
+    /// Add the values from a saved version.
+    Union(usize),
+    /// Save the current version.
+    Snapshot,
+    /// Go back to a saved version.
+    Restore(usize),
+}
+
+/// The operations on saved versions, which every kind of operation
+/// offers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum History {
+    /// Save the current version.
+    Snapshot,
+    /// Go back to the saved version with this index, modulo the
+    /// number of saved versions.
+    Restore(usize),
+}
+
+/// A collection which can be checked against a model.
+pub trait Modelled: Clone + Default {
+    /// The operations which can be applied to it.
+    type Op: Debug;
+    /// A simpler collection which should always hold the same
+    /// elements.
+    type Model: Clone + Default;
+
+    /// Whether an operation saves or restores a version, which
+    /// [`check_against_model`][check_against_model] takes care of,
+    /// rather than changing the collection.
+    ///
+    /// [check_against_model]: ./fn.check_against_model.html
+    fn history(op: &Self::Op) -> Option<History>;
+
+    /// Apply an operation to the collection and to its model, where
+    /// `saved` holds the saved versions of both.
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, saved: &[(Self, Self::Model)]);
+
+    /// Describe the first difference between the collection and its
+    /// model, or the first broken invariant of the collection.
+    fn compare(&self, model: &Self::Model) -> Result<(), String>;
+}
+
+/// A difference between a collection and its model, found by
+/// [`check_against_model`][check_against_model].
+///
+/// [check_against_model]: ./fn.check_against_model.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelError {
+    step: usize,
+    operation: String,
+    message: String,
+}
+
+impl ModelError {
+    /// The index of the operation after which the difference was
+    /// found. If it's the number of operations, the difference is
+    /// in one of the saved versions, checked after the last one.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// The operation after which the difference was found.
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// A description of the difference.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "after step {} ({}): {}",
+            self.step, self.operation, self.message
+        )
+    }
+}
+
+impl Error for ModelError {}
+
+// A collection and its model, along with the saved versions of both.
+struct Run<C: Modelled> {
+    current: (C, C::Model),
+    saved: Vec<(C, C::Model)>,
+}
+
+impl<C: Modelled> Run<C> {
+    fn new() -> Self {
+        Run {
+            current: Default::default(),
+            saved: Vec::new(),
+        }
+    }
+
+    fn step(&mut self, op: &C::Op) {
+        match C::history(op) {
+            Some(History::Snapshot) => self.saved.push(self.current.clone()),
+            Some(History::Restore(index)) => if !self.saved.is_empty() {
+                self.current = self.saved[index % self.saved.len()].clone();
+            },
+            None => {
+                let (ref mut collection, ref mut model) = self.current;
+                collection.apply(model, op, &self.saved);
+            }
+        }
+    }
+}
+
+/// Apply a sequence of operations to an empty collection and to an
+/// empty model of it, checking after each one that they match, and
+/// return the resulting collection.
+///
+/// The versions saved by the operations are checked again at the
+/// end.
+pub fn check_against_model<C: Modelled>(ops: &[C::Op]) -> Result<C, ModelError> {
+    let error = |step: usize, operation: String, message: String| ModelError {
+        step,
+        operation,
+        message,
+    };
+    let mut run = Run::<C>::new();
+    for (step, op) in ops.iter().enumerate() {
+        run.step(op);
+        let (ref collection, ref model) = run.current;
+        collection
+            .compare(model)
+            .map_err(|message| error(step, format!("{:?}", op), message))?;
+    }
+    for (index, &(ref collection, ref model)) in run.saved.iter().enumerate() {
+        collection
+            .compare(model)
+            .map_err(|message| error(ops.len(), format!("saved version {}", index), message))?;
+    }
+    Ok(run.current.0)
+}
+
+/// Apply a sequence of operations to an empty collection, without
+/// checking it, and return the result.
+pub fn apply_ops<C: Modelled>(ops: &[C::Op]) -> C {
+    let mut run = Run::<C>::new();
+    for op in ops {
+        run.step(op);
+    }
+    run.current.0
+}
+
+// Compare a collection's values with a model's, in order.
+fn compare_seq<'a, A, I, J>(len: usize, values: I, model: J) -> Result<(), String>
+where
+    A: PartialEq + Debug + 'a,
+    I: Iterator<Item = ::std::sync::Arc<A>>,
+    J: ExactSizeIterator<Item = &'a A>,
+{
+    if len != model.len() {
+        return Err(format!("length is {} but should be {}", len, model.len()));
+    }
+    for (index, (value, expected)) in values.zip(model).enumerate() {
+        if *value != *expected {
+            return Err(format!(
+                "value {} is {:?} but should be {:?}",
+                index, value, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Keep a list from growing past `APPEND_LIMIT` by appending to itself.
+fn can_append(len: usize) -> bool {
+    len * 2 <= APPEND_LIMIT
+}
+
+impl<A> Modelled for Vector<A>
+where
+    A: Clone + PartialEq + Debug,
+{
+    type Op = VectorOp<A>;
+    type Model = Vec<A>;
+
+    fn history(op: &Self::Op) -> Option<History> {
+        match *op {
+            VectorOp::Snapshot => Some(History::Snapshot),
+            VectorOp::Restore(index) => Some(History::Restore(index)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, _: &[(Self, Self::Model)]) {
+        match *op {
+            VectorOp::PushFront(ref value) => {
+                self.push_front_mut(value.clone());
+                model.insert(0, value.clone());
+            }
+            VectorOp::PushBack(ref value) => {
+                self.push_back_mut(value.clone());
+                model.push(value.clone());
+            }
+            VectorOp::PopFront => if !model.is_empty() {
+                self.pop_front_mut();
+                model.remove(0);
+            },
+            VectorOp::PopBack => {
+                self.pop_back_mut();
+                model.pop();
+            }
+            VectorOp::Set(index, ref value) => if !model.is_empty() {
+                let index = index % model.len();
+                self.set_mut(index, value.clone());
+                model[index] = value.clone();
+            },
+            VectorOp::Slice(start, end) => {
+                let start = start % (model.len() + 1);
+                let end = end % (model.len() + 1);
+                *self = self.slice(start, end);
+                if start < end {
+                    model.truncate(end);
+                    model.drain(..start);
+                } else {
+                    model.clear();
+                }
+            }
+            VectorOp::Append => if can_append(model.len()) {
+                *self = self.append(&*self);
+                let copy = model.clone();
+                model.extend(copy);
+            },
+            VectorOp::Reverse => {
+                self.reverse_mut();
+                model.reverse();
+            }
+            VectorOp::Snapshot | VectorOp::Restore(_) => (),
+        }
+    }
+
+    fn compare(&self, model: &Self::Model) -> Result<(), String> {
+        compare_seq(self.len(), self.iter(), model.iter())?;
+        self.validate().map_err(|error| error.to_string())
+    }
+}
+
+impl<A> Modelled for CatList<A>
+where
+    A: Clone + PartialEq + Debug,
+{
+    type Op = ListOp<A>;
+    type Model = Vec<A>;
+
+    fn history(op: &Self::Op) -> Option<History> {
+        match *op {
+            ListOp::Snapshot => Some(History::Snapshot),
+            ListOp::Restore(index) => Some(History::Restore(index)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, _: &[(Self, Self::Model)]) {
+        match *op {
+            ListOp::PushFront(ref value) => {
+                self.push_front_mut(value.clone());
+                model.insert(0, value.clone());
+            }
+            ListOp::PushBack(ref value) => {
+                self.push_back_mut(value.clone());
+                model.push(value.clone());
+            }
+            ListOp::PopFront => if !model.is_empty() {
+                self.pop_front_mut();
+                model.remove(0);
+            },
+            ListOp::PopBack => {
+                self.pop_back_mut();
+                model.pop();
+            }
+            ListOp::Append => if can_append(model.len()) {
+                let copy = self.clone();
+                self.append_mut(copy);
+                let copy = model.clone();
+                model.extend(copy);
+            },
+            ListOp::Reverse => {
+                *self = self.clone().reverse();
+                model.reverse();
+            }
+            ListOp::Snapshot | ListOp::Restore(_) => (),
+        }
+    }
+
+    fn compare(&self, model: &Self::Model) -> Result<(), String> {
+        compare_seq(self.len(), self.iter(), model.iter())?;
+        self.validate().map_err(|error| error.to_string())
+    }
+}
+
+// Find the saved version a `Union` refers to.
+fn saved_version<C, M>(saved: &[(C, M)], index: usize) -> Option<&(C, M)> {
+    if saved.is_empty() {
+        None
+    } else {
+        Some(&saved[index % saved.len()])
+    }
+}
+
+impl<K, V> Modelled for OrdMap<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone + PartialEq + Debug,
+{
+    type Op = MapOp<K, V>;
+    type Model = BTreeMap<K, V>;
+
+    fn history(op: &Self::Op) -> Option<History> {
+        match *op {
+            MapOp::Snapshot => Some(History::Snapshot),
+            MapOp::Restore(index) => Some(History::Restore(index)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, saved: &[(Self, Self::Model)]) {
+        match *op {
+            MapOp::Insert(ref key, ref value) => {
+                self.insert_mut(key.clone(), value.clone());
+                model.insert(key.clone(), value.clone());
+            }
+            MapOp::Remove(ref key) => {
+                self.remove_mut(key);
+                model.remove(key);
+            }
+            MapOp::Union(index) => {
+                if let Some(&(ref other, ref other_model)) = saved_version(saved, index) {
+                    *self = self.union(other);
+                    for (key, value) in other_model {
+                        model.entry(key.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+            }
+            MapOp::Snapshot | MapOp::Restore(_) => (),
+        }
+    }
+
+    fn compare(&self, model: &Self::Model) -> Result<(), String> {
+        if self.len() != model.len() {
+            return Err(format!("length is {} but should be {}", self.len(), model.len()));
+        }
+        for ((key, value), (expected_key, expected_value)) in self.iter().zip(model) {
+            if (&*key, &*value) != (expected_key, expected_value) {
+                return Err(format!(
+                    "found {:?} => {:?} but should be {:?} => {:?}",
+                    key, value, expected_key, expected_value
+                ));
+            }
+        }
+        self.validate().map_err(|error| error.to_string())
+    }
+}
+
+impl<K, V, S> Modelled for HashMap<K, V, S>
+where
+    K: Hash + Eq + Clone + Debug,
+    V: Clone + PartialEq + Debug,
+    S: BuildHasher + Default,
+{
+    type Op = MapOp<K, V>;
+    type Model = collections::HashMap<K, V>;
+
+    fn history(op: &Self::Op) -> Option<History> {
+        match *op {
+            MapOp::Snapshot => Some(History::Snapshot),
+            MapOp::Restore(index) => Some(History::Restore(index)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, saved: &[(Self, Self::Model)]) {
+        match *op {
+            MapOp::Insert(ref key, ref value) => {
+                self.insert_mut(key.clone(), value.clone());
+                model.insert(key.clone(), value.clone());
+            }
+            MapOp::Remove(ref key) => {
+                self.remove_mut(key);
+                model.remove(key);
+            }
+            MapOp::Union(index) => {
+                if let Some(&(ref other, ref other_model)) = saved_version(saved, index) {
+                    *self = self.union(other);
+                    for (key, value) in other_model {
+                        model.entry(key.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+            }
+            MapOp::Snapshot | MapOp::Restore(_) => (),
+        }
+    }
+
+    fn compare(&self, model: &Self::Model) -> Result<(), String> {
+        if self.len() != model.len() {
+            return Err(format!("length is {} but should be {}", self.len(), model.len()));
+        }
+        for (key, expected) in model {
+            match self.get(key) {
+                Some(ref value) if **value == *expected => (),
+                value => {
+                    return Err(format!(
+                        "{:?} maps to {:?} but should map to {:?}",
+                        key, value, expected
+                    ))
+                }
+            }
+        }
+        self.validate().map_err(|error| error.to_string())
+    }
+}
+
+impl<A> Modelled for OrdSet<A>
+where
+    A: Ord + Clone + Debug,
+{
+    type Op = SetOp<A>;
+    type Model = BTreeSet<A>;
+
+    fn history(op: &Self::Op) -> Option<History> {
+        match *op {
+            SetOp::Snapshot => Some(History::Snapshot),
+            SetOp::Restore(index) => Some(History::Restore(index)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, saved: &[(Self, Self::Model)]) {
+        match *op {
+            SetOp::Insert(ref value) => {
+                self.insert_mut(value.clone());
+                model.insert(value.clone());
+            }
+            SetOp::Remove(ref value) => {
+                self.remove_mut(value);
+                model.remove(value);
+            }
+            SetOp::Union(index) => {
+                if let Some(&(ref other, ref other_model)) = saved_version(saved, index) {
+                    *self = self.union(other);
+                    model.extend(other_model.iter().cloned());
+                }
+            }
+            SetOp::Snapshot | SetOp::Restore(_) => (),
+        }
+    }
+
+    fn compare(&self, model: &Self::Model) -> Result<(), String> {
+        compare_seq(self.len(), self.iter(), model.iter())?;
+        self.validate().map_err(|error| error.to_string())
+    }
+}
+
+impl<A, S> Modelled for HashSet<A, S>
+where
+    A: Hash + Eq + Clone + Debug,
+    S: BuildHasher + Default,
+{
+    type Op = SetOp<A>;
+    type Model = collections::HashSet<A>;
+
+    fn history(op: &Self::Op) -> Option<History> {
+        match *op {
+            SetOp::Snapshot => Some(History::Snapshot),
+            SetOp::Restore(index) => Some(History::Restore(index)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, saved: &[(Self, Self::Model)]) {
+        match *op {
+            SetOp::Insert(ref value) => {
+                self.insert_mut(value.clone());
+                model.insert(value.clone());
+            }
+            SetOp::Remove(ref value) => {
+                self.remove_mut(value);
+                model.remove(value);
+            }
+            SetOp::Union(index) => {
+                if let Some(&(ref other, ref other_model)) = saved_version(saved, index) {
+                    *self = self.union(other);
+                    model.extend(other_model.iter().cloned());
+                }
+            }
+            SetOp::Snapshot | SetOp::Restore(_) => (),
+        }
+    }
+
+    fn compare(&self, model: &Self::Model) -> Result<(), String> {
+        if self.len() != model.len() {
+            return Err(format!("length is {} but should be {}", self.len(), model.len()));
+        }
+        if let Some(missing) = model.iter().find(|value| !self.contains(value)) {
+            return Err(format!("{:?} is missing", missing));
+        }
+        self.validate().map_err(|error| error.to_string())
+    }
+}
+
+// Choosing operations
+
+// The operations are chosen by a number below `OPS`, with the ones
+// which add elements more likely than the ones which remove them, so
+// that collections grow.
+const VECTOR_OPS: u8 = 13;
+const LIST_OPS: u8 = 11;
+const MAP_OPS: u8 = 10;
+
+pub(crate) fn vector_op<A>(choice: u8, value: A, index: usize, other: usize) -> VectorOp<A> {
+    match choice % VECTOR_OPS {
+        0..=2 => VectorOp::PushBack(value),
+        3..=4 => VectorOp::PushFront(value),
+        5 => VectorOp::PopFront,
+        6 => VectorOp::PopBack,
+        7 => VectorOp::Set(index, value),
+        8 => VectorOp::Slice(index, other),
+        9 => VectorOp::Append,
+        10 => VectorOp::Reverse,
+        11 => VectorOp::Snapshot,
+        _ => VectorOp::Restore(index),
+    }
+}
+
+pub(crate) fn list_op<A>(choice: u8, value: A, index: usize) -> ListOp<A> {
+    match choice % LIST_OPS {
+        0..=2 => ListOp::PushBack(value),
+        3..=4 => ListOp::PushFront(value),
+        5 => ListOp::PopFront,
+        6 => ListOp::PopBack,
+        7 => ListOp::Append,
+        8 => ListOp::Reverse,
+        9 => ListOp::Snapshot,
+        _ => ListOp::Restore(index),
+    }
+}
+
+pub(crate) fn map_op<K, V>(choice: u8, key: K, value: V, index: usize) -> MapOp<K, V> {
+    match choice % MAP_OPS {
+        0..=4 => MapOp::Insert(key, value),
+        5 | 6 => MapOp::Remove(key),
+        7 => MapOp::Union(index),
+        8 => MapOp::Snapshot,
+        _ => MapOp::Restore(index),
+    }
+}
+
+pub(crate) fn set_op<A>(choice: u8, value: A, index: usize) -> SetOp<A> {
+    match map_op(choice, value, (), index) {
+        MapOp::Insert(value, ()) => SetOp::Insert(value),
+        MapOp::Remove(value) => SetOp::Remove(value),
+        MapOp::Union(index) => SetOp::Union(index),
+        MapOp::Snapshot => SetOp::Snapshot,
+        MapOp::Restore(index) => SetOp::Restore(index),
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Arbitrary> Arbitrary for VectorOp<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        vector_op(
+            u8::arbitrary(g),
+            A::arbitrary(g),
+            usize::arbitrary(g),
+            usize::arbitrary(g),
+        )
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Arbitrary> Arbitrary for ListOp<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        list_op(u8::arbitrary(g), A::arbitrary(g), usize::arbitrary(g))
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K: Arbitrary, V: Arbitrary> Arbitrary for MapOp<K, V> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        map_op(
+            u8::arbitrary(g),
+            K::arbitrary(g),
+            V::arbitrary(g),
+            usize::arbitrary(g),
+        )
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Arbitrary> Arbitrary for SetOp<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        set_op(u8::arbitrary(g), A::arbitrary(g), usize::arbitrary(g))
+    }
+}
+
+// Proptest
+
+/// A strategy for a collection built by a sequence of random
+/// operations, generated by `ops`, such as
+/// [`vector::proptest::vector_ops`][vector_ops].
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// proptest! {
+///     #[test]
+///     fn shaped_vector(ref v in from_ops::<Vector<i32>, _>(vector_ops(0..100i32, 0..500))) {
+///         assert_eq!(v.len(), v.iter().count());
+///     }
+/// }
+/// ```
+///
+/// [vector_ops]: ../vector/proptest/fn.vector_ops.html
+#[cfg(any(test, feature = "proptest"))]
+pub fn from_ops<C, S>(ops: S) -> ::proptest::strategy::BoxedStrategy<C>
+where
+    C: Modelled + Debug + 'static,
+    S: ::proptest::strategy::Strategy + 'static,
+    S::Value: ::proptest::strategy::ValueTree<Value = Vec<C::Op>>,
+{
+    use proptest::strategy::Strategy;
+    ops.prop_map(|ops| apply_ops(&ops)).boxed()
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use catlist::proptest::list_ops;
+    use ordmap::proptest::map_ops;
+    use ordset::proptest::set_ops;
+    use proptest::num::{i16, i32};
+    use std::hash::BuildHasherDefault;
+    use test::{CollidingHasher, LolHasher};
+    use vector::proptest::vector_ops;
+
+    // A vector which forgets to pop its last element, to check that a
+    // divergence is caught at the step where it happens.
+    #[derive(Clone, Debug, Default)]
+    struct NoPopBack(Vector<i32>);
+
+    impl Modelled for NoPopBack {
+        type Op = VectorOp<i32>;
+        type Model = Vec<i32>;
+
+        fn history(op: &Self::Op) -> Option<History> {
+            Vector::<i32>::history(op)
+        }
+
+        fn apply(&mut self, model: &mut Self::Model, op: &Self::Op, _: &[(Self, Self::Model)]) {
+            match *op {
+                VectorOp::PopBack => {
+                    model.pop();
+                }
+                _ => self.0.apply(model, op, &[]),
+            }
+        }
+
+        fn compare(&self, model: &Self::Model) -> Result<(), String> {
+            self.0.compare(model)
+        }
+    }
+
+    #[test]
+    fn reports_the_failing_step() {
+        let ops = vec![
+            VectorOp::PushBack(1),
+            VectorOp::PushBack(2),
+            VectorOp::Snapshot,
+            VectorOp::Append,
+            VectorOp::Restore(0),
+        ];
+        let vector: Vector<i32> = check_against_model(&ops).unwrap();
+        assert_eq!(vector![1, 2], vector);
+        check_against_model::<NoPopBack>(&ops).unwrap();
+
+        let ops = vec![
+            VectorOp::PushBack(1),
+            VectorOp::PushBack(2),
+            VectorOp::Snapshot,
+            VectorOp::PushFront(3),
+            VectorOp::PopBack,
+            VectorOp::PopBack,
+        ];
+        let error = check_against_model::<NoPopBack>(&ops).unwrap_err();
+        assert_eq!(4, error.step());
+        assert_eq!("PopBack", error.operation());
+        assert_eq!("length is 3 but should be 2", error.message());
+        assert_eq!(
+            "after step 4 (PopBack): length is 3 but should be 2",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn unions_share_saved_versions() {
+        let mut ops: Vec<MapOp<i32, i32>> = (0..1000).map(|i| MapOp::Insert(i, i)).collect();
+        ops.push(MapOp::Snapshot);
+        ops.extend((0..1000).map(MapOp::Remove));
+        ops.push(MapOp::Insert(-1, -1));
+        ops.push(MapOp::Union(0));
+        let map: OrdMap<i32, i32> = check_against_model(&ops).unwrap();
+        assert_eq!(1001, map.len());
+        let map: HashMap<i32, i32> = check_against_model(&ops).unwrap();
+        assert_eq!(1001, map.len());
+    }
+
+    quickcheck! {
+        fn qc_vector_ops(ops: Vec<VectorOp<i32>>) -> bool {
+            check_against_model::<Vector<i32>>(&ops).is_ok()
+        }
+
+        fn qc_map_ops(ops: Vec<MapOp<u8, i32>>) -> bool {
+            check_against_model::<OrdMap<u8, i32>>(&ops).is_ok()
+                && check_against_model::<HashMap<u8, i32>>(&ops).is_ok()
+        }
+
+        fn qc_set_ops(ops: Vec<SetOp<u8>>) -> bool {
+            check_against_model::<OrdSet<u8>>(&ops).is_ok()
+                && check_against_model::<HashSet<u8>>(&ops).is_ok()
+        }
+
+        fn qc_list_ops(ops: Vec<ListOp<i32>>) -> bool {
+            check_against_model::<CatList<i32>>(&ops).is_ok()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn vector_matches_model(ref ops in vector_ops(i32::ANY, 0..500)) {
+            check_against_model::<Vector<i32>>(ops).unwrap();
+        }
+
+        #[test]
+        fn catlist_matches_model(ref ops in list_ops(i32::ANY, 0..500)) {
+            check_against_model::<CatList<i32>>(ops).unwrap();
+        }
+
+        #[test]
+        fn ordmap_matches_model(ref ops in map_ops(0..512i16, i16::ANY, 0..1000)) {
+            check_against_model::<OrdMap<i16, i16>>(ops).unwrap();
+        }
+
+        #[test]
+        fn hashmap_matches_model(ref ops in map_ops(0..512i16, i16::ANY, 0..1000)) {
+            check_against_model::<HashMap<i16, i16, BuildHasherDefault<LolHasher>>>(ops).unwrap();
+            check_against_model::<HashMap<i16, i16, BuildHasherDefault<CollidingHasher>>>(ops)
+                .unwrap();
+        }
+
+        #[test]
+        fn sets_match_model(ref ops in set_ops(0..512i16, 0..1000)) {
+            check_against_model::<OrdSet<i16>>(ops).unwrap();
+            check_against_model::<HashSet<i16>>(ops).unwrap();
+        }
+
+        #[test]
+        fn shaped_vectors(ref vector in from_ops::<Vector<i32>, _>(vector_ops(i32::ANY, 0..500))) {
+            vector.assert_invariants();
+            assert_eq!(vector.len(), vector.iter().count());
+        }
+    }
+}
@@ -0,0 +1,1224 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Multimaps.
+//!
+//! A multimap is a map where each key can be associated with any
+//! number of distinct values. It's implemented as a map from keys to
+//! sets of values, with the bookkeeping taken care of for you: sets
+//! are created when the first value is inserted for a key and removed
+//! again when the last value for a key is removed, and the total
+//! number of key/value pairs is tracked so that
+//! [`len()`][HashMultiMap::len] is O(1).
+//!
+//! [`HashMultiMap`][HashMultiMap] is built on
+//! [`HashMap`][hashmap::HashMap] and [`HashSet`][hashset::HashSet],
+//! and [`OrdMultiMap`][OrdMultiMap] is built on
+//! [`OrdMap`][ordmap::OrdMap] and [`OrdSet`][ordset::OrdSet].
+//!
+//! [HashMultiMap]: ./struct.HashMultiMap.html
+//! [HashMultiMap::len]: ./struct.HashMultiMap.html#method.len
+//! [OrdMultiMap]: ./struct.OrdMultiMap.html
+//! [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+//! [hashset::HashSet]: ../hashset/struct.HashSet.html
+//! [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+//! [ordset::OrdSet]: ../ordset/struct.OrdSet.html
+
+use std::borrow::Borrow;
+use std::cmp::{max, Ordering};
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+use std::fmt::{Debug, Error, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::iter::FromIterator;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::{Footprint, HeapSize};
+use hashmap::{self, HashMap};
+use hashset::HashSet;
+use ordmap::{self, OrdMap};
+use ordset::OrdSet;
+use shared::Shared;
+
+use nodes::btree;
+use nodes::hamt;
+
+/// A hashed multimap.
+///
+/// A map from keys to sets of values, built from a
+/// [`HashMap`][hashmap::HashMap] of [`HashSet`][hashset::HashSet]s.
+/// Keys with no values are never stored.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::multimap::HashMultiMap;
+/// # fn main() {
+/// let map = HashMultiMap::new()
+///     .insert("fruit", "apple")
+///     .insert("fruit", "pear")
+///     .insert("veg", "leek");
+/// assert_eq!(3, map.len());
+/// assert_eq!(2, map.get_all("fruit").len());
+///
+/// let map = map.remove("veg", "leek");
+/// assert!(!map.contains_key("veg"));
+/// # }
+/// ```
+///
+/// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
+/// [hashset::HashSet]: ../hashset/struct.HashSet.html
+#[cfg(feature = "std")]
+pub struct HashMultiMap<K, V, S = RandomState> {
+    map: HashMap<K, HashSet<V, S>, S>,
+    size: usize,
+    hasher: Arc<S>,
+}
+
+/// A hash multimap.
+///
+/// Without the `std` feature there is no default hasher, so one must
+/// always be given through `S`.
+#[cfg(not(feature = "std"))]
+pub struct HashMultiMap<K, V, S> {
+    map: HashMap<K, HashSet<V, S>, S>,
+    size: usize,
+    hasher: Arc<S>,
+}
+
+#[cfg(feature = "std")]
+impl<K, V> HashMultiMap<K, V, RandomState>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+{
+    /// Construct an empty multimap.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Construct a multimap with a single key/value pair.
+    #[inline]
+    pub fn singleton<RK, RV>(k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        HashMultiMap::new().insert(k, v)
+    }
+}
+
+impl<K, V, S> HashMultiMap<K, V, S> {
+    /// Test whether a multimap is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of key/value pairs in a multimap.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Get the number of distinct keys in a multimap.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn key_count(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Get the underlying map from keys to sets of values.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn as_map(&self) -> &HashMap<K, HashSet<V, S>, S> {
+        &self.map
+    }
+}
+
+impl<K, V, S> HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Construct an empty multimap using the provided hasher for both
+    /// keys and values.
+    #[inline]
+    pub fn with_hasher<RS>(hasher: RS) -> Self
+    where
+        RS: Shared<S>,
+    {
+        let hasher = hasher.shared();
+        HashMultiMap {
+            map: HashMap::with_hasher(&hasher),
+            size: 0,
+            hasher,
+        }
+    }
+
+    /// Get an iterator over the key/value pairs of a multimap.
+    ///
+    /// All the pairs for a given key come out next to each other.
+    #[inline]
+    pub fn iter(&self) -> HashIter<K, V, S> {
+        HashIter {
+            outer: self.map.iter(),
+            current: None,
+            remaining: self.size,
+        }
+    }
+
+    /// Get an iterator over the distinct keys of a multimap.
+    #[inline]
+    pub fn keys(&self) -> hashmap::Keys<K, HashSet<V, S>> {
+        self.map.keys()
+    }
+
+    /// Get the set of values for a key.
+    ///
+    /// If the key isn't in the map, you get an empty set.
+    ///
+    /// Time: O(log n)
+    pub fn get_all<BK>(&self, k: &BK) -> HashSet<V, S>
+    where
+        BK: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+    {
+        match self.map.get(k) {
+            Some(set) => (*set).clone(),
+            None => HashSet::with_hasher(&self.hasher),
+        }
+    }
+
+    /// Test whether a key has any values in a multimap.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn contains_key<BK>(&self, k: &BK) -> bool
+    where
+        BK: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Test whether a key/value pair is present in a multimap.
+    ///
+    /// Time: O(log n)
+    pub fn contains<BK, BV>(&self, k: &BK, v: &BV) -> bool
+    where
+        BK: Hash + Eq + ?Sized,
+        BV: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+        V: Borrow<BV>,
+    {
+        match self.map.get(k) {
+            Some(set) => set.contains(v),
+            None => false,
+        }
+    }
+
+    /// Construct a new multimap by adding a value to a key.
+    ///
+    /// If the pair is already present, the map is unchanged.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RK, RV>(&self, k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(k, v);
+        out
+    }
+
+    /// Add a value to a key.
+    ///
+    /// If the pair is already present, the map is unchanged.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<RK, RV>(&mut self, k: RK, v: RV)
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let k = k.shared();
+        if self.map.contains_key(&*k) {
+            let set = &mut self.map[&*k];
+            let before = set.len();
+            set.insert_mut(v);
+            self.size += set.len() - before;
+        } else {
+            let mut set = HashSet::with_hasher(&self.hasher);
+            set.insert_mut(v);
+            self.map.insert_mut(k, set);
+            self.size += 1;
+        }
+    }
+
+    /// Construct a new multimap without the given key/value pair.
+    ///
+    /// If this was the last value for the key, the key is removed
+    /// as well.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BK, BV>(&self, k: &BK, v: &BV) -> Self
+    where
+        BK: Hash + Eq + ?Sized,
+        BV: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+        V: Borrow<BV>,
+    {
+        if !self.contains(k, v) {
+            return self.clone();
+        }
+        let mut out = self.clone();
+        out.remove_mut(k, v);
+        out
+    }
+
+    /// Remove a key/value pair if it exists.
+    ///
+    /// If this was the last value for the key, the key is removed
+    /// as well.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut<BK, BV>(&mut self, k: &BK, v: &BV)
+    where
+        BK: Hash + Eq + ?Sized,
+        BV: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+        V: Borrow<BV>,
+    {
+        let last = match self.map.get(k) {
+            Some(ref set) if set.contains(v) => set.len() == 1,
+            _ => return,
+        };
+        if last {
+            self.map.remove_mut(k);
+        } else {
+            self.map[k].remove_mut(v);
+        }
+        self.size -= 1;
+    }
+
+    /// Construct a new multimap without any values for the given
+    /// key.
+    ///
+    /// Time: O(log n)
+    pub fn remove_all<BK>(&self, k: &BK) -> Self
+    where
+        BK: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+    {
+        match self.map.pop(k) {
+            None => self.clone(),
+            Some((set, map)) => HashMultiMap {
+                map,
+                size: self.size - set.len(),
+                hasher: self.hasher.clone(),
+            },
+        }
+    }
+
+    /// Remove all the values for a key, and return them.
+    ///
+    /// If the key isn't in the map, you get an empty set.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn remove_all_mut<BK>(&mut self, k: &BK) -> HashSet<V, S>
+    where
+        BK: Hash + Eq + ?Sized,
+        K: Borrow<BK>,
+    {
+        match self.map.pop_mut(k) {
+            None => HashSet::with_hasher(&self.hasher),
+            Some(set) => {
+                self.size -= set.len();
+                (*set).clone()
+            }
+        }
+    }
+}
+
+/// An ordered multimap.
+///
+/// A map from keys to sets of values, built from an
+/// [`OrdMap`][ordmap::OrdMap] of [`OrdSet`][ordset::OrdSet]s. Keys
+/// with no values are never stored, and pairs are iterated in key
+/// order, then value order.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::multimap::OrdMultiMap;
+/// # fn main() {
+/// let mut map = OrdMultiMap::new();
+/// map.insert_mut(2, "b");
+/// map.insert_mut(1, "z");
+/// map.insert_mut(2, "a");
+/// assert_eq!(ordset!["a", "b"], map.get_all(&2));
+/// assert_eq!(
+///   vec![(1, "z"), (2, "a"), (2, "b")],
+///   map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+/// );
+/// # }
+/// ```
+///
+/// [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+/// [ordset::OrdSet]: ../ordset/struct.OrdSet.html
+pub struct OrdMultiMap<K, V> {
+    map: OrdMap<K, OrdSet<V>>,
+    size: usize,
+}
+
+impl<K, V> OrdMultiMap<K, V> {
+    /// Construct an empty multimap.
+    #[inline]
+    pub fn new() -> Self {
+        OrdMultiMap {
+            map: OrdMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Test whether a multimap is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of key/value pairs in a multimap.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Get the number of distinct keys in a multimap.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn key_count(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Get the underlying map from keys to sets of values.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn as_map(&self) -> &OrdMap<K, OrdSet<V>> {
+        &self.map
+    }
+}
+
+impl<K: Ord, V: Ord> OrdMultiMap<K, V> {
+    /// Construct a multimap with a single key/value pair.
+    #[inline]
+    pub fn singleton<RK, RV>(k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        OrdMultiMap::new().insert(k, v)
+    }
+
+    /// Get an iterator over the key/value pairs of a multimap, in
+    /// key order, then value order.
+    #[inline]
+    pub fn iter(&self) -> OrdIter<K, V> {
+        OrdIter {
+            outer: self.map.iter(),
+            current: None,
+            remaining: self.size,
+        }
+    }
+
+    /// Get an iterator over the distinct keys of a multimap, in
+    /// sorted order.
+    #[inline]
+    pub fn keys(&self) -> ordmap::Keys<K, OrdSet<V>> {
+        self.map.keys()
+    }
+
+    /// Get the set of values for a key.
+    ///
+    /// If the key isn't in the map, you get an empty set.
+    ///
+    /// Time: O(log n)
+    pub fn get_all<BK>(&self, k: &BK) -> OrdSet<V>
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        match self.map.get(k) {
+            Some(set) => (*set).clone(),
+            None => OrdSet::new(),
+        }
+    }
+
+    /// Test whether a key has any values in a multimap.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn contains_key<BK>(&self, k: &BK) -> bool
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Test whether a key/value pair is present in a multimap.
+    ///
+    /// Time: O(log n)
+    pub fn contains<BK, BV>(&self, k: &BK, v: &BV) -> bool
+    where
+        BK: Ord + ?Sized,
+        BV: Ord + ?Sized,
+        K: Borrow<BK>,
+        V: Borrow<BV>,
+    {
+        match self.map.get(k) {
+            Some(set) => set.contains(v),
+            None => false,
+        }
+    }
+
+    /// Construct a new multimap by adding a value to a key.
+    ///
+    /// If the pair is already present, the map is unchanged.
+    ///
+    /// Time: O(log n)
+    pub fn insert<RK, RV>(&self, k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(k, v);
+        out
+    }
+
+    /// Add a value to a key.
+    ///
+    /// If the pair is already present, the map is unchanged.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn insert_mut<RK, RV>(&mut self, k: RK, v: RV)
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let k = k.shared();
+        if self.map.contains_key(&*k) {
+            let set = &mut self.map[&*k];
+            let before = set.len();
+            set.insert_mut(v);
+            self.size += set.len() - before;
+        } else {
+            self.map.insert_mut(k, OrdSet::singleton(v));
+            self.size += 1;
+        }
+    }
+
+    /// Construct a new multimap without the given key/value pair.
+    ///
+    /// If this was the last value for the key, the key is removed
+    /// as well.
+    ///
+    /// Time: O(log n)
+    pub fn remove<BK, BV>(&self, k: &BK, v: &BV) -> Self
+    where
+        BK: Ord + ?Sized,
+        BV: Ord + ?Sized,
+        K: Borrow<BK>,
+        V: Borrow<BV>,
+    {
+        if !self.contains(k, v) {
+            return self.clone();
+        }
+        let mut out = self.clone();
+        out.remove_mut(k, v);
+        out
+    }
+
+    /// Remove a key/value pair if it exists.
+    ///
+    /// If this was the last value for the key, the key is removed
+    /// as well.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut<BK, BV>(&mut self, k: &BK, v: &BV)
+    where
+        BK: Ord + ?Sized,
+        BV: Ord + ?Sized,
+        K: Borrow<BK>,
+        V: Borrow<BV>,
+    {
+        let last = match self.map.get(k) {
+            Some(ref set) if set.contains(v) => set.len() == 1,
+            _ => return,
+        };
+        if last {
+            self.map.remove_mut(k);
+        } else {
+            self.map[k].remove_mut(v);
+        }
+        self.size -= 1;
+    }
+
+    /// Construct a new multimap without any values for the given
+    /// key.
+    ///
+    /// Time: O(log n)
+    pub fn remove_all<BK>(&self, k: &BK) -> Self
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        match self.map.pop(k) {
+            None => self.clone(),
+            Some((set, map)) => OrdMultiMap {
+                map,
+                size: self.size - set.len(),
+            },
+        }
+    }
+
+    /// Remove all the values for a key, and return them.
+    ///
+    /// If the key isn't in the map, you get an empty set.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(log n)
+    pub fn remove_all_mut<BK>(&mut self, k: &BK) -> OrdSet<V>
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        match self.map.pop_mut(k) {
+            None => OrdSet::new(),
+            Some(set) => {
+                self.size -= set.len();
+                (*set).clone()
+            }
+        }
+    }
+}
+
+// Core traits
+
+impl<K, V, S> Clone for HashMultiMap<K, V, S> {
+    #[inline]
+    fn clone(&self) -> Self {
+        HashMultiMap {
+            map: self.map.clone(),
+            size: self.size,
+            hasher: self.hasher.clone(),
+        }
+    }
+}
+
+impl<K, V, S> PartialEq for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size && self.map == other.map
+    }
+}
+
+impl<K: Hash + Eq, V: Hash + Eq, S: BuildHasher + Default> Eq for HashMultiMap<K, V, S> {}
+
+impl<K, V, S> Default for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    #[inline]
+    fn default() -> Self {
+        HashMultiMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> HeapSize for HashMultiMap<K, V, S> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        let mut below = 0;
+        let depth = self
+            .map
+            .measure_heap_with(footprint, &mut |footprint, &(ref key, ref set)| {
+                footprint.value(key);
+                if footprint.value(set) {
+                    below = max(below, set.measure_heap(footprint));
+                }
+            });
+        depth + below
+    }
+}
+
+impl<K, V, S, RK, RV> Extend<(RK, RV)> for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (RK, RV)>,
+    {
+        for (k, v) in iter {
+            self.insert_mut(k, v);
+        }
+    }
+}
+
+impl<K, V, S> Debug for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq + Debug,
+    V: Hash + Eq + Debug,
+    S: BuildHasher + Default,
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K, V> Clone for OrdMultiMap<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        OrdMultiMap {
+            map: self.map.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl<K: Ord, V: Ord> PartialEq for OrdMultiMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size && self.map == other.map
+    }
+}
+
+impl<K: Ord, V: Ord> Eq for OrdMultiMap<K, V> {}
+
+impl<K: Ord, V: Ord> PartialOrd for OrdMultiMap<K, V> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V: Ord> Ord for OrdMultiMap<K, V> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K: Ord + Hash, V: Ord + Hash> Hash for OrdMultiMap<K, V> {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        for i in self.iter() {
+            i.hash(state);
+        }
+    }
+}
+
+impl<K, V> Default for OrdMultiMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        OrdMultiMap::new()
+    }
+}
+
+impl<K, V> HeapSize for OrdMultiMap<K, V> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        let mut below = 0;
+        let depth = self
+            .map
+            .measure_heap_with(footprint, &mut |footprint, &(ref key, ref set)| {
+                footprint.value(key);
+                if footprint.value(set) {
+                    below = max(below, set.measure_heap(footprint));
+                }
+            });
+        depth + below
+    }
+}
+
+impl<K, V, RK, RV> Extend<(RK, RV)> for OrdMultiMap<K, V>
+where
+    K: Ord,
+    V: Ord,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (RK, RV)>,
+    {
+        for (k, v) in iter {
+            self.insert_mut(k, v);
+        }
+    }
+}
+
+impl<K: Ord + Debug, V: Ord + Debug> Debug for OrdMultiMap<K, V> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+// Iterators
+
+/// An iterator over the key/value pairs of a
+/// [`HashMultiMap`][HashMultiMap].
+///
+/// [HashMultiMap]: ./struct.HashMultiMap.html
+pub struct HashIter<K, V, S> {
+    outer: hamt::Iter<(Arc<K>, Arc<HashSet<V, S>>)>,
+    current: Option<(Arc<K>, hamt::Iter<Arc<V>>)>,
+    remaining: usize,
+}
+
+impl<K, V, S> Iterator for HashIter<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = (Arc<K>, Arc<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((ref key, ref mut values)) = self.current {
+                if let Some(value) = values.next() {
+                    self.remaining -= 1;
+                    return Some((key.clone(), value));
+                }
+            }
+            match self.outer.next() {
+                None => return None,
+                Some((key, set)) => self.current = Some((key, set.iter())),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, S> ExactSizeIterator for HashIter<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher,
+{
+}
+
+/// An iterator over the key/value pairs of an
+/// [`OrdMultiMap`][OrdMultiMap].
+///
+/// [OrdMultiMap]: ./struct.OrdMultiMap.html
+pub struct OrdIter<K, V> {
+    outer: btree::Iter<(Arc<K>, Arc<OrdSet<V>>)>,
+    current: Option<(Arc<K>, btree::Iter<Arc<V>>)>,
+    remaining: usize,
+}
+
+impl<K: Ord, V: Ord> Iterator for OrdIter<K, V> {
+    type Item = (Arc<K>, Arc<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((ref key, ref mut values)) = self.current {
+                if let Some(value) = values.next() {
+                    self.remaining -= 1;
+                    return Some((key.clone(), value));
+                }
+            }
+            match self.outer.next() {
+                None => return None,
+                Some((key, set)) => self.current = Some((key, set.iter())),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Ord, V: Ord> ExactSizeIterator for OrdIter<K, V> {}
+
+impl<'a, K, V, S> IntoIterator for &'a HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = (Arc<K>, Arc<V>);
+    type IntoIter = HashIter<K, V, S>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, S> IntoIterator for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = (Arc<K>, Arc<V>);
+    type IntoIter = HashIter<K, V, S>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Ord, V: Ord> IntoIterator for &'a OrdMultiMap<K, V> {
+    type Item = (Arc<K>, Arc<V>);
+    type IntoIter = OrdIter<K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V: Ord> IntoIterator for OrdMultiMap<K, V> {
+    type Item = (Arc<K>, Arc<V>);
+    type IntoIter = OrdIter<K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Conversions
+
+impl<K, V, S, RK, RV> FromIterator<(RK, RV)> for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher + Default,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from_iter<T>(i: T) -> Self
+    where
+        T: IntoIterator<Item = (RK, RV)>,
+    {
+        let mut map: Self = Default::default();
+        map.extend(i);
+        map
+    }
+}
+
+impl<K, V, S, RK, RV> From<Vec<(RK, RV)>> for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Hash + Eq,
+    S: BuildHasher + Default,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from(v: Vec<(RK, RV)>) -> Self {
+        v.into_iter().collect()
+    }
+}
+
+impl<K, V, RK, RV> FromIterator<(RK, RV)> for OrdMultiMap<K, V>
+where
+    K: Ord,
+    V: Ord,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from_iter<T>(i: T) -> Self
+    where
+        T: IntoIterator<Item = (RK, RV)>,
+    {
+        let mut map = OrdMultiMap::new();
+        map.extend(i);
+        map
+    }
+}
+
+impl<K, V, RK, RV> From<Vec<(RK, RV)>> for OrdMultiMap<K, V>
+where
+    K: Ord,
+    V: Ord,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from(v: Vec<(RK, RV)>) -> Self {
+        v.into_iter().collect()
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K, V> Arbitrary for HashMultiMap<K, V>
+where
+    K: Hash + Eq + Arbitrary + Sync,
+    V: Hash + Eq + Arbitrary + Sync,
+{
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        HashMultiMap::from(Vec::<(K, V)>::arbitrary(g))
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K, V> Arbitrary for OrdMultiMap<K, V>
+where
+    K: Ord + Arbitrary + Sync,
+    V: Ord + Arbitrary + Sync,
+{
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        OrdMultiMap::from(Vec::<(K, V)>::arbitrary(g))
+    }
+}
+
+// Proptest
+
+#[cfg(any(test, feature = "proptest"))]
+pub mod proptest {
+    use super::*;
+    use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
+    use std::ops::Range;
+
+    /// A strategy for a hashed multimap with a given number of
+    /// key/value pairs.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_works(ref m in hash_multi_map(0..9999, ".*", 10..100)) {
+    ///         assert!(m.len() < 100);
+    ///         assert!(m.len() >= 10);
+    ///     }
+    /// }
+    /// ```
+    pub fn hash_multi_map<K: Strategy + 'static, V: Strategy + 'static>(
+        key: K,
+        value: V,
+        size: Range<usize>,
+    ) -> BoxedStrategy<HashMultiMap<<K::Value as ValueTree>::Value, <V::Value as ValueTree>::Value>>
+    where
+        <K::Value as ValueTree>::Value: Hash + Eq,
+        <V::Value as ValueTree>::Value: Hash + Eq,
+    {
+        ::proptest::collection::vec((key, value), size.clone())
+            .prop_map(HashMultiMap::from)
+            .prop_filter("HashMultiMap minimum size".to_owned(), move |m| {
+                m.len() >= size.start
+            })
+            .boxed()
+    }
+
+    /// A strategy for an ordered multimap with a given number of
+    /// key/value pairs.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_works(ref m in ord_multi_map(0..9999, ".*", 10..100)) {
+    ///         assert!(m.len() < 100);
+    ///         assert!(m.len() >= 10);
+    ///     }
+    /// }
+    /// ```
+    pub fn ord_multi_map<K: Strategy + 'static, V: Strategy + 'static>(
+        key: K,
+        value: V,
+        size: Range<usize>,
+    ) -> BoxedStrategy<OrdMultiMap<<K::Value as ValueTree>::Value, <V::Value as ValueTree>::Value>>
+    where
+        <K::Value as ValueTree>::Value: Ord,
+        <V::Value as ValueTree>::Value: Ord,
+    {
+        ::proptest::collection::vec((key, value), size.clone())
+            .prop_map(OrdMultiMap::from)
+            .prop_filter("OrdMultiMap minimum size".to_owned(), move |m| {
+                m.len() >= size.start
+            })
+            .boxed()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::proptest::*;
+    use super::*;
+    use proptest::collection;
+    use proptest::num::u8;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn empty_sets_are_removed() {
+        let map = HashMultiMap::new().insert(1, 2).insert(1, 3);
+        assert_eq!(1, map.key_count());
+        let map = map.remove(&1, &2).remove(&1, &3);
+        assert!(map.is_empty());
+        assert_eq!(0, map.key_count());
+        assert!(!map.contains_key(&1));
+    }
+
+    #[test]
+    fn duplicates_are_ignored() {
+        let mut map = OrdMultiMap::new();
+        map.insert_mut("a", 1);
+        map.insert_mut("a", 1);
+        map.remove_mut("a", &2);
+        map.remove_mut("b", &1);
+        assert_eq!(1, map.len());
+        assert_eq!(OrdMultiMap::singleton("a", 1), map);
+    }
+
+    #[test]
+    fn remove_all_updates_count() {
+        let mut map: HashMultiMap<i32, i32> = HashMultiMap::from(vec![(1, 1), (1, 2), (2, 1)]);
+        let removed = map.remove_all_mut(&1);
+        assert_eq!(2, removed.len());
+        assert_eq!(1, map.len());
+        assert_eq!(map, HashMultiMap::singleton(2, 1));
+        assert!(map.remove_all_mut(&1).is_empty());
+    }
+
+    #[test]
+    fn persistence() {
+        let map1 = OrdMultiMap::singleton(1, 1);
+        let map2 = map1.insert(1, 2);
+        let map3 = map2.remove_all(&1);
+        assert_eq!(ordset![1], map1.get_all(&1));
+        assert_eq!(ordset![1, 2], map2.get_all(&1));
+        assert!(map3.get_all(&1).is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn ordered_matches_model(ref ops in collection::vec((u8::ANY, u8::ANY, u8::ANY), 0..200)) {
+            let mut map = OrdMultiMap::new();
+            let mut model: BTreeMap<u8, BTreeSet<u8>> = BTreeMap::new();
+            for &(op, k, v) in ops {
+                match op % 3 {
+                    0 | 1 => {
+                        map = map.insert(k % 16, v % 16);
+                        model.entry(k % 16).or_default().insert(v % 16);
+                    }
+                    _ => {
+                        map.remove_mut(&(k % 16), &(v % 16));
+                        let empty = match model.get_mut(&(k % 16)) {
+                            Some(set) => {
+                                set.remove(&(v % 16));
+                                set.is_empty()
+                            }
+                            None => false,
+                        };
+                        if empty {
+                            model.remove(&(k % 16));
+                        }
+                    }
+                }
+                assert_eq!(model.len(), map.key_count());
+                assert_eq!(model.values().map(|s| s.len()).sum::<usize>(), map.len());
+            }
+            let expected: Vec<(u8, u8)> = model
+                .iter()
+                .flat_map(|(k, vs)| vs.iter().map(move |v| (*k, *v)))
+                .collect();
+            let actual: Vec<(u8, u8)> = map.iter().map(|(k, v)| (*k, *v)).collect();
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn hashed_remove_everything(ref map in hash_multi_map(u8::ANY, u8::ANY, 0..100)) {
+            let mut removed = map.clone();
+            let mut count = map.len();
+            assert_eq!(count, map.iter().count());
+            for (k, v) in map.iter() {
+                assert!(removed.contains(&*k, &*v));
+                removed = removed.remove(&*k, &*v);
+                count -= 1;
+                assert_eq!(count, removed.len());
+            }
+            assert!(removed.is_empty());
+            assert_eq!(0, removed.key_count());
+        }
+
+        #[test]
+        fn ordered_remove_all(ref map in ord_multi_map(u8::ANY, u8::ANY, 0..100)) {
+            let mut removed = map.clone();
+            for k in map.keys() {
+                let values = map.get_all(&*k);
+                assert_eq!(values, removed.remove_all_mut(&*k));
+            }
+            assert!(removed.is_empty());
+        }
+    }
+}
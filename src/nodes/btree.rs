@@ -2,10 +2,17 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::borrow::Borrow;
-use std::cmp::Ordering;
+use std::borrow::{Borrow, ToOwned};
+use std::cmp::{max, Ordering};
+use std::mem;
 use std::ops::IndexMut;
+use std::string::String;
 use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::Footprint;
+#[cfg(any(test, feature = "dot"))]
+use dot::Dot;
 
 use self::Insert::*;
 use self::InsertAction::*;
@@ -36,6 +43,7 @@ pub enum Insert<A> {
     NoChange,
     JustInc,
     Update(Node<A>),
+    Replaced(Node<A>),
     Split(Node<A>, A, Node<A>),
 }
 
@@ -54,7 +62,7 @@ pub enum Remove<A> {
 
 enum RemoveAction {
     DeleteAt(usize),
-    PullUp(usize, usize, usize),
+    PullUp(usize, usize),
     Merge(usize),
     StealFromLeft(usize),
     StealFromRight(usize),
@@ -101,6 +109,17 @@ impl<A> NodeData<A> {
     }
 }
 
+// The most values a tree of the given height can hold, or `None` if
+// that's more than fits in a `usize`.
+fn capacity(height: usize) -> Option<usize> {
+    (0..height).try_fold(NODE_SIZE, |below, _| {
+        below
+            .checked_add(1)?
+            .checked_mul(NODE_SIZE + 1)
+            .map(|total| total - 1)
+    })
+}
+
 impl<A> Default for Node<A> {
     fn default() -> Self {
         let mut children = Vec::with_capacity(NODE_SIZE + 1);
@@ -181,6 +200,121 @@ impl<A> Node<A> {
         Arc::ptr_eq(&self.0, &other.0)
     }
 
+    /// An address identifying this node, shared by all its clones.
+    #[inline]
+    pub fn id(&self) -> usize {
+        &*self.0 as *const NodeData<A> as usize
+    }
+
+    #[inline]
+    pub fn keys(&self) -> &[A] {
+        &self.0.keys
+    }
+
+    #[inline]
+    pub fn children(&self) -> &[Option<Node<A>>] {
+        &self.0.children
+    }
+
+    /// Build a tree of `len` values pulled in order from `next`,
+    /// which must produce them in ascending order. The tree is built
+    /// bottom up, with no intermediate buffer and no rebalancing.
+    ///
+    /// Returns `None` without pulling any values if `len` is more
+    /// than any tree can hold, so that a length read from untrusted
+    /// input can be passed straight in: a length which is merely too
+    /// large for the input fails when `next` runs out of values.
+    pub fn from_sorted<E, F>(len: usize, next: &mut F) -> Option<Result<Self, E>>
+    where
+        F: FnMut() -> Result<A, E>,
+    {
+        let mut height = 0;
+        while capacity(height)? < len {
+            height += 1;
+        }
+        Some(Node::build_sorted(len, height, next))
+    }
+
+    fn build_sorted<E, F>(len: usize, height: usize, next: &mut F) -> Result<Self, E>
+    where
+        F: FnMut() -> Result<A, E>,
+    {
+        let mut keys = Vec::with_capacity(NODE_SIZE);
+        let mut children = Vec::with_capacity(NODE_SIZE + 1);
+        if height == 0 {
+            children.push(None);
+            for _ in 0..len {
+                keys.push(next()?);
+                children.push(None);
+            }
+        } else {
+            // Use as few children as will fit, and spread the values
+            // evenly between them, which keeps every child at least
+            // half full.
+            // A tree this high fits in a `usize`, so the ones below
+            // it do too.
+            let below = capacity(height - 1).unwrap();
+            let width = len / (below + 1) + 1;
+            let spread = len - (width - 1);
+            for index in 0..width {
+                let size = spread / width + if index < spread % width { 1 } else { 0 };
+                children.push(Some(Node::build_sorted(size, height - 1, next)?));
+                if index + 1 < width {
+                    keys.push(next()?);
+                }
+            }
+        }
+        Ok(Node::wrap(NodeData {
+            count: len,
+            keys,
+            children,
+        }))
+    }
+
+    fn depth(&self) -> usize {
+        match self.0.children[0] {
+            None => 0,
+            Some(ref child) => 1 + child.depth(),
+        }
+    }
+
+    /// Count the memory used by this subtree, calling `value` for
+    /// each value in it, and return its height.
+    pub fn measure_heap<F>(&self, footprint: &mut Footprint, value: &mut F) -> usize
+    where
+        F: FnMut(&mut Footprint, &A),
+    {
+        let data = &*self.0;
+        footprint.node(&self.0, |footprint| {
+            footprint.buffer::<A>(data.keys.capacity());
+            footprint.buffer::<Option<Node<A>>>(data.children.capacity());
+            for key in &data.keys {
+                value(footprint, key);
+            }
+            let mut height = 0;
+            for child in data.children.iter().flatten() {
+                height = max(height, child.measure_heap(footprint, value));
+            }
+            if height == 0 {
+                footprint.leaf(data.keys.len(), NODE_SIZE);
+            }
+            height + 1
+        })
+    }
+
+    /// Draw this subtree into `dot`, with an edge from `from`.
+    #[cfg(any(test, feature = "dot"))]
+    pub fn write_dot(&self, dot: &mut Dot, from: &str) {
+        if let Some(id) = dot.edge(from, "", &self.0) {
+            let data = &*self.0;
+            let text = format!("{} values", data.count);
+            dot.label(&id, &text, data.keys.len(), NODE_SIZE);
+            for child in data.children.iter().flatten() {
+                child.write_dot(dot, &id);
+            }
+        }
+    }
+
     pub fn min(&self) -> Option<&A> {
         match *self.0.children.first().unwrap() {
             None => self.0.keys.first(),
@@ -197,6 +331,129 @@ impl<A> Node<A> {
 }
 
 impl<A: BTreeValue> Node<A> {
+    /// Build a node from its keys and children, or describe why they
+    /// wouldn't make a valid subtree: the keys must be in order and
+    /// fit in a node, and the children must be either all absent or
+    /// all subtrees of the same depth, ordered around the keys, and
+    /// at least as full as removals keep a node which isn't a root.
+    pub fn from_parts(keys: Vec<A>, children: Vec<Option<Node<A>>>) -> Result<Self, String> {
+        if keys.len() > NODE_SIZE {
+            return Err(format!("a node has {} keys", keys.len()));
+        }
+        if children.len() != keys.len() + 1 {
+            return Err(format!(
+                "a node has {} keys but {} children",
+                keys.len(),
+                children.len()
+            ));
+        }
+        if keys
+            .windows(2)
+            .any(|pair| pair[0].cmp_keys(&pair[1]) != Ordering::Less)
+        {
+            return Err("keys are out of order".to_owned());
+        }
+        if children.iter().any(Option::is_some) {
+            if keys.is_empty() {
+                return Err("a node has children but no keys".to_owned());
+            }
+            let depth = match children[0] {
+                Some(ref child) => child.depth(),
+                None => return Err("a node has both present and missing children".to_owned()),
+            };
+            for (index, child) in children.iter().enumerate() {
+                let child = match *child {
+                    Some(ref child) => child,
+                    None => return Err("a node has both present and missing children".to_owned()),
+                };
+                if child.depth() != depth {
+                    return Err("leaves are at different depths".to_owned());
+                }
+                if child.0.keys.len() < MEDIAN - 1 {
+                    return Err(format!("a node has only {} keys", child.0.keys.len()));
+                }
+                let (min, max) = (child.min().unwrap(), child.max().unwrap());
+                if index > 0 && keys[index - 1].cmp_keys(min) != Ordering::Less {
+                    return Err("keys are out of order".to_owned());
+                }
+                if index < keys.len() && max.cmp_keys(&keys[index]) != Ordering::Less {
+                    return Err("keys are out of order".to_owned());
+                }
+            }
+        }
+        let mut data = NodeData {
+            count: keys.len(),
+            keys,
+            children,
+        };
+        data.count = data.sum_up_children();
+        Ok(Node::wrap(data))
+    }
+
+    /// Check the structure of the tree with this node at its root,
+    /// describing the first problem found.
+    pub fn check(&self) -> Result<(), String> {
+        if self.0.keys.is_empty() && self.0.children[0].is_some() {
+            return Err("the root has children but no keys".to_owned());
+        }
+        self.check_node(None, None, true).map(|_| ())
+    }
+
+    // Check a subtree whose values must all sort between `min` and
+    // `max`, and return its depth.
+    fn check_node(&self, min: Option<&A>, max: Option<&A>, root: bool) -> Result<usize, String> {
+        let data = &*self.0;
+        if data.keys.len() > NODE_SIZE {
+            return Err(format!("a node has {} keys", data.keys.len()));
+        }
+        // Removals make sure a child has at least `MEDIAN` keys before
+        // removing one from it.
+        if !root && data.keys.len() < MEDIAN - 1 {
+            return Err(format!("a node has only {} keys", data.keys.len()));
+        }
+        if data.children.len() != data.keys.len() + 1 {
+            return Err(format!(
+                "a node has {} keys but {} children",
+                data.keys.len(),
+                data.children.len()
+            ));
+        }
+        let mut bounds = min.into_iter().chain(data.keys.iter()).chain(max);
+        if let Some(mut previous) = bounds.next() {
+            for key in bounds {
+                if previous.cmp_keys(key) != Ordering::Less {
+                    return Err("keys are out of order".to_owned());
+                }
+                previous = key;
+            }
+        }
+        let leaf = data.children[0].is_none();
+        let mut depth = None;
+        let mut count = data.keys.len();
+        for (index, child) in data.children.iter().enumerate() {
+            let child = match (child, leaf) {
+                (&None, true) => continue,
+                (&Some(ref child), false) => child,
+                _ => return Err("a node has both present and missing children".to_owned()),
+            };
+            let lower = if index == 0 { min } else { data.keys.get(index - 1) };
+            let upper = data.keys.get(index).or(max);
+            let child_depth = child.check_node(lower, upper, false)?;
+            if depth.is_some() && depth != Some(child_depth) {
+                return Err("leaves are at different depths".to_owned());
+            }
+            depth = Some(child_depth);
+            count += child.len();
+        }
+        if data.count != count {
+            return Err(format!(
+                "a node's count is {} but it holds {} values",
+                data.count, count
+            ));
+        }
+        Ok(depth.map_or(0, |depth| depth + 1))
+    }
+
     pub fn lookup<BK>(&self, key: &BK) -> Option<&A>
     where
         BK: Ord + ?Sized,
@@ -277,7 +534,8 @@ impl<A: BTreeValue> Node<A> {
                 } else {
                     let mut new_data = (&*self.0).clone();
                     new_data.keys[index] = value;
-                    Insert::Update(Node::wrap(new_data))
+                    // Replacing a key leaves the size as it was.
+                    Insert::Replaced(Node::wrap(new_data))
                 }
             }
             // Key is adjacent to some key in node
@@ -305,6 +563,11 @@ impl<A: BTreeValue> Node<A> {
                         new_data.count += 1;
                         Insert::Update(Node::wrap(new_data))
                     }
+                    Insert::Replaced(new_node) => {
+                        let mut new_data = (*self.0).clone();
+                        new_data.children[index] = Some(new_node);
+                        Insert::Replaced(Node::wrap(new_data))
+                    }
                     Insert::Split(left, median, right) => {
                         // Child node split; insert it.
                         if self.has_room() {
@@ -400,11 +663,33 @@ impl<A: BTreeValue> Node<A> {
         node.children.push(child);
     }
 
-    fn pull_up<BK>(
-        &self,
+    // Replace the largest value in the tree with the given one,
+    // returning the value it replaced.
+    fn swap_max_mut(&mut self, value: A) -> A {
+        let node = Arc::make_mut(&mut self.0);
+        if let Some(ref mut child) = *node.children.last_mut().unwrap() {
+            return child.swap_max_mut(value);
+        }
+        mem::replace(node.keys.last_mut().unwrap(), value)
+    }
+
+    // Replace the smallest value in the tree with the given one,
+    // returning the value it replaced.
+    fn swap_min_mut(&mut self, value: A) -> A {
+        let node = Arc::make_mut(&mut self.0);
+        if let Some(ref mut child) = node.children[0] {
+            return child.swap_min_mut(value);
+        }
+        mem::replace(&mut node.keys[0], value)
+    }
+
+    // Swap the key at `pull_to` with its predecessor, if `child` is
+    // the child to its left, or its successor, if it's the one to its
+    // right, which will be at the bottom of the child's subtree, and
+    // then remove it from there. The child must not be too small.
+    fn pull_up_mut<BK>(
+        node: &mut NodeData<A>,
         key: &BK,
-        from_index: usize,
-        from_child: &Node<A>,
         pull_to: usize,
         child_index: usize,
     ) -> Remove<A>
@@ -412,17 +697,37 @@ impl<A: BTreeValue> Node<A> {
         BK: Ord + ?Sized,
         A::Key: Borrow<BK>,
     {
-        match from_child.remove_index(Ok(from_index), key) {
+        let removing = node.keys[pull_to].clone();
+        let child = match node.children[child_index] {
+            Some(ref mut child) => child,
+            None => unreachable!(),
+        };
+        node.keys[pull_to] = if child_index == pull_to {
+            child.swap_max_mut(removing)
+        } else {
+            child.swap_min_mut(removing)
+        };
+        let (pair, update) = match child.remove_mut(key) {
             Remove::NoChange => unreachable!(),
-            Remove::Removed(_) => unreachable!(),
-            Remove::Update(pulled_pair, new_child) => {
-                let mut new_data = (&*self.0).clone();
-                new_data.keys.push(pulled_pair);
-                let pair = new_data.keys.swap_remove(pull_to);
-                new_data.children[child_index] = Some(new_child);
-                new_data.count -= 1;
-                Remove::Update(pair, Node::wrap(new_data))
-            }
+            Remove::Removed(pair) => (pair, None),
+            Remove::Update(pair, new_child) => (pair, Some(new_child)),
+        };
+        if let Some(new_child) = update {
+            *child = new_child;
+        }
+        node.count -= 1;
+        Remove::Removed(pair)
+    }
+
+    fn pull_up<BK>(&self, key: &BK, pull_to: usize, child_index: usize) -> Remove<A>
+    where
+        BK: Ord + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        let mut new_data = (&*self.0).clone();
+        match Node::pull_up_mut(&mut new_data, key, pull_to, child_index) {
+            Remove::Removed(pair) => Remove::Update(pair, Node::wrap(new_data)),
+            _ => unreachable!(),
         }
     }
 
@@ -453,11 +758,11 @@ impl<A: BTreeValue> Node<A> {
                     }
                     // If the left hand child has capacity, pull the predecessor up.
                     (&Some(ref left), _) if !left.too_small() => {
-                        self.pull_up(key, left.0.keys.len() - 1, left, index, index)
+                        self.pull_up(key, index, index)
                     }
                     // If the right hand child has capacity, pull the successor up.
                     (_, &Some(ref right)) if !right.too_small() => {
-                        self.pull_up(key, 0, right, index, index + 1)
+                        self.pull_up(key, index, index + 1)
                     }
                     // If neither child has capacity, we'll have to merge them.
                     (&Some(ref left), &Some(ref right)) => {
@@ -622,7 +927,8 @@ impl<A: BTreeValue> Node<A> {
                 } else {
                     let mut node = Arc::make_mut(&mut self.0);
                     node.keys[index] = value;
-                    return Insert::JustInc;
+                    // Replacing a key leaves the size as it was.
+                    return Insert::NoChange;
                 }
             }
             // Key is adjacent to some key in node
@@ -636,7 +942,7 @@ impl<A: BTreeValue> Node<A> {
                     Some(ref mut child) => match child.insert_mut(value.clone()) {
                         Insert::NoChange => NoAction,
                         Insert::JustInc => IncAction,
-                        Insert::Update(_) => unreachable!(),
+                        Insert::Update(_) | Insert::Replaced(_) => unreachable!(),
                         Insert::Split(left, median, right) => InsertSplit(left, median, right),
                     },
                 };
@@ -695,11 +1001,11 @@ impl<A: BTreeValue> Node<A> {
                     (&None, &None) => RemoveAction::DeleteAt(index),
                     // If the left hand child has capacity, pull the predecessor up.
                     (&Some(ref left), _) if !left.too_small() => {
-                        RemoveAction::PullUp(left.0.keys.len() - 1, index, index)
+                        RemoveAction::PullUp(index, index)
                     }
                     // If the right hand child has capacity, pull the successor up.
                     (_, &Some(ref right)) if !right.too_small() => {
-                        RemoveAction::PullUp(0, index, index + 1)
+                        RemoveAction::PullUp(index, index + 1)
                     }
                     // If neither child has capacity, we'll have to merge them.
                     (&Some(_), &Some(_)) => RemoveAction::Merge(index),
@@ -748,33 +1054,8 @@ impl<A: BTreeValue> Node<A> {
                 node.count -= 1;
                 Remove::Removed(pair)
             }
-            RemoveAction::PullUp(target_index, pull_to, child_index) => {
-                let mut node = Arc::make_mut(&mut self.0);
-                let mut children = &mut node.children;
-                let mut update = None;
-                let mut pair;
-                if let Some(&mut Some(ref mut child)) = children.get_mut(child_index) {
-                    match child.remove_mut_index(Ok(target_index), key) {
-                        Remove::NoChange => unreachable!(),
-                        Remove::Removed(pulled_pair) => {
-                            node.keys.push(pulled_pair);
-                            pair = node.keys.swap_remove(pull_to);
-                            node.count -= 1;
-                        }
-                        Remove::Update(pulled_pair, new_child) => {
-                            node.keys.push(pulled_pair);
-                            pair = node.keys.swap_remove(pull_to);
-                            node.count -= 1;
-                            update = Some(new_child);
-                        }
-                    }
-                } else {
-                    unreachable!()
-                }
-                if let Some(new_child) = update {
-                    children[child_index] = Some(new_child);
-                }
-                Remove::Removed(pair)
+            RemoveAction::PullUp(pull_to, child_index) => {
+                Node::pull_up_mut(Arc::make_mut(&mut self.0), key, pull_to, child_index)
             }
             RemoveAction::Merge(index) => {
                 let mut merged_child = if let (Some(&Some(ref left)), Some(&Some(ref right))) =
@@ -1102,7 +1383,7 @@ pub struct DiffIter<A> {
     new_stack: Vec<IterItem<A>>,
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum DiffItem<A> {
     Add(A),
     Update { old: A, new: A },
@@ -0,0 +1,508 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// The nodes of a finger tree are nested: the middle of a tree holds
+// 2-3 nodes of the elements one level up. Rather than expressing this
+// as a nested type, which Rust can't monomorphise, a node is either a
+// leaf holding an element or a branch holding 2-3 nodes of the level
+// below, and each level of the tree keeps track of its own depth
+// implicitly.
+
+use std::cmp::max;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use fingertree::Measure;
+use footprint::Footprint;
+
+pub enum Node<A, M: Measure<A>> {
+    Leaf(Arc<A>),
+    Branch(Arc<Branch<A, M>>),
+}
+
+pub struct Branch<A, M: Measure<A>> {
+    measure: M::Value,
+    children: Vec<Node<A, M>>,
+}
+
+pub enum Tree<A, M: Measure<A>> {
+    Empty,
+    Single(Node<A, M>),
+    Deep(Arc<Deep<A, M>>),
+}
+
+pub struct Deep<A, M: Measure<A>> {
+    measure: M::Value,
+    prefix: Vec<Node<A, M>>,
+    middle: Tree<A, M>,
+    suffix: Vec<Node<A, M>>,
+}
+
+impl<A, M: Measure<A>> Clone for Node<A, M> {
+    fn clone(&self) -> Self {
+        match *self {
+            Node::Leaf(ref value) => Node::Leaf(value.clone()),
+            Node::Branch(ref branch) => Node::Branch(branch.clone()),
+        }
+    }
+}
+
+impl<A, M: Measure<A>> Clone for Tree<A, M> {
+    fn clone(&self) -> Self {
+        match *self {
+            Tree::Empty => Tree::Empty,
+            Tree::Single(ref node) => Tree::Single(node.clone()),
+            Tree::Deep(ref deep) => Tree::Deep(deep.clone()),
+        }
+    }
+}
+
+// The nodes before a split point, the node at it, and the nodes after.
+type Split<A, M> = (Vec<Node<A, M>>, Node<A, M>, Vec<Node<A, M>>);
+
+fn measure_nodes<A, M: Measure<A>>(nodes: &[Node<A, M>]) -> M::Value {
+    nodes
+        .iter()
+        .fold(M::zero(), |acc, node| M::combine(&acc, &node.measure()))
+}
+
+// Group a list of at least two nodes into 2-3 nodes, preferring
+// 3-nodes.
+fn make_nodes<A, M: Measure<A>>(mut nodes: &[Node<A, M>]) -> Vec<Node<A, M>> {
+    let mut out = Vec::with_capacity(nodes.len() / 3 + 1);
+    loop {
+        match nodes.len() {
+            2 | 3 => {
+                out.push(Node::branch(nodes.to_vec()));
+                return out;
+            }
+            4 => {
+                out.push(Node::branch(nodes[..2].to_vec()));
+                out.push(Node::branch(nodes[2..].to_vec()));
+                return out;
+            }
+            _ => {
+                out.push(Node::branch(nodes[..3].to_vec()));
+                nodes = &nodes[3..];
+            }
+        }
+    }
+}
+
+fn split_nodes<A, M, P>(
+    pred: &P,
+    mut acc: M::Value,
+    nodes: &[Node<A, M>],
+) -> Split<A, M>
+where
+    M: Measure<A>,
+    P: Fn(&M::Value) -> bool,
+{
+    let last = nodes.len() - 1;
+    for (index, node) in nodes.iter().enumerate() {
+        acc = M::combine(&acc, &node.measure());
+        if index == last || pred(&acc) {
+            return (
+                nodes[..index].to_vec(),
+                node.clone(),
+                nodes[index + 1..].to_vec(),
+            );
+        }
+    }
+    unreachable!()
+}
+
+fn lookup_nodes<'a, A, M, P>(
+    pred: &P,
+    mut acc: M::Value,
+    nodes: &'a [Node<A, M>],
+) -> (M::Value, &'a Node<A, M>)
+where
+    M: Measure<A>,
+    P: Fn(&M::Value) -> bool,
+{
+    let last = nodes.len() - 1;
+    for (index, node) in nodes.iter().enumerate() {
+        let next = M::combine(&acc, &node.measure());
+        if index == last || pred(&next) {
+            return (acc, node);
+        }
+        acc = next;
+    }
+    unreachable!()
+}
+
+impl<A, M: Measure<A>> Node<A, M> {
+    fn branch(children: Vec<Node<A, M>>) -> Self {
+        Node::Branch(Arc::new(Branch {
+            measure: measure_nodes(&children),
+            children,
+        }))
+    }
+
+    pub fn measure(&self) -> M::Value {
+        match *self {
+            Node::Leaf(ref value) => M::measure(value),
+            Node::Branch(ref branch) => branch.measure.clone(),
+        }
+    }
+
+    fn children(&self) -> &[Node<A, M>] {
+        match *self {
+            Node::Branch(ref branch) => &branch.children,
+            Node::Leaf(_) => panic!("fingertree::Node::children: called on a leaf"),
+        }
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Node::Leaf(left), Node::Leaf(right)) => Arc::ptr_eq(left, right),
+            (Node::Branch(left), Node::Branch(right)) => Arc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+
+    pub fn unwrap_leaf(&self) -> &Arc<A> {
+        match *self {
+            Node::Leaf(ref value) => value,
+            Node::Branch(_) => panic!("fingertree::Node::unwrap_leaf: called on a branch"),
+        }
+    }
+
+    /// Count the memory used by this node and return its height,
+    /// which is zero for an element.
+    pub fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        match *self {
+            Node::Leaf(ref value) => {
+                footprint.value(value);
+                0
+            }
+            Node::Branch(ref branch) => footprint.node(branch, |footprint| {
+                footprint.buffer::<Node<A, M>>(branch.children.capacity());
+                let mut height = 0;
+                for child in &branch.children {
+                    height = max(height, child.measure_heap(footprint));
+                }
+                if height == 0 {
+                    footprint.leaf(branch.children.len(), 3);
+                }
+                height + 1
+            }),
+        }
+    }
+}
+
+impl<A, M: Measure<A>> Tree<A, M> {
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Tree::Empty, Tree::Empty) => true,
+            (Tree::Single(left), Tree::Single(right)) => left.ptr_eq(right),
+            (Tree::Deep(left), Tree::Deep(right)) => Arc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+
+    /// Count the memory used by this tree and return its height.
+    pub fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        match *self {
+            Tree::Empty => 0,
+            Tree::Single(ref node) => node.measure_heap(footprint),
+            Tree::Deep(ref deep) => footprint.node(deep, |footprint| {
+                footprint.buffer::<Node<A, M>>(deep.prefix.capacity());
+                footprint.buffer::<Node<A, M>>(deep.suffix.capacity());
+                let mut height = deep.middle.measure_heap(footprint);
+                for node in deep.prefix.iter().chain(deep.suffix.iter()) {
+                    height = max(height, node.measure_heap(footprint));
+                }
+                height + 1
+            }),
+        }
+    }
+
+    fn deep(prefix: Vec<Node<A, M>>, middle: Tree<A, M>, suffix: Vec<Node<A, M>>) -> Self {
+        let measure = M::combine(
+            &M::combine(&measure_nodes(&prefix), &middle.measure()),
+            &measure_nodes(&suffix),
+        );
+        Tree::Deep(Arc::new(Deep {
+            measure,
+            prefix,
+            middle,
+            suffix,
+        }))
+    }
+
+    fn from_nodes(nodes: &[Node<A, M>]) -> Self {
+        nodes
+            .iter()
+            .fold(Tree::Empty, |tree, node| tree.push_back(node.clone()))
+    }
+
+    // Build a tree from a prefix which may be empty, borrowing a node
+    // from the middle if it is.
+    fn deep_left(prefix: Vec<Node<A, M>>, middle: &Tree<A, M>, suffix: &[Node<A, M>]) -> Self {
+        if !prefix.is_empty() {
+            return Tree::deep(prefix, middle.clone(), suffix.to_vec());
+        }
+        match middle.pop_front() {
+            None => Tree::from_nodes(suffix),
+            Some((node, middle)) => Tree::deep(node.children().to_vec(), middle, suffix.to_vec()),
+        }
+    }
+
+    // Build a tree from a suffix which may be empty, borrowing a node
+    // from the middle if it is.
+    fn deep_right(prefix: &[Node<A, M>], middle: &Tree<A, M>, suffix: Vec<Node<A, M>>) -> Self {
+        if !suffix.is_empty() {
+            return Tree::deep(prefix.to_vec(), middle.clone(), suffix);
+        }
+        match middle.pop_back() {
+            None => Tree::from_nodes(prefix),
+            Some((node, middle)) => Tree::deep(prefix.to_vec(), middle, node.children().to_vec()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head().is_none()
+    }
+
+    pub fn measure(&self) -> M::Value {
+        match *self {
+            Tree::Empty => M::zero(),
+            Tree::Single(ref node) => node.measure(),
+            Tree::Deep(ref deep) => deep.measure.clone(),
+        }
+    }
+
+    pub fn head(&self) -> Option<&Node<A, M>> {
+        match *self {
+            Tree::Empty => None,
+            Tree::Single(ref node) => Some(node),
+            Tree::Deep(ref deep) => deep.prefix.first(),
+        }
+    }
+
+    pub fn last(&self) -> Option<&Node<A, M>> {
+        match *self {
+            Tree::Empty => None,
+            Tree::Single(ref node) => Some(node),
+            Tree::Deep(ref deep) => deep.suffix.last(),
+        }
+    }
+
+    pub fn push_front(&self, node: Node<A, M>) -> Self {
+        match *self {
+            Tree::Empty => Tree::Single(node),
+            Tree::Single(ref other) => Tree::deep(vec![node], Tree::Empty, vec![other.clone()]),
+            Tree::Deep(ref deep) => {
+                if deep.prefix.len() == 4 {
+                    let middle = deep.middle.push_front(Node::branch(deep.prefix[1..].to_vec()));
+                    Tree::deep(
+                        vec![node, deep.prefix[0].clone()],
+                        middle,
+                        deep.suffix.clone(),
+                    )
+                } else {
+                    let mut prefix = Vec::with_capacity(deep.prefix.len() + 1);
+                    prefix.push(node);
+                    prefix.extend(deep.prefix.iter().cloned());
+                    Tree::deep(prefix, deep.middle.clone(), deep.suffix.clone())
+                }
+            }
+        }
+    }
+
+    pub fn push_back(&self, node: Node<A, M>) -> Self {
+        match *self {
+            Tree::Empty => Tree::Single(node),
+            Tree::Single(ref other) => Tree::deep(vec![other.clone()], Tree::Empty, vec![node]),
+            Tree::Deep(ref deep) => {
+                if deep.suffix.len() == 4 {
+                    let middle = deep.middle.push_back(Node::branch(deep.suffix[..3].to_vec()));
+                    Tree::deep(
+                        deep.prefix.clone(),
+                        middle,
+                        vec![deep.suffix[3].clone(), node],
+                    )
+                } else {
+                    let mut suffix = Vec::with_capacity(deep.suffix.len() + 1);
+                    suffix.extend(deep.suffix.iter().cloned());
+                    suffix.push(node);
+                    Tree::deep(deep.prefix.clone(), deep.middle.clone(), suffix)
+                }
+            }
+        }
+    }
+
+    pub fn pop_front(&self) -> Option<(Node<A, M>, Self)> {
+        match *self {
+            Tree::Empty => None,
+            Tree::Single(ref node) => Some((node.clone(), Tree::Empty)),
+            Tree::Deep(ref deep) => Some((
+                deep.prefix[0].clone(),
+                Tree::deep_left(deep.prefix[1..].to_vec(), &deep.middle, &deep.suffix),
+            )),
+        }
+    }
+
+    pub fn pop_back(&self) -> Option<(Node<A, M>, Self)> {
+        match *self {
+            Tree::Empty => None,
+            Tree::Single(ref node) => Some((node.clone(), Tree::Empty)),
+            Tree::Deep(ref deep) => {
+                let last = deep.suffix.len() - 1;
+                Some((
+                    deep.suffix[last].clone(),
+                    Tree::deep_right(&deep.prefix, &deep.middle, deep.suffix[..last].to_vec()),
+                ))
+            }
+        }
+    }
+
+    /// Concatenate two trees with a list of nodes in between them.
+    ///
+    /// Time: O(log min(n, m))
+    pub fn concat(left: &Self, middle: &[Node<A, M>], right: &Self) -> Self {
+        match (left, right) {
+            (Tree::Empty, _) => middle
+                .iter()
+                .rev()
+                .fold(right.clone(), |tree, node| tree.push_front(node.clone())),
+            (_, Tree::Empty) => middle
+                .iter()
+                .fold(left.clone(), |tree, node| tree.push_back(node.clone())),
+            (Tree::Single(node), _) => {
+                Tree::concat(&Tree::Empty, middle, right).push_front(node.clone())
+            }
+            (_, Tree::Single(node)) => {
+                Tree::concat(left, middle, &Tree::Empty).push_back(node.clone())
+            }
+            (Tree::Deep(l), Tree::Deep(r)) => {
+                let mut nodes = Vec::with_capacity(l.suffix.len() + middle.len() + r.prefix.len());
+                nodes.extend(l.suffix.iter().cloned());
+                nodes.extend(middle.iter().cloned());
+                nodes.extend(r.prefix.iter().cloned());
+                Tree::deep(
+                    l.prefix.clone(),
+                    Tree::concat(&l.middle, &make_nodes(&nodes), &r.middle),
+                    r.suffix.clone(),
+                )
+            }
+        }
+    }
+
+    /// Split a non-empty tree at the first node where `pred` becomes
+    /// true for the accumulated measure, starting from `acc`.
+    ///
+    /// If `pred` never becomes true, the split happens at the last
+    /// node.
+    pub fn split<P>(&self, pred: &P, acc: M::Value) -> (Self, Node<A, M>, Self)
+    where
+        P: Fn(&M::Value) -> bool,
+    {
+        match *self {
+            Tree::Empty => panic!("fingertree::Tree::split: called on an empty tree"),
+            Tree::Single(ref node) => (Tree::Empty, node.clone(), Tree::Empty),
+            Tree::Deep(ref deep) => {
+                let vpr = M::combine(&acc, &measure_nodes(&deep.prefix));
+                if pred(&vpr) {
+                    let (l, x, r) = split_nodes(pred, acc, &deep.prefix);
+                    return (
+                        Tree::from_nodes(&l),
+                        x,
+                        Tree::deep_left(r, &deep.middle, &deep.suffix),
+                    );
+                }
+                let vm = M::combine(&vpr, &deep.middle.measure());
+                if pred(&vm) {
+                    let (ml, xs, mr) = deep.middle.split(pred, vpr.clone());
+                    let acc = M::combine(&vpr, &ml.measure());
+                    let (l, x, r) = split_nodes(pred, acc, xs.children());
+                    (
+                        Tree::deep_right(&deep.prefix, &ml, l),
+                        x,
+                        Tree::deep_left(r, &mr, &deep.suffix),
+                    )
+                } else {
+                    let (l, x, r) = split_nodes(pred, vm, &deep.suffix);
+                    (
+                        Tree::deep_right(&deep.prefix, &deep.middle, l),
+                        x,
+                        Tree::from_nodes(&r),
+                    )
+                }
+            }
+        }
+    }
+
+    /// Find the first node in a non-empty tree where `pred` becomes
+    /// true for the accumulated measure, starting from `acc`, without
+    /// building any new trees.
+    ///
+    /// Returns the measure accumulated before the node, and the node.
+    pub fn lookup<P>(&self, pred: &P, acc: M::Value) -> (M::Value, &Node<A, M>)
+    where
+        P: Fn(&M::Value) -> bool,
+    {
+        match *self {
+            Tree::Empty => panic!("fingertree::Tree::lookup: called on an empty tree"),
+            Tree::Single(ref node) => (acc, node),
+            Tree::Deep(ref deep) => {
+                let vpr = M::combine(&acc, &measure_nodes(&deep.prefix));
+                if pred(&vpr) {
+                    return lookup_nodes(pred, acc, &deep.prefix);
+                }
+                let vm = M::combine(&vpr, &deep.middle.measure());
+                if pred(&vm) {
+                    let (acc, node) = deep.middle.lookup(pred, vpr);
+                    lookup_nodes(pred, acc, node.children())
+                } else {
+                    lookup_nodes(pred, vm, &deep.suffix)
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter<A, M> {
+        Iter {
+            stack: vec![IterItem::Tree(self.clone())],
+        }
+    }
+}
+
+// Iterators
+
+enum IterItem<A, M: Measure<A>> {
+    Tree(Tree<A, M>),
+    Node(Node<A, M>),
+}
+
+pub struct Iter<A, M: Measure<A>> {
+    stack: Vec<IterItem<A, M>>,
+}
+
+impl<A, M: Measure<A>> Iterator for Iter<A, M> {
+    type Item = Arc<A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(item) = self.stack.pop() {
+            match item {
+                IterItem::Node(Node::Leaf(value)) => return Some(value),
+                IterItem::Node(Node::Branch(branch)) => self
+                    .stack
+                    .extend(branch.children.iter().rev().cloned().map(IterItem::Node)),
+                IterItem::Tree(Tree::Empty) => (),
+                IterItem::Tree(Tree::Single(node)) => self.stack.push(IterItem::Node(node)),
+                IterItem::Tree(Tree::Deep(deep)) => {
+                    self.stack
+                        .extend(deep.suffix.iter().rev().cloned().map(IterItem::Node));
+                    self.stack.push(IterItem::Tree(deep.middle.clone()));
+                    self.stack
+                        .extend(deep.prefix.iter().rev().cloned().map(IterItem::Node));
+                }
+            }
+        }
+        None
+    }
+}
@@ -2,11 +2,18 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, ToOwned};
+use std::cmp::max;
+use std::string::String;
 use std::sync::Arc;
+use std::vec::Vec;
 
 use bits::{bitpos, index, Bitmap, HASH_BITS, HASH_SIZE};
+use footprint::Footprint;
+use nodes::btree::DiffItem;
 use shared::Shared;
+#[cfg(any(test, feature = "dot"))]
+use dot::Dot;
 
 pub trait HashValue: Clone {
     type Key: Eq;
@@ -476,10 +483,9 @@ impl<A: HashValue> Node<A> {
                 },
                 Entry::Collision(ref coll) => match coll.remove(key) {
                     None => None,
-                    Some((value, next_coll)) => Some((
-                        value,
-                        self.update_value(pos, Entry::Collision(Arc::new(next_coll))),
-                    )),
+                    Some((value, next_coll)) => {
+                        Some((value, self.update_value(pos, next_coll.into_entry())))
+                    }
                 },
             }
         } else if self.nodemap & pos != 0 {
@@ -519,17 +525,26 @@ impl<A: HashValue> Node<A> {
             let removed;
             match self.data[index] {
                 Entry::Value(ref value, _) => if key == value.extract_key().borrow() {
-                    removed = value.clone();
+                    removed = Some(value.clone());
                 } else {
                     return None;
                 },
                 Entry::Collision(ref mut collisions) => {
-                    let mut coll = Arc::make_mut(collisions);
-                    return coll.remove_mut(key);
+                    let coll = Arc::make_mut(collisions);
+                    removed = coll.remove_mut(key);
+                    if coll.data.len() > 1 {
+                        return removed;
+                    }
                 }
             }
+            if let Entry::Collision(ref coll) = self.data[index] {
+                // Only one value is left, so it's no longer a collision.
+                let value = coll.data[0].clone();
+                self.data[index] = Entry::Value(value, coll.hash);
+                return removed;
+            }
             self.remove_value_mut(pos);
-            Some(removed)
+            removed
         } else if self.nodemap & pos != 0 {
             // Key is in a subnode.
             let index = self.node_index(pos);
@@ -561,6 +576,165 @@ impl<A: HashValue> Node<A> {
     }
 }
 
+impl<A: HashValue> Node<A> {
+    /// Check the structure of the trie with this node at its root,
+    /// which should hold `size` values hashed by `hash`, describing
+    /// the first problem found.
+    pub fn check<F>(&self, size: usize, hash: &F) -> Result<(), String>
+    where
+        F: Fn(&A::Key) -> Bitmap,
+    {
+        let count = self.check_node(0, 0, hash)?;
+        if count != size {
+            return Err(format!("the size is {} but there are {} values", size, count));
+        }
+        Ok(())
+    }
+
+    // Check a node at `shift`, whose values' hashes must all end with
+    // the bits in `path`, and return how many values it holds.
+    fn check_node<F>(&self, shift: usize, path: Bitmap, hash: &F) -> Result<usize, String>
+    where
+        F: Fn(&A::Key) -> Bitmap,
+    {
+        if self.datamap & self.nodemap != 0 {
+            return Err("a slot holds both a value and a subnode".to_owned());
+        }
+        if self.datamap.count_ones() as usize != self.data.len()
+            || self.nodemap.count_ones() as usize != self.nodes.len()
+        {
+            return Err("a node's bitmaps don't match its contents".to_owned());
+        }
+        if self.nodemap != 0 && shift + HASH_BITS >= HASH_SIZE {
+            return Err("a node below the last level of the trie".to_owned());
+        }
+        // Check that a hash belongs in the slot at `bit`.
+        let check_hash = |value_hash: Bitmap, bit: Bitmap| {
+            let below = (1 << shift) - 1;
+            if value_hash & below != path || bitpos(value_hash, shift) != bit {
+                Err("a value is in the wrong slot for its hash".to_owned())
+            } else {
+                Ok(())
+            }
+        };
+        let mut count = 0;
+        for (bit, entry) in bits(self.datamap).zip(self.data.iter()) {
+            match *entry {
+                Entry::Value(ref value, value_hash) => {
+                    if hash(value.extract_key()) != value_hash {
+                        return Err("a stored hash doesn't match its key".to_owned());
+                    }
+                    check_hash(value_hash, bit)?;
+                    count += 1;
+                }
+                Entry::Collision(ref coll) => {
+                    if coll.data.len() < 2 {
+                        return Err(format!(
+                            "a collision node holds {} values",
+                            coll.data.len()
+                        ));
+                    }
+                    check_hash(coll.hash, bit)?;
+                    for (index, value) in coll.data.iter().enumerate() {
+                        if hash(value.extract_key()) != coll.hash {
+                            return Err("a collision node holds a value with another hash".to_owned());
+                        }
+                        if coll.data[..index]
+                            .iter()
+                            .any(|other| other.extract_key() == value.extract_key())
+                        {
+                            return Err("a collision node holds the same key twice".to_owned());
+                        }
+                    }
+                    count += coll.data.len();
+                }
+            }
+        }
+        for (bit, node) in bits(self.nodemap).zip(self.nodes.iter()) {
+            match node.size_predicate() {
+                SizePredicate::Empty => return Err("a subnode is empty".to_owned()),
+                // A lone value belongs in its parent, though a lone
+                // collision node can sit below a chain of subnodes.
+                SizePredicate::One => if let Entry::Value(_, _) = node.data[0] {
+                    return Err("a subnode holds a single value".to_owned());
+                },
+                SizePredicate::Many => (),
+            }
+            let slot = bit.trailing_zeros() as Bitmap;
+            count += node.check_node(shift + HASH_BITS, path | slot << shift, hash)?;
+        }
+        Ok(count)
+    }
+}
+
+// The set bits of a bitmap, lowest first.
+fn bits(bitmap: Bitmap) -> impl Iterator<Item = Bitmap> {
+    (0..HASH_SIZE)
+        .map(|index| 1 << index)
+        .filter(move |bit| bitmap & bit != 0)
+}
+
+impl<A> Node<A> {
+    /// Count the memory used by this subtree, calling `value` for
+    /// each value in it, and return its height.
+    pub fn measure_heap<F>(this: &Arc<Self>, footprint: &mut Footprint, value: &mut F) -> usize
+    where
+        F: FnMut(&mut Footprint, &A),
+    {
+        footprint.node(this, |footprint| {
+            footprint.buffer::<Entry<A>>(this.data.capacity());
+            footprint.buffer::<Arc<Node<A>>>(this.nodes.capacity());
+            // Collision nodes sit at the same level as the values
+            // beside them, so a node with no child nodes is a leaf
+            // whatever collisions it holds, and each collision adds
+            // its values to the leaf in place of a single one.
+            let mut values = this.data.len();
+            for entry in &this.data {
+                match *entry {
+                    Entry::Value(ref entry_value, _) => value(footprint, entry_value),
+                    Entry::Collision(ref coll) => {
+                        footprint.node(coll, |footprint| {
+                            footprint.buffer::<A>(coll.data.capacity());
+                            for coll_value in &coll.data {
+                                value(footprint, coll_value);
+                            }
+                            0
+                        });
+                        values += coll.data.len() - 1;
+                    }
+                }
+            }
+            let mut height = 0;
+            for node in &this.nodes {
+                height = max(height, Node::measure_heap(node, footprint, value));
+            }
+            if height == 0 {
+                footprint.leaf(values, values - this.data.len() + HASH_SIZE);
+            }
+            height + 1
+        })
+    }
+
+    /// Draw this subtree into `dot`, with an edge from `from`.
+    #[cfg(any(test, feature = "dot"))]
+    pub fn write_dot(this: &Arc<Self>, dot: &mut Dot, from: &str) {
+        if let Some(id) = dot.edge(from, "", this) {
+            let text = format!("{} values, {} subnodes", this.data.len(), this.nodes.len());
+            dot.label(&id, &text, this.data.len() + this.nodes.len(), HASH_SIZE);
+            for entry in &this.data {
+                if let Entry::Collision(ref coll) = *entry {
+                    if let Some(coll_id) = dot.edge(&id, "", coll) {
+                        dot.label(&coll_id, "collision", coll.data.len(), coll.data.len());
+                    }
+                }
+            }
+            for node in &this.nodes {
+                Node::write_dot(node, dot, &id);
+            }
+        }
+    }
+}
+
 impl<A: HashValue> CollisionNode<A> {
     fn new(hash: Bitmap, value1: Entry<A>, value2: Entry<A>) -> Self {
         let mut data = Vec::new();
@@ -635,6 +809,17 @@ impl<A: HashValue> CollisionNode<A> {
         true
     }
 
+    // Turn the node back into a single value once there's no longer a
+    // collision.
+    fn into_entry(self) -> Entry<A> {
+        if self.data.len() == 1 {
+            let hash = self.hash;
+            Entry::Value(self.data.into_iter().next().unwrap(), hash)
+        } else {
+            Entry::Collision(Arc::new(self))
+        }
+    }
+
     fn remove<BK>(&self, key: &BK) -> Option<(A, Self)>
     where
         BK: Eq + ?Sized,
@@ -675,6 +860,173 @@ impl<A: HashValue> CollisionNode<A> {
     }
 }
 
+// Equality
+
+fn values_equal<A: HashValue + PartialEq>(left: &A, right: &A) -> bool {
+    left.ptr_eq(right) || left == right
+}
+
+impl<A: HashValue + PartialEq> Node<A> {
+    // Test whether two tries built with the same hasher hold the same
+    // values, skipping any subtrees they share.
+    //
+    // Where both tries have the same shape, we can compare them slot
+    // by slot. They can have different shapes and still be equal,
+    // because hash collisions aren't always stored the same way, so
+    // when the shapes differ we fall back to looking up every value
+    // from one trie in the other.
+    pub fn equal(&self, other: &Self, shift: usize) -> bool {
+        if self.datamap != other.datamap || self.nodemap != other.nodemap {
+            return self.equal_entries(other, shift);
+        }
+        for (left, right) in self.data.iter().zip(other.data.iter()) {
+            let equal = match (left, right) {
+                (&Entry::Value(ref left, _), &Entry::Value(ref right, _)) => {
+                    values_equal(left, right)
+                }
+                (&Entry::Collision(ref left), &Entry::Collision(ref right)) => {
+                    Arc::ptr_eq(left, right) || left.equal(right)
+                }
+                _ => return self.equal_entries(other, shift),
+            };
+            if !equal {
+                return false;
+            }
+        }
+        self.nodes
+            .iter()
+            .zip(other.nodes.iter())
+            .all(|(left, right)| Arc::ptr_eq(left, right) || left.equal(right, shift + HASH_BITS))
+    }
+
+    fn equal_entries(&self, other: &Self, shift: usize) -> bool {
+        let mut entries = Vec::new();
+        self.collect_entries(&mut entries);
+        let mut others = Vec::new();
+        other.collect_entries(&mut others);
+        entries.len() == others.len()
+            && entries.into_iter().all(|(value, hash)| {
+                match other.get(hash, shift, value.extract_key()) {
+                    Some(found) => values_equal(value, found),
+                    None => false,
+                }
+            })
+    }
+
+    fn collect_entries<'a>(&'a self, out: &mut Vec<(&'a A, Bitmap)>) {
+        for entry in &self.data {
+            match *entry {
+                Entry::Value(ref value, hash) => out.push((value, hash)),
+                Entry::Collision(ref coll) => {
+                    out.extend(coll.data.iter().map(|value| (value, coll.hash)))
+                }
+            }
+        }
+        for node in &self.nodes {
+            node.collect_entries(out);
+        }
+    }
+}
+
+impl<A: HashValue + PartialEq> CollisionNode<A> {
+    fn equal(&self, other: &Self) -> bool {
+        self.data.len() == other.data.len() && self.data.iter().all(|value| {
+            match other.get(value.extract_key()) {
+                Some(found) => values_equal(value, found),
+                None => false,
+            }
+        })
+    }
+}
+
+// Diffing
+
+// What a node holds at one of its slots.
+enum Slot<'a, A: 'a> {
+    Empty,
+    Entry(&'a Entry<A>),
+    Node(&'a Arc<Node<A>>),
+}
+
+impl<A: HashValue + PartialEq> Node<A> {
+    // Collect the changes which turn this trie into `other`, both
+    // built with the same hasher, skipping any subtrees they share.
+    //
+    // Slots holding a subnode on both sides are compared recursively.
+    // Anywhere else the shapes differ, such as where a value has been
+    // pushed down into a new subnode, the values under the slot on
+    // each side are matched up by key.
+    pub fn diff(&self, other: &Self, out: &mut Vec<DiffItem<A>>) {
+        let occupied = self.datamap | self.nodemap | other.datamap | other.nodemap;
+        for bitpos in bits(occupied) {
+            match (self.slot(bitpos), other.slot(bitpos)) {
+                (Slot::Node(old), Slot::Node(new)) => if !Arc::ptr_eq(old, new) {
+                    old.diff(new, out)
+                },
+                (Slot::Entry(old), Slot::Entry(new)) if old == new => (),
+                (old, new) => {
+                    let mut old_values = Vec::new();
+                    Node::slot_values(&old, &mut old_values);
+                    let mut new_values = Vec::new();
+                    Node::slot_values(&new, &mut new_values);
+                    diff_values(&old_values, &new_values, out);
+                }
+            }
+        }
+    }
+
+    fn slot(&self, bitpos: Bitmap) -> Slot<'_, A> {
+        if self.datamap & bitpos != 0 {
+            Slot::Entry(&self.data[self.data_index(bitpos)])
+        } else if self.nodemap & bitpos != 0 {
+            Slot::Node(&self.nodes[self.node_index(bitpos)])
+        } else {
+            Slot::Empty
+        }
+    }
+
+    fn slot_values<'a>(slot: &Slot<'a, A>, out: &mut Vec<&'a A>) {
+        match *slot {
+            Slot::Empty => (),
+            Slot::Entry(&Entry::Value(ref value, _)) => out.push(value),
+            Slot::Entry(&Entry::Collision(ref coll)) => out.extend(coll.data.iter()),
+            Slot::Node(node) => {
+                let mut entries = Vec::new();
+                node.collect_entries(&mut entries);
+                out.extend(entries.into_iter().map(|(value, _)| value));
+            }
+        }
+    }
+}
+
+// Match up two lists of values by key. One of them is usually a
+// single value or a collision node, so this is quadratic in theory
+// only.
+fn diff_values<A: HashValue + PartialEq>(old: &[&A], new: &[&A], out: &mut Vec<DiffItem<A>>) {
+    let mut matched = vec![false; new.len()];
+    for old_value in old {
+        let found = new.iter()
+            .position(|new_value| new_value.extract_key() == old_value.extract_key());
+        match found {
+            Some(index) => {
+                matched[index] = true;
+                if !values_equal(*old_value, new[index]) {
+                    out.push(DiffItem::Update {
+                        old: (*old_value).clone(),
+                        new: new[index].clone(),
+                    });
+                }
+            }
+            None => out.push(DiffItem::Remove((*old_value).clone())),
+        }
+    }
+    for (new_value, matched) in new.iter().zip(matched) {
+        if !matched {
+            out.push(DiffItem::Add((*new_value).clone()));
+        }
+    }
+}
+
 // Iterators
 
 pub struct Iter<A> {
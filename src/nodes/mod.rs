@@ -1,3 +1,6 @@
 pub mod btree;
+pub mod fingertree;
 pub mod hamt;
+pub mod rope;
+pub mod trie;
 pub mod vector;
@@ -0,0 +1,394 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::borrow::ToOwned;
+use std::cmp::{min, Ordering};
+use std::string::String;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::Footprint;
+
+pub const MIN_LEAF: usize = 512;
+pub const MAX_LEAF: usize = 1024; // Must be twice MIN_LEAF!
+const MIN_CHILDREN: usize = 4;
+const MAX_CHILDREN: usize = 8; // Must be twice MIN_CHILDREN!
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub bytes: usize,
+    pub chars: usize,
+    pub lines: usize,
+}
+
+impl Metrics {
+    pub fn of(s: &str) -> Self {
+        Metrics {
+            bytes: s.len(),
+            chars: count_chars(s.as_bytes()),
+            lines: count_lines(s.as_bytes()),
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Metrics {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            lines: self.lines + other.lines,
+        }
+    }
+}
+
+// Count the chars starting inside a byte slice, which needn't end on
+// a char boundary.
+pub fn count_chars(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| (*b & 0xc0) != 0x80).count()
+}
+
+pub fn count_lines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| **b == b'\n').count()
+}
+
+pub struct Node(Arc<NodeData>);
+
+struct NodeData {
+    height: usize,
+    metrics: Metrics,
+    value: NodeValue,
+}
+
+enum NodeValue {
+    Leaf(String),
+    Branch(Vec<Node>),
+}
+
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        Node(self.0.clone())
+    }
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::new()
+    }
+}
+
+impl Node {
+    pub fn new() -> Self {
+        Node::from_leaf(String::new())
+    }
+
+    fn from_leaf(s: String) -> Self {
+        Node(Arc::new(NodeData {
+            height: 0,
+            metrics: Metrics::of(&s),
+            value: NodeValue::Leaf(s),
+        }))
+    }
+
+    fn from_nodes(children: Vec<Node>) -> Self {
+        let metrics = children
+            .iter()
+            .fold(Metrics::default(), |acc, child| acc.add(child.0.metrics));
+        Node(Arc::new(NodeData {
+            height: children[0].height() + 1,
+            metrics,
+            value: NodeValue::Branch(children),
+        }))
+    }
+
+    pub fn from_str(s: &str) -> Self {
+        if s.len() <= MAX_LEAF {
+            return Node::from_leaf(s.to_owned());
+        }
+        // Cut the string into evenly sized leaves, each of which ends
+        // up between MIN_LEAF and MAX_LEAF bytes.
+        let count = (s.len() - 1) / MAX_LEAF + 1;
+        let target = s.len() / count;
+        let mut nodes = Vec::with_capacity(count);
+        let mut rest = s;
+        while rest.len() > MAX_LEAF {
+            let mut split = target;
+            while !rest.is_char_boundary(split) {
+                split += 1;
+            }
+            nodes.push(Node::from_leaf(rest[..split].to_owned()));
+            rest = &rest[split..];
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::from_leaf(rest.to_owned()));
+        }
+        Node::from_level(nodes)
+    }
+
+    // Build a tree bottom up out of a list of nodes of equal height,
+    // spreading them evenly so every branch is at least half full.
+    fn from_level(mut nodes: Vec<Node>) -> Self {
+        while nodes.len() > 1 {
+            let groups = (nodes.len() - 1) / MAX_CHILDREN + 1;
+            let mut level = Vec::with_capacity(groups);
+            let mut remaining = nodes.len();
+            let mut it = nodes.into_iter();
+            for group in 0..groups {
+                let size = remaining / (groups - group);
+                level.push(Node::from_nodes(it.by_ref().take(size).collect()));
+                remaining -= size;
+            }
+            nodes = level;
+        }
+        nodes.pop().unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.0.height
+    }
+
+    #[inline]
+    pub fn metrics(&self) -> Metrics {
+        self.0.metrics
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.metrics.bytes
+    }
+
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    fn children(&self) -> &[Node] {
+        match self.0.value {
+            NodeValue::Branch(ref children) => children,
+            NodeValue::Leaf(_) => panic!("rope::Node::children: called on a leaf"),
+        }
+    }
+
+    fn is_ok_child(&self) -> bool {
+        match self.0.value {
+            NodeValue::Leaf(ref s) => s.len() >= MIN_LEAF,
+            NodeValue::Branch(ref children) => children.len() >= MIN_CHILDREN,
+        }
+    }
+
+    /// Concatenate two trees.
+    ///
+    /// Time: O(log n)
+    pub fn concat(left: Node, right: Node) -> Node {
+        if left.len() == 0 {
+            return right;
+        }
+        if right.len() == 0 {
+            return left;
+        }
+        let (h1, h2) = (left.height(), right.height());
+        match h1.cmp(&h2) {
+            Ordering::Less => {
+                let children = right.children();
+                if h1 == h2 - 1 && left.is_ok_child() {
+                    return merge_nodes(&[left], children);
+                }
+                let joined = Node::concat(left, children[0].clone());
+                if joined.height() == h2 - 1 {
+                    merge_nodes(&[joined], &children[1..])
+                } else {
+                    merge_nodes(joined.children(), &children[1..])
+                }
+            }
+            Ordering::Equal => {
+                if left.is_ok_child() && right.is_ok_child() {
+                    Node::from_nodes(vec![left, right])
+                } else if h1 == 0 {
+                    merge_leaves(&left, &right)
+                } else {
+                    merge_nodes(left.children(), right.children())
+                }
+            }
+            Ordering::Greater => {
+                let children = left.children();
+                let last = children.len() - 1;
+                if h2 == h1 - 1 && right.is_ok_child() {
+                    return merge_nodes(children, &[right]);
+                }
+                let joined = Node::concat(children[last].clone(), right);
+                if joined.height() == h1 - 1 {
+                    merge_nodes(&children[..last], &[joined])
+                } else {
+                    merge_nodes(&children[..last], joined.children())
+                }
+            }
+        }
+    }
+
+    /// Extract the bytes `start..end` as a new tree, sharing every
+    /// subtree which lies entirely inside the range.
+    ///
+    /// Time: O(log n)
+    pub fn slice(&self, start: usize, end: usize) -> Node {
+        if start == 0 && end == self.len() {
+            return self.clone();
+        }
+        if start >= end {
+            return Node::new();
+        }
+        match self.0.value {
+            NodeValue::Leaf(ref s) => Node::from_leaf(s[start..end].to_owned()),
+            NodeValue::Branch(ref children) => {
+                let mut out = Node::new();
+                let mut offset = 0;
+                for child in children {
+                    if offset >= end {
+                        break;
+                    }
+                    let len = child.len();
+                    if offset + len > start {
+                        let piece =
+                            child.slice(start.saturating_sub(offset), min(len, end - offset));
+                        out = Node::concat(out, piece);
+                    }
+                    offset += len;
+                }
+                out
+            }
+        }
+    }
+
+    /// Find the byte offset at which the given metric reaches
+    /// `target`, finishing the search inside a leaf with `scan`.
+    ///
+    /// If `inclusive` is set, the search descends into the first
+    /// child whose metric is at least `target`, otherwise into the
+    /// first child whose metric is greater than `target`.
+    pub fn find<M, S>(&self, mut target: usize, metric: M, inclusive: bool, scan: S) -> usize
+    where
+        M: Fn(&Metrics) -> usize,
+        S: Fn(&str, usize) -> usize,
+    {
+        let mut node = self;
+        let mut bytes = 0;
+        loop {
+            match node.0.value {
+                NodeValue::Leaf(ref s) => return bytes + scan(s, target),
+                NodeValue::Branch(ref children) => {
+                    let last = children.len() - 1;
+                    for (index, child) in children.iter().enumerate() {
+                        let size = metric(&child.0.metrics);
+                        if target < size || (inclusive && target == size) || index == last {
+                            node = child;
+                            break;
+                        }
+                        target -= size;
+                        bytes += child.len();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Measure the bytes before the byte offset `target` using the
+    /// given metric, finishing inside a leaf with `scan`.
+    pub fn measure<M, S>(&self, mut target: usize, metric: M, scan: S) -> usize
+    where
+        M: Fn(&Metrics) -> usize,
+        S: Fn(&[u8]) -> usize,
+    {
+        let mut node = self;
+        let mut measured = 0;
+        loop {
+            match node.0.value {
+                NodeValue::Leaf(ref s) => return measured + scan(&s.as_bytes()[..target]),
+                NodeValue::Branch(ref children) => {
+                    let last = children.len() - 1;
+                    for (index, child) in children.iter().enumerate() {
+                        if target < child.len() || index == last {
+                            node = child;
+                            break;
+                        }
+                        target -= child.len();
+                        measured += metric(&child.0.metrics);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn chunks<'a>(&'a self) -> Chunks<'a> {
+        Chunks { stack: vec![self] }
+    }
+
+    /// Count the memory used by this subtree and return its height,
+    /// with leaves holding their text's bytes.
+    pub fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        footprint.node(&self.0, |footprint| {
+            match self.0.value {
+                NodeValue::Leaf(ref s) => {
+                    footprint.buffer::<u8>(s.capacity());
+                    footprint.leaf(s.len(), MAX_LEAF);
+                }
+                NodeValue::Branch(ref children) => {
+                    footprint.buffer::<Node>(children.capacity());
+                    for child in children {
+                        child.measure_heap(footprint);
+                    }
+                }
+            }
+            self.0.height + 1
+        })
+    }
+}
+
+fn merge_leaves(left: &Node, right: &Node) -> Node {
+    let mut s = String::with_capacity(left.len() + right.len());
+    for node in &[left, right] {
+        if let NodeValue::Leaf(ref chunk) = node.0.value {
+            s.push_str(chunk);
+        }
+    }
+    if s.len() <= MAX_LEAF {
+        return Node::from_leaf(s);
+    }
+    let mut split = s.len() / 2;
+    while !s.is_char_boundary(split) {
+        split += 1;
+    }
+    let tail = s[split..].to_owned();
+    s.truncate(split);
+    Node::from_nodes(vec![Node::from_leaf(s), Node::from_leaf(tail)])
+}
+
+fn merge_nodes(left: &[Node], right: &[Node]) -> Node {
+    let mut children: Vec<Node> = left.iter().chain(right).cloned().collect();
+    if children.len() <= MAX_CHILDREN {
+        return Node::from_nodes(children);
+    }
+    let tail = children.split_off(children.len() / 2);
+    Node::from_nodes(vec![Node::from_nodes(children), Node::from_nodes(tail)])
+}
+
+// Iterators
+
+pub struct Chunks<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            match node.0.value {
+                NodeValue::Leaf(ref s) => {
+                    if !s.is_empty() {
+                        return Some(s);
+                    }
+                }
+                NodeValue::Branch(ref children) => self.stack.extend(children.iter().rev()),
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,317 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp::max;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::Footprint;
+
+// A node in a radix trie. Every node consumes the bytes in its
+// `prefix` on the way down from its parent, so the path from the root
+// to a node spells out the key of its entry. Children are kept sorted
+// by the first byte of their prefix, which is unique among siblings.
+//
+// Apart from the root, a node always has either an entry or at least
+// two children; a node with neither is removed, and a node with one
+// child and no entry is merged into its child.
+pub struct Node<K, V> {
+    prefix: Vec<u8>,
+    entry: Option<(Arc<K>, Arc<V>)>,
+    children: Vec<Arc<Node<K, V>>>,
+}
+
+impl<K, V> Clone for Node<K, V> {
+    fn clone(&self) -> Self {
+        Node {
+            prefix: self.prefix.clone(),
+            entry: self.entry.clone(),
+            children: self.children.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for Node<K, V> {
+    fn default() -> Self {
+        Node::new()
+    }
+}
+
+fn common_prefix(left: &[u8], right: &[u8]) -> usize {
+    left.iter().zip(right).take_while(|&(l, r)| l == r).count()
+}
+
+impl<K, V> Node<K, V> {
+    pub fn new() -> Self {
+        Node {
+            prefix: Vec::new(),
+            entry: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn leaf(prefix: &[u8], entry: (Arc<K>, Arc<V>)) -> Self {
+        Node {
+            prefix: prefix.to_vec(),
+            entry: Some(entry),
+            children: Vec::new(),
+        }
+    }
+
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by(|child| child.prefix[0].cmp(&byte))
+    }
+
+    // Find the child whose prefix starts `key`, and return it along
+    // with what's left of `key` after its prefix.
+    fn descend<'a, 'b>(&'a self, key: &'b [u8]) -> Option<(&'a Self, &'b [u8])> {
+        let index = self.child_index(*key.first()?).ok()?;
+        let child = &self.children[index];
+        if key.starts_with(&child.prefix) {
+            Some((child, &key[child.prefix.len()..]))
+        } else {
+            None
+        }
+    }
+
+    pub fn lookup(&self, mut key: &[u8]) -> Option<&(Arc<K>, Arc<V>)> {
+        let mut node = self;
+        while !key.is_empty() {
+            let (child, rest) = node.descend(key)?;
+            node = child;
+            key = rest;
+        }
+        node.entry.as_ref()
+    }
+
+    pub fn lookup_mut(&mut self, key: &[u8]) -> Option<&mut (Arc<K>, Arc<V>)> {
+        if key.is_empty() {
+            return self.entry.as_mut();
+        }
+        let index = self.child_index(key[0]).ok()?;
+        let len = self.children[index].prefix.len();
+        if !key.starts_with(&self.children[index].prefix) {
+            return None;
+        }
+        Arc::make_mut(&mut self.children[index]).lookup_mut(&key[len..])
+    }
+
+    /// Find the entry with the longest key which is a prefix of `key`.
+    pub fn longest_prefix(&self, mut key: &[u8]) -> Option<&(Arc<K>, Arc<V>)> {
+        let mut node = self;
+        let mut found = node.entry.as_ref();
+        while let Some((child, rest)) = node.descend(key) {
+            node = child;
+            key = rest;
+            found = node.entry.as_ref().or(found);
+        }
+        found
+    }
+
+    /// Find the subtree containing every key starting with `prefix`.
+    pub fn subtree(this: &Arc<Self>, mut prefix: &[u8]) -> Option<Arc<Self>> {
+        let mut node = this;
+        loop {
+            if prefix.is_empty() {
+                return Some(node.clone());
+            }
+            let index = node.child_index(prefix[0]).ok()?;
+            let child = &node.children[index];
+            if prefix.len() <= child.prefix.len() {
+                return if child.prefix.starts_with(prefix) {
+                    Some(child.clone())
+                } else {
+                    None
+                };
+            }
+            if !prefix.starts_with(&child.prefix) {
+                return None;
+            }
+            prefix = &prefix[child.prefix.len()..];
+            node = child;
+        }
+    }
+
+    /// Construct a root holding only the keys starting with `prefix`,
+    /// sharing the subtree they live in with this trie.
+    pub fn restrict(this: &Arc<Self>, mut prefix: &[u8]) -> Option<Arc<Self>> {
+        if prefix.is_empty() {
+            return Some(this.clone());
+        }
+        // The subtree's top node becomes the new root's only child, so
+        // its prefix has to spell out the whole path it was found at.
+        let mut path = Vec::new();
+        let mut node = this;
+        loop {
+            let index = node.child_index(prefix[0]).ok()?;
+            let child = &node.children[index];
+            if prefix.len() <= child.prefix.len() {
+                if !child.prefix.starts_with(prefix) {
+                    return None;
+                }
+                path.extend_from_slice(&child.prefix);
+                let mut top = Node::clone(child);
+                top.prefix = path;
+                let mut root = Node::new();
+                root.children.push(Arc::new(top));
+                return Some(Arc::new(root));
+            }
+            if !prefix.starts_with(&child.prefix) {
+                return None;
+            }
+            path.extend_from_slice(&child.prefix);
+            prefix = &prefix[child.prefix.len()..];
+            node = child;
+        }
+    }
+
+    /// Count the entries in this subtree.
+    pub fn count(&self) -> usize {
+        let below: usize = self.children.iter().map(|child| child.count()).sum();
+        below + if self.entry.is_some() { 1 } else { 0 }
+    }
+
+    /// Insert an entry under `key`, returning the entry it replaced.
+    pub fn insert(&mut self, key: &[u8], entry: (Arc<K>, Arc<V>)) -> Option<(Arc<K>, Arc<V>)> {
+        if key.is_empty() {
+            return self.entry.replace(entry);
+        }
+        let index = match self.child_index(key[0]) {
+            Ok(index) => index,
+            Err(index) => {
+                self.children
+                    .insert(index, Arc::new(Node::leaf(key, entry)));
+                return None;
+            }
+        };
+        let common = common_prefix(&self.children[index].prefix, key);
+        if common == self.children[index].prefix.len() {
+            return Arc::make_mut(&mut self.children[index]).insert(&key[common..], entry);
+        }
+        // The key diverges inside the child's prefix, so the child has
+        // to be split at the point where they diverge.
+        let child = &self.children[index];
+        let lower = Arc::new(Node {
+            prefix: child.prefix[common..].to_vec(),
+            entry: child.entry.clone(),
+            children: child.children.clone(),
+        });
+        let mut split = Node {
+            prefix: key[..common].to_vec(),
+            entry: None,
+            children: vec![lower],
+        };
+        if common == key.len() {
+            split.entry = Some(entry);
+        } else {
+            let leaf = Arc::new(Node::leaf(&key[common..], entry));
+            if leaf.prefix[0] < split.children[0].prefix[0] {
+                split.children.insert(0, leaf);
+            } else {
+                split.children.push(leaf);
+            }
+        }
+        self.children[index] = Arc::new(split);
+        None
+    }
+
+    /// Remove the entry under `key`, if there is one.
+    ///
+    /// Nodes are copied on the way down, so only call this if you
+    /// know the key is present.
+    pub fn remove(&mut self, key: &[u8]) -> Option<(Arc<K>, Arc<V>)> {
+        if key.is_empty() {
+            return self.entry.take();
+        }
+        let index = self.child_index(key[0]).ok()?;
+        let len = self.children[index].prefix.len();
+        if !key.starts_with(&self.children[index].prefix) {
+            return None;
+        }
+        let removed = Arc::make_mut(&mut self.children[index]).remove(&key[len..]);
+        if removed.is_some() {
+            self.compress_child(index);
+        }
+        removed
+    }
+
+    // Restore the invariants for a child after something was removed
+    // from under it.
+    fn compress_child(&mut self, index: usize) {
+        let child = &self.children[index];
+        if child.entry.is_some() || child.children.len() > 1 {
+            return;
+        }
+        match child.children.first().cloned() {
+            None => {
+                self.children.remove(index);
+            }
+            Some(grandchild) => {
+                let mut prefix = child.prefix.clone();
+                prefix.extend_from_slice(&grandchild.prefix);
+                self.children[index] = Arc::new(Node {
+                    prefix,
+                    entry: grandchild.entry.clone(),
+                    children: grandchild.children.clone(),
+                });
+            }
+        }
+    }
+
+    pub fn iter(this: &Arc<Self>) -> Iter<K, V> {
+        Iter {
+            stack: vec![this.clone()],
+        }
+    }
+
+    /// Count the memory used by this subtree and return its height.
+    pub fn measure_heap(this: &Arc<Self>, footprint: &mut Footprint) -> usize {
+        footprint.node(this, |footprint| {
+            footprint.buffer::<u8>(this.prefix.capacity());
+            footprint.buffer::<Arc<Node<K, V>>>(this.children.capacity());
+            if let Some((ref key, ref value)) = this.entry {
+                footprint.value(key);
+                footprint.value(value);
+            }
+            let mut height = 0;
+            for child in &this.children {
+                height = max(height, Node::measure_heap(child, footprint));
+            }
+            if height == 0 {
+                footprint.leaf(if this.entry.is_some() { 1 } else { 0 }, 1);
+            }
+            height + 1
+        })
+    }
+}
+
+// Iterators
+
+pub struct Iter<K, V> {
+    stack: Vec<Arc<Node<K, V>>>,
+}
+
+impl<K, V> Iter<K, V> {
+    pub fn empty() -> Self {
+        Iter { stack: Vec::new() }
+    }
+}
+
+impl<K, V> Iterator for Iter<K, V> {
+    type Item = (Arc<K>, Arc<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A node's own key is a prefix of every key beneath it, so
+        // visiting nodes before their children yields keys in
+        // lexicographic order.
+        while let Some(node) = self.stack.pop() {
+            self.stack.extend(node.children.iter().rev().cloned());
+            if let Some(ref entry) = node.entry {
+                return Some(entry.clone());
+            }
+        }
+        None
+    }
+}
@@ -2,10 +2,16 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::cmp::{max, min};
 use std::fmt::{Debug, Error, Formatter};
+use std::string::String;
 use std::sync::Arc;
+use std::vec::Vec;
 
 use bits::{HASH_BITS, HASH_MASK, HASH_SIZE};
+use footprint::Footprint;
+#[cfg(any(test, feature = "dot"))]
+use dot::Dot;
 
 pub enum Entry<A> {
     Node(Arc<Node<A>>),
@@ -252,6 +258,154 @@ impl<A> Node<A> {
             }
         }
     }
+
+    // Move the values out of every node which is only referenced from
+    // `node`, and out of which the values are only referenced from
+    // those nodes. Anything shared with another tree is just dropped,
+    // leaving it to its other owners.
+    pub fn take_unique(node: Arc<Node<A>>, out: &mut Vec<A>) {
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if let Ok(mut node) = Arc::try_unwrap(node) {
+                for entry in node.children.drain(..) {
+                    match entry {
+                        Entry::Node(child) => stack.push(child),
+                        Entry::Value(value) => {
+                            if let Ok(value) = Arc::try_unwrap(value) {
+                                out.push(value);
+                            }
+                        }
+                        Entry::Empty => (),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check that every index from `start` up to `end` leads to a
+    /// value, where this node sits at `level` and its first index is
+    /// `base`, describing the first index which doesn't.
+    pub fn check(&self, level: usize, base: usize, start: usize, end: usize) -> Result<(), String> {
+        if start >= end {
+            return Ok(());
+        }
+        let first = (start - base) >> level;
+        let last = (end - 1 - base) >> level;
+        if last >= HASH_SIZE {
+            return Err(format!("index {} is outside its node", end - 1));
+        }
+        for slot in first..(last + 1) {
+            let child_base = base + (slot << level);
+            match (level, self.children.get(slot)) {
+                (0, Some(&Entry::Value(_))) => (),
+                (_, Some(&Entry::Node(ref node))) if level > 0 => node.check(
+                    level - HASH_BITS,
+                    child_base,
+                    max(start, child_base),
+                    min(end, child_base + (1 << level)),
+                )?,
+                _ => return Err(format!("index {} has no value", max(start, child_base))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the memory used by this subtree, calling `value` for
+    /// each value in it, and return its height.
+    pub fn measure_heap<F>(this: &Arc<Self>, footprint: &mut Footprint, value: &mut F) -> usize
+    where
+        F: FnMut(&mut Footprint, &Arc<A>),
+    {
+        footprint.node(this, |footprint| {
+            footprint.buffer::<Entry<A>>(this.children.capacity());
+            let mut height = 0;
+            let mut values = 0;
+            for entry in &this.children {
+                match *entry {
+                    Entry::Node(ref node) => {
+                        height = max(height, Node::measure_heap(node, footprint, value))
+                    }
+                    Entry::Value(ref entry_value) => {
+                        values += 1;
+                        value(footprint, entry_value);
+                    }
+                    Entry::Empty => (),
+                }
+            }
+            if height == 0 {
+                footprint.leaf(values, HASH_SIZE);
+            }
+            height + 1
+        })
+    }
+
+    /// Draw this subtree into `dot`, with an edge from `from`
+    /// labelled `edge`.
+    #[cfg(any(test, feature = "dot"))]
+    pub fn write_dot(this: &Arc<Self>, dot: &mut Dot, from: &str, edge: &str) {
+        if let Some(id) = dot.edge(from, edge, this) {
+            let mut values = 0;
+            let mut nodes = Vec::new();
+            for entry in &this.children {
+                match *entry {
+                    Entry::Node(ref node) => nodes.push(node),
+                    Entry::Value(_) => values += 1,
+                    Entry::Empty => (),
+                }
+            }
+            let text = if nodes.is_empty() { "leaf" } else { "branch" };
+            dot.label(&id, text, values + nodes.len(), HASH_SIZE);
+            for node in nodes {
+                Node::write_dot(node, dot, &id, "");
+            }
+        }
+    }
+}
+
+impl<A: PartialEq> Node<A> {
+    // Test whether two tries at the same `level`, both starting at
+    // index `offset`, hold equal values at every index from `from` up
+    // to `to`, skipping any subtrees they share. Gives up with `None`
+    // if the tries turn out not to be laid out the same way inside the
+    // range, leaving the caller to compare them some other way.
+    pub fn equal_range(
+        left: &Arc<Self>,
+        right: &Arc<Self>,
+        level: usize,
+        offset: usize,
+        from: usize,
+        to: usize,
+    ) -> Option<bool> {
+        if from >= to || Arc::ptr_eq(left, right) {
+            return Some(true);
+        }
+        let first = (from - offset) >> level;
+        let last = (to - 1 - offset) >> level;
+        for index in first..last + 1 {
+            let equal = match (left.children.get(index), right.children.get(index)) {
+                (Some(&Entry::Value(ref l)), Some(&Entry::Value(ref r))) => {
+                    Arc::ptr_eq(l, r) || l == r
+                }
+                (Some(&Entry::Node(ref l)), Some(&Entry::Node(ref r))) if level > 0 => {
+                    let start = offset + (index << level);
+                    let end = start + (1 << level);
+                    Node::equal_range(
+                        l,
+                        r,
+                        level - HASH_BITS,
+                        start,
+                        from.max(start),
+                        to.min(end),
+                    )?
+                }
+                _ => return None,
+            };
+            if !equal {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
 }
 
 impl<A> Clone for Node<A> {
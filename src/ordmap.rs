@@ -16,7 +16,7 @@
 //! [hashmap::HashMap]: ../hashmap/struct.HashMap.html
 //! [std::cmp::Ord]: https://doc.rust-lang.org/std/cmp/trait.Ord.html
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, ToOwned};
 use std::cmp::Ordering;
 use std::collections;
 use std::fmt::{Debug, Error, Formatter};
@@ -24,11 +24,17 @@ use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::{FromIterator, Iterator, Sum};
 use std::ops::{Add, Index, IndexMut};
 use std::sync::Arc;
+use std::vec::Vec;
 
+use footprint::{Footprint, HeapSize};
+#[cfg(any(test, feature = "dot"))]
+use dot::{Dot, ToDot};
 use hashmap::HashMap;
+use invariant::InvariantError;
+use patch::Patch;
 use shared::Shared;
 
-use nodes::btree::{BTreeValue, DiffIter, Insert, Iter, Node, Remove};
+use nodes::btree::{BTreeValue, DiffItem, DiffIter, Insert, Iter, Node, Remove};
 
 /// Construct a map from a sequence of key/value pairs.
 ///
@@ -157,6 +163,28 @@ impl<K, V> OrdMap<K, V> {
         self.len() == 0
     }
 
+    /// Test whether two maps refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a map to
+    /// itself, or if you're comparing a map to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.root.ptr_eq(&other.root)
+    }
+
+    #[cfg(any(test, feature = "serde"))]
+    pub(crate) fn root(&self) -> &Node<(Arc<K>, Arc<V>)> {
+        &self.root
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn from_root(root: Node<(Arc<K>, Arc<V>)>) -> Self {
+        OrdMap { root }
+    }
+
     /// Get the size of a map.
     ///
     /// Time: O(1)
@@ -247,6 +275,52 @@ impl<K: Ord, V> OrdMap<K, V> {
         DiffIter::new(&self.root, &other.borrow().root)
     }
 
+    /// Construct a new map with the changes in a
+    /// [`Patch`][Patch] applied to it.
+    ///
+    /// Keys the patch adds or updates are set to their new values,
+    /// and keys it removes are removed. The old values recorded in
+    /// the patch aren't checked against the map.
+    ///
+    /// Time: O(n log m) (where n is the size of the patch)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::patch::Patch;
+    /// # fn main() {
+    /// let server = ordmap!{"apples" => 3, "pears" => 2};
+    /// let client = server.clone();
+    /// let updated = server.insert("apples", 4).remove(&"pears");
+    /// let patch = Patch::between(&server, &updated);
+    /// assert_eq!(updated, client.apply(&patch));
+    /// # }
+    /// ```
+    ///
+    /// [Patch]: ../patch/struct.Patch.html
+    pub fn apply(&self, patch: &Patch<K, V>) -> Self {
+        let mut out = self.clone();
+        out.apply_mut(patch);
+        out
+    }
+
+    /// Apply the changes in a [`Patch`][Patch] to a map, in place.
+    ///
+    /// Time: O(n log m) (where n is the size of the patch)
+    ///
+    /// [Patch]: ../patch/struct.Patch.html
+    pub fn apply_mut(&mut self, patch: &Patch<K, V>) {
+        for (key, change) in patch.iter() {
+            match *change {
+                DiffItem::Add(ref value) | DiffItem::Update { new: ref value, .. } => {
+                    self.insert_mut(key, value)
+                }
+                DiffItem::Remove(_) => self.remove_mut(&*key),
+            }
+        }
+    }
+
     /// Get an iterator over a map's keys.
     pub fn keys(&self) -> Keys<K, V> {
         Keys { it: self.iter() }
@@ -407,7 +481,7 @@ impl<K: Ord, V> OrdMap<K, V> {
         match self.root.insert((key, value)) {
             Insert::NoChange => self.clone(),
             Insert::JustInc => unreachable!(),
-            Insert::Update(root) => OrdMap { root },
+            Insert::Update(root) | Insert::Replaced(root) => OrdMap { root },
             Insert::Split(left, median, right) => OrdMap {
                 root: Node::from_split(left, median, right),
             },
@@ -455,7 +529,7 @@ impl<K: Ord, V> OrdMap<K, V> {
     fn insert_mut_ref(&mut self, key: Arc<K>, value: Arc<V>) {
         match self.root.insert_mut((key, value)) {
             Insert::NoChange | Insert::JustInc => {}
-            Insert::Update(root) => self.root = root,
+            Insert::Update(root) | Insert::Replaced(root) => self.root = root,
             Insert::Split(left, median, right) => self.root = Node::from_split(left, median, right),
         }
     }
@@ -800,6 +874,81 @@ impl<K: Ord, V> OrdMap<K, V> {
             .fold(ordmap![], |a, b| a.union_with_key(&b, &f))
     }
 
+    /// Merge two maps which were both changed from a common `base`.
+    ///
+    /// A key changed on only one side, or changed the same way on
+    /// both, ends up with that change applied. When both sides
+    /// changed a key differently, `resolver` is called with the key
+    /// and its values in `base`, `ours` and `theirs`, where a missing
+    /// value means the key isn't in that map, and the key gets the
+    /// value it returns, or is removed if it returns `None`.
+    ///
+    /// Only the keys which differ from `base` are visited, found with
+    /// [`diff`][diff], which skips the subtrees a map shares with
+    /// `base`, and the changes from the side with fewer of them are
+    /// applied to the other, so that the result shares every node it
+    /// can with the maps it was merged from.
+    ///
+    /// Time: O(n log m) (where n is the number of keys changed on
+    /// either side)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// # fn main() {
+    /// let base = ordmap!{"apples" => 3, "pears" => 2};
+    /// let ours = base.insert("apples", 4).insert("plums", 1);
+    /// let theirs = base.insert("apples", 5).remove(&"pears");
+    /// let merged = OrdMap::merge3(&base, &ours, &theirs, |_, _, ours, _| ours);
+    /// assert_eq!(ordmap!{"apples" => 4, "plums" => 1}, merged);
+    /// # }
+    /// ```
+    ///
+    /// [diff]: #method.diff
+    pub fn merge3<F>(base: &Self, ours: &Self, theirs: &Self, mut resolver: F) -> Self
+    where
+        V: PartialEq,
+        F: FnMut(Arc<K>, Option<Arc<V>>, Option<Arc<V>>, Option<Arc<V>>) -> Option<Arc<V>>,
+    {
+        let our_changes: Vec<_> = base.diff(ours).collect();
+        let their_changes: Vec<_> = base.diff(theirs).collect();
+        let ours_smaller = our_changes.len() <= their_changes.len();
+        let (mut merged, changes) = if ours_smaller {
+            (theirs.clone(), our_changes)
+        } else {
+            (ours.clone(), their_changes)
+        };
+        for change in changes {
+            let (key, old, new) = match change {
+                DiffItem::Add((key, value)) => (key, None, Some(value)),
+                DiffItem::Remove((key, value)) => (key, Some(value), None),
+                DiffItem::Update {
+                    old: (key, old),
+                    new: (_, new),
+                } => (key, Some(old), Some(new)),
+            };
+            let other = merged.get(&*key);
+            let value = if other == old {
+                new
+            } else if other == new {
+                continue;
+            } else if ours_smaller {
+                resolver(key.clone(), old, new, other)
+            } else {
+                resolver(key.clone(), old, other, new)
+            };
+            match value {
+                Some(value) => merged.insert_mut(key, value),
+                None => {
+                    merged.remove_mut(&*key);
+                }
+            }
+        }
+        merged
+    }
+
     /// Construct the difference between two maps by discarding keys
     /// which occur in both maps.
     pub fn difference<B, RM>(&self, other: RM) -> Self
@@ -1057,6 +1206,35 @@ impl<K: Ord, V> OrdMap<K, V> {
     {
         self.is_proper_submap_by(other.borrow(), |a, b| a.as_ref().eq(b.as_ref()))
     }
+
+    /// Check the internal structure of a map, returning a description
+    /// of the first broken invariant found, if any.
+    ///
+    /// This checks that the keys in the map's B-tree are in order,
+    /// that its nodes are all full enough and its leaves all at the
+    /// same depth, and that the cached sizes of its nodes are
+    /// correct. See the [`invariant`][invariant] module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.root
+            .check()
+            .map_err(|message| InvariantError::new("OrdMap", message))
+    }
+
+    /// Panic if the internal structure of a map is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
 }
 
 // Core traits
@@ -1069,31 +1247,9 @@ impl<K, V> Clone for OrdMap<K, V> {
     }
 }
 
-#[cfg(not(has_specialisation))]
 impl<K: Ord + PartialEq, V: PartialEq> PartialEq for OrdMap<K, V> {
     fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() && self.diff(other).next().is_none()
-    }
-}
-
-#[cfg(has_specialisation)]
-impl<K: PartialEq, V: PartialEq> PartialEq for OrdMap<K, V>
-where
-    K: Ord,
-{
-    default fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() && self.diff(other).next().is_none()
-    }
-}
-
-#[cfg(has_specialisation)]
-impl<K: Ord + Eq, V: Eq> PartialEq for OrdMap<K, V>
-where
-    K: Ord,
-{
-    fn eq(&self, other: &Self) -> bool {
-        self.root.ptr_eq(&other.root)
-            || (self.len() == other.len() && self.diff(other).next().is_none())
+        self.ptr_eq(other) || (self.len() == other.len() && self.diff(other).next().is_none())
     }
 }
 
@@ -1131,6 +1287,37 @@ impl<K, V> Default for OrdMap<K, V> {
     }
 }
 
+impl<K, V> OrdMap<K, V> {
+    // Count the memory used by this map, calling `entry` for each
+    // key/value pair in it, and return its depth.
+    pub(crate) fn measure_heap_with<F>(&self, footprint: &mut Footprint, entry: &mut F) -> usize
+    where
+        F: FnMut(&mut Footprint, &(Arc<K>, Arc<V>)),
+    {
+        self.root.measure_heap(footprint, entry)
+    }
+}
+
+impl<K, V> HeapSize for OrdMap<K, V> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.measure_heap_with(footprint, &mut |footprint, &(ref key, ref value)| {
+            footprint.value(key);
+            footprint.value(value);
+        })
+    }
+}
+
+#[cfg(any(test, feature = "dot"))]
+impl<K, V> ToDot for OrdMap<K, V> {
+    fn dot_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write_dot(&self, dot: &mut Dot, from: &str) {
+        self.root.write_dot(dot, from)
+    }
+}
+
 impl<'a, K: Ord, V> Add for &'a OrdMap<K, V> {
     type Output = OrdMap<K, V>;
 
@@ -1386,6 +1573,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<K: Ord, V, RK: Eq + Hash, RV> From<collections::HashMap<RK, RV>> for OrdMap<K, V>
 where
     RK: Shared<K>,
@@ -1398,6 +1586,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a, K: Ord, V, RK: Eq + Hash, RV> From<&'a collections::HashMap<RK, RV>> for OrdMap<K, V>
 where
     &'a RK: Shared<K>,
@@ -1465,6 +1654,9 @@ pub mod proptest {
     use super::*;
     use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
     use std::ops::Range;
+    use model::{map_op, MapOp};
+    use proptest::collection::vec;
+    use proptest::num::{u8, usize};
 
     /// A strategy for a map of a given size.
     ///
@@ -1494,6 +1686,28 @@ pub mod proptest {
             })
             .boxed()
     }
+
+    /// A strategy for a sequence of operations on a map, of a length
+    /// in the given range, for use with
+    /// [`check_against_model`][check_against_model]. The same
+    /// operations apply to a [`HashMap`][HashMap].
+    ///
+    /// [check_against_model]: ../../model/fn.check_against_model.html
+    /// [HashMap]: ../../hashmap/struct.HashMap.html
+    pub fn map_ops<K: Strategy + 'static, V: Strategy + 'static>(
+        key: K,
+        value: V,
+        size: Range<usize>,
+    ) -> BoxedStrategy<Vec<MapOp<<K::Value as ValueTree>::Value, <V::Value as ValueTree>::Value>>>
+    {
+        vec((u8::ANY, key, value, usize::ANY), size)
+            .prop_map(|ops| {
+                ops.into_iter()
+                    .map(|(choice, key, value, index)| map_op(choice, key, value, index))
+                    .collect()
+            })
+            .boxed()
+    }
 }
 
 // Tests
@@ -1506,7 +1720,7 @@ mod test {
     use nodes::btree::DiffItem;
     use proptest::collection;
     use proptest::num::{i16, usize};
-    use test::is_sorted;
+    use test::{edit_model, is_sorted, merge3_model, Edit};
 
     #[test]
     fn iterates_in_order() {
@@ -1613,6 +1827,36 @@ mod test {
         assert_eq!(OrdMap::new(), m);
     }
 
+    #[test]
+    fn replace_mut_keeps_len() {
+        let mut m = OrdMap::new();
+        for i in 0..100 {
+            m.insert_mut(i, i);
+        }
+        for i in 0..100 {
+            m.insert_mut(i, -i);
+        }
+        assert_eq!(100, m.len());
+        assert_eq!(100, m.iter().count());
+    }
+
+    #[test]
+    fn remove_from_internal_nodes() {
+        let mut map: OrdMap<i32, i32> = (0..5000).map(|i| (i, i)).collect();
+        let mut model: ::std::collections::BTreeMap<i32, i32> = (0..5000).map(|i| (i, i)).collect();
+        for i in 0..2500 {
+            let key = (i * 7919) % 5000;
+            if i % 2 == 0 {
+                map.remove_mut(&key);
+            } else {
+                map = map.remove(&key);
+            }
+            model.remove(&key);
+            assert_eq!(model.len(), map.len());
+        }
+        assert!(model.into_iter().eq(map.iter().map(|(k, v)| (*k, *v))));
+    }
+
     #[test]
     fn double_ended_iterator_1() {
         let m = ordmap!{1 => 1, 2 => 2, 3 => 3, 4 => 4};
@@ -1776,7 +2020,33 @@ mod test {
         }
     }
 
+    #[test]
+    fn validate_after_updates() {
+        let mut map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        map.assert_invariants();
+        for i in (0..10_000).filter(|i| i % 3 != 0) {
+            map.remove_mut(&i);
+        }
+        map.assert_invariants();
+        let (left, right) = map.split(&5000);
+        left.assert_invariants();
+        right.assert_invariants();
+    }
+
     proptest! {
+        #[test]
+        fn operations_keep_invariants(ref ops in collection::vec((i16::ANY, i16::ANY), 0..500)) {
+            let mut map = OrdMap::new();
+            for &(k, v) in ops {
+                if v % 3 == 0 {
+                    map = map.remove(&(k & 0x3ff));
+                } else {
+                    map.insert_mut(k & 0x3ff, v);
+                }
+                map.assert_invariants();
+            }
+        }
+
         #[test]
         fn proptest_works(ref m in ord_map(0..9999, ".*", 10..100)) {
             assert!(m.len() < 100);
@@ -1891,4 +2161,50 @@ mod test {
             assert_eq!(0, it.len());
         }
     }
+
+    #[test]
+    fn merge3_shares_unchanged_subtrees() {
+        let base: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let ours = base.insert(10, 0);
+        let theirs = base.insert(9000, 0).remove(&5000);
+        let merged = OrdMap::merge3(&base, &ours, &theirs, |_, _, _, _| unreachable!());
+        assert_eq!(Some(Arc::new(0)), merged.get(&10));
+        assert_eq!(Some(Arc::new(0)), merged.get(&9000));
+        assert_eq!(9999, merged.len());
+        let base_nodes = base.heap_stats().nodes;
+        let both = HeapSize::shared_heap_stats(&[&base, &merged]).nodes;
+        assert!(both < base_nodes + 3 * merged.heap_stats().depth);
+    }
+
+    proptest! {
+        #[test]
+        fn merge3_matches_model(
+            ref base in collection::vec((0..64u8, i16::ANY), 0..100),
+            ref ours in collection::vec((0..64u8, i16::ANY, ::proptest::bool::ANY), 0..20),
+            ref theirs in collection::vec((0..64u8, i16::ANY, ::proptest::bool::ANY), 0..20)
+        ) {
+            let base_model: collections::BTreeMap<u8, i16> = base.iter().cloned().collect();
+            let ours_model = edit_model(&base_model, ours);
+            let theirs_model = edit_model(&base_model, theirs);
+            let (expected, expected_conflicts) =
+                merge3_model(&base_model, &ours_model, &theirs_model);
+
+            let edit = |map: &OrdMap<u8, i16>, edits: &[Edit]| {
+                edits.iter().fold(map.clone(), |map, &(key, value, remove)| {
+                    if remove { map.remove(&key) } else { map.insert(key, value) }
+                })
+            };
+            let base: OrdMap<u8, i16> = base.iter().cloned().collect();
+            let (ours, theirs) = (edit(&base, ours), edit(&base, theirs));
+            let mut conflicts = 0;
+            let merged = OrdMap::merge3(&base, &ours, &theirs, |_, old, left, right| {
+                assert!(left != old && right != old && left != right);
+                conflicts += 1;
+                ::std::cmp::max(left, right)
+            });
+            merged.assert_invariants();
+            assert_eq!(expected_conflicts, conflicts);
+            assert_eq!(OrdMap::from(expected), merged);
+        }
+    }
 }
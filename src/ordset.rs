@@ -10,7 +10,7 @@
 //!
 //! [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, ToOwned};
 use std::cmp::Ordering;
 use std::collections;
 use std::fmt::{Debug, Error, Formatter};
@@ -18,8 +18,14 @@ use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::{FromIterator, IntoIterator, Sum};
 use std::ops::{Add, Mul};
 use std::sync::Arc;
+use std::vec::Vec;
 
+use footprint::{Footprint, HeapSize};
+#[cfg(any(test, feature = "dot"))]
+use dot::{Dot, ToDot};
 use hashset::HashSet;
+use invariant::InvariantError;
+use patch::SetPatch;
 use nodes::btree::{BTreeValue, DiffIter, Insert, Iter, Node, Remove};
 use shared::Shared;
 
@@ -135,6 +141,28 @@ impl<A> OrdSet<A> {
         self.root.len() == 0
     }
 
+    /// Test whether two sets refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a set to
+    /// itself, or if you're comparing a set to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.root.ptr_eq(&other.root)
+    }
+
+    #[cfg(any(test, feature = "serde"))]
+    pub(crate) fn root(&self) -> &Node<Arc<A>> {
+        &self.root
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn from_root(root: Node<Arc<A>>) -> Self {
+        OrdSet { root }
+    }
+
     /// Get the size of a set.
     ///
     /// Time: O(1)
@@ -188,6 +216,33 @@ impl<A: Ord> OrdSet<A> {
         DiffIter::new(&self.root, &other.borrow().root)
     }
 
+    /// Construct a new set with the changes in a
+    /// [`SetPatch`][SetPatch] applied to it.
+    ///
+    /// Time: O(n log m) (where n is the size of the patch)
+    ///
+    /// [SetPatch]: ../patch/struct.SetPatch.html
+    pub fn apply(&self, patch: &SetPatch<A>) -> Self {
+        let mut out = self.clone();
+        out.apply_mut(patch);
+        out
+    }
+
+    /// Apply the changes in a [`SetPatch`][SetPatch] to a set, in
+    /// place.
+    ///
+    /// Time: O(n log m) (where n is the size of the patch)
+    ///
+    /// [SetPatch]: ../patch/struct.SetPatch.html
+    pub fn apply_mut(&mut self, patch: &SetPatch<A>) {
+        for value in patch.removed().iter() {
+            self.remove_mut(&*value);
+        }
+        for value in patch.added().iter() {
+            self.insert_mut(value);
+        }
+    }
+
     /// Insert a value into a set.
     ///
     /// Time: O(log n)
@@ -213,7 +268,7 @@ impl<A: Ord> OrdSet<A> {
         match self.root.insert(a.shared()) {
             Insert::NoChange => self.clone(),
             Insert::JustInc => unreachable!(),
-            Insert::Update(root) => OrdSet { root },
+            Insert::Update(root) | Insert::Replaced(root) => OrdSet { root },
             Insert::Split(left, median, right) => OrdSet {
                 root: Node::from_split(left, median, right),
             },
@@ -253,7 +308,7 @@ impl<A: Ord> OrdSet<A> {
     {
         match self.root.insert_mut(a.shared()) {
             Insert::NoChange | Insert::JustInc => {}
-            Insert::Update(root) => self.root = root,
+            Insert::Update(root) | Insert::Replaced(root) => self.root = root,
             Insert::Split(left, median, right) => self.root = Node::from_split(left, median, right),
         }
     }
@@ -450,6 +505,35 @@ impl<A: Ord> OrdSet<A> {
     pub fn remove_max(&self) -> Self {
         self.pop_max().1
     }
+
+    /// Check the internal structure of a set, returning a description
+    /// of the first broken invariant found, if any.
+    ///
+    /// This checks that the values in the set's B-tree are in order,
+    /// that its nodes are all full enough and its leaves all at the
+    /// same depth, and that the cached sizes of its nodes are
+    /// correct. See the [`invariant`][invariant] module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.root
+            .check()
+            .map_err(|message| InvariantError::new("OrdSet", message))
+    }
+
+    /// Panic if the internal structure of a set is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
 }
 
 // Core traits
@@ -464,8 +548,7 @@ impl<A> Clone for OrdSet<A> {
 
 impl<A: Ord> PartialEq for OrdSet<A> {
     fn eq(&self, other: &Self) -> bool {
-        self.root.ptr_eq(&other.root)
-            || (self.len() == other.len() && self.diff(other).next().is_none())
+        self.ptr_eq(other) || (self.len() == other.len() && self.diff(other).next().is_none())
     }
 }
 
@@ -500,6 +583,25 @@ impl<A> Default for OrdSet<A> {
     }
 }
 
+impl<A> HeapSize for OrdSet<A> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.root.measure_heap(footprint, &mut |footprint, value| {
+            footprint.value(value);
+        })
+    }
+}
+
+#[cfg(any(test, feature = "dot"))]
+impl<A> ToDot for OrdSet<A> {
+    fn dot_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write_dot(&self, dot: &mut Dot, from: &str) {
+        self.root.write_dot(dot, from)
+    }
+}
+
 impl<A: Ord> Add for OrdSet<A> {
     type Output = OrdSet<A>;
 
@@ -642,18 +744,21 @@ impl<'a, A: Ord> From<&'a Vec<Arc<A>>> for OrdSet<A> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<A: Eq + Hash + Ord> From<collections::HashSet<A>> for OrdSet<A> {
     fn from(hash_set: collections::HashSet<A>) -> Self {
         hash_set.into_iter().collect()
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a, A: Eq + Hash + Ord + Clone> From<&'a collections::HashSet<A>> for OrdSet<A> {
     fn from(hash_set: &collections::HashSet<A>) -> Self {
         hash_set.into_iter().cloned().collect()
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a, A: Eq + Hash + Ord> From<&'a collections::HashSet<Arc<A>>> for OrdSet<A> {
     fn from(hash_set: &collections::HashSet<Arc<A>>) -> Self {
         hash_set.into_iter().cloned().collect()
@@ -709,6 +814,9 @@ pub mod proptest {
     use super::*;
     use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
     use std::ops::Range;
+    use model::{set_op, SetOp};
+    use proptest::collection::vec;
+    use proptest::num::{u8, usize};
 
     /// A strategy for a set of a given size.
     ///
@@ -737,6 +845,26 @@ pub mod proptest {
             })
             .boxed()
     }
+
+    /// A strategy for a sequence of operations on a set, of a length
+    /// in the given range, for use with
+    /// [`check_against_model`][check_against_model]. The same
+    /// operations apply to a [`HashSet`][HashSet].
+    ///
+    /// [check_against_model]: ../../model/fn.check_against_model.html
+    /// [HashSet]: ../../hashset/struct.HashSet.html
+    pub fn set_ops<T: Strategy + 'static>(
+        element: T,
+        size: Range<usize>,
+    ) -> BoxedStrategy<Vec<SetOp<<T::Value as ValueTree>::Value>>> {
+        vec((u8::ANY, element, usize::ANY), size)
+            .prop_map(|ops| {
+                ops.into_iter()
+                    .map(|(choice, value, index)| set_op(choice, value, index))
+                    .collect()
+            })
+            .boxed()
+    }
 }
 
 #[cfg(test)]
@@ -750,7 +878,34 @@ mod test {
         assert!(set.contains("foo"));
     }
 
+    #[test]
+    fn validate_after_updates() {
+        let set: OrdSet<i32> = (0..10_000).collect();
+        set.assert_invariants();
+        let set = (0..10_000).filter(|i| i % 7 != 0).fold(set, |set, i| set.remove(&i));
+        set.assert_invariants();
+        assert_eq!(Ok(()), set.insert(-1).validate());
+    }
+
+    #[test]
+    fn reinserting_keeps_length() {
+        let set: OrdSet<i32> = (0..1000).collect();
+        let same = (0..1000).fold(set.clone(), |set, i| set.insert(i));
+        assert_eq!(1000, same.len());
+        same.assert_invariants();
+    }
+
     proptest! {
+        #[test]
+        fn removals_keep_invariants(ref values in ::proptest::collection::vec(::proptest::num::i16::ANY, 0..500)) {
+            let mut set: OrdSet<i16> = values.iter().cloned().collect();
+            set.assert_invariants();
+            for value in values {
+                set.remove_mut(value);
+                set.assert_invariants();
+            }
+        }
+
         #[test]
         fn proptest_a_set(ref s in ord_set(".*", 10..100)) {
             assert!(s.len() < 100);
@@ -0,0 +1,529 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Patches.
+//!
+//! A [`Patch`][Patch] records the changes between two versions of a
+//! map, and a [`SetPatch`][SetPatch] the changes between two versions
+//! of a set. Unlike the iterator returned by
+//! [`OrdMap::diff`][ordmap::OrdMap::diff], a patch holds on to
+//! nothing but the changed entries themselves, so it can be kept
+//! around, serialised and sent somewhere else, and applied there to
+//! bring another copy of the old version up to date.
+//!
+//! A patch remembers the old value of every key it changes as well
+//! as the new one, so it can be [inverted][Patch::invert] to undo
+//! what it does, and two patches made one after the other can be
+//! [composed][Patch::compose] into a single patch which does the
+//! work of both.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::patch::Patch;
+//! # fn main() {
+//! let old = ordmap!{1 => "one", 2 => "two"};
+//! let new = ordmap!{2 => "deux", 3 => "trois"};
+//! let patch: Patch<_, _> = old.diff(&new).collect();
+//! assert_eq!(3, patch.len());
+//! assert_eq!(new, old.apply(&patch));
+//! assert_eq!(old, new.apply(&patch.invert()));
+//! # }
+//! ```
+//!
+//! [Patch]: ./struct.Patch.html
+//! [Patch::invert]: ./struct.Patch.html#method.invert
+//! [Patch::compose]: ./struct.Patch.html#method.compose
+//! [SetPatch]: ./struct.SetPatch.html
+//! [ordmap::OrdMap::diff]: ../ordmap/struct.OrdMap.html#method.diff
+
+use std::borrow::Borrow;
+use std::fmt::{Debug, Error, Formatter};
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use nodes::btree::{DiffItem, Iter};
+use ordmap::OrdMap;
+use ordset::OrdSet;
+
+/// The change a [`Patch`][Patch] makes to a single key, holding its
+/// value before the change, after it, or both.
+///
+/// [Patch]: ./struct.Patch.html
+pub type Change<V> = DiffItem<Arc<V>>;
+
+/// A set of changes to a map.
+///
+/// Each changed key maps to a [`Change`][Change], a
+/// [`DiffItem`][DiffItem] of its old and new values. Build one by
+/// collecting the output of [`OrdMap::diff`][OrdMap::diff] or
+/// [`HashMap::diff`][HashMap::diff], and apply it with
+/// [`OrdMap::apply`][OrdMap::apply].
+///
+/// [Change]: ./type.Change.html
+/// [DiffItem]: ../btree/enum.DiffItem.html
+/// [OrdMap::diff]: ../ordmap/struct.OrdMap.html#method.diff
+/// [OrdMap::apply]: ../ordmap/struct.OrdMap.html#method.apply
+/// [HashMap::diff]: ../hashmap/struct.HashMap.html#method.diff
+pub struct Patch<K, V> {
+    pub(crate) changes: OrdMap<K, Change<V>>,
+}
+
+impl<K, V> Patch<K, V> {
+    /// Construct a patch which changes nothing.
+    #[inline]
+    pub fn new() -> Self {
+        Patch {
+            changes: OrdMap::new(),
+        }
+    }
+
+    /// Test whether a patch changes nothing.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Get the number of keys a patch changes.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+}
+
+impl<K: Ord, V> Patch<K, V> {
+    /// Construct the patch which turns `old` into `new`.
+    ///
+    /// This is the same as collecting `old.diff(new)`, and only
+    /// visits the parts of the maps which aren't shared.
+    ///
+    /// Time: O(n) (where n is the number of keys which differ)
+    pub fn between(old: &OrdMap<K, V>, new: &OrdMap<K, V>) -> Self
+    where
+        V: PartialEq,
+    {
+        old.diff(new).collect()
+    }
+
+    /// Get an iterator over the changed keys, in key order, along
+    /// with their changes.
+    #[inline]
+    pub fn iter(&self) -> Iter<(Arc<K>, Arc<Change<V>>)> {
+        self.changes.iter()
+    }
+
+    /// Get the change a patch makes to a key, if any.
+    ///
+    /// Time: O(log n)
+    pub fn get<BK>(&self, key: &BK) -> Option<Arc<Change<V>>>
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        self.changes.get(key)
+    }
+
+    /// Construct the patch which undoes this one.
+    ///
+    /// Added keys become removed keys and the other way round, and
+    /// updated keys swap their old and new values.
+    ///
+    /// Time: O(n log n)
+    pub fn invert(&self) -> Self {
+        let changes = self.changes.iter().map(|(key, change)| {
+            let inverse = match *change {
+                DiffItem::Add(ref value) => DiffItem::Remove(value.clone()),
+                DiffItem::Remove(ref value) => DiffItem::Add(value.clone()),
+                DiffItem::Update { ref old, ref new } => DiffItem::Update {
+                    old: new.clone(),
+                    new: old.clone(),
+                },
+            };
+            (key, inverse)
+        });
+        Patch {
+            changes: changes.collect(),
+        }
+    }
+
+    /// Construct a patch which does the work of this one followed by
+    /// `next`, which is expected to have been made against the
+    /// result of applying this one.
+    ///
+    /// A key changed by both patches keeps its old value from this
+    /// one and its new value from `next`, and a key which ends up
+    /// with the value it started with is dropped from the patch.
+    ///
+    /// Time: O(m log n) (where m is the size of `next`)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::patch::Patch;
+    /// # fn main() {
+    /// let first = ordmap!{1 => 1};
+    /// let second = ordmap!{1 => 2, 2 => 2};
+    /// let third = ordmap!{1 => 1, 2 => 3};
+    /// let patch = Patch::between(&first, &second).compose(&Patch::between(&second, &third));
+    /// assert_eq!(Patch::between(&first, &third), patch);
+    /// assert_eq!(1, patch.len());
+    /// # }
+    /// ```
+    pub fn compose(&self, next: &Self) -> Self
+    where
+        V: PartialEq,
+    {
+        let mut changes = self.changes.clone();
+        for (key, change) in next.changes.iter() {
+            let old = match changes.get(&*key) {
+                None => before(&change),
+                Some(ref previous) => before(previous),
+            };
+            match change_between(old, after(&change)) {
+                Some(change) => changes.insert_mut(key, change),
+                None => changes.remove_mut(&*key),
+            }
+        }
+        Patch { changes }
+    }
+}
+
+fn before<A: Clone>(change: &DiffItem<A>) -> Option<A> {
+    match *change {
+        DiffItem::Add(_) => None,
+        DiffItem::Update { ref old, .. } | DiffItem::Remove(ref old) => Some(old.clone()),
+    }
+}
+
+fn after<A: Clone>(change: &DiffItem<A>) -> Option<A> {
+    match *change {
+        DiffItem::Remove(_) => None,
+        DiffItem::Update { ref new, .. } | DiffItem::Add(ref new) => Some(new.clone()),
+    }
+}
+
+fn change_between<A: PartialEq>(old: Option<Arc<A>>, new: Option<Arc<A>>) -> Option<Change<A>> {
+    match (old, new) {
+        (None, None) => None,
+        (None, Some(new)) => Some(DiffItem::Add(new)),
+        (Some(old), None) => Some(DiffItem::Remove(old)),
+        (Some(old), Some(new)) => if old == new {
+            None
+        } else {
+            Some(DiffItem::Update { old, new })
+        },
+    }
+}
+
+/// A set of changes to a set.
+///
+/// Holds the values added to the set and the values removed from it.
+/// Build one by collecting the output of
+/// [`OrdSet::diff`][OrdSet::diff], and apply it with
+/// [`OrdSet::apply`][OrdSet::apply].
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::patch::SetPatch;
+/// # fn main() {
+/// let old = ordset![1, 2, 3];
+/// let new = ordset![2, 3, 4];
+/// let patch: SetPatch<_> = old.diff(&new).collect();
+/// assert_eq!(&ordset![4], patch.added());
+/// assert_eq!(&ordset![1], patch.removed());
+/// assert_eq!(new, old.apply(&patch));
+/// # }
+/// ```
+///
+/// [OrdSet::diff]: ../ordset/struct.OrdSet.html#method.diff
+/// [OrdSet::apply]: ../ordset/struct.OrdSet.html#method.apply
+pub struct SetPatch<A> {
+    pub(crate) added: OrdSet<A>,
+    pub(crate) removed: OrdSet<A>,
+}
+
+impl<A> SetPatch<A> {
+    /// Construct a patch which changes nothing.
+    #[inline]
+    pub fn new() -> Self {
+        SetPatch {
+            added: OrdSet::new(),
+            removed: OrdSet::new(),
+        }
+    }
+
+    /// Test whether a patch changes nothing.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Get the number of values a patch adds or removes.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len()
+    }
+
+    /// Get the values a patch adds.
+    #[inline]
+    pub fn added(&self) -> &OrdSet<A> {
+        &self.added
+    }
+
+    /// Get the values a patch removes.
+    #[inline]
+    pub fn removed(&self) -> &OrdSet<A> {
+        &self.removed
+    }
+
+    /// Construct the patch which undoes this one, by swapping the
+    /// added and removed values.
+    ///
+    /// Time: O(1)
+    pub fn invert(&self) -> Self {
+        SetPatch {
+            added: self.removed.clone(),
+            removed: self.added.clone(),
+        }
+    }
+}
+
+impl<A: Ord> SetPatch<A> {
+    /// Construct the patch which turns `old` into `new`.
+    ///
+    /// Time: O(n) (where n is the number of values which differ)
+    pub fn between(old: &OrdSet<A>, new: &OrdSet<A>) -> Self {
+        old.diff(new).collect()
+    }
+
+    /// Construct a patch which does the work of this one followed by
+    /// `next`, which is expected to have been made against the
+    /// result of applying this one.
+    ///
+    /// A value added by one patch and removed by the other is
+    /// dropped from the patch.
+    ///
+    /// Time: O(m log n) (where m is the size of `next`)
+    pub fn compose(&self, next: &Self) -> Self {
+        let mut patch = self.clone();
+        for value in next.added.iter() {
+            if patch.removed.contains(&*value) {
+                patch.removed.remove_mut(&*value);
+            } else {
+                patch.added.insert_mut(value);
+            }
+        }
+        for value in next.removed.iter() {
+            if patch.added.contains(&*value) {
+                patch.added.remove_mut(&*value);
+            } else {
+                patch.removed.insert_mut(value);
+            }
+        }
+        patch
+    }
+}
+
+// Core traits
+
+impl<K, V> Clone for Patch<K, V> {
+    fn clone(&self) -> Self {
+        Patch {
+            changes: self.changes.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for Patch<K, V> {
+    fn default() -> Self {
+        Patch::new()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for Patch<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.changes == other.changes
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for Patch<K, V> {}
+
+impl<K: Ord + Debug, V: Debug> Debug for Patch<K, V> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_tuple("Patch").field(&self.changes).finish()
+    }
+}
+
+impl<K: Ord, V> FromIterator<DiffItem<(Arc<K>, Arc<V>)>> for Patch<K, V> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = DiffItem<(Arc<K>, Arc<V>)>>,
+    {
+        let mut changes = OrdMap::new();
+        for item in iter {
+            let (key, change) = match item {
+                DiffItem::Add((key, value)) => (key, DiffItem::Add(value)),
+                DiffItem::Remove((key, value)) => (key, DiffItem::Remove(value)),
+                DiffItem::Update {
+                    old: (key, old),
+                    new: (_, new),
+                } => (key, DiffItem::Update { old, new }),
+            };
+            changes.insert_mut(key, change);
+        }
+        Patch { changes }
+    }
+}
+
+impl<A> Clone for SetPatch<A> {
+    fn clone(&self) -> Self {
+        SetPatch {
+            added: self.added.clone(),
+            removed: self.removed.clone(),
+        }
+    }
+}
+
+impl<A> Default for SetPatch<A> {
+    fn default() -> Self {
+        SetPatch::new()
+    }
+}
+
+impl<A: Ord> PartialEq for SetPatch<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.added == other.added && self.removed == other.removed
+    }
+}
+
+impl<A: Ord> Eq for SetPatch<A> {}
+
+impl<A: Ord + Debug> Debug for SetPatch<A> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_struct("SetPatch")
+            .field("added", &self.added)
+            .field("removed", &self.removed)
+            .finish()
+    }
+}
+
+// Values which only changed in ways their ordering can't see are
+// still the same value as far as the set is concerned, so updates are
+// left out.
+impl<A: Ord> FromIterator<DiffItem<Arc<A>>> for SetPatch<A> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = DiffItem<Arc<A>>>,
+    {
+        let mut patch = SetPatch::new();
+        for item in iter {
+            match item {
+                DiffItem::Add(value) => patch.added.insert_mut(value),
+                DiffItem::Remove(value) => patch.removed.insert_mut(value),
+                DiffItem::Update { .. } => {}
+            }
+        }
+        patch
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ordmap::proptest::ord_map;
+    use ordset::proptest::ord_set;
+    use proptest::num::{i16, u8};
+
+    #[test]
+    fn empty_patch_changes_nothing() {
+        let map = ordmap!{1 => 2};
+        let patch = Patch::new();
+        assert!(patch.is_empty());
+        assert_eq!(map, map.apply(&patch));
+        assert_eq!(patch, patch.invert());
+    }
+
+    #[test]
+    fn compose_drops_round_trips() {
+        let first = ordmap!{1 => 1};
+        let second = ordmap!{1 => 2, 2 => 2};
+        let there = Patch::between(&first, &second);
+        let back = Patch::between(&second, &first);
+        assert_eq!(back, there.invert());
+        assert!(there.compose(&back).is_empty());
+
+        let first = ordset![1, 2];
+        let second = ordset![2, 3];
+        let there = SetPatch::between(&first, &second);
+        assert_eq!(SetPatch::between(&second, &first), there.invert());
+        assert!(there.compose(&there.invert()).is_empty());
+    }
+
+    #[test]
+    fn patches_share_values() {
+        let old = ordmap!{1 => vector![1, 2, 3]};
+        let new = old.insert(1, vector![4]);
+        let patch = Patch::between(&old, &new);
+        match *patch.get(&1).unwrap() {
+            DiffItem::Update {
+                old: ref before,
+                new: ref after,
+            } => {
+                assert!(Arc::ptr_eq(before, &old.get(&1).unwrap()));
+                assert!(Arc::ptr_eq(after, &new.get(&1).unwrap()));
+            }
+            ref change => panic!("expected an update, got {:?}", change),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn apply_between(ref old in ord_map(u8::ANY, i16::ANY, 0..64),
+                         ref new in ord_map(u8::ANY, i16::ANY, 0..64)) {
+            let patch = Patch::between(old, new);
+            assert_eq!(new, &old.apply(&patch));
+            assert_eq!(old, &new.apply(&patch.invert()));
+        }
+
+        #[test]
+        fn compose_map_patches(ref first in ord_map(u8::ANY, i16::ANY, 0..64),
+                               ref second in ord_map(u8::ANY, i16::ANY, 0..64),
+                               ref third in ord_map(u8::ANY, i16::ANY, 0..64)) {
+            let patch = Patch::between(first, second).compose(&Patch::between(second, third));
+            assert_eq!(Patch::between(first, third), patch);
+            assert_eq!(third, &first.apply(&patch));
+        }
+
+        #[test]
+        fn apply_set_between(ref old in ord_set(u8::ANY, 0..64),
+                             ref new in ord_set(u8::ANY, 0..64)) {
+            let patch = SetPatch::between(old, new);
+            assert_eq!(new, &old.apply(&patch));
+            assert_eq!(old, &new.apply(&patch.invert()));
+        }
+
+        #[test]
+        fn compose_set_patches(ref first in ord_set(u8::ANY, 0..64),
+                               ref second in ord_set(u8::ANY, 0..64),
+                               ref third in ord_set(u8::ANY, 0..64)) {
+            let patch = SetPatch::between(first, second).compose(&SetPatch::between(second, third));
+            assert_eq!(SetPatch::between(first, third), patch);
+            assert_eq!(third, &first.apply(&patch));
+        }
+    }
+}
@@ -0,0 +1,742 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A persistent rope.
+//!
+//! A rope is a string type designed for editing large texts: it
+//! stores its text as a balanced tree of UTF-8 chunks, where every
+//! node keeps track of how many bytes, chars and line breaks it
+//! contains. This makes inserting, removing and slicing O(log n), as
+//! well as converting between byte, char and line indices.
+//!
+//! Like the other data structures in this library, ropes share
+//! structure with each other, so keeping every version of a text
+//! around for undo costs only the parts which have changed.
+//!
+//! Indices passed to and returned from the editing methods are char
+//! indices; lines are counted by `'\n'` characters, so a rope always
+//! has one more line than it has line breaks.
+
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::{Add, Range};
+use std::str;
+use std::string::{String, ToString};
+
+use footprint::{Footprint, HeapSize};
+use nodes::rope::{count_chars, count_lines, Node};
+
+pub use nodes::rope::Chunks;
+
+/// A persistent rope.
+///
+/// A string type stored as a B-tree of UTF-8 chunks, with O(log n)
+/// editing, slicing and index conversion.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate im;
+/// # use im::rope::Rope;
+/// # fn main() {
+/// let text = Rope::from("hello\nworld");
+/// let edited = text.insert(5, ", dear").remove(0..1);
+/// assert_eq!("ello, dear\nworld", edited.to_string());
+/// assert_eq!(2, edited.len_lines());
+/// assert_eq!(11, edited.line_to_char(1));
+///
+/// // The original is untouched.
+/// assert_eq!("hello\nworld", text.to_string());
+/// # }
+/// ```
+pub struct Rope {
+    root: Node,
+}
+
+impl Rope {
+    /// Construct an empty rope.
+    #[inline]
+    pub fn new() -> Self {
+        Rope { root: Node::new() }
+    }
+
+    /// Get the length of a rope in bytes.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len_bytes(&self) -> usize {
+        self.root.metrics().bytes
+    }
+
+    /// Get the length of a rope in chars.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len_chars(&self) -> usize {
+        self.root.metrics().chars
+    }
+
+    /// Get the number of lines in a rope.
+    ///
+    /// This is always one more than the number of line breaks, so an
+    /// empty rope has one line.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len_lines(&self) -> usize {
+        self.root.metrics().lines + 1
+    }
+
+    /// Test whether a rope is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len_bytes() == 0
+    }
+
+    /// Test whether two ropes refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a rope to itself,
+    /// or if you're comparing a rope to a fresh clone of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.root.ptr_eq(&other.root)
+    }
+
+    /// Get the byte index of a char index.
+    ///
+    /// Panics if `char_idx` is greater than the number of chars.
+    ///
+    /// Time: O(log n)
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        assert!(
+            char_idx <= self.len_chars(),
+            "Rope::char_to_byte: index out of bounds"
+        );
+        self.root
+            .find(char_idx, |m| m.chars, false, |s, index| {
+                s.char_indices()
+                    .nth(index)
+                    .map(|(pos, _)| pos)
+                    .unwrap_or_else(|| s.len())
+            })
+    }
+
+    /// Get the char index of a byte index.
+    ///
+    /// If the byte index falls inside a char, you get the index of
+    /// the char after it.
+    ///
+    /// Panics if `byte_idx` is greater than the number of bytes.
+    ///
+    /// Time: O(log n)
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        assert!(
+            byte_idx <= self.len_bytes(),
+            "Rope::byte_to_char: index out of bounds"
+        );
+        self.root.measure(byte_idx, |m| m.chars, count_chars)
+    }
+
+    /// Get the index of the line containing a byte index.
+    ///
+    /// Panics if `byte_idx` is greater than the number of bytes.
+    ///
+    /// Time: O(log n)
+    pub fn byte_to_line(&self, byte_idx: usize) -> usize {
+        assert!(
+            byte_idx <= self.len_bytes(),
+            "Rope::byte_to_line: index out of bounds"
+        );
+        self.root.measure(byte_idx, |m| m.lines, count_lines)
+    }
+
+    /// Get the byte index of the start of a line.
+    ///
+    /// Passing [`len_lines()`][len_lines] gets you the length of the
+    /// rope in bytes. Panics if `line_idx` is greater than that.
+    ///
+    /// Time: O(log n)
+    ///
+    /// [len_lines]: #method.len_lines
+    pub fn line_to_byte(&self, line_idx: usize) -> usize {
+        assert!(
+            line_idx <= self.len_lines(),
+            "Rope::line_to_byte: index out of bounds"
+        );
+        if line_idx == 0 {
+            return 0;
+        }
+        if line_idx == self.len_lines() {
+            return self.len_bytes();
+        }
+        self.root.find(line_idx, |m| m.lines, true, |s, index| {
+            s.bytes()
+                .enumerate()
+                .filter(|&(_, b)| b == b'\n')
+                .nth(index - 1)
+                .map(|(pos, _)| pos + 1)
+                .unwrap_or_else(|| s.len())
+        })
+    }
+
+    /// Get the index of the line containing a char index.
+    ///
+    /// Panics if `char_idx` is greater than the number of chars.
+    ///
+    /// Time: O(log n)
+    #[inline]
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        self.byte_to_line(self.char_to_byte(char_idx))
+    }
+
+    /// Get the char index of the start of a line.
+    ///
+    /// Passing [`len_lines()`][len_lines] gets you the length of the
+    /// rope in chars. Panics if `line_idx` is greater than that.
+    ///
+    /// Time: O(log n)
+    ///
+    /// [len_lines]: #method.len_lines
+    #[inline]
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        self.byte_to_char(self.line_to_byte(line_idx))
+    }
+
+    /// Get the char at a given char index, or `None` if the index is
+    /// out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn char_at(&self, char_idx: usize) -> Option<char> {
+        if char_idx >= self.len_chars() {
+            return None;
+        }
+        let start = self.char_to_byte(char_idx);
+        let end = self.char_to_byte(char_idx + 1);
+        self.root.slice(start, end).chunks().flat_map(str::chars).next()
+    }
+
+    /// Get a line of a rope, including its trailing line break if it
+    /// has one.
+    ///
+    /// Panics if `line_idx` is not less than the number of lines.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate im;
+    /// # use im::rope::Rope;
+    /// # fn main() {
+    /// let text = Rope::from("one\ntwo\nthree");
+    /// assert_eq!("two\n", text.line(1).to_string());
+    /// assert_eq!("three", text.line(2).to_string());
+    /// # }
+    /// ```
+    pub fn line(&self, line_idx: usize) -> Rope {
+        assert!(
+            line_idx < self.len_lines(),
+            "Rope::line: index out of bounds"
+        );
+        let start = self.line_to_byte(line_idx);
+        let end = self.line_to_byte(line_idx + 1);
+        Rope {
+            root: self.root.slice(start, end),
+        }
+    }
+
+    /// Construct a rope from a range of chars in the current rope.
+    ///
+    /// Panics if the range is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn slice(&self, range: Range<usize>) -> Rope {
+        let (start, end) = self.byte_range("Rope::slice", range);
+        Rope {
+            root: self.root.slice(start, end),
+        }
+    }
+
+    /// Split a rope at a given char index, returning a rope with
+    /// every char before the index and a rope with every char from
+    /// the index onward.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn split_at(&self, char_idx: usize) -> (Rope, Rope) {
+        assert!(
+            char_idx <= self.len_chars(),
+            "Rope::split_at: index out of bounds"
+        );
+        let byte_idx = self.char_to_byte(char_idx);
+        (
+            Rope {
+                root: self.root.slice(0, byte_idx),
+            },
+            Rope {
+                root: self.root.slice(byte_idx, self.len_bytes()),
+            },
+        )
+    }
+
+    /// Construct a new rope by appending another rope to the end of
+    /// the current rope.
+    ///
+    /// Time: O(log n)
+    pub fn append(&self, other: &Rope) -> Rope {
+        Rope {
+            root: Node::concat(self.root.clone(), other.root.clone()),
+        }
+    }
+
+    /// Construct a new rope by inserting a string at a given char
+    /// index.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n + m) where m = the length of the string
+    pub fn insert(&self, char_idx: usize, text: &str) -> Rope {
+        assert!(
+            char_idx <= self.len_chars(),
+            "Rope::insert: index out of bounds"
+        );
+        if text.is_empty() {
+            return self.clone();
+        }
+        let byte_idx = self.char_to_byte(char_idx);
+        let left = Node::concat(self.root.slice(0, byte_idx), Node::from_str(text));
+        Rope {
+            root: Node::concat(left, self.root.slice(byte_idx, self.len_bytes())),
+        }
+    }
+
+    /// Insert a string at a given char index.
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// Time: O(log n + m) where m = the length of the string
+    pub fn insert_mut(&mut self, char_idx: usize, text: &str) {
+        *self = self.insert(char_idx, text);
+    }
+
+    /// Construct a new rope with a range of chars removed.
+    ///
+    /// Panics if the range is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn remove(&self, range: Range<usize>) -> Rope {
+        let (start, end) = self.byte_range("Rope::remove", range);
+        if start == end {
+            return self.clone();
+        }
+        Rope {
+            root: Node::concat(
+                self.root.slice(0, start),
+                self.root.slice(end, self.len_bytes()),
+            ),
+        }
+    }
+
+    /// Remove a range of chars.
+    ///
+    /// Panics if the range is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn remove_mut(&mut self, range: Range<usize>) {
+        *self = self.remove(range);
+    }
+
+    /// Get an iterator over the chunks of text in a rope.
+    ///
+    /// Chunks are never empty, but they may be of any length, and
+    /// where a rope is split into chunks depends on how it was
+    /// edited.
+    #[inline]
+    pub fn chunks<'a>(&'a self) -> Chunks<'a> {
+        self.root.chunks()
+    }
+
+    /// Get an iterator over the chars in a rope.
+    #[inline]
+    pub fn chars<'a>(&'a self) -> Chars<'a> {
+        Chars {
+            chunks: self.chunks(),
+            current: "".chars(),
+        }
+    }
+
+    fn byte_range(&self, name: &str, range: Range<usize>) -> (usize, usize) {
+        assert!(
+            range.start <= range.end && range.end <= self.len_chars(),
+            "{}: range out of bounds",
+            name
+        );
+        let start = self.char_to_byte(range.start);
+        let end = self.char_to_byte(range.end);
+        (start, end)
+    }
+}
+
+// Core traits
+
+impl Clone for Rope {
+    #[inline]
+    fn clone(&self) -> Self {
+        Rope {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl Default for Rope {
+    #[inline]
+    fn default() -> Self {
+        Rope::new()
+    }
+}
+
+impl HeapSize for Rope {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.root.measure_heap(footprint)
+    }
+}
+
+impl PartialEq for Rope {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+            || (self.len_bytes() == other.len_bytes()
+                && self.chars().eq(other.chars()))
+    }
+}
+
+impl Eq for Rope {}
+
+impl<'a> PartialEq<&'a str> for Rope {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.len_bytes() == other.len() && self.chars().eq(other.chars())
+    }
+}
+
+impl PartialEq<String> for Rope {
+    fn eq(&self, other: &String) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl PartialOrd for Rope {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rope {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.chars().cmp(other.chars())
+    }
+}
+
+impl Hash for Rope {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Hash byte by byte, so the result doesn't depend on where
+        // the chunk boundaries fall.
+        for chunk in self.chunks() {
+            for byte in chunk.bytes() {
+                state.write_u8(byte);
+            }
+        }
+        state.write_u8(0xff);
+    }
+}
+
+impl Add for Rope {
+    type Output = Rope;
+
+    fn add(self, other: Self) -> Self::Output {
+        self.append(&other)
+    }
+}
+
+impl<'a> Add for &'a Rope {
+    type Output = Rope;
+
+    fn add(self, other: Self) -> Self::Output {
+        self.append(other)
+    }
+}
+
+impl Debug for Rope {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Debug::fmt(&self.to_string(), f)
+    }
+}
+
+impl Display for Rope {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+// Iterators
+
+/// An iterator over the chars of a [`Rope`][Rope].
+///
+/// [Rope]: ./struct.Rope.html
+pub struct Chars<'a> {
+    chunks: Chunks<'a>,
+    current: str::Chars<'a>,
+}
+
+impl<'a> Iterator for Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(c) = self.current.next() {
+                return Some(c);
+            }
+            match self.chunks.next() {
+                None => return None,
+                Some(chunk) => self.current = chunk.chars(),
+            }
+        }
+    }
+}
+
+// Conversions
+
+impl<'a> From<&'a str> for Rope {
+    fn from(s: &'a str) -> Self {
+        Rope {
+            root: Node::from_str(s),
+        }
+    }
+}
+
+impl From<String> for Rope {
+    fn from(s: String) -> Self {
+        Rope::from(s.as_str())
+    }
+}
+
+impl<'a> From<&'a String> for Rope {
+    fn from(s: &'a String) -> Self {
+        Rope::from(s.as_str())
+    }
+}
+
+impl<'a> From<&'a Rope> for String {
+    fn from(rope: &'a Rope) -> Self {
+        rope.to_string()
+    }
+}
+
+impl From<Rope> for String {
+    fn from(rope: Rope) -> Self {
+        rope.to_string()
+    }
+}
+
+impl FromIterator<char> for Rope {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = char>,
+    {
+        Rope::from(iter.into_iter().collect::<String>())
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Rope {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        Rope::from(iter.into_iter().collect::<String>())
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl Arbitrary for Rope {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Rope::from(String::arbitrary(g))
+    }
+}
+
+// Proptest
+
+#[cfg(any(test, feature = "proptest"))]
+pub mod proptest {
+    use super::*;
+    use proptest::strategy::{BoxedStrategy, Strategy};
+
+    /// A strategy for a rope built from strings matching a regular
+    /// expression.
+    ///
+    /// The rope is assembled from the generated pieces one insertion
+    /// at a time, so that its chunk structure resembles that of an
+    /// edited text rather than a freshly loaded one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_works(ref r in rope("[a-z\n]{0,100}", 0..10)) {
+    ///         assert!(r.len_chars() < 1000);
+    ///     }
+    /// }
+    /// ```
+    pub fn rope(regex: &str, pieces: ::std::ops::Range<usize>) -> BoxedStrategy<Rope> {
+        let piece = ::proptest::string::string_regex(regex).expect("rope: invalid regex");
+        ::proptest::collection::vec(piece, pieces)
+            .prop_map(|pieces| {
+                let mut rope = Rope::new();
+                for piece in pieces {
+                    let at = rope.len_chars() / 2;
+                    rope.insert_mut(at, &piece);
+                }
+                rope
+            })
+            .boxed()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::proptest::*;
+    use super::*;
+    use proptest::collection;
+    use proptest::num::usize;
+
+    fn char_to_byte(s: &str, index: usize) -> usize {
+        s.char_indices().nth(index).map(|(i, _)| i).unwrap_or_else(|| s.len())
+    }
+
+    #[test]
+    fn empty_rope() {
+        let rope = Rope::new();
+        assert!(rope.is_empty());
+        assert_eq!(1, rope.len_lines());
+        assert_eq!(0, rope.line_to_char(0));
+        assert_eq!(0, rope.line_to_char(1));
+        assert_eq!(None, rope.chunks().next());
+        assert_eq!(Rope::from(""), rope);
+    }
+
+    #[test]
+    fn large_text_round_trips() {
+        let text: String = (0..20_000).map(|i| format!("line {} λ\n", i)).collect();
+        let rope = Rope::from(text.as_str());
+        assert_eq!(text, rope.to_string());
+        assert_eq!(20_001, rope.len_lines());
+        assert_eq!("line 12345 λ\n", rope.line(12345).to_string());
+        assert!(rope.chunks().count() > 100);
+    }
+
+    #[test]
+    fn edits_share_structure() {
+        let text: String = (0..10_000).map(|i| format!("{}\n", i)).collect();
+        let rope = Rope::from(text.as_str());
+        let edited = rope.insert(5, "hello");
+        let same: usize = rope
+            .chunks()
+            .zip(edited.chunks())
+            .filter(|&(a, b)| a.as_ptr() == b.as_ptr())
+            .count();
+        assert!(same > rope.chunks().count() / 2);
+    }
+
+    #[test]
+    fn multibyte_conversions() {
+        let rope = Rope::from("aλ🦀\nb");
+        assert_eq!(5, rope.len_chars());
+        assert_eq!(9, rope.len_bytes());
+        assert_eq!(3, rope.char_to_byte(2));
+        assert_eq!(2, rope.byte_to_char(3));
+        assert_eq!(3, rope.byte_to_char(4));
+        assert_eq!(Some('🦀'), rope.char_at(2));
+        assert_eq!(1, rope.char_to_line(4));
+        assert_eq!(4, rope.line_to_char(1));
+    }
+
+    proptest! {
+        #[test]
+        fn edits_match_string(ref edits in collection::vec(
+            (usize::ANY, usize::ANY, "[a-c\nλ🦀]{0,300}"), 1..40
+        )) {
+            let mut rope = Rope::new();
+            let mut model = String::new();
+            for (index, &(a, b, ref text)) in edits.iter().enumerate() {
+                let len = model.chars().count();
+                if index % 3 == 2 && len > 0 {
+                    let (start, end) = (a % (len + 1), b % (len + 1));
+                    let range = if start <= end { start..end } else { end..start };
+                    rope.remove_mut(range.clone());
+                    let (bs, be) = (char_to_byte(&model, range.start), char_to_byte(&model, range.end));
+                    model.replace_range(bs..be, "");
+                } else {
+                    let at = a % (len + 1);
+                    rope.insert_mut(at, text);
+                    let byte = char_to_byte(&model, at);
+                    model.insert_str(byte, text);
+                }
+                assert_eq!(model.len(), rope.len_bytes());
+                assert_eq!(model.chars().count(), rope.len_chars());
+            }
+            assert_eq!(model, rope.to_string());
+            assert_eq!(model.split('\n').count(), rope.len_lines());
+        }
+
+        #[test]
+        fn index_conversions(ref rope in rope("[ab\nλ]{0,200}", 0..8)) {
+            let text = rope.to_string();
+            let mut line = 0;
+            let mut line_start = (0, 0);
+            for (char_idx, (byte_idx, c)) in text.char_indices().enumerate() {
+                assert_eq!(byte_idx, rope.char_to_byte(char_idx));
+                assert_eq!(char_idx, rope.byte_to_char(byte_idx));
+                assert_eq!(line, rope.byte_to_line(byte_idx));
+                assert_eq!(line, rope.char_to_line(char_idx));
+                if c == '\n' {
+                    assert_eq!(line_start.0, rope.line_to_byte(line));
+                    assert_eq!(line_start.1, rope.line_to_char(line));
+                    line += 1;
+                    line_start = (byte_idx + 1, char_idx + 1);
+                }
+            }
+            assert_eq!(line + 1, rope.len_lines());
+            assert_eq!(line_start.0, rope.line_to_byte(line));
+            assert_eq!(line_start.1, rope.line_to_char(line));
+        }
+
+        #[test]
+        fn slice_and_split(ref rope in rope("[ab\nλ🦀]{0,300}", 0..20), a in usize::ANY, b in usize::ANY) {
+            let text: Vec<char> = rope.chars().collect();
+            let (start, end) = (a % (text.len() + 1), b % (text.len() + 1));
+            let (start, end) = if start <= end { (start, end) } else { (end, start) };
+            let expected: String = text[start..end].iter().collect();
+            assert_eq!(expected, rope.slice(start..end).to_string());
+            let (left, right) = rope.split_at(start);
+            assert_eq!(*rope, &left + &right);
+            assert_eq!(start, left.len_chars());
+        }
+    }
+}
@@ -0,0 +1,472 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Serialisation for the types in `crdt`.
+//
+// States and deltas serialise the same way, so either can be sent to
+// another replica. Only the parts of a state which merging looks at
+// are written out; anything cached, like a map's clock or the order
+// of a sequence's elements, is worked out again on the other side.
+
+use serde::de::{
+    Deserialize, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use super::{
+    deserialize_map, deserialize_seq, MapTarget, MapVisitor, SeqTarget, SeqVisitor, VariantSeed,
+};
+use crdt::{Dot, Element, GCounter, LwwMap, LwwRegister, OrSet, PNCounter, Rga, Stamped};
+use ordmap::OrdMap;
+
+// Slot
+
+// The value under a map key or a sequence element goes out tagged as
+// either live or removed, rather than as an optional value, because a
+// live value may itself serialise as nothing, like `()` or `None`.
+
+const SLOTS: &[&str] = &["Live", "Removed"];
+
+pub(super) struct Slot<A>(Option<A>);
+
+impl<A: Serialize> Serialize for Slot<A> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Some(ref value) => ser.serialize_newtype_variant("Slot", 0, SLOTS[0], value),
+            None => ser.serialize_unit_variant("Slot", 1, SLOTS[1]),
+        }
+    }
+}
+
+impl<'de, A: Deserialize<'de>> Deserialize<'de> for Slot<A> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_enum("Slot", SLOTS, SlotVisitor(PhantomData))
+    }
+}
+
+struct SlotVisitor<A>(PhantomData<A>);
+
+impl<'de, A: Deserialize<'de>> Visitor<'de> for SlotVisitor<A> {
+    type Value = Slot<A>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a live or removed value")
+    }
+
+    fn visit_enum<Access>(self, access: Access) -> Result<Self::Value, Access::Error>
+    where
+        Access: EnumAccess<'de>,
+    {
+        let (index, variant) = access.variant_seed(VariantSeed(SLOTS))?;
+        match index {
+            0 => Ok(Slot(Some(variant.newtype_variant()?))),
+            _ => {
+                variant.unit_variant()?;
+                Ok(Slot(None))
+            }
+        }
+    }
+}
+
+// Dot
+
+// A dot goes out as its counter followed by its replica.
+
+impl<'de, R: Deserialize<'de>> Deserialize<'de> for Dot<R> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (counter, replica) = Deserialize::deserialize(des)?;
+        Ok(Dot { counter, replica })
+    }
+}
+
+impl<R: Serialize> Serialize for Dot<R> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (self.counter, &self.replica).serialize(ser)
+    }
+}
+
+// GCounter
+
+impl<'de, R: Deserialize<'de> + Ord> Deserialize<'de> for GCounter<R> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(GCounter {
+            counts: Deserialize::deserialize(des)?,
+        })
+    }
+}
+
+impl<R: Serialize + Ord> Serialize for GCounter<R> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.counts.serialize(ser)
+    }
+}
+
+// PNCounter
+
+impl<'de, R: Deserialize<'de> + Ord> Deserialize<'de> for PNCounter<R> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (increments, decrements) = Deserialize::deserialize(des)?;
+        Ok(PNCounter {
+            increments,
+            decrements,
+        })
+    }
+}
+
+impl<R: Serialize + Ord> Serialize for PNCounter<R> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (&self.increments, &self.decrements).serialize(ser)
+    }
+}
+
+// LwwRegister
+
+impl<'de, A: Deserialize<'de>, R: Deserialize<'de>> Deserialize<'de> for LwwRegister<A, R> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let entry: Option<(Dot<R>, A)> = Deserialize::deserialize(des)?;
+        Ok(LwwRegister {
+            entry: entry.map(|(stamp, value)| (stamp, Arc::new(value))),
+        })
+    }
+}
+
+impl<A: Serialize, R: Serialize> Serialize for LwwRegister<A, R> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.entry
+            .as_ref()
+            .map(|(stamp, value)| (stamp, value.deref()))
+            .serialize(ser)
+    }
+}
+
+// OrSet
+
+// A set goes out as its live dots, its removed dots and its clock.
+
+impl<'de, A, R> Deserialize<'de> for OrSet<A, R>
+where
+    A: Deserialize<'de> + Hash + Eq,
+    R: Deserialize<'de> + Ord + Hash + Clone,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (entries, removed, clock) = Deserialize::deserialize(des)?;
+        Ok(OrSet {
+            entries,
+            removed,
+            clock,
+        })
+    }
+}
+
+impl<A, R> Serialize for OrSet<A, R>
+where
+    A: Serialize + Hash + Eq,
+    R: Serialize + Ord + Hash,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (&self.entries, &self.removed, &self.clock).serialize(ser)
+    }
+}
+
+// LwwMap
+
+// Each key goes out with the stamp of its last write and its value,
+// in a slot which says whether it was removed.
+
+impl<'de, K, V, R> MapTarget<'de> for LwwMap<K, V, R>
+where
+    K: Deserialize<'de> + Ord,
+    V: Deserialize<'de>,
+    R: Deserialize<'de> + Ord + Clone,
+{
+    type Key = K;
+    type Value = (Dot<R>, Slot<V>);
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: MapAccess<'de>,
+    {
+        *self = LwwMap::new();
+        while let Some((key, (stamp, Slot(value)))) = access.next_entry::<K, Self::Value>()? {
+            self.clock = self.clock.max(stamp.counter);
+            let entry = Stamped {
+                stamp,
+                value: value.map(Arc::new),
+            };
+            self.put(Arc::new(key), Arc::new(entry));
+        }
+        Ok(())
+    }
+}
+
+impl<'de, K, V, R> Deserialize<'de> for LwwMap<K, V, R>
+where
+    K: Deserialize<'de> + Ord,
+    V: Deserialize<'de>,
+    R: Deserialize<'de> + Ord + Clone,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_map(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_map(MapVisitor { place })
+    }
+}
+
+impl<K: Serialize + Ord, V: Serialize, R: Serialize> Serialize for LwwMap<K, V, R> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_map(Some(self.entries.len()))?;
+        for (key, entry) in self.entries.iter() {
+            let value = Slot(entry.value.as_deref());
+            s.serialize_entry(key.deref(), &(&entry.stamp, value))?;
+        }
+        s.end()
+    }
+}
+
+// Rga
+
+// A sequence goes out as its elements in id order, each with the id
+// of the element it was inserted after, and its value in a slot which
+// says whether it was removed.
+
+impl<'de, A: Deserialize<'de>, R: Deserialize<'de> + Ord + Clone> SeqTarget<'de> for Rga<A, R> {
+    type Item = (Dot<R>, Option<Dot<R>>, Slot<A>);
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        let mut elements = OrdMap::new();
+        while let Some((id, after, Slot(value))) = access.next_element::<Self::Item>()? {
+            let element = Element {
+                after,
+                value: value.map(Arc::new),
+            };
+            elements.insert_mut(id, element);
+        }
+        *self = Rga::from_elements(elements);
+        Ok(())
+    }
+}
+
+impl<'de, A: Deserialize<'de>, R: Deserialize<'de> + Ord + Clone> Deserialize<'de> for Rga<A, R> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_seq(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor { place })
+    }
+}
+
+impl<A: Serialize, R: Serialize + Ord> Serialize for Rga<A, R> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.elements.len()))?;
+        for (id, element) in self.elements.iter() {
+            let value = Slot(element.value.as_deref());
+            s.serialize_element(&(id.deref(), &element.after, value))?;
+        }
+        s.end()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crdt::Crdt;
+    use proptest::collection::vec;
+    use proptest::num::{i16, u8};
+    use serde_json::{from_str, to_string};
+
+    #[test]
+    fn crdt_format() {
+        let mut counter = PNCounter::new();
+        counter.increment_by(1u8, 3);
+        counter.decrement(2);
+        assert_eq!(r#"[{"1":3},{"2":1}]"#, to_string(&counter).unwrap());
+
+        let mut map = LwwMap::new();
+        map.insert(1u8, 10, 7u8);
+        map.insert(2, 20, 7);
+        map.remove(1, 8);
+        assert_eq!(
+            r#"{"1":[[3,8],"Removed"],"2":[[2,7],{"Live":20}]}"#,
+            to_string(&map).unwrap()
+        );
+
+        let mut rga = Rga::new();
+        rga.insert(0, 'a', 5u8);
+        rga.insert(0, 'b', 6);
+        assert_eq!(
+            r#"[[[1,5],null,{"Live":"a"}],[[2,6],null,{"Live":"b"}]]"#,
+            to_string(&rga).unwrap()
+        );
+    }
+
+    #[test]
+    fn ser_lww_map_of_units() {
+        let mut map = LwwMap::new();
+        map.insert(1u8, (), 7u8);
+        map.insert(2, (), 7);
+        map.remove(2, 8);
+        let json = to_string(&map).unwrap();
+        assert_eq!(r#"{"1":[[1,7],{"Live":null}],"2":[[3,8],"Removed"]}"#, json);
+        let decoded: LwwMap<u8, (), u8> = from_str(&json).unwrap();
+        assert_eq!(map, decoded);
+        assert_eq!(1, decoded.len());
+        assert!(decoded.contains_key(&1));
+    }
+
+    #[test]
+    fn ser_lww_map_of_options() {
+        let mut map = LwwMap::new();
+        map.insert(1u8, None, 7u8);
+        map.insert(2, Some(20), 7);
+        map.insert(3, None, 7);
+        map.remove(3, 8);
+        let decoded: LwwMap<u8, Option<i16>, u8> = from_str(&to_string(&map).unwrap()).unwrap();
+        assert_eq!(map, decoded);
+        assert_eq!(2, decoded.len());
+        assert_eq!(Some(Arc::new(None)), decoded.get(&1));
+        assert_eq!(Some(Arc::new(Some(20))), decoded.get(&2));
+        assert_eq!(None, decoded.get(&3));
+    }
+
+    proptest! {
+        #[test]
+        fn ser_counters(ref ops in vec((u8::ANY, u8::ANY), 0..50)) {
+            let mut counter = PNCounter::new();
+            for &(replica, amount) in ops {
+                if amount % 2 == 0 {
+                    counter.increment_by(replica, u64::from(amount));
+                } else {
+                    counter.decrement_by(replica, u64::from(amount));
+                }
+            }
+            assert_eq!(counter, from_str::<PNCounter<u8>>(&to_string(&counter).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_lww_register(ref ops in vec((u8::ANY, i16::ANY), 0..10)) {
+            let mut register = LwwRegister::new();
+            for &(replica, value) in ops {
+                register.set(value, replica);
+            }
+            let json = to_string(&register).unwrap();
+            assert_eq!(register, from_str::<LwwRegister<i16, u8>>(&json).unwrap());
+        }
+
+        #[test]
+        fn ser_or_set(ref ops in vec((u8::ANY, u8::ANY), 0..50)) {
+            let mut set = OrSet::new();
+            for &(replica, value) in ops {
+                if replica % 3 == 0 {
+                    set.remove(&(value % 16));
+                } else {
+                    set.insert(value % 16, replica);
+                }
+            }
+            assert_eq!(set, from_str::<OrSet<u8, u8>>(&to_string(&set).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_lww_map(ref ops in vec((u8::ANY, u8::ANY, i16::ANY), 0..50)) {
+            let mut map = LwwMap::new();
+            for &(replica, key, value) in ops {
+                if replica % 3 == 0 {
+                    map.remove(key % 16, replica);
+                } else {
+                    map.insert(key % 16, value, replica);
+                }
+            }
+            let decoded: LwwMap<u8, i16, u8> = from_str(&to_string(&map).unwrap()).unwrap();
+            assert_eq!(map, decoded);
+            assert_eq!(map.len(), decoded.len());
+            let mut next = decoded.clone();
+            let delta = next.insert(0, 0, 0);
+            assert_eq!(next, map.merge(&delta));
+        }
+
+        #[test]
+        fn ser_rga(ref ops in vec((u8::ANY, u8::ANY, i16::ANY), 0..50)) {
+            let mut rga = Rga::new();
+            for &(replica, index, value) in ops {
+                let index = index as usize;
+                if replica % 3 == 0 && !rga.is_empty() {
+                    let index = index % rga.len();
+                    rga.remove(index);
+                } else {
+                    let index = index % (rga.len() + 1);
+                    rga.insert(index, value, replica);
+                }
+            }
+            let decoded: Rga<i16, u8> = from_str(&to_string(&rga).unwrap()).unwrap();
+            assert_eq!(rga, decoded);
+            assert!(rga.iter().eq(decoded.iter()));
+        }
+    }
+}
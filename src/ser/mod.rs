@@ -0,0 +1,901 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use serde::de::{
+    Deserialize, DeserializeSeed, Deserializer, EnumAccess, Error, MapAccess, SeqAccess,
+    Unexpected, VariantAccess, Visitor,
+};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use catlist::CatList;
+use conslist::ConsList;
+use hashmap::HashMap;
+use hashset::HashSet;
+use nodes::btree::{BTreeValue, DiffItem, Node};
+use ordmap::OrdMap;
+use ordset::OrdSet;
+use patch::{Patch, SetPatch};
+use vector::Vector;
+
+pub mod shared;
+
+#[cfg(feature = "std")]
+mod crdt;
+
+// Deserialising writes each element straight into the target
+// collection as it arrives, rather than collecting them all into a
+// `Vec` first, which would need twice the memory at the peak.
+
+trait SeqTarget<'de> {
+    type Item: Deserialize<'de>;
+
+    // Replace the contents with the elements of the sequence.
+    fn fill<Access>(&mut self, access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>;
+}
+
+trait MapTarget<'de> {
+    type Key: Deserialize<'de>;
+    type Value: Deserialize<'de>;
+
+    // Replace the contents with the entries of the map.
+    fn fill<Access>(&mut self, access: Access) -> Result<(), Access::Error>
+    where
+        Access: MapAccess<'de>;
+}
+
+struct SeqVisitor<'a, S: 'a> {
+    place: &'a mut S,
+}
+
+impl<'a, 'de, S> Visitor<'de> for SeqVisitor<'a, S>
+where
+    S: SeqTarget<'de>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<Access>(self, access: Access) -> Result<Self::Value, Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        self.place.fill(access)
+    }
+}
+
+struct MapVisitor<'a, S: 'a> {
+    place: &'a mut S,
+}
+
+impl<'a, 'de, S> Visitor<'de> for MapVisitor<'a, S>
+where
+    S: MapTarget<'de>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<Access>(self, access: Access) -> Result<Self::Value, Access::Error>
+    where
+        Access: MapAccess<'de>,
+    {
+        self.place.fill(access)
+    }
+}
+
+fn deserialize_seq<'de, D, S>(des: D) -> Result<S, D::Error>
+where
+    D: Deserializer<'de>,
+    S: SeqTarget<'de> + Default,
+{
+    let mut out = S::default();
+    des.deserialize_seq(SeqVisitor { place: &mut out })?;
+    Ok(out)
+}
+
+fn deserialize_map<'de, D, S>(des: D) -> Result<S, D::Error>
+where
+    D: Deserializer<'de>,
+    S: MapTarget<'de> + Default,
+{
+    let mut out = S::default();
+    des.deserialize_map(MapVisitor { place: &mut out })?;
+    Ok(out)
+}
+
+// Enums are written with their variant named, which formats may send
+// as either the name or its index in the list of names, so reading
+// one back accepts both and yields the index.
+struct VariantSeed(&'static [&'static str]);
+
+impl<'de> DeserializeSeed<'de> for VariantSeed {
+    type Value = usize;
+
+    fn deserialize<D>(self, des: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for VariantSeed {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "one of {:?}", self.0)
+    }
+
+    fn visit_u64<E: Error>(self, index: u64) -> Result<Self::Value, E> {
+        if index < self.0.len() as u64 {
+            Ok(index as usize)
+        } else {
+            Err(E::invalid_value(Unexpected::Unsigned(index), &self))
+        }
+    }
+
+    fn visit_str<E: Error>(self, name: &str) -> Result<Self::Value, E> {
+        self.0
+            .iter()
+            .position(|variant| *variant == name)
+            .ok_or_else(|| E::unknown_variant(name, self.0))
+    }
+}
+
+// Ordered collections serialise in key order, so a round trip hands
+// the deserialiser sorted input, which `Node::from_sorted` turns into
+// a B-tree bottom up in linear time. That needs to know how many
+// values are coming: formats which say so up front have the values
+// stream straight into the tree, and for the rest they're gathered
+// first, already in the `Arc`s the tree will keep them in, so only
+// pointers are buffered. Input which turns out not to be sorted still
+// ends up in the tree, in the order it arrived, along with `false` to
+// tell the caller to put it in order. Should a format give too small
+// a length, the values past it are left for the caller to insert.
+fn build_sorted<A, E, F>(len: Option<usize>, mut next: F) -> Result<(Node<A>, bool), E>
+where
+    A: BTreeValue,
+    E: Error,
+    F: FnMut() -> Result<Option<A>, E>,
+{
+    let mut sorted = true;
+    let mut last: Option<A> = None;
+    let mut pull = || -> Result<Option<A>, E> {
+        let value = next()?;
+        if let Some(ref value) = value {
+            if let Some(ref last) = last {
+                sorted = sorted && last.cmp_keys(value) == Ordering::Less;
+            }
+            last = Some(value.clone());
+        }
+        Ok(value)
+    };
+    let streamed = len.and_then(|len| {
+        Node::from_sorted(len, &mut || {
+            pull()?.ok_or_else(|| E::custom("fewer values than the length given"))
+        })
+    });
+    let root = match streamed {
+        Some(root) => root?,
+        None => {
+            let mut values = Vec::new();
+            while let Some(value) = pull()? {
+                values.push(value);
+            }
+            let len = values.len();
+            let mut values = values.into_iter();
+            match Node::from_sorted(len, &mut || values.next().ok_or(())) {
+                Some(Ok(root)) => root,
+                _ => unreachable!(),
+            }
+        }
+    };
+    Ok((root, sorted))
+}
+
+// CatList
+
+impl<'de, A: Deserialize<'de>> SeqTarget<'de> for CatList<A> {
+    type Item = A;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        *self = CatList::new();
+        while let Some(value) = access.next_element::<A>()? {
+            self.push_back_mut(value);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, A: Deserialize<'de>> Deserialize<'de> for CatList<A> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_seq(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor { place })
+    }
+}
+
+impl<A: Serialize> Serialize for CatList<A> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.iter() {
+            s.serialize_element(i.deref())?;
+        }
+        s.end()
+    }
+}
+
+// ConsList
+
+impl<'de, A: Deserialize<'de>> SeqTarget<'de> for ConsList<A> {
+    type Item = A;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        // A cons list only grows at the front, so gather the values
+        // first and add them from the back.
+        let mut values = Vec::new();
+        while let Some(value) = access.next_element::<A>()? {
+            values.push(value);
+        }
+        let mut list = ConsList::new();
+        for value in values.into_iter().rev() {
+            list = list.cons(value);
+        }
+        *self = list;
+        Ok(())
+    }
+}
+
+impl<'de, A: Deserialize<'de>> Deserialize<'de> for ConsList<A> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_seq(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor { place })
+    }
+}
+
+impl<A: Serialize> Serialize for ConsList<A> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.iter() {
+            s.serialize_element(i.deref())?;
+        }
+        s.end()
+    }
+}
+
+// Set
+
+impl<'de, A: Deserialize<'de> + Ord> SeqTarget<'de> for OrdSet<A> {
+    type Item = A;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        let len = access.size_hint();
+        let (root, sorted) = build_sorted(len, || Ok(access.next_element::<A>()?.map(Arc::new)))?;
+        let built = OrdSet::from_root(root);
+        *self = if sorted { built } else { built.iter().collect() };
+        while let Some(value) = access.next_element::<A>()? {
+            self.insert_mut(value);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, A: Deserialize<'de> + Ord> Deserialize<'de> for OrdSet<A> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_seq(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor { place })
+    }
+}
+
+impl<A: Ord + Serialize> Serialize for OrdSet<A> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.iter() {
+            s.serialize_element(i.deref())?;
+        }
+        s.end()
+    }
+}
+
+// Map
+
+impl<'de, K: Deserialize<'de> + Ord, V: Deserialize<'de>> MapTarget<'de> for OrdMap<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: MapAccess<'de>,
+    {
+        let len = access.size_hint();
+        let (root, sorted) = build_sorted(len, || {
+            Ok(access
+                .next_entry::<K, V>()?
+                .map(|(key, value)| (Arc::new(key), Arc::new(value))))
+        })?;
+        let built = OrdMap::from_root(root);
+        *self = if sorted { built } else { built.iter().collect() };
+        while let Some((key, value)) = access.next_entry::<K, V>()? {
+            self.insert_mut(key, value);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, K: Deserialize<'de> + Ord, V: Deserialize<'de>> Deserialize<'de> for OrdMap<K, V> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_map(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_map(MapVisitor { place })
+    }
+}
+
+impl<K: Serialize + Ord, V: Serialize> Serialize for OrdMap<K, V> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            s.serialize_entry(k.deref(), v.deref())?;
+        }
+        s.end()
+    }
+}
+
+// HashMap
+
+impl<'de, K: Deserialize<'de> + Hash + Eq, V: Deserialize<'de>, S: BuildHasher + Default>
+    MapTarget<'de> for HashMap<K, V, S>
+{
+    type Key = K;
+    type Value = V;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: MapAccess<'de>,
+    {
+        // Start over with the same hasher.
+        *self = self.new_from();
+        while let Some((key, value)) = access.next_entry::<K, V>()? {
+            self.insert_mut(key, value);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, K: Deserialize<'de> + Hash + Eq, V: Deserialize<'de>, S: BuildHasher + Default>
+    Deserialize<'de> for HashMap<K, V, S>
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_map(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_map(MapVisitor { place })
+    }
+}
+
+impl<K: Serialize + Hash + Eq, V: Serialize, S: BuildHasher + Default> Serialize
+    for HashMap<K, V, S>
+{
+    fn serialize<Ser>(&self, ser: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+    {
+        let mut s = ser.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            s.serialize_entry(k.deref(), v.deref())?;
+        }
+        s.end()
+    }
+}
+
+// HashSet
+
+impl<'de, A: Deserialize<'de> + Hash + Eq, S: BuildHasher + Default> SeqTarget<'de>
+    for HashSet<A, S>
+{
+    type Item = A;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        // Start over with the same hasher.
+        *self = self.new_from();
+        while let Some(value) = access.next_element::<A>()? {
+            self.insert_mut(value);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, A: Deserialize<'de> + Hash + Eq, S: BuildHasher + Default> Deserialize<'de>
+    for HashSet<A, S>
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_seq(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor { place })
+    }
+}
+
+impl<A: Serialize + Hash + Eq, S: BuildHasher + Default> Serialize for HashSet<A, S> {
+    fn serialize<Ser>(&self, ser: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.iter() {
+            s.serialize_element(i.deref())?;
+        }
+        s.end()
+    }
+}
+
+// Vector
+
+impl<'de, A: Deserialize<'de>> SeqTarget<'de> for Vector<A> {
+    type Item = A;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: SeqAccess<'de>,
+    {
+        *self = Vector::new();
+        while let Some(value) = access.next_element::<A>()? {
+            self.push_back_mut(value);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, A: Deserialize<'de>> Deserialize<'de> for Vector<A> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_seq(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor { place })
+    }
+}
+
+impl<A: Serialize> Serialize for Vector<A> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.iter() {
+            s.serialize_element(i.deref())?;
+        }
+        s.end()
+    }
+}
+
+// Patch
+
+// Each change goes out as an enum naming what kind of change it is,
+// holding the added value, the removed value, or the old and new
+// values as a pair.
+
+const CHANGES: &[&str] = &["Add", "Remove", "Update"];
+
+struct Change<A>(A);
+
+impl<'a, V: Serialize> Serialize for Change<&'a DiffItem<Arc<V>>> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self.0 {
+            DiffItem::Add(ref new) => {
+                ser.serialize_newtype_variant("Change", 0, CHANGES[0], new.deref())
+            }
+            DiffItem::Remove(ref old) => {
+                ser.serialize_newtype_variant("Change", 1, CHANGES[1], old.deref())
+            }
+            DiffItem::Update { ref old, ref new } => {
+                let values = (old.deref(), new.deref());
+                ser.serialize_newtype_variant("Change", 2, CHANGES[2], &values)
+            }
+        }
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for Change<DiffItem<Arc<V>>> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_enum("Change", CHANGES, ChangeVisitor(PhantomData))
+    }
+}
+
+struct ChangeVisitor<V>(PhantomData<V>);
+
+impl<'de, V: Deserialize<'de>> Visitor<'de> for ChangeVisitor<V> {
+    type Value = Change<DiffItem<Arc<V>>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a change")
+    }
+
+    fn visit_enum<Access>(self, access: Access) -> Result<Self::Value, Access::Error>
+    where
+        Access: EnumAccess<'de>,
+    {
+        let (index, variant) = access.variant_seed(VariantSeed(CHANGES))?;
+        let change = match index {
+            0 => DiffItem::Add(Arc::new(variant.newtype_variant()?)),
+            1 => DiffItem::Remove(Arc::new(variant.newtype_variant()?)),
+            _ => {
+                let (old, new) = variant.newtype_variant()?;
+                DiffItem::Update {
+                    old: Arc::new(old),
+                    new: Arc::new(new),
+                }
+            }
+        };
+        Ok(Change(change))
+    }
+}
+
+impl<'de, K: Deserialize<'de> + Ord, V: Deserialize<'de>> MapTarget<'de> for Patch<K, V> {
+    type Key = K;
+    type Value = Change<DiffItem<Arc<V>>>;
+
+    fn fill<Access>(&mut self, mut access: Access) -> Result<(), Access::Error>
+    where
+        Access: MapAccess<'de>,
+    {
+        *self = Patch::new();
+        while let Some((key, Change(change))) = access.next_entry::<K, Self::Value>()? {
+            self.changes.insert_mut(key, change);
+        }
+        Ok(())
+    }
+}
+
+impl<'de, K: Deserialize<'de> + Ord, V: Deserialize<'de>> Deserialize<'de> for Patch<K, V> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_map(des)
+    }
+
+    fn deserialize_in_place<D>(des: D, place: &mut Self) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_map(MapVisitor { place })
+    }
+}
+
+impl<K: Serialize + Ord, V: Serialize> Serialize for Patch<K, V> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_map(Some(self.len()))?;
+        for (k, change) in self.iter() {
+            s.serialize_entry(k.deref(), &Change(change.deref()))?;
+        }
+        s.end()
+    }
+}
+
+// SetPatch
+
+// A set patch goes out as its added values followed by its removed
+// values.
+
+impl<'de, A: Deserialize<'de> + Ord> Deserialize<'de> for SetPatch<A> {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (added, removed) = Deserialize::deserialize(des)?;
+        Ok(SetPatch { added, removed })
+    }
+}
+
+impl<A: Serialize + Ord> Serialize for SetPatch<A> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (&self.added, &self.removed).serialize(ser)
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use catlist::proptest::catlist;
+    use conslist::proptest::conslist;
+    use hashmap::proptest::hash_map;
+    use hashset::proptest::hash_set;
+    use ordmap::proptest::ord_map;
+    use ordset::proptest::ord_set;
+    use proptest::collection;
+    use proptest::num::i32;
+    use serde::de::value::{Error as ValueError, MapDeserializer, SeqDeserializer};
+    use serde_json::{from_str, to_string};
+    use std::collections::BTreeMap;
+    use vector::proptest::vector;
+
+    // Check that all leaves are at the same depth and that every node
+    // but the root is at least half full, returning the depth.
+    fn check_balanced<A>(node: &Node<A>, root: bool) -> usize {
+        assert!(root || node.keys().len() >= 8);
+        let depths: Vec<usize> = node
+            .children()
+            .iter()
+            .map(|child| match *child {
+                None => 0,
+                Some(ref child) => 1 + check_balanced(child, false),
+            })
+            .collect();
+        assert!(depths.iter().all(|depth| *depth == depths[0]));
+        depths[0]
+    }
+
+    fn json_map(pairs: &[(i32, i32)]) -> String {
+        let entries: Vec<String> = pairs
+            .iter()
+            .map(|&(key, value)| format!("\"{}\":{}", key, value))
+            .collect();
+        format!("{{{}}}", entries.join(","))
+    }
+
+    #[test]
+    fn sorted_input_builds_balanced_trees() {
+        for len in 0..1200 {
+            let values: Vec<i32> = (0..len).collect();
+            let set: OrdSet<i32> = from_str(&to_string(&values).unwrap()).unwrap();
+            check_balanced(set.root(), true);
+            assert_eq!(len as usize, set.len());
+            assert!(set.iter().map(|value| *value).eq(0..len));
+        }
+    }
+
+    #[test]
+    fn sorted_input_with_a_length_streams_into_trees() {
+        for len in 0..1200 {
+            let des = SeqDeserializer::<_, ValueError>::new(0..len);
+            let set = OrdSet::<i32>::deserialize(des).unwrap();
+            check_balanced(set.root(), true);
+            assert!(set.iter().map(|value| *value).eq(0..len));
+        }
+        let des = MapDeserializer::<_, ValueError>::new((0..3000).map(|key| (key, -key)));
+        let map = OrdMap::<i32, i32>::deserialize(des).unwrap();
+        check_balanced(map.root(), true);
+        assert!(map.iter().all(|(key, value)| *key == -*value));
+        let des = SeqDeserializer::<_, ValueError>::new((0..1000).rev().chain(500..1500));
+        let set = OrdSet::<i32>::deserialize(des).unwrap();
+        assert!(set.iter().map(|value| *value).eq(0..1500));
+    }
+
+    #[test]
+    fn sorted_map_supports_updates() {
+        let pairs: Vec<(i32, i32)> = (0..5000).map(|key| (key, -key)).collect();
+        let mut map: OrdMap<i32, i32> = from_str(&json_map(&pairs)).unwrap();
+        let mut model: BTreeMap<i32, i32> = pairs.into_iter().collect();
+        check_balanced(map.root(), true);
+        for step in 0..5000 {
+            let key = (step * 7919) % 5000;
+            if step % 3 == 0 {
+                map.insert_mut(key + 5000, key);
+                model.insert(key + 5000, key);
+            }
+            map.remove_mut(&key);
+            model.remove(&key);
+            assert_eq!(model.len(), map.len());
+        }
+        assert!(map
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .eq(model.into_iter()));
+    }
+
+    #[test]
+    fn deserialize_in_place_replaces_contents() {
+        let mut vector = vector![1, 2, 3];
+        let mut des = serde_json::Deserializer::from_str("[4, 5]");
+        Deserialize::deserialize_in_place(&mut des, &mut vector).unwrap();
+        assert_eq!(vector![4, 5], vector);
+
+        let mut map = hashmap!{1 => 2};
+        let mut des = serde_json::Deserializer::from_str(r#"{"3": 4}"#);
+        Deserialize::deserialize_in_place(&mut des, &mut map).unwrap();
+        assert_eq!(hashmap!{3 => 4}, map);
+
+        let mut set = ordset![1, 2, 3];
+        let mut des = serde_json::Deserializer::from_str("[3, 1]");
+        Deserialize::deserialize_in_place(&mut des, &mut set).unwrap();
+        assert_eq!(ordset![1, 3], set);
+    }
+
+    #[test]
+    fn patch_format() {
+        let old = ordmap!{1 => 1, 2 => 2};
+        let new = ordmap!{2 => 3, 4 => 4};
+        let json = to_string(&Patch::between(&old, &new)).unwrap();
+        assert_eq!(
+            r#"{"1":{"Remove":1},"2":{"Update":[2,3]},"4":{"Add":4}}"#,
+            json
+        );
+        assert!(from_str::<Patch<i32, i32>>(r#"{"1":{"Move":1}}"#).is_err());
+    }
+
+    #[test]
+    fn patch_of_units() {
+        let old = ordmap!{1 => (), 2 => ()};
+        let new = ordmap!{2 => (), 3 => ()};
+        let patch = Patch::between(&old, &new);
+        let json = to_string(&patch).unwrap();
+        assert_eq!(r#"{"1":{"Remove":null},"3":{"Add":null}}"#, json);
+        let decoded: Patch<i32, ()> = from_str(&json).unwrap();
+        assert_eq!(patch, decoded);
+        assert_eq!(new, old.apply(&decoded));
+    }
+
+    proptest! {
+        #[test]
+        fn ser_catlist(ref v in catlist(i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<CatList<i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_conslist(ref v in conslist(i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<ConsList<i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_ordset(ref v in ord_set(i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<OrdSet<i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_ordmap(ref v in ord_map(i32::ANY, i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<OrdMap<i32, i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_hashmap(ref v in hash_map(i32::ANY, i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<HashMap<i32, i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_hashset(ref v in hash_set(i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<HashSet<i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_unsorted_ordset(ref v in collection::vec(i32::ANY, 0..100)) {
+            let expected: OrdSet<i32> = v.iter().cloned().collect();
+            let set: OrdSet<i32> = from_str(&to_string(&v).unwrap()).unwrap();
+            check_balanced(set.root(), true);
+            assert_eq!(expected, set);
+        }
+
+        #[test]
+        fn ser_unsorted_ordmap(ref v in collection::vec((i32::ANY, i32::ANY), 0..100)) {
+            let expected: OrdMap<i32, i32> = v.iter().cloned().collect();
+            assert_eq!(expected, from_str::<OrdMap<i32, i32>>(&json_map(&v)).unwrap());
+        }
+
+        #[test]
+        fn ser_patch(ref old in ord_map(i32::ANY, i32::ANY, 0..100),
+                     ref new in ord_map(i32::ANY, i32::ANY, 0..100)) {
+            let patch = Patch::between(old, new);
+            let decoded: Patch<i32, i32> = from_str(&to_string(&patch).unwrap()).unwrap();
+            assert_eq!(patch, decoded);
+            assert_eq!(new, &old.apply(&decoded));
+        }
+
+        #[test]
+        fn ser_set_patch(ref old in ord_set(i32::ANY, 0..100),
+                         ref new in ord_set(i32::ANY, 0..100)) {
+            let patch = SetPatch::between(old, new);
+            let decoded: SetPatch<i32> = from_str(&to_string(&patch).unwrap()).unwrap();
+            assert_eq!(patch, decoded);
+            assert_eq!(new, &old.apply(&decoded));
+        }
+
+        #[test]
+        fn ser_vector(ref v in vector(i32::ANY, 0..100)) {
+            assert_eq!(v, &from_str::<Vector<i32>>(&to_string(&v).unwrap()).unwrap());
+        }
+    }
+}
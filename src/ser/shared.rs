@@ -0,0 +1,475 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Serialisation which preserves structural sharing.
+//!
+//! Serialising a list of collections the usual way writes out every
+//! one of them in full, even when they're successive versions of the
+//! same collection sharing nearly all of their nodes, like the
+//! snapshots in an undo history. [`serialize_many`][serialize_many]
+//! instead writes out each distinct node once, along with the
+//! references between them, and
+//! [`deserialize_many`][deserialize_many] reads them back into
+//! collections which share those nodes again, so the output takes up
+//! space proportional to the distinct data rather than the sum of
+//! the collections' sizes.
+//!
+//! This is currently supported for [`OrdMap`][ordmap::OrdMap] and
+//! [`OrdSet`][ordset::OrdSet]. The functions fit serde's
+//! `serialize_with` and `deserialize_with` field attributes for a
+//! `Vec` of either.
+//!
+//! The serialised form is a pair of a list of nodes, each written as
+//! its elements and the indices of its children in that list, and a
+//! list of the indices of each collection's root node. Children
+//! always come before their parents, and deserialisation checks that
+//! the nodes make valid trees, so malformed input results in an
+//! error rather than a broken collection.
+//!
+//! [serialize_many]: ./fn.serialize_many.html
+//! [deserialize_many]: ./fn.deserialize_many.html
+//! [ordmap::OrdMap]: ../../ordmap/struct.OrdMap.html
+//! [ordset::OrdSet]: ../../ordset/struct.OrdSet.html
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nodes::btree::{BTreeValue, Node};
+use ordmap::OrdMap;
+use ordset::OrdSet;
+
+/// Collections which can be serialised with
+/// [`serialize_many`][serialize_many].
+///
+/// [serialize_many]: ./fn.serialize_many.html
+pub trait SerializeShared: Sized {
+    #[doc(hidden)]
+    fn serialize_shared<S>(items: &[Self], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer;
+}
+
+/// Collections which can be deserialised with
+/// [`deserialize_many`][deserialize_many].
+///
+/// [deserialize_many]: ./fn.deserialize_many.html
+pub trait DeserializeShared<'de>: Sized {
+    #[doc(hidden)]
+    fn deserialize_shared<D>(deserializer: D) -> Result<Vec<Self>, D::Error>
+    where
+        D: Deserializer<'de>;
+}
+
+/// Serialise a list of collections, writing each node they share
+/// only once.
+///
+/// Time: O(n) in the number of distinct nodes
+pub fn serialize_many<C, S>(items: &[C], serializer: S) -> Result<S::Ok, S::Error>
+where
+    C: SerializeShared,
+    S: Serializer,
+{
+    C::serialize_shared(items, serializer)
+}
+
+/// Deserialise a list of collections written by
+/// [`serialize_many`][serialize_many], restoring the nodes they
+/// shared.
+///
+/// Time: O(n) in the number of distinct nodes
+///
+/// [serialize_many]: ./fn.serialize_many.html
+pub fn deserialize_many<'de, C, D>(deserializer: D) -> Result<Vec<C>, D::Error>
+where
+    C: DeserializeShared<'de>,
+    D: Deserializer<'de>,
+{
+    C::deserialize_shared(deserializer)
+}
+
+// Elements
+
+trait SerializeElement {
+    fn serialize_element<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer;
+}
+
+trait DeserializeElement<'de> {
+    type Repr: Deserialize<'de>;
+
+    fn from_repr(repr: Self::Repr) -> Self;
+}
+
+impl<K: Serialize, V: Serialize> SerializeElement for (Arc<K>, Arc<V>) {
+    fn serialize_element<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (&*self.0, &*self.1).serialize(serializer)
+    }
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> DeserializeElement<'de> for (Arc<K>, Arc<V>) {
+    type Repr = (K, V);
+
+    fn from_repr((key, value): Self::Repr) -> Self {
+        (Arc::new(key), Arc::new(value))
+    }
+}
+
+impl<A: Serialize> SerializeElement for Arc<A> {
+    fn serialize_element<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (**self).serialize(serializer)
+    }
+}
+
+impl<'de, A: Deserialize<'de>> DeserializeElement<'de> for Arc<A> {
+    type Repr = A;
+
+    fn from_repr(repr: Self::Repr) -> Self {
+        Arc::new(repr)
+    }
+}
+
+struct Element<'a, A: 'a>(&'a A);
+
+impl<'a, A: SerializeElement> Serialize for Element<'a, A> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize_element(serializer)
+    }
+}
+
+// B-trees
+
+struct BTreeTable<'a, A: 'a> {
+    nodes: Vec<&'a Node<A>>,
+    index: HashMap<usize, usize>,
+}
+
+impl<'a, A> BTreeTable<'a, A> {
+    fn new() -> Self {
+        BTreeTable {
+            nodes: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, node: &'a Node<A>) -> usize {
+        if let Some(index) = self.index.get(&node.id()) {
+            return *index;
+        }
+        for child in node.children() {
+            if let Some(ref child) = *child {
+                self.add(child);
+            }
+        }
+        let index = self.nodes.len();
+        self.nodes.push(node);
+        self.index.insert(node.id(), index);
+        index
+    }
+
+    fn index_of(&self, node: &Node<A>) -> usize {
+        self.index[&node.id()]
+    }
+}
+
+struct BTreeNodes<'a, 'b, A: 'a + 'b>(&'b BTreeTable<'a, A>);
+
+impl<'a, 'b, A: SerializeElement> Serialize for BTreeNodes<'a, 'b, A> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let table = self.0;
+        serializer.collect_seq(table.nodes.iter().map(|node| {
+            let keys: Vec<_> = node.keys().iter().map(Element).collect();
+            let children: Vec<_> = node
+                .children()
+                .iter()
+                .map(|child| child.as_ref().map(|child| table.index_of(child)))
+                .collect();
+            (keys, children)
+        }))
+    }
+}
+
+fn serialize_btrees<'a, A, I, S>(roots: I, serializer: S) -> Result<S::Ok, S::Error>
+where
+    A: SerializeElement + 'a,
+    I: IntoIterator<Item = &'a Node<A>>,
+    S: Serializer,
+{
+    let mut table = BTreeTable::new();
+    let roots: Vec<usize> = roots.into_iter().map(|root| table.add(root)).collect();
+    (BTreeNodes(&table), roots).serialize(serializer)
+}
+
+// A list of nodes, as their elements and the indices of their
+// children, and the indices of the root nodes.
+type RawBTrees<A> = (Vec<(Vec<A>, Vec<Option<usize>>)>, Vec<usize>);
+
+fn deserialize_btrees<'de, A, D>(deserializer: D) -> Result<Vec<Node<A>>, D::Error>
+where
+    A: BTreeValue + DeserializeElement<'de>,
+    D: Deserializer<'de>,
+{
+    let (raw_nodes, roots): RawBTrees<A::Repr> = Deserialize::deserialize(deserializer)?;
+    let mut nodes: Vec<Node<A>> = Vec::with_capacity(raw_nodes.len());
+    for (keys, children) in raw_nodes {
+        let keys = keys.into_iter().map(A::from_repr).collect();
+        let children = children
+            .into_iter()
+            .map(|child| match child {
+                None => Ok(None),
+                Some(index) => match nodes.get(index) {
+                    Some(node) => Ok(Some(node.clone())),
+                    None => Err(D::Error::custom(format!(
+                        "node {} refers to child {} which doesn't precede it",
+                        nodes.len(),
+                        index
+                    ))),
+                },
+            })
+            .collect::<Result<_, _>>()?;
+        match Node::from_parts(keys, children) {
+            Ok(node) => nodes.push(node),
+            Err(problem) => {
+                return Err(D::Error::custom(format!(
+                    "node {} is not a valid tree: {}",
+                    nodes.len(),
+                    problem
+                )))
+            }
+        }
+    }
+    roots
+        .into_iter()
+        .map(|index| match nodes.get(index) {
+            Some(node) => Ok(node.clone()),
+            None => Err(D::Error::custom(format!("no root node {}", index))),
+        })
+        .collect()
+}
+
+// Collections
+
+impl<K: Ord + Serialize, V: Serialize> SerializeShared for OrdMap<K, V> {
+    fn serialize_shared<S>(items: &[Self], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_btrees(items.iter().map(OrdMap::root), serializer)
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> DeserializeShared<'de>
+    for OrdMap<K, V>
+{
+    fn deserialize_shared<D>(deserializer: D) -> Result<Vec<Self>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let roots = deserialize_btrees(deserializer)?;
+        Ok(roots.into_iter().map(OrdMap::from_root).collect())
+    }
+}
+
+impl<A: Ord + Serialize> SerializeShared for OrdSet<A> {
+    fn serialize_shared<S>(items: &[Self], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_btrees(items.iter().map(OrdSet::root), serializer)
+    }
+}
+
+impl<'de, A: Ord + Deserialize<'de>> DeserializeShared<'de> for OrdSet<A> {
+    fn deserialize_shared<D>(deserializer: D) -> Result<Vec<Self>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let roots = deserialize_btrees(deserializer)?;
+        Ok(roots.into_iter().map(OrdSet::from_root).collect())
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::num::i16;
+    use serde_json;
+    use std::collections::HashSet;
+
+    fn to_json<C: SerializeShared>(items: &[C]) -> Vec<u8> {
+        let mut out = Vec::new();
+        serialize_many(items, &mut serde_json::Serializer::new(&mut out)).unwrap();
+        out
+    }
+
+    fn from_json<'de, C: DeserializeShared<'de>>(json: &'de [u8]) -> serde_json::Result<Vec<C>> {
+        deserialize_many(&mut serde_json::Deserializer::from_slice(json))
+    }
+
+    fn count_nodes<A>(roots: &[&Node<A>]) -> usize {
+        fn walk<A>(node: &Node<A>, seen: &mut HashSet<usize>) {
+            if seen.insert(node.id()) {
+                for child in node.children() {
+                    if let Some(ref child) = *child {
+                        walk(child, seen);
+                    }
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        for root in roots {
+            walk(root, &mut seen);
+        }
+        seen.len()
+    }
+
+    fn history(size: i32, steps: i32) -> Vec<OrdMap<i32, i32>> {
+        let mut map: OrdMap<i32, i32> = (0..size).map(|i| (i, i)).collect();
+        let mut history = vec![map.clone()];
+        for step in 0..steps {
+            map.insert_mut((step * 7919) % size, -step);
+            history.push(map.clone());
+        }
+        history
+    }
+
+    #[test]
+    fn restores_sharing() {
+        let history = history(1000, 100);
+        let json = to_json(&history);
+        let full: usize = history
+            .iter()
+            .map(|map| serde_json::to_vec(map).unwrap().len())
+            .sum();
+        assert!(json.len() * 10 < full);
+
+        let restored: Vec<OrdMap<i32, i32>> = from_json(&json).unwrap();
+        assert_eq!(history, restored);
+        let before: Vec<_> = history.iter().map(OrdMap::root).collect();
+        let after: Vec<_> = restored.iter().map(OrdMap::root).collect();
+        assert_eq!(count_nodes(&before), count_nodes(&after));
+    }
+
+    #[test]
+    fn empty_collections() {
+        let maps: Vec<OrdMap<i32, i32>> = vec![OrdMap::new(), OrdMap::new()];
+        assert_eq!(maps, from_json::<OrdMap<i32, i32>>(&to_json(&maps)).unwrap());
+        let sets: Vec<OrdSet<i32>> = Vec::new();
+        assert_eq!(sets, from_json::<OrdSet<i32>>(&to_json(&sets)).unwrap());
+    }
+
+    // A leaf node holding a run of consecutive keys.
+    fn leaf(keys: ::std::ops::Range<i32>) -> String {
+        let keys: Vec<String> = keys.map(|key| key.to_string()).collect();
+        let children = vec!["null"; keys.len() + 1];
+        format!("[[{}],[{}]]", keys.join(","), children.join(","))
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let cases = vec![
+            (
+                "[[[[2,1],[null,null,null]]],[0]]".to_owned(),
+                "node 0 is not a valid tree: keys are out of order",
+            ),
+            (
+                "[[[[1],[null,null,null]]],[0]]".to_owned(),
+                "node 0 is not a valid tree: a node has 1 keys but 3 children",
+            ),
+            (
+                "[[[[1],[1,null]]],[0]]".to_owned(),
+                "node 0 refers to child 1 which doesn't precede it",
+            ),
+            (
+                format!("[[{},{},[[5],[0,1]]],[2]]", leaf(1..8), leaf(10..17)),
+                "node 2 is not a valid tree: keys are out of order",
+            ),
+            (
+                format!(
+                    "[[{},{},{},[[20],[1,2]],[[10],[0,3]]],[4]]",
+                    leaf(1..8),
+                    leaf(11..18),
+                    leaf(21..28)
+                ),
+                "node 4 is not a valid tree: leaves are at different depths",
+            ),
+            (
+                "[[[[1],[null,null]],[[3],[null,null]],[[2],[0,1]]],[2]]".to_owned(),
+                "node 2 is not a valid tree: a node has only 1 keys",
+            ),
+            (
+                "[[[[1,2,3],[null,null,null,null]],[[],[0]]],[1]]".to_owned(),
+                "node 1 is not a valid tree: a node has children but no keys",
+            ),
+            ("[[],[0]]".to_owned(), "no root node 0"),
+        ];
+        for (json, message) in cases {
+            match from_json::<OrdSet<i32>>(json.as_bytes()) {
+                Ok(_) => panic!("accepted {}", json),
+                Err(error) => assert!(error.to_string().contains(message), "{}: {}", json, error),
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_hand_built_trees() {
+        let json = format!("[[{},{},[[10],[0,1]]],[2]]", leaf(1..8), leaf(11..18));
+        let sets: Vec<OrdSet<i32>> = from_json(json.as_bytes()).unwrap();
+        sets[0].assert_invariants();
+        assert!(sets[0].iter().map(|value| *value).eq((1..8).chain(10..18)));
+    }
+
+    proptest! {
+        #[test]
+        fn maps_round_trip(ref edits in vec((i16::ANY, i16::ANY), 0..200)) {
+            let mut map = OrdMap::new();
+            let mut history = vec![map.clone()];
+            for &(key, value) in edits {
+                if value % 3 == 0 {
+                    map.remove_mut(&key);
+                } else {
+                    map.insert_mut(key, value);
+                }
+                history.push(map.clone());
+            }
+            let json = to_json(&history);
+            let restored: Vec<OrdMap<i16, i16>> = from_json(&json).unwrap();
+            assert_eq!(history, restored);
+        }
+
+        #[test]
+        fn sets_round_trip(ref edits in vec(i16::ANY, 0..200)) {
+            let mut set = OrdSet::new();
+            let mut history = vec![set.clone()];
+            for &value in edits {
+                if value % 3 == 0 {
+                    set.remove_mut(&(value / 3));
+                } else {
+                    set.insert_mut(value);
+                }
+                history.push(set.clone());
+            }
+            let json = to_json(&history);
+            let restored: Vec<OrdSet<i16>> = from_json(&json).unwrap();
+            assert_eq!(history, restored);
+        }
+    }
+}
@@ -0,0 +1,823 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A compact binary format for saving and restoring collections.
+//!
+//! A snapshot is a self-describing, versioned encoding of a single
+//! collection. Collections implementing [`Snapshot`][Snapshot] are
+//! written straight from their iterators, and read back without
+//! going through an intermediate `Vec`: [`OrdMap`][ordmap::OrdMap]
+//! and [`OrdSet`][ordset::OrdSet] are bulk loaded bottom up from
+//! their sorted elements, and the other collections are built up one
+//! element at a time as they're read.
+//!
+//! Elements are encoded by their [`Codec`][Codec], which is
+//! implemented for booleans, characters, strings and the primitive
+//! numeric types, and which you can implement for your own types.
+//!
+//! # Format
+//!
+//! All numbers are little endian.
+//!
+//! * The magic bytes `imsn`.
+//! * The format version, one byte, currently `1`.
+//! * The [`Kind`][Kind] of collection, one byte.
+//! * The [`TAG`][Codec::TAG] of the element codec, or of the key
+//!   codec for maps, one byte.
+//! * The tag of the value codec for maps, or `0` for other
+//!   collections, one byte.
+//! * The number of elements, eight bytes.
+//! * The elements, in iteration order, each one encoded by its
+//!   codec, with maps writing each key followed by its value.
+//! * A 64 bit [FNV-1a][fnv] checksum of everything before it.
+//!
+//! Reading a snapshot checks its header against the type being read,
+//! and its checksum and the validity of its elements against the
+//! collection, and fails with an [`io::Error`][io::Error] of kind
+//! `InvalidData` if anything doesn't match.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::ordmap::OrdMap;
+//! # use im::snapshot::Snapshot;
+//! # fn main() {
+//! let map = ordmap!{1 => "one".to_string(), 2 => "two".to_string()};
+//! let mut buffer = Vec::new();
+//! map.write_snapshot(&mut buffer).unwrap();
+//! let restored: OrdMap<i32, String> = Snapshot::read_snapshot(&buffer[..]).unwrap();
+//! assert_eq!(map, restored);
+//! # }
+//! ```
+//!
+//! [Snapshot]: ./trait.Snapshot.html
+//! [Codec]: ./trait.Codec.html
+//! [Codec::TAG]: ./trait.Codec.html#associatedconstant.TAG
+//! [Kind]: ./enum.Kind.html
+//! [ordmap::OrdMap]: ../ordmap/struct.OrdMap.html
+//! [ordset::OrdSet]: ../ordset/struct.OrdSet.html
+//! [io::Error]: https://doc.rust-lang.org/std/io/struct.Error.html
+//! [fnv]: https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use catlist::CatList;
+use conslist::ConsList;
+use hashmap::HashMap;
+use hashset::HashSet;
+use nodes::btree::Node;
+use ordmap::OrdMap;
+use ordset::OrdSet;
+use vector::Vector;
+
+const MAGIC: [u8; 4] = *b"imsn";
+
+/// The version of the format written by this module.
+pub const VERSION: u8 = 1;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn invalid<M: Into<String>>(message: M) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// The kinds of collection a snapshot can hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    ConsList = 1,
+    CatList = 2,
+    Vector = 3,
+    OrdSet = 4,
+    OrdMap = 5,
+    HashSet = 6,
+    HashMap = 7,
+}
+
+impl Kind {
+    fn from_tag(tag: u8) -> Option<Kind> {
+        match tag {
+            1 => Some(Kind::ConsList),
+            2 => Some(Kind::CatList),
+            3 => Some(Kind::Vector),
+            4 => Some(Kind::OrdSet),
+            5 => Some(Kind::OrdMap),
+            6 => Some(Kind::HashSet),
+            7 => Some(Kind::HashMap),
+            _ => None,
+        }
+    }
+}
+
+/// The header at the start of a snapshot.
+///
+/// [`Header::read`][Header::read] lets you find out what a snapshot
+/// contains before deciding how to read the rest of it.
+///
+/// [Header::read]: #method.read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Header {
+    /// The kind of collection in the snapshot.
+    pub kind: Kind,
+    /// The tag of the codec for its elements, or for its keys if
+    /// it's a map.
+    pub key_codec: u8,
+    /// The tag of the codec for its values if it's a map, or `0`.
+    pub value_codec: u8,
+    /// The number of elements in the collection.
+    pub len: u64,
+}
+
+impl Header {
+    fn new(kind: Kind, key_codec: u8, value_codec: u8, len: usize) -> Self {
+        Header {
+            kind,
+            key_codec,
+            value_codec,
+            len: len as u64,
+        }
+    }
+
+    /// Read a snapshot header, checking its magic bytes and version.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid("not an im snapshot"));
+        }
+        let mut tags = [0; 4];
+        reader.read_exact(&mut tags)?;
+        if tags[0] != VERSION {
+            return Err(invalid(format!(
+                "unsupported snapshot version {}",
+                tags[0]
+            )));
+        }
+        let kind = match Kind::from_tag(tags[1]) {
+            Some(kind) => kind,
+            None => return Err(invalid(format!("unknown collection kind {}", tags[1]))),
+        };
+        Ok(Header {
+            kind,
+            key_codec: tags[2],
+            value_codec: tags[3],
+            len: u64::decode(reader)?,
+        })
+    }
+
+    /// Write a snapshot header.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[
+            VERSION,
+            self.kind as u8,
+            self.key_codec,
+            self.value_codec,
+        ])?;
+        self.len.encode(writer)
+    }
+
+    fn expect(&self, expected: Header) -> io::Result<usize> {
+        if (self.kind, self.key_codec, self.value_codec)
+            != (expected.kind, expected.key_codec, expected.value_codec)
+        {
+            return Err(invalid(format!(
+                "expected a snapshot of {:?} with codecs {}/{}, found {:?} with codecs {}/{}",
+                expected.kind,
+                expected.key_codec,
+                expected.value_codec,
+                self.kind,
+                self.key_codec,
+                self.value_codec
+            )));
+        }
+        usize::try_from(self.len).map_err(|_| invalid("snapshot too large"))
+    }
+}
+
+/// Encoding and decoding of collection elements.
+///
+/// The [`TAG`][Codec::TAG] is recorded in a snapshot's header, so
+/// that reading it as the wrong type of element fails instead of
+/// producing garbage. Tags below 128 are reserved for the codecs in
+/// this module; use tags from 128 up for your own.
+///
+/// [Codec::TAG]: #associatedconstant.TAG
+pub trait Codec: Sized {
+    /// The tag identifying this codec.
+    const TAG: u8;
+
+    /// Write a value.
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+
+    /// Read a value back.
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! number_codecs {
+    ($($type:ty => $tag:expr),*) => {$(
+        impl Codec for $type {
+            const TAG: u8 = $tag;
+
+            fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                writer.write_all(&self.to_le_bytes())
+            }
+
+            fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+                let mut buffer = [0; ::std::mem::size_of::<$type>()];
+                reader.read_exact(&mut buffer)?;
+                Ok(<$type>::from_le_bytes(buffer))
+            }
+        }
+    )*};
+}
+
+number_codecs! {
+    u8 => 4, u16 => 5, u32 => 6, u64 => 7,
+    i8 => 9, i16 => 10, i32 => 11, i64 => 12,
+    f32 => 14, f64 => 15
+}
+
+impl Codec for bool {
+    const TAG: u8 = 1;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u8).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(invalid(format!("invalid bool {}", byte))),
+        }
+    }
+}
+
+impl Codec for char {
+    const TAG: u8 = 2;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u32).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let code = u32::decode(reader)?;
+        ::std::char::from_u32(code).ok_or_else(|| invalid(format!("invalid char {:#x}", code)))
+    }
+}
+
+/// Strings are written as their length in bytes, eight bytes long,
+/// followed by their UTF-8 encoding.
+impl Codec for String {
+    const TAG: u8 = 3;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = u64::decode(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(|_| invalid("invalid UTF-8 in string"))
+    }
+}
+
+/// `usize` is always written as eight bytes, and reading a value
+/// which doesn't fit fails.
+impl Codec for usize {
+    const TAG: u8 = 8;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u64).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        usize::try_from(u64::decode(reader)?).map_err(|_| invalid("usize out of range"))
+    }
+}
+
+/// `isize` is always written as eight bytes, and reading a value
+/// which doesn't fit fails.
+impl Codec for isize {
+    const TAG: u8 = 13;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as i64).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        isize::try_from(i64::decode(reader)?).map_err(|_| invalid("isize out of range"))
+    }
+}
+
+// Checksums
+
+struct Checksummed<S> {
+    inner: S,
+    hash: u64,
+}
+
+impl<S> Checksummed<S> {
+    fn new(inner: S) -> Self {
+        Checksummed {
+            inner,
+            hash: FNV_OFFSET,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash = (self.hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+impl<W: Write> Checksummed<W> {
+    fn finish(mut self) -> io::Result<()> {
+        let hash = self.hash;
+        hash.encode(&mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Checksummed<R> {
+    fn verify(mut self) -> io::Result<()> {
+        let hash = self.hash;
+        if u64::decode(&mut self.inner)? != hash {
+            return Err(invalid("snapshot checksum mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn write_snapshot<W, F>(writer: W, header: Header, body: F) -> io::Result<()>
+where
+    W: Write,
+    F: FnOnce(&mut Checksummed<W>) -> io::Result<()>,
+{
+    let mut writer = Checksummed::new(writer);
+    header.write(&mut writer)?;
+    body(&mut writer)?;
+    writer.finish()
+}
+
+fn read_snapshot<R, F, T>(reader: R, expected: Header, body: F) -> io::Result<T>
+where
+    R: Read,
+    F: FnOnce(&mut Checksummed<R>, usize) -> io::Result<T>,
+{
+    let mut reader = Checksummed::new(reader);
+    let len = Header::read(&mut reader)?.expect(expected)?;
+    let result = body(&mut reader, len)?;
+    reader.verify()?;
+    Ok(result)
+}
+
+fn check_len(len: usize, expected: usize) -> io::Result<()> {
+    if len != expected {
+        return Err(invalid("duplicate elements in snapshot"));
+    }
+    Ok(())
+}
+
+fn check_order<A: Ord>(previous: &Option<Arc<A>>, next: &A) -> io::Result<()> {
+    match *previous {
+        Some(ref previous) if previous.as_ref().cmp(next) != Ordering::Less => {
+            Err(invalid("snapshot elements out of order"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Collections which can be saved to and restored from a snapshot.
+///
+/// Neither method buffers its input or output, so you'll usually
+/// want to pass them a [`BufWriter`][BufWriter] or a
+/// [`BufReader`][BufReader].
+///
+/// [BufWriter]: https://doc.rust-lang.org/std/io/struct.BufWriter.html
+/// [BufReader]: https://doc.rust-lang.org/std/io/struct.BufReader.html
+pub trait Snapshot: Sized {
+    /// Write a snapshot of the collection.
+    ///
+    /// Time: O(n)
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()>;
+
+    /// Read a collection back from a snapshot.
+    ///
+    /// Time: O(n) for lists and ordered collections, O(n log n) for
+    /// hashed collections
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self>;
+}
+
+impl<A: Codec> Snapshot for ConsList<A> {
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::ConsList, A::TAG, 0, self.len());
+        write_snapshot(writer, header, |writer| {
+            for item in self.iter() {
+                item.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::ConsList, A::TAG, 0, 0);
+        read_snapshot(reader, header, |reader, len| {
+            // Cons lists can only be built from the back, so we
+            // build one in reverse and turn it around.
+            let mut list = ConsList::new();
+            for _ in 0..len {
+                list = list.cons(A::decode(reader)?);
+            }
+            Ok(list.reverse())
+        })
+    }
+}
+
+impl<A: Codec> Snapshot for CatList<A> {
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::CatList, A::TAG, 0, self.len());
+        write_snapshot(writer, header, |writer| {
+            for item in self.iter() {
+                item.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::CatList, A::TAG, 0, 0);
+        read_snapshot(reader, header, |reader, len| {
+            let mut list = CatList::new();
+            for _ in 0..len {
+                list.push_back_mut(A::decode(reader)?);
+            }
+            Ok(list)
+        })
+    }
+}
+
+impl<A: Codec> Snapshot for Vector<A> {
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::Vector, A::TAG, 0, self.len());
+        write_snapshot(writer, header, |writer| {
+            for item in self.iter() {
+                item.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::Vector, A::TAG, 0, 0);
+        read_snapshot(reader, header, |reader, len| {
+            let mut vector = Vector::new();
+            for _ in 0..len {
+                vector.push_back_mut(A::decode(reader)?);
+            }
+            Ok(vector)
+        })
+    }
+}
+
+impl<A: Codec + Ord> Snapshot for OrdSet<A> {
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::OrdSet, A::TAG, 0, self.len());
+        write_snapshot(writer, header, |writer| {
+            for item in self.iter() {
+                item.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::OrdSet, A::TAG, 0, 0);
+        read_snapshot(reader, header, |reader, len| {
+            let mut previous = None;
+            let mut next = || -> io::Result<_> {
+                let item = A::decode(reader)?;
+                check_order(&previous, &item)?;
+                let item = Arc::new(item);
+                previous = Some(item.clone());
+                Ok(item)
+            };
+            match Node::from_sorted(len, &mut next) {
+                None => Err(invalid("snapshot too large")),
+                Some(root) => Ok(OrdSet::from_root(root?)),
+            }
+        })
+    }
+}
+
+impl<K: Codec + Ord, V: Codec> Snapshot for OrdMap<K, V> {
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::OrdMap, K::TAG, V::TAG, self.len());
+        write_snapshot(writer, header, |writer| {
+            for (key, value) in self.iter() {
+                key.encode(writer)?;
+                value.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::OrdMap, K::TAG, V::TAG, 0);
+        read_snapshot(reader, header, |reader, len| {
+            let mut previous = None;
+            let mut next = || -> io::Result<_> {
+                let key = K::decode(reader)?;
+                check_order(&previous, &key)?;
+                let key = Arc::new(key);
+                previous = Some(key.clone());
+                Ok((key, Arc::new(V::decode(reader)?)))
+            };
+            match Node::from_sorted(len, &mut next) {
+                None => Err(invalid("snapshot too large")),
+                Some(root) => Ok(OrdMap::from_root(root?)),
+            }
+        })
+    }
+}
+
+impl<A, S> Snapshot for HashSet<A, S>
+where
+    A: Codec + Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::HashSet, A::TAG, 0, self.len());
+        write_snapshot(writer, header, |writer| {
+            for item in self.iter() {
+                item.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::HashSet, A::TAG, 0, 0);
+        read_snapshot(reader, header, |reader, len| {
+            let mut set = HashSet::default();
+            for _ in 0..len {
+                set.insert_mut(A::decode(reader)?);
+            }
+            check_len(set.len(), len)?;
+            Ok(set)
+        })
+    }
+}
+
+impl<K, V, S> Snapshot for HashMap<K, V, S>
+where
+    K: Codec + Hash + Eq,
+    V: Codec,
+    S: BuildHasher + Default,
+{
+    fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let header = Header::new(Kind::HashMap, K::TAG, V::TAG, self.len());
+        write_snapshot(writer, header, |writer| {
+            for (key, value) in self.iter() {
+                key.encode(writer)?;
+                value.encode(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_snapshot<R: Read>(reader: R) -> io::Result<Self> {
+        let header = Header::new(Kind::HashMap, K::TAG, V::TAG, 0);
+        read_snapshot(reader, header, |reader, len| {
+            let mut map = HashMap::default();
+            for _ in 0..len {
+                let key = K::decode(reader)?;
+                map.insert_mut(key, V::decode(reader)?);
+            }
+            check_len(map.len(), len)?;
+            Ok(map)
+        })
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use catlist::proptest::catlist;
+    use conslist::proptest::conslist;
+    use hashmap::proptest::hash_map;
+    use hashset::proptest::hash_set;
+    use ordmap::proptest::ord_map;
+    use ordset::proptest::ord_set;
+    use proptest::collection::vec;
+    use proptest::num::{i16, i32};
+    use std::fmt::Debug;
+    use vector::proptest::vector;
+
+    fn round_trip<C: Snapshot + PartialEq + Debug>(collection: &C) {
+        let mut buffer = Vec::new();
+        collection.write_snapshot(&mut buffer).unwrap();
+        let restored = C::read_snapshot(&buffer[..]).unwrap();
+        assert_eq!(collection, &restored);
+    }
+
+    fn snapshot<C: Snapshot>(collection: &C) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        collection.write_snapshot(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn reseal(buffer: &mut Vec<u8>) {
+        let body = buffer.len() - 8;
+        buffer.truncate(body);
+        let mut hasher = Checksummed::new(());
+        hasher.update(buffer);
+        let hash = hasher.hash;
+        hash.encode(buffer).unwrap();
+    }
+
+    #[test]
+    fn header() {
+        let buffer = snapshot(&ordmap!{1u8 => "one".to_string()});
+        assert_eq!(
+            Header {
+                kind: Kind::OrdMap,
+                key_codec: 4,
+                value_codec: 3,
+                len: 1,
+            },
+            Header::read(&mut &buffer[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn strings_and_chars() {
+        round_trip(&vector!["".to_string(), "héllo".to_string()]);
+        round_trip(&ordset!['a', 'é', '\u{1f600}']);
+        round_trip(&hashmap!{true => 1.5f64, false => -0.25});
+    }
+
+    #[test]
+    fn large_ordmap() {
+        let map: OrdMap<u32, u64> = (0..100_000).map(|i| (i, u64::from(i) * 3)).collect();
+        let buffer = snapshot(&map);
+        assert_eq!(8 + 8 + 100_000 * 12 + 8, buffer.len());
+        let restored = OrdMap::<u32, u64>::read_snapshot(&buffer[..]).unwrap();
+        assert!(map == restored);
+        assert_eq!(map.len(), restored.len());
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let buffer = snapshot(&vector![1i32, 2, 3]);
+        for index in 0..buffer.len() {
+            let mut corrupt = buffer.clone();
+            corrupt[index] ^= 0x10;
+            assert!(Vector::<i32>::read_snapshot(&corrupt[..]).is_err());
+        }
+        for len in 0..buffer.len() {
+            assert!(Vector::<i32>::read_snapshot(&buffer[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_impossible_lengths() {
+        // A header claiming `u64::MAX` elements, and nothing after it.
+        let mut buffer = b"imsn".to_vec();
+        buffer.extend_from_slice(&[VERSION, Kind::OrdSet as u8, i32::TAG, 0]);
+        buffer.extend_from_slice(&[0xff; 8]);
+        assert_eq!(16, buffer.len());
+        let error = OrdSet::<i32>::read_snapshot(&buffer[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        buffer[5] = Kind::OrdMap as u8;
+        buffer[7] = i32::TAG;
+        let error = OrdMap::<i32, i32>::read_snapshot(&buffer[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+        // A length a tree could hold, but far more than the input has.
+        buffer.truncate(8);
+        (1u64 << 40).encode(&mut buffer).unwrap();
+        2i32.encode(&mut buffer).unwrap();
+        let error = OrdMap::<i32, i32>::read_snapshot(&buffer[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+        buffer[5] = Kind::OrdSet as u8;
+        buffer[7] = 0;
+        let error = OrdSet::<i32>::read_snapshot(&buffer[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    }
+
+    #[test]
+    fn rejects_wrong_type() {
+        let buffer = snapshot(&vector![1i32, 2, 3]);
+        assert!(Vector::<u32>::read_snapshot(&buffer[..]).is_err());
+        assert!(CatList::<i32>::read_snapshot(&buffer[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_elements() {
+        // An ordered set with its elements out of order.
+        let mut buffer = snapshot(&vector![2i32, 1]);
+        buffer[5] = Kind::OrdSet as u8;
+        reseal(&mut buffer);
+        let error = OrdSet::<i32>::read_snapshot(&buffer[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+        // A hash set with a duplicate element.
+        let mut buffer = snapshot(&vector![1i32, 1]);
+        buffer[5] = Kind::HashSet as u8;
+        reseal(&mut buffer);
+        let error = HashSet::<i32>::read_snapshot(&buffer[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    proptest! {
+        #[test]
+        fn conslist_round_trip(ref list in conslist(i32::ANY, 0..100)) {
+            round_trip(list);
+        }
+
+        #[test]
+        fn catlist_round_trip(ref list in catlist(i32::ANY, 0..100)) {
+            round_trip(list);
+        }
+
+        #[test]
+        fn vector_round_trip(ref vec in vector(i32::ANY, 0..1000)) {
+            round_trip(vec);
+        }
+
+        #[test]
+        fn ordset_round_trip(ref set in ord_set(i32::ANY, 0..1000)) {
+            round_trip(set);
+        }
+
+        #[test]
+        fn ordmap_round_trip(ref map in ord_map(i32::ANY, i32::ANY, 0..1000)) {
+            round_trip(map);
+        }
+
+        #[test]
+        fn hashset_round_trip(ref set in hash_set(i32::ANY, 0..1000)) {
+            round_trip(set);
+        }
+
+        #[test]
+        fn hashmap_round_trip(ref map in hash_map(i32::ANY, i32::ANY, 0..1000)) {
+            round_trip(map);
+        }
+
+        #[test]
+        fn bulk_loaded_ordmap_updates(size in 0..5000i16, ref edits in vec(i16::ANY, 0..500)) {
+            let map: OrdMap<i16, i16> = (0..size).map(|i| (i, i)).collect();
+            let mut loaded = OrdMap::<i16, i16>::read_snapshot(&snapshot(&map)[..]).unwrap();
+            let mut model = ::std::collections::BTreeMap::new();
+            model.extend((0..size).map(|i| (i, i)));
+            for &edit in edits {
+                if edit % 2 == 0 {
+                    loaded.remove_mut(&(edit % size.max(1)));
+                    model.remove(&(edit % size.max(1)));
+                } else {
+                    loaded.insert_mut(edit, edit);
+                    model.insert(edit, edit);
+                }
+                assert_eq!(model.len(), loaded.len());
+            }
+            assert!(model.into_iter().eq(loaded.iter().map(|(k, v)| (*k, *v))));
+        }
+    }
+}
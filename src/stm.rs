@@ -0,0 +1,483 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Software transactional memory over persistent values.
+//!
+//! An [`Atom`][atom::Atom] can only update a single value atomically.
+//! When several values have to change together, put each of them in
+//! a [`TVar`][TVar], and read and write them inside a transaction
+//! run by [`atomically`][atomically]. Every transaction sees the
+//! values as they were at a single point in time, and its writes
+//! become visible to other threads all at once when it commits, or
+//! not at all.
+//!
+//! Transactions are optimistic: nothing is locked while one runs.
+//! When it commits, the values it read are checked to make sure no
+//! other transaction has changed them in the meantime, and if one
+//! has, or if a value it tries to read has changed since it started,
+//! it's thrown away and run again from the start. The function you
+//! pass to [`atomically`][atomically] should therefore have no side
+//! effects besides its reads and writes of `TVar`s, and should pass
+//! on the [`Conflict`][Conflict] errors returned by them with `?`.
+//!
+//! Reading a `TVar` gives you an `Arc` of its value, so reads never
+//! copy anything, and since the collections in this crate share
+//! structure between versions, writing back a changed copy of one
+//! only costs what the change itself does.
+//!
+//! This module is only available with the `std` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::stm::{atomically, TVar};
+//! # fn main() {
+//! let stock = TVar::new(ordmap!{"apple" => 3, "pear" => 1});
+//! let basket = TVar::new(ordmap!{});
+//! atomically(|tx| {
+//!     let apples = *tx.read(&stock)?.get(&"apple").unwrap();
+//!     tx.modify(&stock, |stock| stock.remove(&"apple"))?;
+//!     tx.modify(&basket, |basket| basket.insert("apple", apples))?;
+//!     Ok(())
+//! });
+//! assert_eq!(ordmap!{"pear" => 1}, *stock.load());
+//! assert_eq!(ordmap!{"apple" => 3}, *basket.load());
+//! # }
+//! ```
+//!
+//! [atom::Atom]: ../atom/struct.Atom.html
+//! [TVar]: ./struct.TVar.html
+//! [atomically]: ./fn.atomically.html
+//! [Conflict]: ./struct.Conflict.html
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use shared::Shared;
+
+// The version of the most recent commit.
+static CLOCK: AtomicUsize = AtomicUsize::new(0);
+
+// Held while a transaction commits, so that commits happen one at a
+// time.
+static COMMIT: Mutex<()> = Mutex::new(());
+
+// The ids given to `TVar`s, to order them within a transaction.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Nothing panics while holding one of this module's locks, but if a
+// thread panics in the middle of a commit anyway, the values it's
+// protecting are still consistent.
+fn lock<A>(mutex: &Mutex<A>) -> MutexGuard<'_, A> {
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+struct Slot<T> {
+    value: Arc<T>,
+    // The version of the commit which stored `value`.
+    version: usize,
+    // Set while a commit is writing to this slot.
+    locked: bool,
+}
+
+struct Cell<T> {
+    id: usize,
+    slot: Mutex<Slot<T>>,
+}
+
+/// A transactional variable, holding a value which can be read and
+/// written inside a transaction.
+///
+/// Cloning a `TVar` gives you another reference to the same
+/// variable.
+pub struct TVar<T> {
+    cell: Arc<Cell<T>>,
+}
+
+impl<T> TVar<T> {
+    /// Construct a variable holding `value`.
+    pub fn new<R>(value: R) -> Self
+    where
+        R: Shared<T>,
+    {
+        TVar {
+            cell: Arc::new(Cell {
+                id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+                slot: Mutex::new(Slot {
+                    value: value.shared(),
+                    version: 0,
+                    locked: false,
+                }),
+            }),
+        }
+    }
+
+    /// Get the current value, outside of a transaction.
+    pub fn load(&self) -> Arc<T> {
+        self.read().1
+    }
+
+    /// Test whether two `TVar`s refer to the same variable.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cell, &other.cell)
+    }
+
+    // Read the version and the value, waiting for any commit writing
+    // to the variable to finish.
+    fn read(&self) -> (usize, Arc<T>) {
+        loop {
+            {
+                let slot = lock(&self.cell.slot);
+                if !slot.locked {
+                    return (slot.version, slot.value.clone());
+                }
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+impl<T> Clone for TVar<T> {
+    fn clone(&self) -> Self {
+        TVar {
+            cell: self.cell.clone(),
+        }
+    }
+}
+
+impl<T: Debug> Debug for TVar<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("TVar").field(&self.load()).finish()
+    }
+}
+
+/// The error returned when a transaction has to be run again,
+/// because a variable it read has been changed by another
+/// transaction.
+///
+/// Pass it on from the function given to [`atomically`][atomically]
+/// with `?`, and [`atomically`][atomically] will take care of
+/// running the transaction again.
+///
+/// [atomically]: ./fn.atomically.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Conflict;
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a transactional variable changed during the transaction")
+    }
+}
+
+impl Error for Conflict {}
+
+// A variable read or written by a transaction, with the value read
+// or to be written.
+struct Access<T> {
+    var: TVar<T>,
+    version: usize,
+    value: Arc<T>,
+}
+
+// The type erased view of an `Access` used when committing.
+trait Logged {
+    // Whether the variable has changed since it was read.
+    fn changed(&self) -> bool;
+    fn lock(&self);
+    fn publish(&self, version: usize);
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Send + Sync + 'static> Logged for Access<T> {
+    fn changed(&self) -> bool {
+        lock(&self.var.cell.slot).version != self.version
+    }
+
+    fn lock(&self) {
+        lock(&self.var.cell.slot).locked = true;
+    }
+
+    fn publish(&self, version: usize) {
+        let mut slot = lock(&self.var.cell.slot);
+        slot.value = self.value.clone();
+        slot.version = version;
+        slot.locked = false;
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A transaction in progress.
+///
+/// This is passed to the function given to
+/// [`atomically`][atomically], which reads and writes variables
+/// through it.
+///
+/// [atomically]: ./fn.atomically.html
+pub struct Transaction {
+    // The version of the most recent commit when the transaction
+    // started: every value it reads has to be from then or earlier.
+    start: usize,
+    reads: BTreeMap<usize, Box<dyn Logged>>,
+    writes: BTreeMap<usize, Box<dyn Logged>>,
+}
+
+impl Transaction {
+    fn new() -> Self {
+        Transaction {
+            start: CLOCK.load(Ordering::SeqCst),
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Read a variable.
+    ///
+    /// This gives you the value written by this transaction, if
+    /// there is one, or else the value the variable had when the
+    /// transaction started. Fails with a [`Conflict`][Conflict] if
+    /// another transaction has changed the variable since then.
+    ///
+    /// [Conflict]: ./struct.Conflict.html
+    pub fn read<T>(&mut self, var: &TVar<T>) -> Result<Arc<T>, Conflict>
+    where
+        T: Send + Sync + 'static,
+    {
+        let id = var.cell.id;
+        if let Some(access) = self.writes.get(&id).or_else(|| self.reads.get(&id)) {
+            return Ok(Transaction::value(&**access));
+        }
+        let (version, value) = var.read();
+        if version > self.start {
+            return Err(Conflict);
+        }
+        self.reads.insert(
+            id,
+            Box::new(Access {
+                var: var.clone(),
+                version,
+                value: value.clone(),
+            }),
+        );
+        Ok(value)
+    }
+
+    /// Write a variable. Nothing else can see the new value until
+    /// the transaction commits.
+    pub fn write<T, R>(&mut self, var: &TVar<T>, value: R)
+    where
+        T: Send + Sync + 'static,
+        R: Shared<T>,
+    {
+        self.writes.insert(
+            var.cell.id,
+            Box::new(Access {
+                var: var.clone(),
+                version: 0,
+                value: value.shared(),
+            }),
+        );
+    }
+
+    /// Replace the value of a variable with the result of calling
+    /// `f` on it, and return the new value.
+    pub fn modify<T, F, R>(&mut self, var: &TVar<T>, f: F) -> Result<Arc<T>, Conflict>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(&T) -> R,
+        R: Shared<T>,
+    {
+        let value = f(&*self.read(var)?).shared();
+        self.write(var, value.clone());
+        Ok(value)
+    }
+
+    fn value<T: 'static>(access: &dyn Logged) -> Arc<T> {
+        access
+            .as_any()
+            .downcast_ref::<Access<T>>()
+            .expect("stm::Transaction: variable id shared between types")
+            .value
+            .clone()
+    }
+
+    // Publish the writes, unless one of the variables read has
+    // changed since it was read. Returns whether it committed.
+    fn commit(self) -> bool {
+        if self.writes.is_empty() {
+            // Everything read was current as of `start`.
+            return true;
+        }
+        let _commit = lock(&COMMIT);
+        if self.reads.values().any(|access| access.changed()) {
+            return false;
+        }
+        // Readers wait for locked variables, so none of them can see
+        // some of these writes and not others.
+        for access in self.writes.values() {
+            access.lock();
+        }
+        let version = CLOCK.fetch_add(1, Ordering::SeqCst) + 1;
+        for access in self.writes.values() {
+            access.publish(version);
+        }
+        true
+    }
+}
+
+/// Run `f` as a transaction, running it again until it completes
+/// without conflicting with another transaction, and return its
+/// result.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::stm::{atomically, TVar};
+/// # use std::sync::Arc;
+/// # use std::thread;
+/// # fn main() {
+/// let from = TVar::new(100);
+/// let to = TVar::new(0);
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let (from, to) = (from.clone(), to.clone());
+///         thread::spawn(move || {
+///             for _ in 0..10 {
+///                 atomically(|tx| {
+///                     tx.modify(&from, |n| n - 1)?;
+///                     tx.modify(&to, |n| n + 1)?;
+///                     Ok(())
+///                 });
+///             }
+///         })
+///     })
+///     .collect();
+/// for thread in threads {
+///     thread.join().unwrap();
+/// }
+/// assert_eq!((60, 40), (*from.load(), *to.load()));
+/// # }
+/// ```
+pub fn atomically<F, A>(mut f: F) -> A
+where
+    F: FnMut(&mut Transaction) -> Result<A, Conflict>,
+{
+    loop {
+        let mut tx = Transaction::new();
+        if let Ok(result) = f(&mut tx) {
+            if tx.commit() {
+                return result;
+            }
+        }
+        thread::yield_now();
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hashmap::HashMap;
+    use ordmap::OrdMap;
+
+    #[test]
+    fn reads_own_writes() {
+        let var = TVar::new(vector![1]);
+        let other = var.clone();
+        let result = atomically(|tx| {
+            tx.modify(&var, |vec| vec.push_back(2))?;
+            let seen = tx.read(&other)?;
+            assert_eq!(vector![1], *var.load());
+            Ok(seen)
+        });
+        assert_eq!(vector![1, 2], *result);
+        assert_eq!(vector![1, 2], *var.load());
+        assert!(var.ptr_eq(&other));
+    }
+
+    #[test]
+    fn conflicts_retry() {
+        let var = TVar::new(0);
+        let mut runs = 0;
+        atomically(|tx| {
+            runs += 1;
+            let value = tx.read(&var)?;
+            if runs == 1 {
+                // Another transaction changes the variable after it
+                // was read.
+                atomically(|tx| {
+                    tx.write(&var, 10);
+                    Ok(())
+                });
+            }
+            tx.write(&var, *value + 1);
+            Ok(())
+        });
+        assert_eq!(2, runs);
+        assert_eq!(11, *var.load());
+    }
+
+    #[test]
+    fn stale_reads_conflict() {
+        let var = TVar::new(0);
+        let mut tx = Transaction::new();
+        atomically(|tx| {
+            tx.write(&var, 1);
+            Ok(())
+        });
+        assert_eq!(Err(Conflict), tx.read(&var).map(|_| ()));
+    }
+
+    #[test]
+    fn transfers_keep_totals() {
+        let accounts: Vec<TVar<OrdMap<usize, i64>>> = (0..4)
+            .map(|_| TVar::new((0..10).map(|i| (i, 100)).collect::<OrdMap<_, _>>()))
+            .collect();
+        let log = TVar::new(HashMap::<(usize, usize), usize>::new());
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let accounts = accounts.clone();
+                let log = log.clone();
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        let from = &accounts[(thread + i) % 4];
+                        let to = &accounts[(thread + i + 1) % 4];
+                        let key = i % 10;
+                        atomically(|tx| {
+                            tx.modify(from, |map| map.update(&key, |n| Some(Arc::new(*n - 1))))?;
+                            tx.modify(to, |map| map.update(&key, |n| Some(Arc::new(*n + 1))))?;
+                            tx.modify(&log, |log| log.insert((thread, i), key))?;
+                            // Every snapshot is consistent.
+                            let mut total = 0;
+                            for account in &accounts {
+                                total += tx.read(account)?.values().map(|n| *n).sum::<i64>();
+                            }
+                            assert_eq!(4000, total);
+                            Ok(())
+                        });
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let total: i64 = accounts
+            .iter()
+            .map(|account| account.load().values().map(|n| *n).sum::<i64>())
+            .sum();
+        assert_eq!(4000, total);
+        assert_eq!(1600, log.load().len());
+    }
+}
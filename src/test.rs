@@ -2,6 +2,9 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::cell::Cell;
+use std::cmp::max;
+use std::collections::{BTreeMap, BTreeSet};
 use std::hash::Hasher;
 
 pub fn is_sorted<A, I>(l: I) -> bool
@@ -51,3 +54,83 @@ impl Default for LolHasher {
         LolHasher { state: 0, shift: 0 }
     }
 }
+
+// A hasher which only produces a handful of different hashes, to
+// force lots of hash collisions.
+#[derive(Default)]
+pub struct CollidingHasher(u64);
+
+impl Hasher for CollidingHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = self.0.wrapping_add(u64::from(*byte));
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0 & 0x3
+    }
+}
+
+thread_local!(static COMPARISONS: Cell<usize> = Cell::new(0));
+
+// A value which counts how many times values of its type have been
+// compared for equality on the current thread.
+#[derive(Clone, Debug)]
+pub struct Counted(pub usize);
+
+impl Counted {
+    pub fn comparisons() -> usize {
+        COMPARISONS.with(|count| count.replace(0))
+    }
+}
+
+impl PartialEq for Counted {
+    fn eq(&self, other: &Self) -> bool {
+        COMPARISONS.with(|count| count.set(count.get() + 1));
+        self.0 == other.0
+    }
+}
+
+// An edit to a map: set a key to a value, or remove it if the flag is
+// set.
+pub type Edit = (u8, i16, bool);
+
+pub fn edit_model(map: &BTreeMap<u8, i16>, edits: &[Edit]) -> BTreeMap<u8, i16> {
+    let mut map = map.clone();
+    for &(key, value, remove) in edits {
+        if remove {
+            map.remove(&key);
+        } else {
+            map.insert(key, value);
+        }
+    }
+    map
+}
+
+// A three way merge done key by key, resolving conflicts by taking
+// the larger value, along with the number of conflicts.
+pub fn merge3_model(
+    base: &BTreeMap<u8, i16>,
+    ours: &BTreeMap<u8, i16>,
+    theirs: &BTreeMap<u8, i16>,
+) -> (BTreeMap<u8, i16>, usize) {
+    let keys: BTreeSet<u8> = base.keys().chain(ours.keys()).chain(theirs.keys()).cloned().collect();
+    let mut merged = BTreeMap::new();
+    let mut conflicts = 0;
+    for key in keys {
+        let (old, left, right) = (base.get(&key), ours.get(&key), theirs.get(&key));
+        let value = if left == right || right == old {
+            left
+        } else if left == old {
+            right
+        } else {
+            conflicts += 1;
+            max(left, right)
+        };
+        if let Some(value) = value {
+            merged.insert(key, *value);
+        }
+    }
+    (merged, conflicts)
+}
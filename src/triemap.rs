@@ -0,0 +1,834 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A persistent trie map.
+//!
+//! This is a compressed radix trie keyed by byte sequences: anything
+//! which implements `AsRef<[u8]>`, such as `String`, `&str` or
+//! `Vec<u8>`. Each node in the trie is labelled with a run of bytes
+//! rather than a single byte, so a chain of nodes with only one child
+//! each takes up a single node.
+//!
+//! Because keys which share a prefix share a path from the root, a
+//! trie can find every key starting with a given prefix, or the
+//! longest key which is a prefix of a given string, in time
+//! proportional to the length of the prefix rather than to the size
+//! of the map. This makes it a good fit for routing tables and
+//! autocompletion.
+//!
+//! Keys are compared by their bytes only, and iteration is in
+//! lexicographic byte order, which for strings is the same as their
+//! `Ord` order.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections;
+use std::fmt::{Debug, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, Sum};
+use std::ops::{Add, Index, IndexMut};
+use std::sync::Arc;
+use std::vec::Vec;
+
+use footprint::{Footprint, HeapSize};
+use nodes::trie::Node;
+use ordmap::OrdMap;
+use shared::Shared;
+
+pub use nodes::trie::Iter;
+
+/// Construct a trie map from a sequence of key/value pairs.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::triemap::TrieMap;
+/// # fn main() {
+/// assert_eq!(
+///   triemap!{"one" => 1, "two" => 2, "three" => 3},
+///   TrieMap::from(vec![("one", 1), ("two", 2), ("three", 3)])
+/// );
+/// # }
+/// ```
+#[macro_export]
+macro_rules! triemap {
+    () => { $crate::triemap::TrieMap::new() };
+
+    ( $( $key:expr => $value:expr ),* ) => {{
+        let mut map = $crate::triemap::TrieMap::new();
+        $({
+            map.insert_mut($key, $value);
+        })*;
+        map
+    }};
+}
+
+/// A persistent trie map.
+///
+/// A map from byte sequence keys to values, stored as a compressed
+/// radix trie, with structural sharing between versions.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::triemap::TrieMap;
+/// # use std::sync::Arc;
+/// # fn main() {
+/// let routes = triemap!{
+///     "/" => "index",
+///     "/users" => "users",
+///     "/users/admin" => "admin",
+///     "/posts" => "posts"
+/// };
+/// assert_eq!(
+///     Some(Arc::new("users")),
+///     routes.longest_prefix_match("/users/bob").map(|(_, v)| v)
+/// );
+/// let users: Vec<_> = routes.iter_prefix("/users").map(|(k, _)| *k).collect();
+/// assert_eq!(vec!["/users", "/users/admin"], users);
+/// # }
+/// ```
+pub struct TrieMap<K, V> {
+    root: Arc<Node<K, V>>,
+    size: usize,
+}
+
+impl<K, V> TrieMap<K, V> {
+    /// Construct an empty trie map.
+    #[inline]
+    pub fn new() -> Self {
+        TrieMap {
+            root: Default::default(),
+            size: 0,
+        }
+    }
+
+    /// Test whether a trie map is empty.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Test whether two maps refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a map to
+    /// itself, or if you're comparing a map to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    /// Get the number of key/value pairs in a trie map.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Get an iterator over the key/value pairs of a trie map, in
+    /// lexicographic order of their keys.
+    #[inline]
+    pub fn iter(&self) -> Iter<K, V> {
+        Node::iter(&self.root)
+    }
+
+    /// Get an iterator over the keys of a trie map, in lexicographic
+    /// order.
+    #[inline]
+    pub fn keys(&self) -> Keys<K, V> {
+        Keys { it: self.iter() }
+    }
+
+    /// Get an iterator over the values of a trie map, in
+    /// lexicographic order of their keys.
+    #[inline]
+    pub fn values(&self) -> Values<K, V> {
+        Values { it: self.iter() }
+    }
+}
+
+impl<K: AsRef<[u8]>, V> TrieMap<K, V> {
+    /// Construct a trie map with a single mapping.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate im;
+    /// # use im::triemap::TrieMap;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let map = TrieMap::singleton("hello", 123);
+    /// assert_eq!(Some(Arc::new(123)), map.get("hello"));
+    /// # }
+    /// ```
+    #[inline]
+    pub fn singleton<RK, RV>(k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        TrieMap::new().insert(k, v)
+    }
+
+    /// Get the value for a key from a trie map.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn get<BK>(&self, k: &BK) -> Option<Arc<V>>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.root.lookup(k.as_ref()).map(|(_, v)| v.clone())
+    }
+
+    /// Get the key/value pair for a key from a trie map.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn get_key_value<BK>(&self, k: &BK) -> Option<(Arc<K>, Arc<V>)>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.root.lookup(k.as_ref()).cloned()
+    }
+
+    /// Test for the presence of a key in a trie map.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn contains_key<BK>(&self, k: &BK) -> bool
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.root.lookup(k.as_ref()).is_some()
+    }
+
+    /// Insert a key/value mapping into a trie map.
+    ///
+    /// If the map already has a mapping for the given key, the
+    /// previous value is overwritten.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn insert<RK, RV>(&self, k: RK, v: RV) -> Self
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let mut out = self.clone();
+        out.insert_mut(k, v);
+        out
+    }
+
+    /// Insert a key/value mapping into a trie map, mutating it in
+    /// place when it is safe to do so.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn insert_mut<RK, RV>(&mut self, k: RK, v: RV)
+    where
+        RK: Shared<K>,
+        RV: Shared<V>,
+    {
+        let k = k.shared();
+        let root = Arc::make_mut(&mut self.root);
+        if root.insert((*k).as_ref(), (k.clone(), v.shared())).is_none() {
+            self.size += 1;
+        }
+    }
+
+    /// Remove a key/value pair from a trie map, if it exists.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn remove<BK>(&self, k: &BK) -> Self
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.pop(k).map(|(_, m)| m).unwrap_or_else(|| self.clone())
+    }
+
+    /// Remove a key/value pair from a trie map, if it exists,
+    /// mutating it in place when it is safe to do so.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(k) where k = the length of the key
+    #[inline]
+    pub fn remove_mut<BK>(&mut self, k: &BK)
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.pop_with_key_mut(k);
+    }
+
+    /// Remove a key/value pair from a trie map, if it exists, and
+    /// return the removed value as well as the updated map.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn pop<BK>(&self, k: &BK) -> Option<(Arc<V>, Self)>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        let mut out = self.clone();
+        out.pop_mut(k).map(|v| (v, out))
+    }
+
+    /// Remove a key/value pair from a trie map, if it exists, and
+    /// return the removed value.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn pop_mut<BK>(&mut self, k: &BK) -> Option<Arc<V>>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.pop_with_key_mut(k).map(|(_, v)| v)
+    }
+
+    /// Remove a key/value pair from a trie map, if it exists, and
+    /// return the removed key and value.
+    ///
+    /// This is a copy-on-write operation, so that the parts of the
+    /// map's structure which are shared with other maps will be
+    /// safely copied before mutating.
+    ///
+    /// Time: O(k) where k = the length of the key
+    pub fn pop_with_key_mut<BK>(&mut self, k: &BK) -> Option<(Arc<K>, Arc<V>)>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        if !self.contains_key(k) {
+            return None;
+        }
+        let removed = Arc::make_mut(&mut self.root).remove(k.as_ref());
+        self.size -= 1;
+        removed
+    }
+
+    /// Get an iterator over every key/value pair whose key starts
+    /// with `prefix`, in lexicographic order of their keys.
+    ///
+    /// Time: O(p) to find the first pair, where p = the length of
+    /// the prefix
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::triemap::TrieMap;
+    /// # fn main() {
+    /// let words = triemap!{"car" => 1, "cart" => 2, "cat" => 3, "dog" => 4};
+    /// let found: Vec<_> = words.iter_prefix("car").map(|(k, _)| *k).collect();
+    /// assert_eq!(vec!["car", "cart"], found);
+    /// # }
+    /// ```
+    pub fn iter_prefix<BK>(&self, prefix: &BK) -> Iter<K, V>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        match Node::subtree(&self.root, prefix.as_ref()) {
+            None => Iter::empty(),
+            Some(node) => Node::iter(&node),
+        }
+    }
+
+    /// Construct a trie map of every key/value pair whose key starts
+    /// with `prefix`.
+    ///
+    /// The new map shares the subtree holding the matching pairs with
+    /// this one, rather than copying them. Counting them is what takes
+    /// O(m) time.
+    ///
+    /// Time: O(p + m) where p = the length of the prefix and m = the
+    /// number of matching pairs
+    pub fn filter_prefix<BK>(&self, prefix: &BK) -> Self
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        match Node::restrict(&self.root, prefix.as_ref()) {
+            None => TrieMap::new(),
+            Some(ref root) if Arc::ptr_eq(root, &self.root) => self.clone(),
+            Some(root) => TrieMap {
+                size: root.count(),
+                root,
+            },
+        }
+    }
+
+    /// Find the key/value pair with the longest key which is a prefix
+    /// of `key`, including `key` itself.
+    ///
+    /// Time: O(k) where k = the length of the key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::triemap::TrieMap;
+    /// # use std::sync::Arc;
+    /// # fn main() {
+    /// let map = triemap!{"a" => 1, "abc" => 2};
+    /// assert_eq!(Some(Arc::new(1)), map.longest_prefix_match("ab").map(|(_, v)| v));
+    /// assert_eq!(Some(Arc::new(2)), map.longest_prefix_match("abcd").map(|(_, v)| v));
+    /// assert_eq!(None, map.longest_prefix_match("b"));
+    /// # }
+    /// ```
+    pub fn longest_prefix_match<BK>(&self, key: &BK) -> Option<(Arc<K>, Arc<V>)>
+    where
+        BK: AsRef<[u8]> + ?Sized,
+    {
+        self.root.longest_prefix(key.as_ref()).cloned()
+    }
+
+    /// Construct the union of two trie maps, keeping the values in
+    /// the current map when keys exist in both maps.
+    pub fn union<RM>(&self, other: RM) -> Self
+    where
+        RM: Borrow<Self>,
+    {
+        let mut out = self.clone();
+        for (k, v) in other.borrow() {
+            if !out.contains_key(&*k) {
+                out.insert_mut(k, v);
+            }
+        }
+        out
+    }
+}
+
+// Core traits
+
+impl<K, V> Clone for TrieMap<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        TrieMap {
+            root: self.root.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl<K, V> Default for TrieMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        TrieMap::new()
+    }
+}
+
+impl<K, V> HeapSize for TrieMap<K, V> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        Node::measure_heap(&self.root, footprint)
+    }
+}
+
+impl<K: PartialEq, V: PartialEq> PartialEq for TrieMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || (self.len() == other.len() && self.iter().eq(other.iter()))
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for TrieMap<K, V> {}
+
+impl<K: PartialOrd, V: PartialOrd> PartialOrd for TrieMap<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K: Ord, V: Ord> Ord for TrieMap<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K: Hash, V: Hash> Hash for TrieMap<K, V> {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        for i in self.iter() {
+            i.hash(state);
+        }
+    }
+}
+
+impl<K: AsRef<[u8]>, V> Add for &TrieMap<K, V> {
+    type Output = TrieMap<K, V>;
+
+    fn add(self, other: Self) -> Self::Output {
+        self.union(other)
+    }
+}
+
+impl<K: AsRef<[u8]>, V> Add for TrieMap<K, V> {
+    type Output = TrieMap<K, V>;
+
+    fn add(self, other: Self) -> Self::Output {
+        self.union(&other)
+    }
+}
+
+impl<K: AsRef<[u8]>, V> Sum for TrieMap<K, V> {
+    fn sum<I>(it: I) -> Self
+    where
+        I: Iterator<Item = Self>,
+    {
+        it.fold(Default::default(), |a, b| a + b)
+    }
+}
+
+impl<K, V, RK, RV> Extend<(RK, RV)> for TrieMap<K, V>
+where
+    K: AsRef<[u8]>,
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (RK, RV)>,
+    {
+        for (key, value) in iter {
+            self.insert_mut(key, value);
+        }
+    }
+}
+
+impl<BK, K, V> Index<&BK> for TrieMap<K, V>
+where
+    BK: AsRef<[u8]> + ?Sized,
+    K: AsRef<[u8]>,
+{
+    type Output = V;
+
+    fn index(&self, key: &BK) -> &Self::Output {
+        match self.root.lookup(key.as_ref()) {
+            None => panic!("TrieMap::index: invalid key"),
+            Some((_, value)) => value,
+        }
+    }
+}
+
+impl<BK, K, V> IndexMut<&BK> for TrieMap<K, V>
+where
+    BK: AsRef<[u8]> + ?Sized,
+    K: AsRef<[u8]>,
+    V: Clone,
+{
+    fn index_mut(&mut self, key: &BK) -> &mut Self::Output {
+        if !self.contains_key(key) {
+            panic!("TrieMap::index_mut: invalid key");
+        }
+        match Arc::make_mut(&mut self.root).lookup_mut(key.as_ref()) {
+            None => unreachable!(),
+            Some(&mut (_, ref mut value)) => Arc::make_mut(value),
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for TrieMap<K, V> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Iterators
+
+/// An iterator over the keys of a [`TrieMap`][TrieMap].
+///
+/// [TrieMap]: ./struct.TrieMap.html
+pub struct Keys<K, V> {
+    it: Iter<K, V>,
+}
+
+impl<K, V> Iterator for Keys<K, V> {
+    type Item = Arc<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next().map(|(k, _)| k)
+    }
+}
+
+/// An iterator over the values of a [`TrieMap`][TrieMap].
+///
+/// [TrieMap]: ./struct.TrieMap.html
+pub struct Values<K, V> {
+    it: Iter<K, V>,
+}
+
+impl<K, V> Iterator for Values<K, V> {
+    type Item = Arc<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next().map(|(_, v)| v)
+    }
+}
+
+impl<K: AsRef<[u8]>, V, RK, RV> FromIterator<(RK, RV)> for TrieMap<K, V>
+where
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from_iter<T>(i: T) -> Self
+    where
+        T: IntoIterator<Item = (RK, RV)>,
+    {
+        let mut m = TrieMap::default();
+        m.extend(i);
+        m
+    }
+}
+
+impl<K, V> IntoIterator for &TrieMap<K, V> {
+    type Item = (Arc<K>, Arc<V>);
+    type IntoIter = Iter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V> IntoIterator for TrieMap<K, V> {
+    type Item = (Arc<K>, Arc<V>);
+    type IntoIter = Iter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Conversions
+
+impl<K: AsRef<[u8]>, V, RK, RV> From<Vec<(RK, RV)>> for TrieMap<K, V>
+where
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from(m: Vec<(RK, RV)>) -> TrieMap<K, V> {
+        m.into_iter().collect()
+    }
+}
+
+impl<K: AsRef<[u8]>, V, RK, RV> From<collections::BTreeMap<RK, RV>> for TrieMap<K, V>
+where
+    RK: Shared<K>,
+    RV: Shared<V>,
+{
+    fn from(m: collections::BTreeMap<RK, RV>) -> TrieMap<K, V> {
+        m.into_iter().collect()
+    }
+}
+
+impl<K: AsRef<[u8]> + Ord, V> From<OrdMap<K, V>> for TrieMap<K, V> {
+    fn from(m: OrdMap<K, V>) -> Self {
+        m.into_iter().collect()
+    }
+}
+
+impl<'a, K: AsRef<[u8]> + Ord, V> From<&'a OrdMap<K, V>> for TrieMap<K, V> {
+    fn from(m: &'a OrdMap<K, V>) -> Self {
+        m.into_iter().collect()
+    }
+}
+
+impl<K: Ord, V> From<TrieMap<K, V>> for OrdMap<K, V> {
+    fn from(m: TrieMap<K, V>) -> Self {
+        m.into_iter().collect()
+    }
+}
+
+impl<'a, K: Ord, V> From<&'a TrieMap<K, V>> for OrdMap<K, V> {
+    fn from(m: &'a TrieMap<K, V>) -> Self {
+        m.into_iter().collect()
+    }
+}
+
+// QuickCheck
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K: AsRef<[u8]> + Arbitrary + Sync, V: Arbitrary + Sync> Arbitrary for TrieMap<K, V> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        TrieMap::from_iter(Vec::<(K, V)>::arbitrary(g))
+    }
+}
+
+// Proptest
+
+#[cfg(any(test, feature = "proptest"))]
+pub mod proptest {
+    use super::*;
+    use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
+    use std::ops::Range;
+
+    /// A strategy for a trie map of a given size.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn proptest_works(ref m in trie_map("[a-z]{0,8}", 0..9999, 10..100)) {
+    ///         assert!(m.len() < 100);
+    ///         assert!(m.len() >= 10);
+    ///     }
+    /// }
+    /// ```
+    pub fn trie_map<K: Strategy + 'static, V: Strategy + 'static>(
+        key: K,
+        value: V,
+        size: Range<usize>,
+    ) -> BoxedStrategy<TrieMap<<K::Value as ValueTree>::Value, <V::Value as ValueTree>::Value>>
+    where
+        <K::Value as ValueTree>::Value: AsRef<[u8]>,
+    {
+        ::proptest::collection::vec((key, value), size.clone())
+            .prop_map(TrieMap::from)
+            .prop_filter("TrieMap minimum size".to_owned(), move |m| {
+                m.len() >= size.start
+            })
+            .boxed()
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::proptest::*;
+    use super::*;
+    use proptest::collection;
+    use proptest::num::i16;
+    use std::collections::BTreeMap;
+
+    fn key() -> ::proptest::string::RegexGeneratorStrategy<String> {
+        ::proptest::string::string_regex("[abc]{0,6}").unwrap()
+    }
+
+    #[test]
+    fn empty_key() {
+        let map = triemap!{"" => 1, "a" => 2};
+        assert_eq!(Some(Arc::new(1)), map.get(""));
+        assert_eq!(Some(Arc::new(1)), map.longest_prefix_match("b").map(|(_, v)| v));
+        assert_eq!(2, map.iter_prefix("").count());
+        let map = map.remove("");
+        assert_eq!(None, map.get(""));
+        assert_eq!(1, map.len());
+    }
+
+    #[test]
+    fn byte_keys() {
+        let mut map: TrieMap<Vec<u8>, i32> = TrieMap::new();
+        map.insert_mut(vec![0, 255], 1);
+        map.insert_mut(vec![0], 2);
+        map.insert_mut(vec![0, 1, 2], 3);
+        let keys: Vec<Vec<u8>> = map.keys().map(|k| (*k).clone()).collect();
+        assert_eq!(vec![vec![0], vec![0, 1, 2], vec![0, 255]], keys);
+        assert_eq!(3, map[&[0u8, 1, 2][..]]);
+    }
+
+    #[test]
+    fn persistence() {
+        let map = triemap!{"romane" => 1, "romanus" => 2, "romulus" => 3};
+        let removed = map.remove("romanus");
+        let inserted = map.insert("rom", 4);
+        assert_eq!(3, map.len());
+        assert_eq!(Some(Arc::new(2)), map.get("romanus"));
+        assert_eq!(None, removed.get("romanus"));
+        assert_eq!(None, map.get("rom"));
+        assert_eq!(4, inserted.len());
+    }
+
+    #[test]
+    fn filter_prefix_shares_subtree() {
+        let map = triemap!{"car" => 1, "cart" => 2, "cat" => 3, "dog" => 4};
+        let cars = map.filter_prefix("ca");
+        assert_eq!(3, cars.len());
+        let keys: Vec<_> = cars.keys().map(|k| *k).collect();
+        assert_eq!(vec!["car", "cart", "cat"], keys);
+        assert_eq!(None, cars.get("dog"));
+        let cars = cars.insert("ca", 5).insert("d", 6);
+        assert_eq!(Some(Arc::new(5)), cars.get("ca"));
+        assert_eq!(Some(Arc::new(6)), cars.get("d"));
+        assert_eq!(None, map.get("ca"));
+        assert!(map.filter_prefix("").ptr_eq(&map));
+        assert!(map.filter_prefix("cb").is_empty());
+    }
+
+    #[test]
+    fn ordmap_round_trip() {
+        let ordmap = ordmap!{"b".to_string() => 2, "a".to_string() => 1, "ab".to_string() => 3};
+        let trie = TrieMap::from(&ordmap);
+        let keys: Vec<_> = trie.keys().map(|k| (*k).clone()).collect();
+        assert_eq!(vec!["a", "ab", "b"], keys);
+        assert_eq!(ordmap, OrdMap::from(trie));
+    }
+
+    proptest! {
+        #[test]
+        fn matches_btreemap(ref ops in collection::vec((key(), i16::ANY, 0..3u8), 0..200)) {
+            let mut map = TrieMap::new();
+            let mut model = BTreeMap::new();
+            for &(ref k, v, op) in ops {
+                if op == 0 {
+                    assert_eq!(model.remove(k).map(Arc::new), map.pop_mut(k));
+                } else {
+                    map.insert_mut(k.clone(), v);
+                    model.insert(k.clone(), v);
+                }
+                assert_eq!(model.len(), map.len());
+            }
+            let expected: Vec<(String, i16)> = model.clone().into_iter().collect();
+            let actual: Vec<(String, i16)> = map.iter().map(|(k, v)| ((*k).clone(), *v)).collect();
+            assert_eq!(expected, actual);
+            for (k, v) in &model {
+                assert_eq!(Some(Arc::new(*v)), map.get(k));
+            }
+        }
+
+        #[test]
+        fn prefix_queries(ref map in trie_map(key(), i16::ANY, 0..100), ref query in key()) {
+            let expected: Vec<Arc<String>> = map.keys().filter(|k| k.starts_with(query.as_str())).collect();
+            let actual: Vec<Arc<String>> = map.iter_prefix(query).map(|(k, _)| k).collect();
+            assert_eq!(expected, actual);
+            let longest = map.keys().filter(|k| query.starts_with(k.as_str())).max_by_key(|k| k.len());
+            assert_eq!(longest, map.longest_prefix_match(query).map(|(k, _)| k));
+            let filtered = map.filter_prefix(query);
+            assert_eq!(expected.len(), filtered.len());
+            assert_eq!(expected, filtered.keys().collect::<Vec<_>>());
+            for k in &expected {
+                assert_eq!(map.get(&**k), filtered.get(&**k));
+                assert_eq!(None, filtered.remove(&**k).get(&**k));
+            }
+            let mut extended = filtered.clone();
+            extended.insert_mut(format!("{}c", query), 0);
+            assert_eq!(Some(Arc::new(0)), extended.get(&format!("{}c", query)));
+        }
+
+        #[test]
+        fn remove_everything(ref map in trie_map(key(), i16::ANY, 0..100)) {
+            let mut removed = map.clone();
+            for k in map.keys() {
+                removed.remove_mut(&*k);
+                assert!(!removed.contains_key(&*k));
+                assert!(map.contains_key(&*k));
+            }
+            assert!(removed.is_empty());
+            assert_eq!(None, removed.iter().next());
+        }
+    }
+}
@@ -28,14 +28,20 @@
 //! [CatList]: ../catlist/struct.CatList.html
 
 use std::borrow::Borrow;
-use std::cmp::Ordering;
+use std::cmp::{max, min, Ordering};
 use std::fmt::{Debug, Error, Formatter};
 use std::hash::{Hash, Hasher};
 use std::iter::{FromIterator, Sum};
-use std::ops::{Add, Index, IndexMut};
+use std::ops::{Add, Index, IndexMut, Range};
+use std::string::String;
 use std::sync::Arc;
+use std::vec::Vec;
 
 use bits::{HASH_BITS, HASH_MASK, HASH_SIZE};
+use footprint::{Footprint, HeapSize};
+#[cfg(any(test, feature = "dot"))]
+use dot::{Dot, ToDot};
+use invariant::InvariantError;
 use shared::Shared;
 
 use nodes::vector::{Entry, Node};
@@ -67,7 +73,7 @@ macro_rules! vector {
     }};
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 struct Meta {
     origin: usize,
     capacity: usize,
@@ -133,7 +139,10 @@ impl<A> Vector<A> {
         let mut tail = Node::new();
         tail.push(Entry::Value(a.shared()));
         Vector {
-            meta: Default::default(),
+            meta: Meta {
+                capacity: 1,
+                ..Default::default()
+            },
             root: Default::default(),
             tail: Arc::new(tail),
         }
@@ -164,6 +173,19 @@ impl<A> Vector<A> {
         self.len() == 0
     }
 
+    /// Test whether two vectors refer to the same content in memory.
+    ///
+    /// This would return true if you're comparing a vector to
+    /// itself, or if you're comparing a vector to a fresh clone
+    /// of itself.
+    ///
+    /// Time: O(1)
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.meta == other.meta && Arc::ptr_eq(&self.root, &other.root)
+            && Arc::ptr_eq(&self.tail, &other.tail)
+    }
+
     /// Get an iterator over a vector.
     ///
     /// Time: O(log n) per [`next()`][next] call
@@ -592,6 +614,14 @@ impl<A> Vector<A> {
     ///
     /// Time: O(n) where n = the length of the iterator
     pub fn write<I: IntoIterator<Item = R>, R: Shared<A>>(&mut self, index: usize, iter: I) {
+        if self.meta.reverse {
+            // The raw indices run backwards, so write one at a time.
+            let len = self.len();
+            for (index, value) in (index..len).zip(iter) {
+                self.set_mut(index, value);
+            }
+            return;
+        }
         if let Some(raw_index) = self.map_index(index) {
             let cap = self.meta.capacity;
             let tail_offset = tail_offset(cap);
@@ -842,8 +872,64 @@ impl<A> Vector<A> {
         out
     }
 
+    /// Check the internal structure of a vector, returning a
+    /// description of the first broken invariant found, if any.
+    ///
+    /// This checks that the vector's origin, capacity and tree level
+    /// are consistent with each other, and that every index in the
+    /// vector leads to a value. See the [`invariant`][invariant]
+    /// module.
+    ///
+    /// Time: O(n)
+    ///
+    /// [invariant]: ../invariant/index.html
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.check()
+            .map_err(|message| InvariantError::new("Vector", message))
+    }
+
+    /// Panic if the internal structure of a vector is broken, as
+    /// described by [`validate`][validate].
+    ///
+    /// Time: O(n)
+    ///
+    /// [validate]: #method.validate
+    pub fn assert_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("{}", error)
+        }
+    }
+
     // Implementation details
 
+    fn check(&self) -> Result<(), String> {
+        let Meta {
+            origin,
+            capacity,
+            level,
+            ..
+        } = self.meta;
+        if origin > capacity {
+            return Err(format!(
+                "the origin {} is past the capacity {}",
+                origin, capacity
+            ));
+        }
+        if level == 0 || level % HASH_BITS != 0 {
+            return Err(format!("the root is at level {}", level));
+        }
+        let tail_offset = tail_offset(capacity);
+        if tail_offset >= 1 << (level + HASH_BITS) {
+            return Err(format!(
+                "the tail starts at {}, past the end of a root at level {}",
+                tail_offset, level
+            ));
+        }
+        self.root.check(level, 0, origin, min(capacity, tail_offset))?;
+        self.tail
+            .check(0, tail_offset, max(origin, tail_offset), capacity)
+    }
+
     fn map_index(&self, index: usize) -> Option<usize> {
         let len = self.len();
         if index >= len {
@@ -878,6 +964,79 @@ impl<A> Vector<A> {
         }
     }
 
+    // The entries of the leaf holding the element at `index`, where in
+    // them it sits, and whether going forwards or backwards through
+    // the vector from there goes forwards through the leaf.
+    fn leaf_run(&self, index: usize, forward: bool) -> (&[Entry<A>], usize, bool) {
+        let index = self.map_index(index).unwrap();
+        (
+            &self.node_for(index).children,
+            index & HASH_MASK as usize,
+            forward != self.meta.reverse,
+        )
+    }
+
+    // The nodes on the way to a physical index, from the root down to
+    // the leaf, with the level of each and the physical index where
+    // the vector stops reading from it.
+    fn spine<'a>(&'a self, index: usize) -> Spine<'a, A> {
+        let offset = tail_offset(self.meta.capacity);
+        if index >= offset {
+            Spine {
+                next: Some((0, &self.tail)),
+                index,
+                end: self.meta.capacity,
+            }
+        } else {
+            Spine {
+                next: Some((self.meta.level, &self.root)),
+                index,
+                end: offset,
+            }
+        }
+    }
+
+    // The number of elements starting at `index` here and at
+    // `other_index` in `other`, and going forwards or backwards from
+    // there, which are known to be the same because both vectors
+    // keep them in the same place in a node they share. Reversed
+    // vectors lay their elements out backwards, so they're never
+    // considered to share anything.
+    fn shared_run(&self, index: usize, other: &Self, other_index: usize, forward: bool) -> usize {
+        if self.meta.reverse || other.meta.reverse {
+            return 0;
+        }
+        let (left, right) = (self.meta.origin + index, other.meta.origin + other_index);
+        // Both spines go down a level at a time, so walk them side by
+        // side, comparing the nodes at each level both of them have.
+        let mut other_spine = other.spine(right).peekable();
+        for (level, node, end) in self.spine(left) {
+            while let Some(&(other_level, _, _)) = other_spine.peek() {
+                if other_level <= level {
+                    break;
+                }
+                other_spine.next();
+            }
+            let other_end = match other_spine.peek() {
+                Some(&(other_level, other_node, other_end))
+                    if other_level == level && Arc::ptr_eq(node, other_node) =>
+                {
+                    other_end
+                }
+                _ => continue,
+            };
+            let span = 1 << (level + HASH_BITS);
+            if left % span == right % span {
+                return if forward {
+                    min(span - left % span, min(end - left, other_end - right))
+                } else {
+                    min(left % span, min(index, other_index)) + 1
+                };
+            }
+        }
+        0
+    }
+
     fn clear(&mut self) {
         self.meta = Default::default();
         self.root = Default::default();
@@ -1029,6 +1188,184 @@ impl<A> Vector<A> {
             tail: tail.shared(),
         }
     }
+
+    // Take the elements out of a vector which aren't shared with any
+    // other vector, so that the caller can dispose of them without
+    // relying on a recursive drop.
+    pub(crate) fn into_unique(self) -> Vec<A> {
+        let mut out = Vec::new();
+        Node::take_unique(self.root, &mut out);
+        Node::take_unique(self.tail, &mut out);
+        out
+    }
+}
+
+impl<A: PartialEq> Vector<A> {
+    /// Find the changes which turn this vector into another one, as
+    /// an edit script of ranges to insert, delete or replace.
+    ///
+    /// The script is as short as possible, counting each element
+    /// inserted or deleted as one step and each element replaced as
+    /// two, and its edits are in order and don't overlap, so
+    /// stepping through them while copying the elements in between
+    /// from this vector gives you the other one.
+    ///
+    /// The common elements at either end are skipped without being
+    /// compared when they sit in nodes the two vectors share, and
+    /// [Myers' algorithm][myers] is only run on what's left, skipping
+    /// shared nodes in the same way wherever it finds a run of
+    /// matching elements. Comparing a vector with a modified copy of
+    /// itself is fast, however large the vector is, when the copy
+    /// was changed in only a few places.
+    ///
+    /// Time: O((n + m) d) (where d is the length of the edit script)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::vector::Edit;
+    /// # fn main() {
+    /// let old = vector![1, 2, 3, 4, 5];
+    /// let new = vector![1, 3, 4, 6, 5, 7];
+    /// assert_eq!(
+    ///   vec![
+    ///     Edit::Delete { old: 1..2, new: 1 },
+    ///     Edit::Insert { old: 4, new: 3..4 },
+    ///     Edit::Insert { old: 5, new: 5..6 },
+    ///   ],
+    ///   old.diff(&new)
+    /// );
+    /// # }
+    /// ```
+    ///
+    /// [myers]: http://www.xmailserver.org/diff2.pdf
+    pub fn diff<RV: Borrow<Self>>(&self, other: RV) -> Vec<Edit> {
+        let other = other.borrow();
+        let mut differ = Differ {
+            old: self,
+            new: other,
+            matches: Vec::new(),
+        };
+        differ.diff(0, self.len(), 0, other.len());
+        let mut edits = Vec::new();
+        let (mut old, mut new) = (0, 0);
+        let end = (self.len(), other.len(), 0);
+        for (old_start, new_start, len) in differ.matches.into_iter().chain(Some(end)) {
+            if old_start > old && new_start > new {
+                edits.push(Edit::Replace {
+                    old: old..old_start,
+                    new: new..new_start,
+                });
+            } else if old_start > old {
+                edits.push(Edit::Delete {
+                    old: old..old_start,
+                    new,
+                });
+            } else if new_start > new {
+                edits.push(Edit::Insert {
+                    old,
+                    new: new..new_start,
+                });
+            }
+            old = old_start + len;
+            new = new_start + len;
+        }
+        edits
+    }
+
+    // Count the elements which match, up to `limit` of them, going
+    // forwards from `index` here and `other_index` in `other`, or
+    // backwards from just before them, stepping over the nodes the
+    // two vectors share.
+    fn matching(
+        &self,
+        index: usize,
+        other: &Self,
+        other_index: usize,
+        limit: usize,
+        forward: bool,
+    ) -> usize {
+        let mut count = 0;
+        while count < limit {
+            let (left, right) = if forward {
+                (index + count, other_index + count)
+            } else {
+                (index - count - 1, other_index - count - 1)
+            };
+            let shared = self.shared_run(left, other, right, forward);
+            if shared > 0 {
+                count += min(shared, limit - count);
+                continue;
+            }
+            // Nothing is shared until one side moves into another
+            // leaf, so compare the elements up to there, straight out
+            // of the leaves.
+            let (left, right) = if forward {
+                (index + count, other_index + count)
+            } else {
+                (index - count - 1, other_index - count - 1)
+            };
+            let (left_leaf, left_at, left_forward) = self.leaf_run(left, forward);
+            let (right_leaf, right_at, right_forward) = other.leaf_run(right, forward);
+            let room = |at: usize, forward: bool| if forward { HASH_SIZE - at } else { at + 1 };
+            let step = min(
+                limit - count,
+                min(room(left_at, left_forward), room(right_at, right_forward)),
+            );
+            for offset in 0..step {
+                let step_from = |at: usize, forward: bool| {
+                    if forward {
+                        at + offset
+                    } else {
+                        at - offset
+                    }
+                };
+                let left = left_leaf[step_from(left_at, left_forward)].unwrap_val();
+                let right = right_leaf[step_from(right_at, right_forward)].unwrap_val();
+                if !(Arc::ptr_eq(&left, &right) || left == right) {
+                    return count;
+                }
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn test_eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        if self.meta != other.meta {
+            return self.iter().eq(other.iter());
+        }
+        // With the same layout, both tries should hold each index in
+        // the same place, so we can compare them node by node and skip
+        // over anything they share, falling back on comparing their
+        // values in order if they don't.
+        let Meta {
+            origin,
+            capacity,
+            level,
+            ..
+        } = self.meta;
+        let offset = tail_offset(capacity);
+        Node::equal_range(&self.root, &other.root, level, 0, origin, offset)
+            .and_then(|equal| {
+                if !equal {
+                    return Some(false);
+                }
+                Node::equal_range(
+                    &self.tail,
+                    &other.tail,
+                    0,
+                    offset,
+                    origin.max(offset),
+                    capacity,
+                )
+            })
+            .unwrap_or_else(|| self.iter().eq(other.iter()))
+    }
 }
 
 fn tail_offset(size: usize) -> usize {
@@ -1039,6 +1376,210 @@ fn tail_offset(size: usize) -> usize {
     }
 }
 
+// Diffing
+
+/// An edit in the script returned by [`Vector::diff`][diff].
+///
+/// Indices and ranges under `old` refer to the vector `diff` was
+/// called on, and those under `new` to the vector it was compared
+/// with, where the elements to put in place can be found.
+///
+/// [diff]: ./struct.Vector.html#method.diff
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Edit {
+    /// Insert the elements in the `new` range before index `old`.
+    Insert { old: usize, new: Range<usize> },
+    /// Delete the elements in the `old` range, which would have
+    /// started at index `new`.
+    Delete { old: Range<usize>, new: usize },
+    /// Replace the elements in the `old` range with those in the
+    /// `new` range.
+    Replace {
+        old: Range<usize>,
+        new: Range<usize>,
+    },
+}
+
+// The nodes from `Vector::spine`, found one level at a time so that
+// looking for shared nodes doesn't allocate.
+struct Spine<'a, A: 'a> {
+    next: Option<(usize, &'a Arc<Node<A>>)>,
+    index: usize,
+    end: usize,
+}
+
+impl<'a, A> Iterator for Spine<'a, A> {
+    type Item = (usize, &'a Arc<Node<A>>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (level, node) = self.next.take()?;
+        if level > 0 {
+            match node.children.get((self.index >> level) & HASH_MASK as usize) {
+                Some(&Entry::Node(ref child)) => self.next = Some((level - HASH_BITS, child)),
+                _ => panic!("Vector::spine: encountered value or null where node was expected"),
+            }
+        }
+        Some((level, node, self.end))
+    }
+}
+
+// The linear space version of Myers' algorithm, which splits the
+// problem in two at a point halfway along a shortest edit path,
+// found by searching from both ends at once, until what's left of it
+// is all insertions or all deletions. Matching elements are found
+// with `Vector::matching`, so runs of them held in shared nodes are
+// stepped over whole.
+struct Differ<'a, A: 'a> {
+    old: &'a Vector<A>,
+    new: &'a Vector<A>,
+    // The runs of matching elements found so far, in order, as their
+    // index in each vector and their length.
+    matches: Vec<(usize, usize, usize)>,
+}
+
+impl<'a, A: PartialEq> Differ<'a, A> {
+    fn diff(
+        &mut self,
+        mut old_start: usize,
+        mut old_end: usize,
+        mut new_start: usize,
+        mut new_end: usize,
+    ) {
+        let limit = min(old_end - old_start, new_end - new_start);
+        let prefix = self
+            .old
+            .matching(old_start, self.new, new_start, limit, true);
+        if prefix > 0 {
+            self.matches.push((old_start, new_start, prefix));
+            old_start += prefix;
+            new_start += prefix;
+        }
+        let limit = min(old_end - old_start, new_end - new_start);
+        let suffix = self.old.matching(old_end, self.new, new_end, limit, false);
+        old_end -= suffix;
+        new_end -= suffix;
+        if old_start < old_end && new_start < new_end {
+            let (old_mid, new_mid) = self.split(old_start, old_end, new_start, new_end);
+            self.diff(old_start, old_mid, new_start, new_mid);
+            self.diff(old_mid, old_end, new_mid, new_end);
+        }
+        if suffix > 0 {
+            self.matches.push((old_end, new_end, suffix));
+        }
+    }
+
+    // Find a point on a shortest edit path through the given ranges
+    // with half of the path's edits on either side of it. The ranges
+    // must both be non-empty and differ at both ends, so the path
+    // has at least two edits and the point lies strictly inside it.
+    fn split(
+        &self,
+        old_start: usize,
+        old_end: usize,
+        new_start: usize,
+        new_end: usize,
+    ) -> (usize, usize) {
+        let (n, m) = (old_end - old_start, new_end - new_start);
+        let delta = n as isize - m as isize;
+        let odd = delta % 2 != 0;
+        let mut forward = Frontier::new();
+        let mut backward = Frontier::new();
+        // The searches meet by the time each has made half the edits.
+        for d in 0..=((n + m) / 2 + 1) as isize {
+            forward.widen(d);
+            backward.widen(d);
+            for k in (-d..d + 1).filter(|k| (k + d) % 2 == 0) {
+                let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                    forward[k + 1]
+                } else {
+                    forward[k - 1] + 1
+                };
+                let y = (x as isize - k) as usize;
+                let (x0, y0) = (x, y);
+                if x < n && y < m {
+                    x += self.old.matching(
+                        old_start + x,
+                        self.new,
+                        new_start + y,
+                        min(n - x, m - y),
+                        true,
+                    );
+                }
+                forward[k] = x;
+                if odd && (k - delta).abs() < d && forward[k] + backward[delta - k] >= n {
+                    return (old_start + x0, new_start + y0);
+                }
+            }
+            for k in (-d..d + 1).filter(|k| (k + d) % 2 == 0) {
+                let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+                    backward[k + 1]
+                } else {
+                    backward[k - 1] + 1
+                };
+                let mut y = (x as isize - k) as usize;
+                if x < n && y < m {
+                    let run = self.old.matching(
+                        old_end - x,
+                        self.new,
+                        new_end - y,
+                        min(n - x, m - y),
+                        false,
+                    );
+                    x += run;
+                    y += run;
+                }
+                backward[k] = x;
+                if !odd && (k - delta).abs() <= d && backward[k] + forward[delta - k] >= n {
+                    return (old_end - x, new_end - y);
+                }
+            }
+        }
+        unreachable!("Differ::split: the searches from either end never met")
+    }
+}
+
+// How far along each diagonal a search in `Differ::split` has got,
+// growing to fit the diagonals as the search widens.
+struct Frontier {
+    reach: Vec<usize>,
+    centre: isize,
+}
+
+impl Frontier {
+    fn new() -> Self {
+        Frontier {
+            reach: vec![0; 3],
+            centre: 1,
+        }
+    }
+
+    // Make room for the diagonals from -d - 1 to d + 1.
+    fn widen(&mut self, d: isize) {
+        if d + 1 > self.centre {
+            let centre = max(d + 1, self.centre * 2);
+            let offset = (centre - self.centre) as usize;
+            let mut reach = vec![0; 2 * centre as usize + 1];
+            reach[offset..offset + self.reach.len()].copy_from_slice(&self.reach);
+            self.reach = reach;
+            self.centre = centre;
+        }
+    }
+}
+
+impl Index<isize> for Frontier {
+    type Output = usize;
+
+    fn index(&self, diagonal: isize) -> &usize {
+        &self.reach[(diagonal + self.centre) as usize]
+    }
+}
+
+impl IndexMut<isize> for Frontier {
+    fn index_mut(&mut self, diagonal: isize) -> &mut usize {
+        &mut self.reach[(diagonal + self.centre) as usize]
+    }
+}
+
 // Core traits
 
 impl<A> Clone for Vector<A> {
@@ -1057,42 +1598,48 @@ impl<A> Default for Vector<A> {
     }
 }
 
-impl<A: Debug> Debug for Vector<A> {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.debug_list().entries(self.iter()).finish()
+impl<A> Vector<A> {
+    // Count the memory used by this vector, calling `value` for each
+    // value in it, and return its depth.
+    pub(crate) fn measure_heap_with<F>(&self, footprint: &mut Footprint, value: &mut F) -> usize
+    where
+        F: FnMut(&mut Footprint, &Arc<A>),
+    {
+        let root = Node::measure_heap(&self.root, footprint, value);
+        let tail = Node::measure_heap(&self.tail, footprint, value);
+        max(root, tail)
     }
 }
 
-#[cfg(not(has_specialisation))]
-impl<A: PartialEq> PartialEq for Vector<A> {
-    fn eq(&self, other: &Self) -> bool {
-        if self.len() != other.len() {
-            return false;
-        }
-        self.iter().eq(other.iter())
+impl<A> HeapSize for Vector<A> {
+    fn measure_heap(&self, footprint: &mut Footprint) -> usize {
+        self.measure_heap_with(footprint, &mut |footprint, value| {
+            footprint.value(value);
+        })
     }
 }
 
-#[cfg(has_specialisation)]
-impl<A: PartialEq> PartialEq for Vector<A> {
-    default fn eq(&self, other: &Self) -> bool {
-        if self.len() != other.len() {
-            return false;
-        }
-        self.iter().eq(other.iter())
+#[cfg(any(test, feature = "dot"))]
+impl<A> ToDot for Vector<A> {
+    fn dot_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write_dot(&self, dot: &mut Dot, from: &str) {
+        Node::write_dot(&self.root, dot, from, "root");
+        Node::write_dot(&self.tail, dot, from, "tail");
     }
 }
 
-#[cfg(has_specialisation)]
-impl<A: Eq> PartialEq for Vector<A> {
+impl<A: Debug> Debug for Vector<A> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<A: PartialEq> PartialEq for Vector<A> {
     fn eq(&self, other: &Self) -> bool {
-        if self.len() != other.len() {
-            return false;
-        }
-        if Arc::ptr_eq(&self.root, &other.root) && Arc::ptr_eq(&self.tail, &other.tail) {
-            return true;
-        }
-        self.iter().eq(other.iter())
+        self.ptr_eq(other) || self.test_eq(other)
     }
 }
 
@@ -1352,6 +1899,9 @@ pub mod proptest {
     use super::*;
     use proptest::strategy::{BoxedStrategy, Strategy, ValueTree};
     use std::ops::Range;
+    use model::{vector_op, VectorOp};
+    use proptest::collection::vec;
+    use proptest::num::{u8, usize};
 
     /// A strategy for generating a vector of a certain size.
     ///
@@ -1374,6 +1924,35 @@ pub mod proptest {
             .prop_map(Vector::from_iter)
             .boxed()
     }
+
+    /// A strategy for a sequence of operations on a vector, of a
+    /// length in the given range, for use with
+    /// [`check_against_model`][check_against_model].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// proptest! {
+    ///     #[test]
+    ///     fn vectors_match_model(ref ops in vector_ops(0..100i32, 0..500)) {
+    ///         check_against_model::<Vector<i32>>(ops).unwrap();
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [check_against_model]: ../../model/fn.check_against_model.html
+    pub fn vector_ops<T: Strategy + 'static>(
+        element: T,
+        size: Range<usize>,
+    ) -> BoxedStrategy<Vec<VectorOp<<T::Value as ValueTree>::Value>>> {
+        vec((u8::ANY, element, usize::ANY, usize::ANY), size)
+            .prop_map(|ops| {
+                ops.into_iter()
+                    .map(|(choice, value, index, other)| vector_op(choice, value, index, other))
+                    .collect()
+            })
+            .boxed()
+    }
 }
 
 // Tests
@@ -1398,8 +1977,11 @@ mod test {
     use super::proptest::*;
     use super::*;
     use proptest::collection;
-    use proptest::num::i32;
+    use proptest::bool;
+    use proptest::num::{i32, usize};
     use std::iter;
+    use std::ops::Range;
+    use test::Counted;
 
     #[test]
     fn wat() {
@@ -1410,6 +1992,15 @@ mod test {
         }
     }
 
+    #[test]
+    fn singleton() {
+        let vector = Vector::singleton(1);
+        assert_eq!(1, vector.len());
+        assert_eq!(vector![1, 2], vector.push_back(2));
+        assert_eq!(vector![0, 1], vector.push_front(0));
+        vector.assert_invariants();
+    }
+
     #[test]
     fn double_ended_iterator() {
         let vector = Vector::<i32>::from_iter(1..6);
@@ -1440,6 +2031,48 @@ mod test {
         assert_eq!(vector![1, 2, 3, 9, 5], vec);
     }
 
+    #[test]
+    fn equality_skips_shared_nodes() {
+        let vec: Vector<Counted> = (0..100_000).map(Counted).collect();
+        let same = vec.set(50_000, Counted(50_000));
+        let different = vec.set(50_000, Counted(0));
+        Counted::comparisons();
+        assert!(vec == same);
+        assert!(vec != different);
+        assert!(Counted::comparisons() <= 2);
+        assert!(
+            vec.push_front(Counted(0))
+                == different.push_front(Counted(0)).set(50_001, Counted(50_000))
+        );
+    }
+
+    #[test]
+    fn equality_gives_up_on_mismatched_nodes() {
+        let value = Node::from_vec(Some(0), vec![Entry::Value(Arc::new(1))]);
+        let empty = Node::from_vec(Some(0), vec![Entry::Empty]);
+        let parent = Node::from_vec(Some(0), vec![Entry::Node(Arc::new(value.clone()))]);
+        let value = Arc::new(value);
+        assert_eq!(None, Node::equal_range(&value, &Arc::new(empty), 0, 0, 0, 1));
+        assert_eq!(None, Node::equal_range(&value, &Arc::new(parent), 0, 0, 0, 1));
+    }
+
+    #[test]
+    fn ptr_eq() {
+        let vec = Vector::from_iter(0..1000);
+        assert!(vec.ptr_eq(&vec.clone()));
+        assert!(!vec.ptr_eq(&vec.set(500, 0)));
+        assert!(!vec.ptr_eq(&Vector::from_iter(0..1000)));
+        assert!(!vec.ptr_eq(&vec.reverse()));
+    }
+
+    #[test]
+    fn equality_assumes_reflexive_elements() {
+        let vec = Vector::from_iter((0..1000).map(|i| i as f64)).push_back(::std::f64::NAN);
+        assert!(vec == vec.clone());
+        assert!(vec.set(0, 1.0) != vec.set(0, 2.0));
+        assert!(!vec.iter().eq(vec.clone().iter()));
+    }
+
     #[test]
     fn add_operator() {
         let vec1 = vector![1, 2, 3];
@@ -1447,7 +2080,176 @@ mod test {
         assert_eq!(vector![1, 2, 3, 4, 5, 6], vec1 + vec2);
     }
 
+    #[test]
+    fn validate_large_vectors() {
+        let vector: Vector<usize> = (0..100_000).collect();
+        vector.assert_invariants();
+        vector.skip(50_000).assert_invariants();
+        vector.take(50_000).assert_invariants();
+        vector.push_front(0).assert_invariants();
+        vector.append(&vector).reverse().assert_invariants();
+    }
+
+    #[test]
+    fn validate_finds_missing_values() {
+        let mut vector: Vector<i32> = (0..1000).collect();
+        vector.meta.capacity += 1;
+        let error = vector.validate().unwrap_err();
+        assert_eq!("Vector", error.collection());
+        assert_eq!("index 1000 has no value", error.message());
+        vector.meta.capacity = 1000;
+        vector.meta.level = 0;
+        assert!(vector.validate().is_err());
+    }
+
+    #[test]
+    fn append_to_reversed() {
+        let vector: Vector<i32> = (0..100).collect();
+        let appended = vector.reverse().append(&vector);
+        let expected: Vec<i32> = (0..100).rev().chain(0..100).collect();
+        assert_eq!(expected, Vec::from_iter(appended.iter().map(|a| *a)));
+    }
+
+    // Follow an edit script from `old`, returning what it produces and
+    // how many elements it inserts and deletes along the way.
+    fn follow<A: Clone>(old: &Vector<A>, new: &Vector<A>, edits: &[Edit]) -> (Vec<A>, usize) {
+        let mut out = Vec::new();
+        let mut cost = 0;
+        let mut index = 0;
+        let mut copy = |out: &mut Vec<A>, vector: &Vector<A>, range: Range<usize>| {
+            out.extend(range.map(|index| (*vector.get_unwrapped(index)).clone()))
+        };
+        for edit in edits {
+            let (old_range, new_range) = match *edit {
+                Edit::Insert { old, ref new } => (old..old, new.clone()),
+                Edit::Delete { ref old, new } => (old.clone(), new..new),
+                Edit::Replace { ref old, ref new } => (old.clone(), new.clone()),
+            };
+            assert!(index <= old_range.start, "edits out of order: {:?}", edits);
+            copy(&mut out, old, index..old_range.start);
+            assert_eq!(new_range.start, out.len());
+            cost += old_range.len() + new_range.len();
+            copy(&mut out, new, new_range);
+            index = old_range.end;
+        }
+        copy(&mut out, old, index..old.len());
+        (out, cost)
+    }
+
+    fn edit_distance<A: PartialEq>(old: &[A], new: &[A]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in 0..old.len() {
+            for j in 0..new.len() {
+                lcs[i + 1][j + 1] = if old[i] == new[j] {
+                    lcs[i][j] + 1
+                } else {
+                    max(lcs[i][j + 1], lcs[i + 1][j])
+                };
+            }
+        }
+        old.len() + new.len() - 2 * lcs[old.len()][new.len()]
+    }
+
+    #[test]
+    fn diff_edges() {
+        let empty: Vector<i32> = Vector::new();
+        assert_eq!(Vec::<Edit>::new(), empty.diff(&empty));
+        assert_eq!(
+            vec![Edit::Insert { old: 0, new: 0..2 }],
+            empty.diff(vector![1, 2])
+        );
+        assert_eq!(
+            vec![Edit::Delete { old: 0..2, new: 0 }],
+            vector![1, 2].diff(&empty)
+        );
+        assert_eq!(
+            vec![Edit::Replace {
+                old: 1..2,
+                new: 1..3
+            }],
+            vector![1, 2, 3].diff(vector![1, 4, 5, 3])
+        );
+    }
+
+    #[test]
+    fn diff_skips_shared_nodes() {
+        let old: Vector<Counted> = (0..20_000).map(Counted).collect();
+        let new = old
+            .set(5_000, Counted(20_000))
+            .set(15_000, Counted(20_001))
+            .push_front(Counted(20_002))
+            .pop_back()
+            .unwrap()
+            .1;
+        Counted::comparisons();
+        let edits = old.diff(&new);
+        assert!(Counted::comparisons() < 200);
+        assert_eq!(
+            vec![
+                Edit::Insert { old: 0, new: 0..1 },
+                Edit::Replace {
+                    old: 5_000..5_001,
+                    new: 5_001..5_002,
+                },
+                Edit::Replace {
+                    old: 15_000..15_001,
+                    new: 15_001..15_002,
+                },
+                Edit::Delete {
+                    old: 19_999..20_000,
+                    new: 20_000,
+                },
+            ],
+            edits
+        );
+    }
+
     proptest! {
+        #[test]
+        fn operations_keep_invariants(ref ops in collection::vec((0u8..8, usize::ANY), 0..200)) {
+            let mut vector = Vector::new();
+            let mut model = Vec::new();
+            for &(op, value) in ops {
+                match op {
+                    0 => {
+                        vector.push_back_mut(value);
+                        model.push(value);
+                    }
+                    1 => {
+                        vector.push_front_mut(value);
+                        model.insert(0, value);
+                    }
+                    2 => {
+                        vector.pop_back_mut();
+                        model.pop();
+                    }
+                    3 => if vector.pop_front_mut().is_some() {
+                        model.remove(0);
+                    },
+                    4 => {
+                        let count = value % (model.len() + 1);
+                        vector = vector.skip(count);
+                        model.drain(..count);
+                    }
+                    5 => {
+                        let count = value % (model.len() + 1);
+                        vector = vector.take(count);
+                        model.truncate(count);
+                    }
+                    6 => if model.len() < 1000 {
+                        vector = vector.append(&vector);
+                        model = model.iter().chain(model.iter()).cloned().collect();
+                    },
+                    _ => {
+                        vector.reverse_mut();
+                        model.reverse();
+                    }
+                }
+                vector.assert_invariants();
+            }
+            assert_eq!(model, Vec::from_iter(vector.iter().map(|a| *a)));
+        }
+
         #[test]
         fn push_back(ref input in collection::vec(i32::ANY, 0..100)) {
             let mut vector = Vector::new();
@@ -1643,6 +2445,50 @@ mod test {
             assert_eq!(0, it.len());
         }
 
+        #[test]
+        fn equality_after_updates(
+            ref input in collection::vec(i32::ANY, 1..200),
+            ref updates in collection::vec((usize::ANY, i32::ANY), 0..10),
+            reversed in bool::ANY
+        ) {
+            let mut vector = Vector::from_iter(input.iter().cloned());
+            if reversed {
+                vector = vector.reverse();
+            }
+            let mut expected: Vec<i32> = vector.iter().map(|v| *v).collect();
+            let mut updated = vector.clone();
+            for &(index, value) in updates {
+                let index = index % expected.len();
+                expected[index] = value;
+                updated.set_mut(index, value);
+            }
+            let original: Vec<i32> = vector.iter().map(|v| *v).collect();
+            assert_eq!(original == expected, vector == updated);
+            assert_eq!(vector.clone(), vector);
+        }
+
+        #[test]
+        fn equality_across_histories(
+            ref input in collection::vec(i32::ANY, 0..2000),
+            split in usize::ANY
+        ) {
+            let split = split % (input.len() + 1);
+            let mut pushed = Vector::new();
+            for value in input {
+                pushed.push_back_mut(*value);
+            }
+            let mut consed = Vector::new();
+            for value in input.iter().rev() {
+                consed.push_front_mut(*value);
+            }
+            let (left, right) = input.split_at(split);
+            let appended = Vector::from_iter(left.iter().cloned())
+                .append(Vector::from_iter(right.iter().cloned()));
+            assert_eq!(pushed, consed);
+            assert_eq!(pushed, appended);
+            assert_eq!(consed, appended);
+        }
+
         #[test]
         fn sort(ref input in collection::vec(i32::ANY, 0..100)) {
             let vector = Vector::from_iter(input.iter().cloned());
@@ -1709,6 +2555,57 @@ mod test {
             assert_eq!(0, vector.len());
         }
 
+        #[test]
+        fn diff_is_minimal(ref old in vector(0u8..4, 0..150), ref new in vector(0u8..4, 0..150)) {
+            let edits = old.diff(new);
+            let (out, cost) = follow(old, new, &edits);
+            let old: Vec<u8> = old.iter().map(|a| *a).collect();
+            let new: Vec<u8> = new.iter().map(|a| *a).collect();
+            assert_eq!(new, out);
+            assert_eq!(edit_distance(&old, &new), cost);
+        }
+
+        #[test]
+        fn diff_against_reversed(ref old in vector(0u8..4, 0..150), ref new in vector(0u8..4, 0..150)) {
+            let new = new.reverse();
+            let edits = old.diff(&new);
+            let (out, cost) = follow(old, &new, &edits);
+            let old: Vec<u8> = old.iter().map(|a| *a).collect();
+            let new: Vec<u8> = new.iter().map(|a| *a).collect();
+            assert_eq!(new, out);
+            assert_eq!(edit_distance(&old, &new), cost);
+        }
+
+        #[test]
+        fn diff_after_operations(ref old in vector(0u8..4, 0..300),
+                                 ref ops in collection::vec((0u8..5, usize::ANY, 0u8..4), 0..10),
+                                 reverse in bool::ANY) {
+            let old = if reverse { old.reverse() } else { old.clone() };
+            let mut new = old.clone();
+            for &(op, index, value) in ops {
+                match op {
+                    0 if !new.is_empty() => {
+                        let index = index % new.len();
+                        new.set_mut(index, value)
+                    }
+                    1 => new.push_front_mut(value),
+                    2 => new.push_back_mut(value),
+                    3 => {
+                        new.pop_front_mut();
+                    }
+                    _ => {
+                        new.pop_back_mut();
+                    }
+                }
+            }
+            let edits = old.diff(&new);
+            let (out, cost) = follow(&old, &new, &edits);
+            let old: Vec<u8> = old.iter().map(|a| *a).collect();
+            let new: Vec<u8> = new.iter().map(|a| *a).collect();
+            assert_eq!(new, out);
+            assert_eq!(edit_distance(&old, &new), cost);
+        }
+
         #[test]
         fn reversed_pop_back(ref input in collection::vec(i32::ANY, 0..100)) {
             let mut vector = Vector::from_iter(input.iter().cloned()).reverse();